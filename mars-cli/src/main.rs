@@ -1,19 +1,32 @@
 #![allow(non_snake_case)]
 use anyhow::{anyhow, bail, Context, Result};
 use mars_core::{
+    axis_mesh::{write_axes_as_obj, write_axes_as_ply, AxisMesh, AxisMeshBuilder},
+    checkpoint::{CheckpointHeader, SlimRun},
+    chunked::{ChunkedHeader, ChunkedReader, ChunkedWriter},
     complex::Complex,
-    grid::{Index, VineyardsGridMesh},
+    complex::Pos,
+    error::MarsError,
+    feature_size::{write_feature_size_as_obj, write_feature_size_as_ply, FeatureSize},
+    filtration::{Anisotropic, FiltrationKind, ScalarField},
+    grid::{GridRegion, Index, VineyardsGrid, VineyardsGridMesh},
+    planar::Polylines,
+    points::{read_points_from_str, PointComplex},
+    reconstruct::UnionOfBalls,
+    skeleton::{write_skeletons_as_json, write_skeletons_as_obj, Skeleton, ThinningParams},
+    state::{RunParams, State, StateHeader, StateKind},
     stats::{MarsMem, ReductionMem},
-    Grid, Mars, PruningParam, Swap,
+    Grid, Mars, PruningParam, SlimSwapList, SubMars,
 };
 use std::{
-    io::{BufReader, Write},
-    path::PathBuf,
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
 };
-use tracing::{error, info, Level};
+use tracing::{error, info, warn, Level};
 use tracing_subscriber::FmtSubscriber;
 
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[derive(Parser, Debug)]
 #[command(version, about)]
@@ -46,25 +59,272 @@ enum Sub {
     Prune(PruneArgs),
 
     Stats(StatsArgs),
+
+    /// Generate a grid mesh for a complex.
+    ///
+    /// The grid is a regular grid restricted to the inside, outside, or a shell around the
+    /// triangle mesh of the complex, and can be passed to `mars-cli run --mesh-path`.
+    Grid(GridArgs),
+
+    /// Split a problem into sub-problems that can be run separately.
+    ///
+    /// Writes the whole problem to `DIR/problem` and the sub-problems to `DIR/sub-K`.  Each
+    /// sub-problem can be run with `mars-cli run-sub`, possibly on different machines, and the
+    /// results combined with `mars-cli merge`.
+    Split(SplitArgs),
+
+    /// Run a single sub-problem written by `mars-cli split`.
+    RunSub(RunSubArgs),
+
+    /// Merge the results of `mars-cli run-sub` into one state file.
+    Merge(MergeArgs),
+
+    /// Check a complex for problems, and optionally write a repaired copy.
+    ///
+    /// Reports simplices with missing faces, malformed and degenerate simplices, repeated
+    /// simplices, non-manifold simplices, isolated vertices and the number of connected
+    /// components.  Exits with an error if the complex can't be used, unless a repaired copy is
+    /// written.
+    Check(CheckArgs),
+
+    /// Print the barcodes at any point, not just at grid points.
+    ///
+    /// For full state files we start at the closest grid point and run vineyards to the point,
+    /// which also gives the Faustian swaps on the way.  Slim and chunked state files don't have
+    /// reductions, so the barcodes are computed from scratch.
+    Query(QueryArgs),
+}
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+enum FiltrationArg {
+    /// Squared Euclidean distance to the key point.
+    #[default]
+    SquaredEuclidean,
+    /// Euclidean distance to the key point.
+    Euclidean,
+    /// Squared distance with per-axis weights, given with --weights.
+    Anisotropic,
+    /// Per-vertex values from a JSON file, given with --scalar-field.
+    ScalarField,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum PointsArg {
+    /// Vietoris–Rips complex: edges between points closer than --radius.
+    Rips,
+    /// Alpha complex: simplices of the Delaunay triangulation with circumradius at most --radius.
+    Alpha,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Region {
+    Inside,
+    Outside,
+    Shell,
 }
 
 #[derive(Debug, Args)]
-struct RunArgs {
+struct GridArgs {
     #[arg(
         value_name = "complex.obj",
         help = "Path to the .obj file for the input complex."
     )]
     obj_path: PathBuf,
 
+    #[arg(
+        short,
+        long,
+        default_value_t = 2.0,
+        help = "Number of grid points per average longest triangle edge."
+    )]
+    density: f64,
+
+    #[arg(
+        short,
+        long,
+        value_enum,
+        default_value_t = Region::Inside,
+        help = "Which part of space the grid should cover."
+    )]
+    region: Region,
+
+    #[arg(
+        short,
+        long,
+        help = "Width of the shell around the complex. Required for --region shell.",
+        value_name = "WIDTH"
+    )]
+    shell_width: Option<f64>,
+
+    #[arg(
+        short,
+        long,
+        help = "Path to the output grid .obj file.",
+        value_name = "grid.obj"
+    )]
+    output_path: Option<PathBuf>,
+}
+
+impl GridArgs {
+    fn run(&self) -> Result<()> {
+        let region = match self.region {
+            Region::Inside => GridRegion::Inside,
+            Region::Outside => GridRegion::Outside,
+            Region::Shell => {
+                let Some(width) = self.shell_width else {
+                    bail!("--region shell requires --shell-width");
+                };
+                GridRegion::Shell(width)
+            }
+        };
+
+        let complex = Complex::read_from_path(&self.obj_path).context("failed to read complex")?;
+
+        info!("Generate grid");
+        let grid = VineyardsGridMesh::around_complex(&complex, self.density, region)
+            .context("failed to generate grid")?;
+        info!("Generated grid with {} points", grid.points.len());
+
+        if let Some(ref path) = self.output_path {
+            let f = std::fs::File::create(path).context("create output file")?;
+            grid.write_as_obj(std::io::BufWriter::new(f))
+                .context("write obj")?;
+            info!("Wrote grid to {}", path.display());
+        } else {
+            grid.write_as_obj(std::io::stdout().lock())
+                .context("write obj")?;
+            info!("Wrote grid to stdout");
+        }
+
+        Ok(())
+    }
+}
+
+/// The complex and the grid to run on.
+#[derive(Debug, Args)]
+struct ProblemArgs {
+    #[arg(
+        value_name = "complex.obj",
+        help = "Path to the input complex: an .obj, .ply, .stl or .off surface, or a .mesh or .vtk tetrahedral mesh. With --from-points, a point cloud."
+    )]
+    obj_path: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Read the input as a point cloud, with one `x y z` or `v x y z` per line, and build a complex on the points.",
+        requires = "radius"
+    )]
+    from_points: Option<PointsArg>,
+
+    #[arg(
+        long,
+        help = "Radius for building the complex with --from-points.",
+        value_name = "RADIUS",
+        requires = "from_points"
+    )]
+    radius: Option<f64>,
+
+    #[arg(
+        long,
+        help = "Merge input vertices that are closer than DIST, instead of rejecting the complex.",
+        value_name = "DIST",
+        conflicts_with = "from_points"
+    )]
+    merge_vertices: Option<f64>,
+
     #[arg(
         short,
         long,
         help = "Path to the .obj file for a grid mesh.",
-        value_name = "mesh.obj"
+        value_name = "mesh.obj",
+        conflicts_with = "cell_size"
+    )]
+    mesh_path: Option<PathBuf>,
+
+    #[arg(
+        short,
+        long,
+        help = "Use a regular grid around the complex with this cell size instead of a grid mesh.",
+        value_name = "SIZE"
+    )]
+    cell_size: Option<f64>,
+
+    #[arg(
+        short,
+        long,
+        help = "Smallest distance from the regular grid boundary to the complex.",
+        value_name = "BUFFER",
+        default_value_t = 0.0,
+        requires = "cell_size",
+        conflicts_with = "corner"
+    )]
+    buffer: f64,
+
+    #[arg(
+        long,
+        help = "Lower corner of the regular grid. Overrides the automatic grid placement.",
+        value_name = "X,Y,Z",
+        value_parser = parse_triple::<f64>,
+        allow_negative_numbers = true,
+        requires_all = ["cell_size", "shape"]
+    )]
+    corner: Option<[f64; 3]>,
+
+    #[arg(
+        long,
+        help = "Number of grid points along each axis of the regular grid.",
+        value_name = "NX,NY,NZ",
+        value_parser = parse_triple::<isize>,
+        requires = "corner"
+    )]
+    shape: Option<[isize; 3]>,
+
+    #[arg(
+        long,
+        help = "Use a 2D regular grid in the plane of a planar complex, such as a polyline or a planar triangulation with constant z.",
+        requires = "cell_size"
+    )]
+    planar: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        help = "The filtration that the vineyards are computed for."
     )]
-    mesh_path: PathBuf,
+    filtration: FiltrationArg,
+
+    #[arg(
+        long,
+        help = "Axis weights for the anisotropic filtration.",
+        value_name = "WX,WY,WZ",
+        value_parser = parse_triple::<f64>
+    )]
+    weights: Option<[f64; 3]>,
+
+    #[arg(
+        long,
+        help = "JSON file with `key_points` and per-vertex `values` for the scalar-field filtration.",
+        value_name = "field.json"
+    )]
+    scalar_field: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+#[command(
+    mut_arg("obj_path", |a| a.required_unless_present("resume")),
+    mut_arg("mesh_path", |a| a.required_unless_present_any(["cell_size", "resume"]))
+)]
+struct RunArgs {
+    #[command(flatten)]
+    problem: ProblemArgs,
 
-    #[arg(short, long, help = "Number of threads to run in parallel.")]
+    #[arg(
+        short,
+        long,
+        help = "Number of threads to run in parallel. The grid is split into this many parts (default 4)."
+    )]
     threads: Option<usize>,
 
     #[arg(
@@ -86,6 +346,347 @@ struct RunArgs {
 
     #[arg(short, long, help = "Don't include matrices in the output.")]
     slim: bool,
+
+    #[arg(
+        long,
+        help = "Write the output as a chunked state file, which can be read one grid edge at a time. Implies --slim."
+    )]
+    chunked: bool,
+
+    #[arg(
+        long,
+        help = "Refine the regular grid around the medial axes this many times. Implies --slim.",
+        value_name = "DEPTH",
+        requires = "cell_size"
+    )]
+    refine: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Periodically write checkpoints to this directory, so that the run can be resumed with --resume. Only for grid meshes. Implies --slim.",
+        value_name = "DIR",
+        conflicts_with_all = ["resume", "refine"]
+    )]
+    checkpoint: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Number of grid edges between checkpoints.",
+        value_name = "EDGES",
+        default_value_t = 1000
+    )]
+    checkpoint_every: usize,
+
+    #[arg(
+        long,
+        help = "Resume a run from a checkpoint directory written with --checkpoint. Implies --slim.",
+        value_name = "DIR",
+        conflicts_with_all = ["obj_path", "mesh_path", "cell_size", "refine"]
+    )]
+    resume: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+#[command(
+    mut_arg("obj_path", |a| a.required(true)),
+    mut_arg("mesh_path", |a| a.required_unless_present("cell_size"))
+)]
+struct SplitArgs {
+    #[command(flatten)]
+    problem: ProblemArgs,
+
+    #[arg(
+        short,
+        long,
+        help = "Number of sub-problems to split into.",
+        value_name = "N"
+    )]
+    num_parts: usize,
+
+    #[arg(
+        short,
+        long,
+        help = "Directory to write the sub-problems to.",
+        value_name = "DIR"
+    )]
+    output_dir: PathBuf,
+}
+
+impl SplitArgs {
+    fn run(&self) -> Result<()> {
+        let mars = self.problem.read_mars()?;
+        let parts = mars.split_into(self.num_parts)?;
+
+        std::fs::create_dir_all(&self.output_dir).context("create output directory")?;
+        write_rmp_atomic(&self.output_dir.join("problem"), &mars)?;
+        for (k, sub) in parts.iter().enumerate() {
+            write_rmp_atomic(&self.output_dir.join(format!("sub-{}", k)), sub)?;
+        }
+        info!(
+            "Wrote {} sub-problems to {}",
+            parts.len(),
+            self.output_dir.display()
+        );
+        Ok(())
+    }
+}
+
+#[derive(Debug, Args)]
+struct RunSubArgs {
+    #[arg(
+        value_name = "SUB",
+        help = "Path to a sub-problem from `mars-cli split`."
+    )]
+    sub_path: PathBuf,
+
+    #[arg(
+        short,
+        long,
+        help = "Path to the output state file.",
+        value_name = "STATE"
+    )]
+    output_path: PathBuf,
+
+    #[arg(short, long, help = "Don't include matrices in the output.")]
+    slim: bool,
+}
+
+impl RunSubArgs {
+    fn run(&self) -> Result<()> {
+        let sub: SubMars = read_rmp(&self.sub_path)?;
+        let progress = |i: usize, n: usize| log_part_progress(0, i, n);
+
+        let (kind, state) = if self.slim {
+            let swaps = sub.run_slim(progress)?;
+            (StateKind::Slim, State::Slim(swaps, sub.mars))
+        } else {
+            let vin = sub.run(progress)?;
+            (StateKind::Full, State::Full(sub.mars, vin))
+        };
+
+        let header = StateHeader::new(kind, RunParams::default());
+        write_state_file(&self.output_path, &header, &state)?;
+        info!("Wrote output to {}", self.output_path.display());
+        Ok(())
+    }
+}
+
+#[derive(Debug, Args)]
+struct MergeArgs {
+    #[arg(
+        value_name = "PROBLEM",
+        help = "Path to the problem file written by `mars-cli split`."
+    )]
+    problem_path: PathBuf,
+
+    #[arg(
+        value_name = "RESULT",
+        help = "Paths to the outputs of `mars-cli run-sub`.",
+        required = true
+    )]
+    result_paths: Vec<PathBuf>,
+
+    #[arg(
+        short,
+        long,
+        help = "Path to the output state file.",
+        value_name = "STATE"
+    )]
+    output_path: PathBuf,
+}
+
+impl MergeArgs {
+    fn run(&self) -> Result<()> {
+        let mars: Mars = read_rmp(&self.problem_path)?;
+
+        let mut merged: Option<State> = None;
+        for path in &self.result_paths {
+            info!("Read {}", path.display());
+            let (_, state) = read_state_file(path)?;
+            merged = Some(match (merged, state) {
+                (None, State::Full(_, vin)) => State::Full(mars.clone(), vin),
+                (None, State::Slim(swaps, _)) => State::Slim(swaps, mars.clone()),
+                (Some(State::Full(m, mut a)), State::Full(_, b)) => {
+                    a.add_other(b);
+                    State::Full(m, a)
+                }
+                (Some(State::Slim(a, m)), State::Slim(b, _)) => {
+                    State::Slim(join_slim_swaps([a, b]), m)
+                }
+                _ => bail!("Cannot merge full and slim results"),
+            });
+        }
+        let mut merged = merged.ok_or_else(|| anyhow!("no results to merge"))?;
+
+        if let State::Full(_, ref mut vin) = merged {
+            info!("Bake matrices");
+            for r in vin.reductions.values_mut() {
+                r.bake_all_matrices();
+            }
+        }
+
+        let header = StateHeader::new(merged.kind(), RunParams::default());
+        write_state_file(&self.output_path, &header, &merged)?;
+        info!("Wrote output to {}", self.output_path.display());
+        Ok(())
+    }
+}
+
+/// Parse three comma separated values, like `1,2,3`.
+fn parse_triple<T: std::str::FromStr>(s: &str) -> Result<[T; 3], String>
+where
+    T::Err: std::fmt::Display,
+{
+    let parts = s
+        .split(',')
+        .map(|p| p.trim().parse::<T>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    <[T; 3]>::try_from(parts).map_err(|v| format!("expected 3 values, got {}", v.len()))
+}
+
+#[derive(Debug, Args)]
+struct CheckArgs {
+    #[arg(
+        value_name = "complex.obj",
+        help = "Path to the input complex: an .obj, .ply, .stl or .off surface, or a .mesh or .vtk tetrahedral mesh."
+    )]
+    obj_path: PathBuf,
+
+    #[arg(
+        long,
+        help = "Merge input vertices that are closer than DIST, instead of rejecting the complex.",
+        value_name = "DIST"
+    )]
+    merge_vertices: Option<f64>,
+
+    #[arg(
+        short,
+        long,
+        help = "Path to write the repaired complex to, as an .obj file.",
+        value_name = "repaired.obj"
+    )]
+    output_path: Option<PathBuf>,
+}
+
+impl CheckArgs {
+    fn run(&self) -> Result<()> {
+        let complex = Complex::read_from_path_merging(&self.obj_path, self.merge_vertices)
+            .context("failed to read complex")?;
+        info!(
+            "Read complex: #v={} #e={} #t={}",
+            complex.num_simplices_of_dim(0),
+            complex.num_simplices_of_dim(1),
+            complex.num_simplices_of_dim(2)
+        );
+
+        let report = complex.validate();
+        print!("{}", report);
+
+        let Some(ref path) = self.output_path else {
+            if report.has_errors() {
+                bail!("the complex has simplices with missing faces or malformed simplices");
+            }
+            return Ok(());
+        };
+        let repaired = complex.repaired().context("failed to repair complex")?;
+        if 3 <= repaired.dim() && repaired.num_simplices_of_dim(3) != 0 {
+            warn!("The .obj file only has the triangles and edges of the tetrahedra");
+        }
+        let f = std::fs::File::create(path).context("create output file")?;
+        repaired
+            .write_as_obj(std::io::BufWriter::new(f))
+            .context("write obj")?;
+        info!(
+            "Wrote repaired complex to {}: #v={} #e={} #t={}",
+            path.display(),
+            repaired.num_simplices_of_dim(0),
+            repaired.num_simplices_of_dim(1),
+            repaired.num_simplices_of_dim(2)
+        );
+        Ok(())
+    }
+}
+
+#[derive(Debug, Args)]
+struct QueryArgs {
+    #[arg(
+        value_name = "state",
+        help = "Path to the output state file from `mars-cli run`."
+    )]
+    state: PathBuf,
+
+    #[arg(
+        long,
+        help = "The point to compute the barcodes at.",
+        value_name = "X,Y,Z",
+        value_parser = parse_triple::<f64>,
+        allow_negative_numbers = true
+    )]
+    point: [f64; 3],
+
+    #[arg(
+        long,
+        help = "Also print the Betti numbers of the sublevel set at this filtration value.",
+        allow_negative_numbers = true
+    )]
+    value: Option<f64>,
+
+    #[arg(
+        short,
+        long,
+        help = "Path to write the barcodes, Betti numbers and swaps to, as JSON.",
+        value_name = "probe.json"
+    )]
+    output_path: Option<PathBuf>,
+}
+
+impl QueryArgs {
+    fn run(&self) -> Result<()> {
+        let (mars, vineyards) = if is_chunked_file(&self.state)? {
+            info!("Read chunked state");
+            let f = std::fs::File::open(&self.state).context("open file")?;
+            let reader = ChunkedReader::open(BufReader::new(f))?;
+            (reader.header().mars.clone(), None)
+        } else {
+            info!("Read state");
+            match read_state_file(&self.state)?.1 {
+                State::Full(mars, vin) => (mars, Some(vin)),
+                State::Slim(_, mars) => (mars, None),
+            }
+        };
+        if vineyards.is_none() {
+            info!("No reductions in the state file; reducing from scratch");
+        }
+
+        let probe = mars.probe(vineyards.as_ref(), Pos(self.point))?;
+        if let Some((index, pos)) = probe.from {
+            info!("Started from grid point {:?} at {:?}", index.0, pos.0);
+        }
+
+        for (dim, barcode) in &probe.barcodes {
+            println!("dim {}: {} bars", dim, barcode.len());
+            for b in barcode {
+                let birth = b.birth.map_or("-inf".to_string(), |(t, _)| t.to_string());
+                let death = b.death.map_or("inf".to_string(), |(t, _)| t.to_string());
+                println!("  [{}, {})", birth, death);
+            }
+        }
+        println!("betti: {:?}", probe.betti);
+        if let Some(value) = self.value {
+            println!("betti at {}: {:?}", value, probe.betti_at(value));
+        }
+        println!("swaps: {}", probe.swaps.len());
+        for s in &probe.swaps {
+            println!("  dim {}: {} {}", s.dim, s.i, s.j);
+        }
+
+        if let Some(ref path) = self.output_path {
+            let f = std::fs::File::create(path).context("create output file")?;
+            serde_json::to_writer_pretty(BufWriter::new(f), &probe).context("write json")?;
+            info!("Wrote {}", path.display());
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Args)]
@@ -98,20 +699,54 @@ struct StatsArgs {
 }
 
 impl StatsArgs {
+    /// Print the record counts of a chunked file, without reading the swaps.
+    fn run_chunked(&self) -> Result<()> {
+        info!("Read chunked state");
+        let f = std::fs::File::open(&self.state).context("open file")?;
+        let reader = ChunkedReader::open(BufReader::new(f))?;
+
+        let mm: MarsMem = (&reader.header().mars).into();
+        info!("{:?}", mm);
+        info!(pruned = reader.header().pruning.is_some());
+
+        for dim in 0..reader.num_dims() {
+            let (records, swaps) = reader
+                .index()
+                .iter()
+                .filter(|e| e.dim == dim)
+                .fold((0, 0), |(r, s), e| (r + 1, s + e.num_swaps));
+            info!(dim = dim, records = records, swaps = swaps);
+        }
+
+        Ok(())
+    }
+
     fn run(&self) -> Result<()> {
+        if is_chunked_file(&self.state)? {
+            return self.run_chunked();
+        }
         info!("Read state");
-        let (mars, vin): (mars_core::Mars, mars_core::Vineyards) = {
-            let f = std::fs::File::open(&self.state).context("open file")?;
-            let mut reader = BufReader::new(f);
-            rmp_serde::from_read(&mut reader).context("rmp read")?
-        };
+        let (header, state) = read_state_file(&self.state)?;
+        info!(kind = ?header.kind, crate_version = ?header.crate_version);
+        info!(pruned = header.params.pruning.is_some());
 
-        let mm: MarsMem = (&mars).into();
+        let mm: MarsMem = state.mars().into();
         info!("{:?}", mm);
 
+        let vin = match state {
+            State::Full(_, vin) => vin,
+            State::Slim(swaps, _) => {
+                for (dim, dim_swaps) in swaps.iter().enumerate() {
+                    let num_swaps = dim_swaps.iter().map(|s| s.2.len()).sum::<usize>();
+                    info!(dim = dim, records = dim_swaps.len(), swaps = num_swaps);
+                }
+                return Ok(());
+            }
+        };
+
         for (_, r) in vin.reductions.iter().take(3) {
             let mem: ReductionMem = r.into();
-            for dim in 0..3 {
+            for dim in 0..r.stacks.len() {
                 let idim = dim as isize;
                 info!(dim);
 
@@ -171,7 +806,12 @@ struct ObjArgs {
     )]
     state: PathBuf,
 
-    #[arg(short, long, help = "Treat input file as a --slim output")]
+    #[arg(
+        short,
+        long,
+        hide = true,
+        help = "Ignored; the kind of state file is detected automatically."
+    )]
     slim: bool,
 
     #[arg(
@@ -197,136 +837,323 @@ struct ObjArgs {
         value_name = "ma.obj"
     )]
     medial_axes: Option<PathBuf>,
-}
 
-/// Lists of swaps for each dimension, per grid edge.
-/// `(Index, Index, Vec<...>)` is two grid indices and the list of swaps between them.
-/// `(Swap, f64, f64)` is the swap, plus the persistence lifetime of the swapped simplices
-/// (0.0 if ).
-type SlimFile = ([Vec<(Index, Index, Vec<(Swap, f64, f64)>)>; 3], Mars);
+    #[arg(
+        long,
+        help = "Output the medial axes as a .ply with the attributes of each face to this path.",
+        value_name = "ma.ply"
+    )]
+    ply: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Thin the medial axes to curves, and output them as polylines to this path.",
+        value_name = "skeleton.obj"
+    )]
+    skeleton: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Output the curves from --skeleton as a JSON graph to this path.",
+        value_name = "skeleton.json"
+    )]
+    skeleton_json: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value_t = ThinningParams::default().isolation,
+        help = "Number of thinning rounds after which curve ends are no longer shortened."
+    )]
+    skeleton_isolation: usize,
+
+    #[arg(
+        long,
+        default_value_t = ThinningParams::default().min_branch_length,
+        help = "Remove skeleton branches to a free end shorter than this many quad sides."
+    )]
+    skeleton_min_branch: f64,
+
+    #[arg(
+        long,
+        help = "Place the vertices of the medial axes where the swaps happen along the grid edges, instead of at the centers of the grid cells."
+    )]
+    sub_cell: bool,
+
+    #[arg(
+        long,
+        help = "Reconstruct the shape as the union of the medial balls of the axes, and output it as an .obj to this path.",
+        value_name = "shape.obj"
+    )]
+    reconstruct: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Sample spacing for --reconstruct.  Defaults to the average side of the medial axis quads.",
+        value_name = "SIZE"
+    )]
+    reconstruct_cell_size: Option<f64>,
+
+    #[arg(
+        long = "reconstruct-dim",
+        default_value = "0",
+        help = "Medial axis dimensions whose balls are used for --reconstruct.  Repeat to use several.  Only dimension 0 has the radius of the largest empty ball; higher dimensions give larger balls.",
+        value_name = "DIM"
+    )]
+    reconstruct_dims: Vec<usize>,
+
+    #[arg(
+        long,
+        help = "Output the complex with the distance from each vertex to the medial axes (the local feature size) and the thickness there to this path, as a .ply with vertex properties, or as an .obj with vertex colors.",
+        value_name = "lfs.ply"
+    )]
+    feature_size: Option<PathBuf>,
+
+    #[arg(
+        long = "feature-size-dim",
+        default_value = "0",
+        help = "Medial axis dimensions for --feature-size.  Repeat to use several; the colors are from the first.",
+        value_name = "DIM"
+    )]
+    feature_size_dims: Vec<usize>,
+
+    #[arg(
+        long,
+        help = "Output the complex and the medial axes of a planar run as an .svg to this path.",
+        value_name = "ma.svg"
+    )]
+    svg: Option<PathBuf>,
+}
 
 impl ObjArgs {
-    fn run_slim(&self) -> Result<()> {
-        let bytes = std::fs::read(&self.state).context("read state file")?;
-        let slim_file: SlimFile = rmp_serde::from_slice(&bytes).context("rmp read")?;
+    /// If the state is for a planar run, return the 2D grid.
+    fn planar_grid(mars: &Mars) -> Option<&VineyardsGrid> {
+        match mars.grid {
+            Some(Grid::Regular(ref grid)) if grid.is_planar() => Some(grid),
+            _ => None,
+        }
+    }
 
-        let swaps = slim_file.0;
-        let Some(Grid::Mesh(mut grid)) = slim_file.1.grid else {
-            unimplemented!();
-        };
+    /// Write the medial axes of a planar run as polylines, given the grid edges with swaps for
+    /// each dimension.
+    fn write_planar(&self, mars: &Mars, edges: Vec<Vec<(Index, Index)>>) -> Result<()> {
+        let grid = Self::planar_grid(mars).ok_or_else(|| anyhow!("the grid is not planar"))?;
+        let axes = edges
+            .into_iter()
+            .map(|e| Polylines::from_grid_edges(grid, e))
+            .collect::<Vec<_>>();
 
         if let Some(ref p) = self.medial_axes {
-            grid.recompute_dim_dist();
-            let mut f = std::fs::File::create(p).context("create passed file")?;
-            info!("Write medial axes to {}", p.display());
-
+            let mut f = BufWriter::new(std::fs::File::create(p).context("create passed file")?);
+            info!("Write medial axes as polylines to {}", p.display());
             let mut vi = 0;
-            for dim in 0..3 {
-                let swaps = &swaps[dim];
-
+            for (dim, axis) in axes.iter().enumerate() {
                 writeln!(&mut f, "o ma-dim-{}", dim)?;
-
-                for (gi, gj, swaps) in swaps {
-                    if 0 < swaps.len() {
-                        let pts = grid.dual_quad_points(*gi, *gj);
-                        for p in &pts {
-                            writeln!(&mut f, "v {} {} {}", p.x(), p.y(), p.z())?;
-                        }
-                        writeln!(&mut f, "f {} {} {} {}", vi + 1, vi + 2, vi + 3, vi + 4)?;
-                        vi += 4;
-                    }
-                }
+                axis.write_as_obj(&mut f, vi)?;
+                vi += axis.points.len();
             }
         }
 
-        // if let Some(ref p) = self.complex {
-        //     let c = mars
-        //         .complex
-        //         .as_ref()
-        //         .ok_or_else(|| anyhow!("missing complex in state"))?;
+        if let Some(ref p) = self.svg {
+            let complex = mars
+                .complex
+                .as_ref()
+                .ok_or_else(|| anyhow!("missing complex in state"))?;
+            let f = BufWriter::new(std::fs::File::create(p).context("create passed file")?);
+            info!("Write svg to {}", p.display());
+            mars_core::planar::write_svg(f, complex, &axes).context("write svg")?;
+        }
 
-        //     let mut f = std::fs::File::create(p).context("create passed file")?;
-        //     info!("Write complex to {}", p.display());
-        //     c.write_as_obj(&mut f).context("write obj")?;
-        // }
+        Ok(())
+    }
 
-        // if let Some(ref p) = self.grid {
-        //     let c = mars
-        //         .grid
-        //         .as_ref()
-        //         .ok_or_else(|| anyhow!("missing grid in state"))?;
-
-        //     let g = match c {
-        //         mars_core::Grid::Regular(_) => {
-        //             error!("Trying to output a grid, but it is a regular grid.");
-        //             bail!("Trying to output a grid, but it is a regular grid.");
-        //         }
-        //         mars_core::Grid::Mesh(mesh) => mesh,
-        //     };
-
-        //     let mut f = std::fs::File::create(p).context("create passed file")?;
-        //     info!("Write grid to {}", p.display());
-        //     g.write_as_obj(&mut f).context("write obj")?;
-        // }
+    /// Check that `--svg` is only used for planar runs, and `--ply` only for non-planar runs.
+    fn check_svg(&self, mars: &Mars) -> Result<()> {
+        let planar = Self::planar_grid(mars).is_some();
+        if self.svg.is_some() && !planar {
+            bail!("--svg is only supported for planar runs");
+        }
+        if self.ply.is_some() && planar {
+            bail!("--ply is not supported for planar runs");
+        }
+        if self.sub_cell && planar {
+            bail!("--sub-cell is not supported for planar runs");
+        }
+        if self.reconstruct.is_some() && planar {
+            bail!("--reconstruct is not supported for planar runs");
+        }
+        if self.feature_size.is_some() && planar {
+            bail!("--feature-size is not supported for planar runs");
+        }
+        if (self.skeleton.is_some() || self.skeleton_json.is_some()) && planar {
+            bail!(
+                "the medial axes of planar runs are already curves; use -a instead of --skeleton"
+            );
+        }
+        Ok(())
+    }
 
-        // if let Some(ref p) = self.medial_axes {
-        //     let mut f = std::fs::File::create(p).context("create passed file")?;
-
-        //     info!("Write medial axes to {}", p.display());
-        //     let mut vi = 0;
-        //     for dim in 0..3 {
-        //         let swaps = &vin.swaps[dim];
-
-        //         writeln!(&mut f, "o ma-dim-{}", dim)?;
-
-        //         match mars
-        //             .grid
-        //             .as_mut()
-        //             .ok_or_else(|| anyhow!("missing grid in state"))?
-        //         {
-        //             Grid::Regular(grid) => {
-        //                 for s in swaps {
-        //                     if 0 < s.2.v.len() {
-        //                         let pts = grid.dual_quad_points(s.0, s.1);
-        //                         for p in &pts {
-        //                             writeln!(&mut f, "v {} {} {}", p.x(), p.y(), p.z())?;
-        //                         }
-        //                         writeln!(&mut f, "f {} {} {} {}", vi + 1, vi + 2, vi + 3, vi + 4)?;
-        //                         vi += 4;
-        //                     }
-        //                 }
-        //             }
-        //             Grid::Mesh(grid) => {
-        //                 grid.recompute_dim_dist();
-        //                 for s in swaps {
-        //                     if 0 < s.2.v.len() {
-        //                         let pts = grid.dual_quad_points(s.0, s.1);
-        //                         for p in &pts {
-        //                             writeln!(&mut f, "v {} {} {}", p.x(), p.y(), p.z())?;
-        //                         }
-        //                         writeln!(&mut f, "f {} {} {} {}", vi + 1, vi + 2, vi + 3, vi + 4)?;
-        //                         vi += 4;
-        //                     }
-        //                 }
-        //             }
-        //         }
-        //     }
-        // }
+    /// Write the welded medial axes to the requested outputs.
+    fn write_axes(&self, mars: &Mars, mut axes: Vec<AxisMesh>) -> Result<()> {
+        let complex = mars
+            .complex
+            .as_ref()
+            .ok_or_else(|| anyhow!("missing complex in state"))?;
+        let grid = mars
+            .grid
+            .as_ref()
+            .ok_or_else(|| anyhow!("missing grid in state"))?;
+        for axis in &mut axes {
+            if self.sub_cell {
+                axis.place_at_crossings(grid);
+            }
+            axis.compute_radii(complex, &mars.filtration);
+        }
+        for axis in &axes {
+            info!(
+                dim = axis.dim,
+                "medial axis has {} vertices and {} faces",
+                axis.points.len(),
+                axis.faces.len()
+            );
+        }
+        if let Some(ref p) = self.medial_axes {
+            let f = BufWriter::new(std::fs::File::create(p).context("create passed file")?);
+            info!("Write medial axes to {}", p.display());
+            write_axes_as_obj(f, &axes).context("write obj")?;
+        }
+        if let Some(ref p) = self.ply {
+            let f = BufWriter::new(std::fs::File::create(p).context("create passed file")?);
+            info!("Write medial axes to {}", p.display());
+            write_axes_as_ply(f, &axes).context("write ply")?;
+        }
+        if let Some(ref p) = self.reconstruct {
+            self.write_reconstruction(p, complex, &axes)?;
+        }
+        if let Some(ref p) = self.feature_size {
+            self.write_feature_size(p, complex, &axes)?;
+        }
 
+        if self.skeleton.is_none() && self.skeleton_json.is_none() {
+            return Ok(());
+        }
+        let params = ThinningParams {
+            isolation: self.skeleton_isolation,
+            min_branch_length: self.skeleton_min_branch,
+        };
+        let skeletons = axes
+            .iter()
+            .map(|axis| Skeleton::from_axis(axis, &params))
+            .collect::<Vec<_>>();
+        for s in &skeletons {
+            info!(
+                dim = s.dim,
+                "skeleton has {} curves and {} points",
+                s.curves.lines.len(),
+                s.curves.points.len()
+            );
+        }
+        if let Some(ref p) = self.skeleton {
+            let f = BufWriter::new(std::fs::File::create(p).context("create passed file")?);
+            info!("Write skeleton to {}", p.display());
+            write_skeletons_as_obj(f, &skeletons).context("write obj")?;
+        }
+        if let Some(ref p) = self.skeleton_json {
+            let f = BufWriter::new(std::fs::File::create(p).context("create passed file")?);
+            info!("Write skeleton graph to {}", p.display());
+            write_skeletons_as_json(f, &skeletons).context("write json")?;
+        }
         Ok(())
     }
 
-    fn run(&self) -> Result<()> {
-        info!("Reading input file {}", self.state.display());
+    /// Mesh the union of the medial balls of the axes in `--reconstruct-dim`, and log how far the complex is from it.
+    fn write_reconstruction(
+        &self,
+        path: &Path,
+        complex: &Complex,
+        axes: &[AxisMesh],
+    ) -> Result<()> {
+        let axes = axes
+            .iter()
+            .filter(|a| self.reconstruct_dims.contains(&a.dim))
+            .collect::<Vec<_>>();
+        let balls = UnionOfBalls::from_axes(axes.iter().copied());
+        if balls.balls.is_empty() {
+            bail!(
+                "no medial balls in dimensions {:?}; the axes may be empty, or the filtration is not a distance",
+                self.reconstruct_dims
+            );
+        }
+        let cell_size = match self.reconstruct_cell_size {
+            Some(size) => size,
+            None => {
+                let sides = axes
+                    .iter()
+                    .map(|a| a.average_side())
+                    .filter(|&s| 0.0 < s)
+                    .collect::<Vec<_>>();
+                sides.iter().sum::<f64>() / sides.len() as f64
+            }
+        };
+        info!(
+            balls = balls.balls.len(),
+            cell_size, "Reconstruct the shape"
+        );
+        let mesh = balls.mesh(cell_size)?;
+        let error = balls.surface_error(complex);
+        info!(
+            mean = error.mean,
+            max = error.max,
+            inside = error.inside,
+            "Distance from the vertices of the complex to the union of balls"
+        );
+        let f = BufWriter::new(std::fs::File::create(path).context("create passed file")?);
+        info!("Write reconstruction to {}", path.display());
+        mesh.write_as_obj(f).context("write obj")
+    }
 
-        if self.slim {
-            return self.run_slim();
+    /// Write the local feature size of every vertex of the complex for the axes in
+    /// `--feature-size-dim`, as a .ply or a colored .obj depending on the extension of `path`.
+    fn write_feature_size(&self, path: &Path, complex: &Complex, axes: &[AxisMesh]) -> Result<()> {
+        let sizes = self
+            .feature_size_dims
+            .iter()
+            .map(|&dim| {
+                let axis = axes
+                    .iter()
+                    .find(|a| a.dim == dim)
+                    .ok_or_else(|| anyhow!("there is no medial axis of dimension {}", dim))?;
+                Ok(FeatureSize::new(complex, axis))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for s in &sizes {
+            if let Some((lo, hi)) = s.range() {
+                info!(dim = s.dim, min = lo, max = hi, "local feature size");
+            }
+        }
+        let f = BufWriter::new(std::fs::File::create(path).context("create passed file")?);
+        info!("Write local feature size to {}", path.display());
+        if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("obj"))
+        {
+            write_feature_size_as_obj(f, complex, &sizes[0]).context("write obj")
+        } else {
+            write_feature_size_as_ply(f, complex, &sizes).context("write ply")
         }
+    }
 
-        let bytes = std::fs::read(&self.state).context("read state file")?;
-        let (mut mars, vin): (mars_core::Mars, mars_core::Vineyards) =
-            rmp_serde::from_slice(&bytes).context("rmp read")?;
+    /// True if any of the medial axes outputs are requested.
+    fn wants_axes(&self) -> bool {
+        self.medial_axes.is_some()
+            || self.ply.is_some()
+            || self.reconstruct.is_some()
+            || self.feature_size.is_some()
+            || self.skeleton.is_some()
+            || self.skeleton_json.is_some()
+    }
 
+    /// Write out the complex and the grid, if requested.
+    fn write_complex_and_grid(&self, mars: &Mars) -> Result<()> {
         if let Some(ref p) = self.complex {
             let c = mars
                 .complex
@@ -339,73 +1166,170 @@ impl ObjArgs {
         }
 
         if let Some(ref p) = self.grid {
-            let c = mars
+            let g = mars
                 .grid
                 .as_ref()
                 .ok_or_else(|| anyhow!("missing grid in state"))?;
 
-            let g = match c {
-                mars_core::Grid::Regular(_) => {
-                    error!("Trying to output a grid, but it is a regular grid.");
-                    bail!("Trying to output a grid, but it is a regular grid.");
-                }
-                mars_core::Grid::Mesh(mesh) => mesh,
-            };
-
             let mut f = std::fs::File::create(p).context("create passed file")?;
             info!("Write grid to {}", p.display());
-            g.write_as_obj(&mut f).context("write obj")?;
+            match g {
+                Grid::Regular(grid) => grid.write_as_obj(&mut f),
+                Grid::Mesh(mesh) => mesh.write_as_obj(&mut f),
+            }
+            .context("write obj")?;
         }
 
-        if let Some(ref p) = self.medial_axes {
-            let mut f = std::fs::File::create(p).context("create passed file")?;
+        Ok(())
+    }
 
-            info!("Write medial axes to {}", p.display());
-            let mut vi = 0;
-            for dim in 0..3 {
-                let swaps = &vin.swaps[dim];
+    fn run_slim(&self, swaps: Vec<SlimSwapList>, mut mars: Mars) -> Result<()> {
+        self.check_svg(&mars)?;
+        if Self::planar_grid(&mars).is_some() {
+            let edges = swaps
+                .iter()
+                .map(|dim_swaps| {
+                    dim_swaps
+                        .iter()
+                        .filter(|(_, _, s)| !s.is_empty())
+                        .map(|(a, b, _)| (*a, *b))
+                        .collect()
+                })
+                .collect();
+            self.write_planar(&mars, edges)?;
+            return self.write_complex_and_grid(&mars);
+        }
 
-                writeln!(&mut f, "o ma-dim-{}", dim)?;
+        if self.wants_axes() {
+            let grid = mars
+                .grid
+                .as_mut()
+                .ok_or_else(|| anyhow!("missing grid in state"))?;
+            if let Grid::Mesh(grid) = grid {
+                grid.recompute_dim_dist();
+            }
+            let axes = swaps
+                .iter()
+                .enumerate()
+                .map(|(dim, swaps)| AxisMesh::from_slim_swaps(grid, dim, swaps))
+                .collect::<Vec<_>>();
+            self.write_axes(&mars, axes)?;
+        }
 
-                match mars
-                    .grid
-                    .as_mut()
-                    .ok_or_else(|| anyhow!("missing grid in state"))?
-                {
-                    Grid::Regular(grid) => {
-                        for s in swaps {
-                            if 0 < s.2.v.len() {
-                                let pts = grid.dual_quad_points(s.0, s.1);
-                                for p in &pts {
-                                    writeln!(&mut f, "v {} {} {}", p.x(), p.y(), p.z())?;
-                                }
-                                writeln!(&mut f, "f {} {} {} {}", vi + 1, vi + 2, vi + 3, vi + 4)?;
-                                vi += 4;
-                            }
-                        }
-                    }
-                    Grid::Mesh(grid) => {
-                        grid.recompute_dim_dist();
-                        for s in swaps {
-                            if 0 < s.2.v.len() {
-                                let pts = grid.dual_quad_points(s.0, s.1);
-                                for p in &pts {
-                                    writeln!(&mut f, "v {} {} {}", p.x(), p.y(), p.z())?;
-                                }
-                                writeln!(&mut f, "f {} {} {} {}", vi + 1, vi + 2, vi + 3, vi + 4)?;
-                                vi += 4;
-                            }
-                        }
-                    }
-                }
+        self.write_complex_and_grid(&mars)?;
+
+        Ok(())
+    }
+
+    /// Like [ObjArgs::run_slim], but only one record is in memory at a time.
+    fn run_chunked(&self) -> Result<()> {
+        let f = std::fs::File::open(&self.state).context("open file")?;
+        let mut reader = ChunkedReader::open(BufReader::new(f))?;
+
+        self.check_svg(&reader.header().mars)?;
+        if Self::planar_grid(&reader.header().mars).is_some() {
+            let mut edges = Vec::new();
+            for dim in 0..reader.num_dims() {
+                let mut dim_edges = Vec::new();
+                reader.for_each_record(dim, |r| {
+                    dim_edges.push((r.a, r.b));
+                    Ok(())
+                })?;
+                edges.push(dim_edges);
+            }
+            let mars = reader.into_header().mars;
+            self.write_planar(&mars, edges)?;
+            return self.write_complex_and_grid(&mars);
+        }
+
+        if self.wants_axes() {
+            let mut grid = reader
+                .header()
+                .mars
+                .grid
+                .clone()
+                .ok_or_else(|| anyhow!("missing grid in state"))?;
+            if let Grid::Mesh(ref mut grid) = grid {
+                grid.recompute_dim_dist();
+            }
+
+            let mut axes = Vec::new();
+            for dim in 0..reader.num_dims() {
+                let mut builder = AxisMeshBuilder::new(dim);
+                reader.for_each_record(dim, |r| {
+                    let swaps = r.swaps.iter().map(|(s, li, lj)| (s, Some(li.max(*lj))));
+                    builder.push(&grid, r.a, r.b, swaps);
+                    Ok(())
+                })?;
+                axes.push(builder.finish());
+            }
+            self.write_axes(&reader.header().mars, axes)?;
+        }
+
+        self.write_complex_and_grid(&reader.into_header().mars)?;
+
+        Ok(())
+    }
+
+    fn run(&self) -> Result<()> {
+        info!("Reading input file {}", self.state.display());
+
+        if is_chunked_file(&self.state)? {
+            return self.run_chunked();
+        }
+        let (mut mars, vin) = match read_state_file(&self.state)?.1 {
+            State::Full(mars, vin) => (mars, vin),
+            State::Slim(swaps, mars) => return self.run_slim(swaps, mars),
+        };
+
+        self.write_complex_and_grid(&mars)?;
+
+        self.check_svg(&mars)?;
+        if Self::planar_grid(&mars).is_some() {
+            let edges = vin
+                .swaps
+                .iter()
+                .map(|dim_swaps| {
+                    dim_swaps
+                        .iter()
+                        .filter(|s| !s.2.v.is_empty())
+                        .map(|s| (s.0, s.1))
+                        .collect()
+                })
+                .collect();
+            return self.write_planar(&mars, edges);
+        }
+
+        if self.wants_axes() {
+            let grid = mars
+                .grid
+                .as_mut()
+                .ok_or_else(|| anyhow!("missing grid in state"))?;
+            if let Grid::Mesh(grid) = grid {
+                grid.recompute_dim_dist();
             }
+            let complex = mars
+                .complex
+                .as_ref()
+                .ok_or_else(|| anyhow!("missing complex in state"))?;
+            let axes = vin
+                .swaps
+                .iter()
+                .enumerate()
+                .map(|(dim, swaps)| {
+                    AxisMesh::from_swaps(grid, dim, swaps, Some((complex, &mars.filtration, &vin)))
+                })
+                .collect::<Vec<_>>();
+            self.write_axes(&mars, axes)?;
         }
 
         Ok(())
     }
 }
 
-fn default_pruning_params() -> [PruningParam; 3] {
+/// The default pruning parameters for each of the `num_dims` dimensions of simplices.  Dimensions
+/// above 2, like the tetrahedra of volume meshes, are pruned like dimension 2.
+fn default_pruning_params(num_dims: usize) -> Vec<PruningParam> {
     let dim0 = PruningParam {
         euclidean: true,
         euclidean_distance: Some(0.01),
@@ -431,7 +1355,35 @@ fn default_pruning_params() -> [PruningParam; 3] {
         persistence_threshold: None,
     };
 
-    [dim0, dim1, dim2]
+    let mut params = vec![dim0, dim1];
+    params.resize(num_dims.max(params.len() + 1), dim2);
+    params.truncate(num_dims);
+    params
+}
+
+/// Read the pruning parameters from `path`, or use the defaults, with one entry for each of the
+/// `num_dims` dimensions.  Dimensions missing from the file get the default parameters.
+fn read_pruning_params(path: Option<&Path>, num_dims: usize) -> Result<Vec<PruningParam>> {
+    let defaults = default_pruning_params(num_dims);
+    let Some(path) = path else {
+        info!("Use default pruning parameters");
+        return Ok(defaults);
+    };
+    info!("Use pruning parameters from {}", path.display());
+    let file_contents = std::fs::read_to_string(path).context("read prune file")?;
+    let mut params: Vec<PruningParam> =
+        serde_json::from_str(&file_contents).context("read json")?;
+    if params.len() < num_dims {
+        info!(
+            "{} has no parameters for dimensions {}..{}; use the defaults",
+            path.display(),
+            params.len(),
+            num_dims
+        );
+        params.extend_from_slice(&defaults[params.len()..]);
+    }
+    params.truncate(num_dims);
+    Ok(params)
 }
 
 #[derive(Debug, Args)]
@@ -455,34 +1407,94 @@ struct PruneArgs {
     )]
     params: Option<PathBuf>,
 
-    #[arg(short, long, help = "Treat input file as a --slim output")]
+    #[arg(
+        short,
+        long,
+        hide = true,
+        help = "Ignored; the kind of state file is detected automatically."
+    )]
     slim: bool,
 }
 
 impl PruneArgs {
-    fn run_slim(&self) -> Result<()> {
-        info!("Read parameters");
-        let params = if let Some(ref p) = self.params {
-            let f = std::fs::File::open(p).context("open file")?;
-            let mut reader = BufReader::new(f);
-            serde_json::from_reader(&mut reader).context("rmp read")?
-        } else {
-            default_pruning_params()
+    fn read_params(&self, complex: &Complex) -> Result<Vec<PruningParam>> {
+        read_pruning_params(self.params.as_deref(), complex.simplices_per_dim.len())
+    }
+
+    /// Prune a chunked file one record at a time, and write the result as a chunked file.
+    fn run_chunked(&self) -> Result<()> {
+        info!("Read chunked state");
+        let f = std::fs::File::open(&self.state_path).context("open file")?;
+        let mut reader = ChunkedReader::open(BufReader::new(f))?;
+        let mars = reader.header().mars.clone();
+        let complex = mars
+            .complex
+            .as_ref()
+            .ok_or_else(|| anyhow!("Missing complex in state"))?;
+        let params = self.read_params(complex)?;
+
+        let out = std::fs::File::create(&self.output).context("create output file")?;
+        let header = ChunkedHeader {
+            mars: mars.clone(),
+            pruning: Some(params.clone()),
         };
+        let mut writer = ChunkedWriter::new(BufWriter::new(out), &header)?;
 
-        info!("Read state");
-        let bytes = std::fs::read(&self.state_path).context("read state file")?;
-        let (all_swaps, mars): SlimFile = rmp_serde::from_slice(&bytes).context("rmp read")?;
+        info!("Prune");
+        for (dim, dim_params) in params.iter().enumerate() {
+            let (mut num_swaps, mut num_left) = (0, 0);
+            reader.for_each_record(dim, |r| {
+                num_swaps += r.swaps.len();
+                let pruned = mars_core::prune::prune_dim(
+                    &vec![(r.a, r.b, r.swaps)],
+                    dim,
+                    dim_params,
+                    complex,
+                    |_, _| {},
+                );
+                for (a, b, swaps) in pruned {
+                    num_left += swaps.len();
+                    writer.write_record(dim, a, b, swaps)?;
+                }
+                Ok(())
+            })?;
+
+            let num_pruned = num_swaps - num_left;
+            info!(
+                dim = dim,
+                "pruned {} swaps ({}%). New count is {}",
+                num_pruned,
+                ((num_pruned as f64 / num_swaps as f64) * 100.0).floor(),
+                num_left
+            );
+        }
+
+        writer.finish()?;
+        Ok(())
+    }
 
+    fn run_slim(
+        &self,
+        params: Vec<PruningParam>,
+        all_swaps: Vec<SlimSwapList>,
+        mars: Mars,
+        run_params: RunParams,
+    ) -> Result<()> {
         info!("Prune");
-        let mut all_pruned = [Vec::new(), Vec::new(), Vec::new()];
-        for dim in 0..3 {
-            let num_swaps = all_swaps[dim].iter().map(|s| s.2.len()).sum::<usize>();
+        let mut all_pruned = Vec::new();
+        for (dim, dim_swaps) in all_swaps.into_iter().enumerate() {
+            let Some(dim_params) = params.get(dim) else {
+                bail!(
+                    "the state has swaps of dimension {}, which the complex doesn't have",
+                    dim
+                );
+            };
+            let num_swaps = dim_swaps.iter().map(|s| s.2.len()).sum::<usize>();
             info!(dim = dim, "read {} swaps", num_swaps);
             let pruned = mars_core::prune::prune_dim(
-                &all_swaps[dim],
+                &dim_swaps,
                 dim,
-                &params[dim],
+                dim_params,
                 mars.complex.as_ref().expect("Missing complex"),
                 |i, n| {
                     if i % 127 == 0 {
@@ -504,48 +1516,56 @@ impl PruneArgs {
                 ((num_pruned as f64 / num_swaps as f64) * 100.0).floor(),
                 num_left
             );
-            all_pruned[dim] = pruned;
+            all_pruned.push(pruned);
         }
 
         info!("Write output");
-        let output = (all_pruned, mars);
-        let output_bytes = rmp_serde::to_vec(&output)?;
-        let mut f = std::fs::File::create(&self.output).context("create output file")?;
-        f.write_all(&output_bytes).context("write to output file")?;
-
-        Ok(())
+        let header = StateHeader::new(
+            StateKind::Slim,
+            RunParams {
+                pruning: Some(params),
+                ..run_params
+            },
+        );
+        write_state_file(&self.output, &header, &State::Slim(all_pruned, mars))
     }
 
     fn run(&self) -> Result<()> {
-        if self.slim {
-            return self.run_slim();
-        }
-        info!("Read parameters");
-        let params = if let Some(ref p) = self.params {
-            let f = std::fs::File::open(p).context("open file")?;
-            let mut reader = BufReader::new(f);
-            serde_json::from_reader(&mut reader).context("rmp read")?
-        } else {
-            default_pruning_params()
-        };
+        if is_chunked_file(&self.state_path)? {
+            return self.run_chunked();
+        }
 
         info!("Read state");
-        let (mars, mut vin): (mars_core::Mars, mars_core::Vineyards) = {
-            let f = std::fs::File::open(&self.state_path).context("open file")?;
-            let mut reader = BufReader::new(f);
-            rmp_serde::from_read(&mut reader).context("rmp read")?
+        let (header, state) = read_state_file(&self.state_path)?;
+        let (mars, mut vin) = match state {
+            State::Full(mars, vin) => (mars, vin),
+            State::Slim(swaps, mars) => {
+                let complex = mars
+                    .complex
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Missing complex in state"))?;
+                let params = self.read_params(complex)?;
+                return self.run_slim(params, swaps, mars, header.params);
+            }
         };
 
         let complex = mars
             .complex
             .as_ref()
             .ok_or_else(|| anyhow!("Missing complex in state"))?;
+        let params = self.read_params(complex)?;
 
         info!("Prune");
-        for dim in 0..3 {
+        for dim in 0..vin.swaps.len() {
+            let Some(dim_params) = params.get(dim) else {
+                bail!(
+                    "the state has swaps of dimension {}, which the complex doesn't have",
+                    dim
+                );
+            };
             let num_swaps = vin.swaps[dim].iter().map(|s| s.2.v.len()).sum::<usize>();
             info!(dim = dim, "read {} swaps", num_swaps);
-            let pruned = vin.prune_dim(dim, &params[dim], complex, |i, n| {
+            let pruned = vin.prune_dim(dim, dim_params, complex, &mars.filtration, |i, n| {
                 if i % 127 == 0 {
                     info!(
                         dim = dim,
@@ -553,7 +1573,7 @@ impl PruneArgs {
                         ((i as f64 / n as f64) * 100.0).round()
                     );
                 }
-            });
+            })?;
 
             let num_left = pruned.iter().map(|s| s.2.v.len()).sum::<usize>();
             let num_pruned = num_swaps - num_left;
@@ -568,143 +1588,509 @@ impl PruneArgs {
         }
 
         info!("Write output");
-        let output = (mars, vin);
-        let output_bytes = rmp_serde::to_vec(&output)?;
-        let mut f = std::fs::File::create(&self.output).context("create output file")?;
-        f.write_all(&output_bytes).context("write to output file")?;
+        let header = StateHeader::new(
+            StateKind::Full,
+            RunParams {
+                pruning: Some(params),
+                ..header.params
+            },
+        );
+        write_state_file(&self.output, &header, &State::Full(mars, vin))
+    }
+}
 
-        Ok(())
+/// Concatenate the slim swaps of several parts, dimension by dimension.
+fn join_slim_swaps<I: IntoIterator<Item = Vec<SlimSwapList>>>(parts: I) -> Vec<SlimSwapList> {
+    let mut joined: Vec<SlimSwapList> = Vec::new();
+    for part in parts {
+        if joined.len() < part.len() {
+            joined.resize(part.len(), Vec::new());
+        }
+        for (j, p) in joined.iter_mut().zip(part) {
+            j.extend(p);
+        }
     }
+    joined
+}
+
+/// Read a non-chunked state file of any version.
+fn read_state_file(path: &Path) -> Result<(StateHeader, State)> {
+    let bytes = std::fs::read(path).context("read state file")?;
+    mars_core::state::read_state(&bytes).context("read state")
+}
+
+/// Write a state file to `path`.
+fn write_state_file(path: &Path, header: &StateHeader, state: &State) -> Result<()> {
+    let f = std::fs::File::create(path).context("create output file")?;
+    mars_core::state::write_state(BufWriter::new(f), header, state).context("write output file")
+}
+
+/// True if the file at `path` is a chunked state file.
+fn is_chunked_file(path: &Path) -> Result<bool> {
+    let mut f = std::fs::File::open(path).context("open file")?;
+    let mut magic = Vec::with_capacity(mars_core::chunked::MAGIC.len());
+    (&mut f)
+        .take(mars_core::chunked::MAGIC.len() as u64)
+        .read_to_end(&mut magic)
+        .context("read file")?;
+    Ok(mars_core::chunked::is_chunked(&magic))
 }
 
 fn print_prune_config() -> Result<()> {
-    let cfgs = default_pruning_params();
+    // One entry per dimension, up to the tetrahedra of volume meshes.
+    let cfgs = default_pruning_params(4);
     let string = serde_json::to_string_pretty(&cfgs)?;
     println!("{}", string);
     Ok(())
 }
 
+impl ProblemArgs {
+    /// Read the complex and set up the grid, either from the mesh file or as a regular grid.
+    fn read_mars(&self) -> Result<Mars> {
+        let obj_path = self
+            .obj_path
+            .as_ref()
+            .ok_or_else(|| anyhow!("need the path to the complex"))?;
+        let complex = match (self.from_points, self.radius) {
+            (Some(method), Some(radius)) => {
+                let s = std::fs::read_to_string(obj_path).context("read point cloud")?;
+                let points = read_points_from_str(&s).context("failed to read point cloud")?;
+                let method = match method {
+                    PointsArg::Rips => PointComplex::Rips { radius },
+                    PointsArg::Alpha => PointComplex::Alpha { radius },
+                };
+                let complex = method
+                    .build(points)
+                    .context("failed to build complex from points")?;
+                info!(
+                    "Built complex from points: #v={} #e={} #t={}",
+                    complex.num_simplices_of_dim(0),
+                    complex.num_simplices_of_dim(1),
+                    complex.num_simplices_of_dim(2)
+                );
+                complex
+            }
+            _ => Complex::read_from_path_merging(obj_path, self.merge_vertices)
+                .context("failed to read complex")?,
+        };
+
+        let report = complex.validate();
+        if report.has_errors() {
+            bail!(
+                "the complex has problems, see `mars-cli check`:\n{}",
+                report
+            );
+        }
+        if !report.is_clean() {
+            warn!("The complex may be broken, see `mars-cli check`");
+        }
+
+        let grid = if let Some(ref mesh_path) = self.mesh_path {
+            let obj_string = std::fs::read_to_string(mesh_path)
+                .with_context(|| format!("failed to read mesh path: {:?}", mesh_path))?;
+            let mesh_grid = VineyardsGridMesh::read_from_obj_string(&obj_string)
+                .context("failed to read grid mesh")?;
+            Grid::Mesh(mesh_grid)
+        } else {
+            let size = self
+                .cell_size
+                .ok_or_else(|| anyhow!("need either --mesh-path or --cell-size"))?;
+            if !(size.is_finite() && 0.0 < size) {
+                bail!("--cell-size must be finite and positive, got {}", size);
+            }
+            if !(self.buffer.is_finite() && 0.0 <= self.buffer) {
+                bail!(
+                    "--buffer must be finite and not negative, got {}",
+                    self.buffer
+                );
+            }
+            let grid = match (&self.corner, &self.shape) {
+                (Some(corner), Some(shape)) => {
+                    if self.planar && shape[2] != 1 {
+                        bail!("A planar grid must have NZ = 1 in --shape");
+                    }
+                    VineyardsGrid::new(Pos(*corner), size, *shape)
+                }
+                _ if self.planar => {
+                    VineyardsGrid::around_planar_complex(&complex, size, self.buffer)?
+                }
+                _ => VineyardsGrid::around_complex(&complex, size, self.buffer),
+            };
+            let axes = if self.planar { 2 } else { 3 };
+            if let Some(j) = (0..axes).find(|&j| grid.shape.0[j] < 2) {
+                bail!(
+                    "The regular grid has shape {:?}, with fewer than 2 points along axis {}, so it has no edges there.{}",
+                    grid.shape.0,
+                    ["x", "y", "z"][j],
+                    if j == 2 {
+                        "  Use --planar for a planar complex, or --buffer to grow the grid."
+                    } else {
+                        "  Use a smaller --cell-size or a larger --buffer."
+                    }
+                );
+            }
+            info!(
+                "Use regular grid with corner {:?}, size {}, shape {:?}",
+                grid.corner, grid.size, grid.shape
+            );
+            Grid::Regular(grid)
+        };
+
+        let filtration = self.filtration(&complex)?;
+
+        Ok(Mars {
+            complex: Some(complex),
+            grid: Some(grid),
+            filtration,
+        })
+    }
+
+    fn filtration(&self, complex: &Complex) -> Result<FiltrationKind> {
+        if self.weights.is_some() && !matches!(self.filtration, FiltrationArg::Anisotropic) {
+            bail!("--weights is only used with --filtration anisotropic");
+        }
+        if self.scalar_field.is_some() && !matches!(self.filtration, FiltrationArg::ScalarField) {
+            bail!("--scalar-field is only used with --filtration scalar-field");
+        }
+        Ok(match self.filtration {
+            FiltrationArg::SquaredEuclidean => FiltrationKind::SquaredEuclidean,
+            FiltrationArg::Euclidean => FiltrationKind::Euclidean,
+            FiltrationArg::Anisotropic => {
+                let weights = self
+                    .weights
+                    .ok_or_else(|| anyhow!("--filtration anisotropic needs --weights"))?;
+                if weights.iter().any(|w| w.is_nan() || *w <= 0.0) {
+                    bail!("--weights must all be positive");
+                }
+                FiltrationKind::Anisotropic(Anisotropic { weights })
+            }
+            FiltrationArg::ScalarField => {
+                let path = self
+                    .scalar_field
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("--filtration scalar-field needs --scalar-field"))?;
+                let file_contents =
+                    std::fs::read_to_string(path).context("read scalar field file")?;
+                let field: ScalarField =
+                    serde_json::from_str(&file_contents).context("read json")?;
+                field.validate(complex)?;
+                FiltrationKind::ScalarField(field)
+            }
+        })
+    }
+}
+
 impl RunArgs {
+    fn read_mars(&self) -> Result<Mars> {
+        self.problem.read_mars()
+    }
+
+    /// Run on a regular grid, and refine the grid around the medial axes.  The output is a slim
+    /// file with the swaps of all levels, see [mars_core::adaptive::AdaptiveVineyards::into_multilevel].
+    ///
+    /// If pruning is enabled, the pruning parameters are only used to decide which cells to
+    /// refine; the output is not pruned.
+    fn run_adaptive(&self) -> Result<()> {
+        let depth = self.refine.unwrap_or(0);
+        let mars = self.read_mars()?;
+        let (Some(complex), Some(Grid::Regular(coarse))) = (&mars.complex, &mars.grid) else {
+            bail!("Refinement requires a regular grid");
+        };
+        if coarse.is_planar() {
+            bail!("Refinement is not supported for planar grids");
+        }
+
+        let prune_params = match self.prune {
+            Some(ref path) => Some(read_pruning_params(
+                path.as_deref(),
+                complex.simplices_per_dim.len(),
+            )?),
+            None => None,
+        };
+
+        let adaptive = mars_core::adaptive::run_adaptive(
+            complex,
+            &mars.filtration,
+            coarse,
+            depth,
+            prune_params.as_deref(),
+            |i, n| {
+                if i % 511 == 0 {
+                    let percent = (i as f64 / n as f64) * 100.0;
+                    info!("{percent:3.0}%");
+                }
+            },
+        )?;
+
+        // All levels go into one grid, so that the parts of the axis that were not refined are
+        // kept at their coarser size.
+        let (grid, swaps) = adaptive.into_multilevel();
+
+        self.write_slim_output(
+            swaps,
+            Mars {
+                complex: mars.complex,
+                grid: Some(grid),
+                filtration: mars.filtration,
+            },
+        )
+    }
+
     fn run_slim(&self) -> Result<()> {
         if self.prune.is_some() {
             bail!("Cannot prune and --slim at the same time");
         }
-        let complex = Complex::read_from_obj_path(&self.obj_path)
-            .map_err(|e| anyhow!(e))
-            .context("failed to read complex")
-            .unwrap();
-
-        let mesh_grid = {
-            let obj_string = std::fs::read_to_string(&self.mesh_path)
-                .with_context(|| format!("failed to read mesh path: {:?}", self.mesh_path))
-                .unwrap();
-            VineyardsGridMesh::read_from_obj_string(&obj_string)
+        let mars = self.read_mars()?;
+        if self.chunked {
+            return self.run_chunked(mars);
         }
-        .map_err(|e| anyhow!(e))
-        .context("failed to read complex")
-        .unwrap();
-
-        let mars = mars_core::Mars {
-            complex: Some(complex),
-            grid: Some(mars_core::Grid::Mesh(mesh_grid)),
-        };
 
         use rayon::prelude::*;
-        let mut parts: Vec<_> = {
-            let parts = mars.split_into_4().map_err(|e| anyhow!(e))?;
+        let parts: Vec<_> = {
+            let parts = self.split(&mars)?;
             parts
                 .par_iter()
                 .enumerate()
-                .map(|(k, sub)| {
-                    sub.run_slim(|i, n| {
-                        if i == 0 {
-                            return;
-                        }
-                        let p = (i as f64 / n as f64 * 100.0).round();
-                        let pprev = ((1.0 + i as f64) / n as f64 * 100.0).round();
-                        let on_step = p != pprev;
-                        if on_step || i == n {
-                            info!(?k, "{:3}%", p);
-                        }
-                    })
-                })
+                .map(|(k, sub)| sub.run_slim(|i, n| log_part_progress(k, i, n)))
                 .collect()
         };
 
-        let mut joined = [Vec::new(), Vec::new(), Vec::new()];
-        for part in &mut parts {
-            let Ok(part) = part else {
-                error!("Part failed: {:?}", part);
-                continue;
-            };
-            for dim in 0..3 {
-                joined[dim].extend(std::mem::take(&mut part[dim]));
+        let parts = parts.into_iter().filter_map(|part| match part {
+            Ok(part) => Some(part),
+            Err(e) => {
+                error!("Part failed: {:?}", e);
+                None
             }
+        });
+
+        self.write_slim_output(join_slim_swaps(parts), mars)
+    }
+
+    /// Run slim and write a chunked file.  The swaps of each part are written as soon as the part
+    /// is done, so only the parts that are running are kept in memory.
+    fn run_chunked(&self, mars: Mars) -> Result<()> {
+        use rayon::prelude::*;
+        let parts = self.split(&mars)?;
+        let header = ChunkedHeader {
+            mars,
+            pruning: None,
+        };
+        let writer = Mutex::new(ChunkedWriter::new(self.output()?, &header)?);
+
+        parts
+            .par_iter()
+            .enumerate()
+            .try_for_each(|(k, sub)| -> Result<()> {
+                let swaps = match sub.run_slim(|i, n| log_part_progress(k, i, n)) {
+                    Ok(swaps) => swaps,
+                    Err(e) => {
+                        error!("Part failed: {:?}", e);
+                        return Ok(());
+                    }
+                };
+                let mut writer = writer
+                    .lock()
+                    .map_err(|_| anyhow!("another part failed while writing"))?;
+                writer.write_all(swaps)?;
+                Ok(())
+            })?;
+
+        writer
+            .into_inner()
+            .map_err(|_| anyhow!("a part failed while writing"))?
+            .finish()?;
+        self.log_output_written();
+        Ok(())
+    }
+
+    /// Number of parts to split the grid into.
+    fn num_parts(&self) -> usize {
+        self.threads.unwrap_or(4)
+    }
+
+    /// Split the grid into [RunArgs::num_parts] sub-problems.
+    fn split(&self, mars: &Mars) -> Result<Vec<SubMars>> {
+        let parts = mars.split_into(self.num_parts())?;
+        info!("Split the grid into {} parts", parts.len());
+        Ok(parts)
+    }
+
+    /// Run slim on a grid mesh, and write checkpoints to the `--checkpoint` directory, or resume
+    /// from the `--resume` directory.
+    fn run_checkpointed(&self) -> Result<()> {
+        use rayon::prelude::*;
+
+        if self.prune.is_some() {
+            bail!("Cannot prune and checkpoint at the same time");
         }
 
-        let output: SlimFile = (joined, mars);
+        let (mars, num_parts, dir) = if let Some(ref dir) = self.resume {
+            info!("Resume from checkpoint {}", dir.display());
+            let header: CheckpointHeader = read_rmp(&dir.join("header"))?;
+            header.check_version()?;
+            (header.mars, header.parts, dir)
+        } else {
+            let dir = self
+                .checkpoint
+                .as_ref()
+                .ok_or_else(|| anyhow!("need either --checkpoint or --resume"))?;
+            let mars = self.read_mars()?;
+            std::fs::create_dir_all(dir).context("create checkpoint directory")?;
+            let header = CheckpointHeader::new(mars, self.num_parts());
+            write_rmp_atomic(&dir.join("header"), &header)?;
+            info!("Write checkpoints to {}", dir.display());
+            (header.mars, header.parts, dir)
+        };
+        if !matches!(mars.grid, Some(Grid::Mesh(_))) {
+            bail!("Checkpointing is only supported for grid meshes");
+        }
 
-        let output_bytes = rmp_serde::to_vec(&output)?;
+        let parts = mars.split_into(num_parts)?;
+        let parts = parts
+            .par_iter()
+            .enumerate()
+            .map(|(k, sub)| -> Result<_> {
+                let path = dir.join(format!("part-{}", k));
+                let log_path = dir.join(format!("part-{}.swaps", k));
+                let (mut run, log) = if self.resume.is_some() && path.exists() {
+                    let mut run: SlimRun = read_rmp(&path)?;
+                    let log = std::fs::File::open(&log_path)
+                        .with_context(|| format!("open {}", log_path.display()))?;
+                    run.restore_swaps(BufReader::new(log))?;
+                    // Drop the swaps that were appended after the checkpoint.
+                    let log = std::fs::OpenOptions::new()
+                        .append(true)
+                        .open(&log_path)
+                        .with_context(|| format!("open {}", log_path.display()))?;
+                    log.set_len(run.log_len()).context("truncate swap log")?;
+                    info!(?k, steps = run.steps(), done = run.is_done(), "Resume part");
+                    (run, log)
+                } else {
+                    let log = std::fs::File::create(&log_path)
+                        .with_context(|| format!("create {}", log_path.display()))?;
+                    (SlimRun::new(sub)?, log)
+                };
+                run.run(
+                    sub,
+                    |i, n| log_part_progress(k, i, n),
+                    self.checkpoint_every,
+                    |r| {
+                        r.append_swaps(BufWriter::new(&log))?;
+                        log.sync_data()?;
+                        write_rmp_atomic(&path, r).map_err(|e| MarsError::Io(format!("{:#}", e)))
+                    },
+                )?;
+                Ok(run.into_swaps(sub))
+            })
+            .collect::<Result<Vec<_>>>()?;
 
-        if let Some(ref path) = self.output_path {
-            let mut f = std::fs::File::create(path).context("create output file")?;
-            f.write_all(&output_bytes).context("write to output file")?;
-            info!("Wrote output to {}", path.display());
+        self.write_slim_output(join_slim_swaps(parts), mars)
+    }
+
+    /// The output path, or stdout.
+    fn output(&self) -> Result<BufWriter<Box<dyn Write + Send>>> {
+        let out: Box<dyn Write + Send> = if let Some(ref path) = self.output_path {
+            Box::new(std::fs::File::create(path).context("create output file")?)
+        } else {
+            Box::new(std::io::stdout())
+        };
+        Ok(BufWriter::new(out))
+    }
+
+    fn log_output_written(&self) {
+        match self.output_path {
+            Some(ref path) => info!("Wrote output to {}", path.display()),
+            None => info!("Wrote output to stdout"),
+        }
+    }
+
+    /// Write slim output to the output path or stdout, either as a slim state file or as a
+    /// chunked file if `--chunked` is given.
+    fn write_slim_output(&self, swaps: Vec<SlimSwapList>, mars: Mars) -> Result<()> {
+        let out = self.output()?;
+
+        if self.chunked {
+            let header = ChunkedHeader {
+                mars,
+                pruning: None,
+            };
+            let mut writer = ChunkedWriter::new(out, &header)?;
+            writer.write_all(swaps)?;
+            writer.finish()?;
         } else {
-            std::io::stdout()
-                .write_all(&output_bytes)
-                .context("write to stdout")?;
-            info!("Wrote output to stdout");
+            let header = StateHeader::new(
+                StateKind::Slim,
+                RunParams {
+                    pruning: None,
+                    refine: self.refine,
+                },
+            );
+            mars_core::state::write_state(out, &header, &State::Slim(swaps, mars))
+                .context("write output")?;
         }
 
+        self.log_output_written();
         Ok(())
     }
 }
 
-fn run(args: &RunArgs) -> Result<()> {
-    if args.slim {
-        return args.run_slim();
+/// Log the progress of sub-problem `k` every percent.
+fn log_part_progress(k: usize, i: usize, n: usize) {
+    if i == 0 {
+        return;
+    }
+    let p = (i as f64 / n as f64 * 100.0).round();
+    let pprev = ((1.0 + i as f64) / n as f64 * 100.0).round();
+    let on_step = p != pprev;
+    if on_step || i == n {
+        info!(?k, "{:3}%", p);
     }
+}
+
+/// Read an rmp encoded file.
+fn read_rmp<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T> {
+    let f = std::fs::File::open(path).with_context(|| format!("open {}", path.display()))?;
+    rmp_serde::from_read(BufReader::new(f)).with_context(|| format!("rmp read {}", path.display()))
+}
 
-    let complex = Complex::read_from_obj_path(&args.obj_path)
-        .map_err(|e| anyhow!(e))
-        .context("failed to read complex")
-        .unwrap();
+/// Write an rmp encoded file.  The file is first written next to `path` and then moved into
+/// place, so that `path` is never partially written.
+fn write_rmp_atomic<T: serde::Serialize>(path: &Path, value: &T) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut f = BufWriter::new(std::fs::File::create(&tmp).context("create file")?);
+    rmp_serde::encode::write(&mut f, value).context("write file")?;
+    f.flush().context("write file")?;
+    drop(f);
+    std::fs::rename(&tmp, path).context("move file into place")?;
+    Ok(())
+}
 
-    let mesh_grid = {
-        let obj_string = std::fs::read_to_string(&args.mesh_path)
-            .with_context(|| format!("failed to read mesh path: {:?}", args.mesh_path))
-            .unwrap();
-        VineyardsGridMesh::read_from_obj_string(&obj_string)
+fn run(args: &RunArgs) -> Result<()> {
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .context("build thread pool")?;
+    }
+    if args.refine.is_some() {
+        return args.run_adaptive();
+    }
+    if args.checkpoint.is_some() || args.resume.is_some() {
+        return args.run_checkpointed();
+    }
+    if args.slim || args.chunked {
+        return args.run_slim();
     }
-    .map_err(|e| anyhow!(e))
-    .context("failed to read complex")
-    .unwrap();
 
-    let mars = mars_core::Mars {
-        complex: Some(complex),
-        grid: Some(mars_core::Grid::Mesh(mesh_grid)),
-    };
+    let mars = args.read_mars()?;
 
     use rayon::prelude::*;
     let vin: Vec<_> = {
-        let parts = mars.split_into_4().map_err(|e| anyhow!(e))?;
+        let parts = args.split(&mars)?;
         parts
             .par_iter()
             .enumerate()
-            .map(|(k, sub)| {
-                sub.run(|i, n| {
-                    if i == 0 {
-                        return;
-                    }
-                    let p = (i as f64 / n as f64 * 100.0).round();
-                    let pprev = ((1.0 + i as f64) / n as f64 * 100.0).round();
-                    let on_step = p != pprev;
-                    if on_step || i == n {
-                        info!(?k, "{:3}%", p);
-                    }
-                })
-            })
+            .map(|(k, sub)| sub.run(|i, n| log_part_progress(k, i, n)))
             .collect()
     };
 
@@ -715,38 +2101,33 @@ fn run(args: &RunArgs) -> Result<()> {
             a.add_other(e?);
             Ok(a)
         })
-        .expect("should be four vineyards")
-        .map_err(|e| anyhow!(e))?;
+        .ok_or_else(|| anyhow!("no parts to run"))??;
 
     info!("Bake matrices");
     for r in vin.reductions.values_mut() {
         r.bake_all_matrices();
     }
 
+    let mut run_params = RunParams::default();
     if let Some(ref prune) = args.prune {
         info!("Prune output");
-        let prune_params = if let Some(path) = prune {
-            info!("Use pruning parameters from {}", path.display());
-            let file_contents = std::fs::read_to_string(path).context("read prune file")?;
-            let params: [PruningParam; 3] =
-                serde_json::from_slice(file_contents.as_bytes()).context("read json")?;
-            params
-        } else {
-            info!("Use default pruning parameters");
-            default_pruning_params()
-        };
-
         let c = mars.complex.as_ref().unwrap();
+        let prune_params = read_pruning_params(prune.as_deref(), c.simplices_per_dim.len())?;
 
-        let mut swaps = [Vec::new(), Vec::new(), Vec::new()];
-        for dim in 0..3 {
+        for dim in 0..vin.swaps.len() {
+            let Some(dim_params) = prune_params.get(dim) else {
+                bail!(
+                    "the state has swaps of dimension {}, which the complex doesn't have",
+                    dim
+                );
+            };
             let num_swaps = vin.swaps[dim].iter().map(|s| s.2.v.len()).sum::<usize>();
-            let pruned = vin.prune_dim(dim, &prune_params[dim], &c, |i, n| {
+            let pruned = vin.prune_dim(dim, dim_params, c, &mars.filtration, |i, n| {
                 if i % 511 == 0 {
                     let percent = (i as f64 / n as f64) * 100.0;
                     info!("prune dim {dim}: {percent:3.0}%");
                 }
-            });
+            })?;
             let num_left = pruned.iter().map(|s| s.2.v.len()).sum::<usize>();
             let num_pruned = num_swaps - num_left;
             info!(
@@ -756,26 +2137,17 @@ fn run(args: &RunArgs) -> Result<()> {
                 ((num_pruned as f64 / num_swaps as f64) * 100.0).floor(),
                 num_left
             );
-            swaps[dim] = pruned;
+            vin.swaps[dim] = pruned;
         }
 
-        vin.swaps = swaps;
+        run_params.pruning = Some(prune_params);
     }
 
     info!("Write output");
-    let output = (mars, vin);
-    let output_bytes = rmp_serde::to_vec(&output)?;
-
-    if let Some(ref path) = args.output_path {
-        let mut f = std::fs::File::create(path).context("create output file")?;
-        f.write_all(&output_bytes).context("write to output file")?;
-        info!("Wrote output to {}", path.display());
-    } else {
-        std::io::stdout()
-            .write_all(&output_bytes)
-            .context("write to stdout")?;
-        info!("Wrote output to stdout");
-    }
+    let header = StateHeader::new(StateKind::Full, run_params);
+    mars_core::state::write_state(args.output()?, &header, &State::Full(mars, vin))
+        .context("write output")?;
+    args.log_output_written();
 
     Ok(())
 }
@@ -796,5 +2168,11 @@ fn main() -> Result<()> {
         Sub::Obj(o) => o.run(),
         Sub::Prune(p) => p.run(),
         Sub::Stats(s) => s.run(),
+        Sub::Grid(g) => g.run(),
+        Sub::Split(s) => s.run(),
+        Sub::RunSub(r) => r.run(),
+        Sub::Merge(m) => m.run(),
+        Sub::Check(c) => c.run(),
+        Sub::Query(q) => q.run(),
     }
 }
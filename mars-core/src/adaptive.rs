@@ -0,0 +1,405 @@
+//! Adaptive grid refinement around the medial axes.
+//!
+//! We run Vineyards on a coarse [VineyardsGrid], and find the grid cells that are next to a grid
+//! edge with Faustian swaps.  These cells are split into eight, and we run Vineyards again on the
+//! finer grid points, which is a [VineyardsGridMesh] since the refined region is irregular.  This
+//! is repeated until we reach the target depth.
+//!
+//! All grid points are identified by their integer coordinates on the finest lattice, so that
+//! points shared between neighboring cells are only included once.
+use std::collections::{HashMap, HashSet};
+
+use tracing::info;
+
+use crate::{
+    complex::{Complex, Pos},
+    error::{MarsError, MarsResult},
+    filtration::FiltrationKind,
+    grid::{Index, VineyardsGrid, VineyardsGridMesh},
+    prune, Grid, Mars, PruningParam, SlimSwapList,
+};
+
+/// Integer coordinates of a grid point on the finest lattice.
+type Lattice = [isize; 3];
+
+/// The result of running Vineyards on one refinement level.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct AdaptiveLevel {
+    /// Grid spacing at this level.
+    pub size: f64,
+    /// The grid we ran on.  The first level is [Grid::Regular], and all other levels are
+    /// [Grid::Mesh].
+    pub grid: Grid,
+    /// Swaps for each dimension, with [Index]es into `grid`.
+    pub swaps: Vec<SlimSwapList>,
+    /// Lower corners of the cells that were refined into the next level.
+    pub refined: HashSet<Lattice>,
+    /// Lattice coordinates for each [Index] of the grid.
+    lattice: HashMap<Index, Lattice>,
+}
+
+/// Output of [run_adaptive].
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct AdaptiveVineyards {
+    /// One level per refinement depth, from the coarsest to the finest.
+    pub levels: Vec<AdaptiveLevel>,
+    /// Lattice step of a cell at the coarsest level.
+    coarse_step: isize,
+}
+
+impl AdaptiveLevel {
+    /// The dual quad of a grid edge, sized according to this level.
+    pub fn dual_quad_points(&self, a: Index, b: Index) -> [Pos; 4] {
+        match self.grid {
+            Grid::Regular(ref g) => g.dual_quad_points(a, b),
+            Grid::Mesh(ref g) => g.dual_quad_points(a, b),
+        }
+    }
+}
+
+impl AdaptiveVineyards {
+    /// Dual quads for the swaps of the given dimension.
+    ///
+    /// Grid edges at one level that are covered by refined cells are left out, since the next
+    /// level has the same part of the axis at a higher resolution.
+    pub fn medial_axes_quads(&self, dim: usize) -> Vec<[Pos; 4]> {
+        let mut ret = Vec::new();
+        let mut step = self.coarse_step;
+        for level in &self.levels {
+            for (a, b, swaps) in &level.swaps[dim] {
+                if swaps.is_empty() {
+                    continue;
+                }
+                let covered = adjacent_cells(level.lattice[a], level.lattice[b], step)
+                    .iter()
+                    .all(|c| level.refined.contains(c));
+                if !covered {
+                    ret.push(level.dual_quad_points(*a, *b));
+                }
+            }
+            step /= 2;
+        }
+        ret
+    }
+
+    /// The grid and swaps of the finest level.
+    ///
+    /// This misses the parts of the axis on coarser levels that were not refined, see
+    /// [AdaptiveVineyards::into_multilevel] for the whole axis.
+    pub fn into_finest(mut self) -> Option<(Grid, Vec<SlimSwapList>)> {
+        let level = self.levels.pop()?;
+        Some((level.grid, level.swaps))
+    }
+
+    /// All levels as a single grid with the swaps of every level, leaving out the same grid
+    /// edges as [AdaptiveVineyards::medial_axes_quads].
+    ///
+    /// The grid is a [VineyardsGridMesh] of the grid edges with swaps, which have the lengths of
+    /// their level, so that their dual quads are at the right size per level.
+    pub fn into_multilevel(self) -> (Grid, Vec<SlimSwapList>) {
+        let num_dims = self.levels.iter().map(|l| l.swaps.len()).max().unwrap_or(0);
+        let mut swaps = vec![SlimSwapList::new(); num_dims];
+        let mut vertex_of: HashMap<Lattice, isize> = HashMap::new();
+        let mut points = Vec::new();
+        let mut edges = HashSet::new();
+
+        let mut step = self.coarse_step;
+        for level in self.levels {
+            let position = |i: Index| match level.grid {
+                Grid::Regular(ref g) => g.coordinate(i),
+                Grid::Mesh(ref g) => g.coordinate(i),
+            };
+            for (dim, dim_swaps) in level.swaps.into_iter().enumerate() {
+                for (a, b, s) in dim_swaps {
+                    if s.is_empty() {
+                        continue;
+                    }
+                    let (la, lb) = (level.lattice[&a], level.lattice[&b]);
+                    let covered = adjacent_cells(la, lb, step)
+                        .iter()
+                        .all(|c| level.refined.contains(c));
+                    if covered {
+                        continue;
+                    }
+                    let [va, vb] = [(la, a), (lb, b)].map(|(l, i)| {
+                        *vertex_of.entry(l).or_insert_with(|| {
+                            points.push(position(i));
+                            points.len() as isize - 1
+                        })
+                    });
+                    edges.insert((va.min(vb), va.max(vb)));
+                    swaps[dim].push((Index::fake(va), Index::fake(vb), s));
+                }
+            }
+            step /= 2;
+        }
+
+        let mut neighbors = vec![Vec::new(); points.len()];
+        for (v, w) in edges {
+            neighbors[v as usize].push(w);
+            neighbors[w as usize].push(v);
+        }
+        for n in &mut neighbors {
+            n.sort();
+        }
+        let mut mesh = VineyardsGridMesh::empty();
+        mesh.points = points;
+        mesh.neighbors = neighbors;
+        (Grid::Mesh(mesh), swaps)
+    }
+}
+
+/// The lower corners of the (up to) four cells of size `step` that have the grid edge `a`-`b`
+/// as one of their edges.
+fn adjacent_cells(a: Lattice, b: Lattice, step: isize) -> Vec<Lattice> {
+    let lo = [a[0].min(b[0]), a[1].min(b[1]), a[2].min(b[2])];
+    let Some(axis) = (0..3).find(|&j| a[j] != b[j]) else {
+        return Vec::new();
+    };
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+    let mut ret = Vec::with_capacity(4);
+    for du in [0, step] {
+        for dv in [0, step] {
+            let mut c = lo;
+            c[u] -= du;
+            c[v] -= dv;
+            ret.push(c);
+        }
+    }
+    ret
+}
+
+/// Run Vineyards on `coarse`, and refine the cells around the swaps `depth` times.
+///
+/// If `pruning` is given, the swaps are pruned with these parameters before we decide which cells
+/// to refine, so that swaps that would be pruned away anyways don't cause refinement.  The swaps
+/// stored in each [AdaptiveLevel] are not pruned.
+pub fn run_adaptive<F: Fn(usize, usize)>(
+    complex: &Complex,
+    filtration: &FiltrationKind,
+    coarse: &VineyardsGrid,
+    depth: usize,
+    pruning: Option<&[PruningParam]>,
+    progress: F,
+) -> MarsResult<AdaptiveVineyards> {
+    let coarse_step = 1isize
+        .checked_shl(depth as u32)
+        .filter(|s| 0 < *s)
+        .ok_or_else(|| MarsError::Invalid(format!("Refinement depth too large: {}", depth)))?;
+    // Cells must lie within the coarse grid.
+    let max_corner = coarse.shape.0.map(|n| (n - 1) * coarse_step);
+    let lattice_size = coarse.size / coarse_step as f64;
+    let position = |l: Lattice| {
+        let mut arr = coarse.corner.0;
+        for (a, l) in arr.iter_mut().zip(l) {
+            *a += lattice_size * l as f64;
+        }
+        Pos(arr)
+    };
+
+    let mut levels = Vec::new();
+
+    let mut lattice = HashMap::new();
+    for x in 0..coarse.shape.0[0] {
+        for y in 0..coarse.shape.0[1] {
+            for z in 0..coarse.shape.0[2] {
+                let i = Index([x, y, z]);
+                lattice.insert(i, i.0.map(|c| c * coarse_step));
+            }
+        }
+    }
+    let mut grid = Grid::Regular(coarse.clone());
+    let mut step = coarse_step;
+
+    for level_i in 0..=depth {
+        info!(
+            "adaptive level {}: step {} #points {}",
+            level_i,
+            step,
+            lattice.len()
+        );
+        let mars = Mars {
+            complex: Some(complex.clone()),
+            grid: Some(grid),
+            filtration: filtration.clone(),
+        };
+        let swaps = mars.run_slim(&progress)?;
+        let level_grid = mars.grid.expect("we just set the grid");
+
+        let mut refined = HashSet::new();
+        if level_i < depth {
+            for (dim, dim_swaps) in swaps.iter().enumerate() {
+                let pruned;
+                // There are no pruning parameters for tetrahedra.
+                let dim_swaps = if let Some(params) = pruning.and_then(|p| p.get(dim)) {
+                    pruned = prune::prune_dim(dim_swaps, dim, params, complex, |_, _| {});
+                    &pruned
+                } else {
+                    dim_swaps
+                };
+                for (a, b, s) in dim_swaps {
+                    if s.is_empty() {
+                        continue;
+                    }
+                    for c in adjacent_cells(lattice[a], lattice[b], step) {
+                        let inside = (0..3).all(|j| 0 <= c[j] && c[j] + step <= max_corner[j]);
+                        if inside {
+                            refined.insert(c);
+                        }
+                    }
+                }
+            }
+        }
+
+        levels.push(AdaptiveLevel {
+            size: lattice_size * step as f64,
+            grid: level_grid,
+            swaps,
+            refined,
+            lattice,
+        });
+
+        if level_i == depth {
+            break;
+        }
+        let refined = &levels.last().expect("we just pushed").refined;
+        if refined.is_empty() {
+            break;
+        }
+        step /= 2;
+        let (mesh, mesh_lattice) = refine(refined, step, position);
+        grid = Grid::Mesh(mesh);
+        lattice = mesh_lattice;
+    }
+
+    Ok(AdaptiveVineyards {
+        levels,
+        coarse_step,
+    })
+}
+
+/// Split each of the `cells` into eight cells with side `step`, and make a [VineyardsGridMesh] of
+/// the grid points and edges of the new cells.
+fn refine<P: Fn(Lattice) -> Pos>(
+    cells: &HashSet<Lattice>,
+    step: isize,
+    position: P,
+) -> (VineyardsGridMesh, HashMap<Index, Lattice>) {
+    let mut vertex_of: HashMap<Lattice, isize> = HashMap::new();
+    let mut points = Vec::new();
+    let mut edges = HashSet::new();
+
+    let mut vertex = |l: Lattice, points: &mut Vec<Pos>| {
+        *vertex_of.entry(l).or_insert_with(|| {
+            points.push(position(l));
+            points.len() as isize - 1
+        })
+    };
+
+    for c in cells {
+        for i in 0..3 {
+            for j in 0..3 {
+                for k in 0..3 {
+                    let p = [c[0] + i * step, c[1] + j * step, c[2] + k * step];
+                    let v = vertex(p, &mut points);
+                    for (axis, n) in [i, j, k].into_iter().enumerate() {
+                        if n == 2 {
+                            continue;
+                        }
+                        let mut q = p;
+                        q[axis] += step;
+                        let w = vertex(q, &mut points);
+                        edges.insert((v.min(w), v.max(w)));
+                    }
+                }
+            }
+        }
+    }
+
+    let mut neighbors = vec![Vec::new(); points.len()];
+    for (v, w) in edges {
+        neighbors[v as usize].push(w);
+        neighbors[w as usize].push(v);
+    }
+
+    let lattice = vertex_of
+        .into_iter()
+        .map(|(l, v)| (Index::fake(v), l))
+        .collect();
+
+    let size = position([step, 0, 0]).dist(&position([0, 0, 0]));
+    let mut mesh = VineyardsGridMesh::empty();
+    mesh.points = points;
+    mesh.neighbors = neighbors;
+    mesh.dim_dist = Some((size, size, size));
+    (mesh, lattice)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::*;
+
+    #[test]
+    fn refined_axis_is_finer() {
+        let complex = test_complex_cube();
+        let coarse = test_grid_for_cube();
+        let params = [
+            default_pruning_param(0),
+            default_pruning_param(1),
+            default_pruning_param(2),
+        ];
+
+        let adaptive = run_adaptive(
+            &complex,
+            &FiltrationKind::default(),
+            &coarse,
+            1,
+            Some(&params),
+            |_, _| {},
+        )
+        .unwrap();
+        assert_eq!(adaptive.levels.len(), 2);
+        assert!(!adaptive.levels[0].refined.is_empty());
+        assert!((adaptive.levels[1].size - coarse.size / 2.0).abs() < 1e-9);
+
+        let fine_quads = (0..3)
+            .flat_map(|dim| adaptive.medial_axes_quads(dim))
+            .filter(|quad| (quad[0].dist(&quad[1]) - coarse.size / 2.0).abs() < 1e-9)
+            .count();
+        assert!(0 < fine_quads);
+
+        // The single grid has the same quads, coarse and fine, even after the grid distances are
+        // recomputed as they are when reading a state file.
+        let expected = (0..3)
+            .map(|dim| adaptive.medial_axes_quads(dim))
+            .collect::<Vec<_>>();
+        let (grid, swaps) = adaptive.into_multilevel();
+        let Grid::Mesh(mut mesh) = grid else {
+            panic!("expected a mesh grid");
+        };
+        mesh.recompute_dim_dist();
+        assert!(mesh.dim_dist.is_none());
+        let sorted = |quads: &[[Pos; 4]]| {
+            let mut keys = quads
+                .iter()
+                .map(|q| format!("{:.6?}", q.map(|p| p.0)))
+                .collect::<Vec<_>>();
+            keys.sort();
+            keys
+        };
+        for (dim, dim_swaps) in swaps.iter().enumerate() {
+            let quads = dim_swaps
+                .iter()
+                .map(|(a, b, _)| mesh.dual_quad_points(*a, *b))
+                .collect::<Vec<_>>();
+            assert_eq!(sorted(&quads), sorted(&expected[dim]));
+        }
+        let sizes = swaps
+            .iter()
+            .flatten()
+            .map(|(a, b, _)| (mesh.coordinate(*a).dist(&mesh.coordinate(*b)) * 1e6).round() as i64)
+            .collect::<HashSet<_>>();
+        assert_eq!(sizes.len(), 2);
+    }
+}
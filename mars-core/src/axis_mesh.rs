@@ -0,0 +1,509 @@
+//! Medial axes as welded, indexed meshes.
+//!
+//! Every grid edge with Faustian swaps contributes the dual quad of the edge to the medial axis of
+//! that dimension.  Neighboring quads share corners, so instead of writing four new vertices per
+//! quad we weld the corners into an indexed mesh, which downstream tools can smooth, filter or
+//! split into sheets.  Each face keeps the attributes of the swaps on its grid edge.
+//!
+//! The quads are at the grid resolution, so the axes are blocky.  Every swap also knows where
+//! along its grid edge it happened ([Swap::t]), and [AxisMesh::place_at_crossings] uses this to
+//! move the vertices off the cell centers, like dual contouring does for isosurfaces.
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::{
+    complex::{Complex, Pos},
+    filtration::Filtration,
+    grid::Index,
+    sneaky_matrix::CI,
+    spatial::merge_close_points,
+    Grid, SlimSwapList, Swap, SwapList, Vineyards,
+};
+
+/// Corners closer than this fraction of the shortest quad side are welded together.
+const WELD_FRACTION: f64 = 1e-3;
+/// How strongly [AxisMesh::place_at_crossings] keeps vertices at their cell center, relative to
+/// the pull of a single face.
+const CENTER_WEIGHT: f64 = 1e-2;
+
+/// A face of an [AxisMesh], which is the dual quad of one grid edge.
+#[derive(Clone, Debug, Serialize)]
+pub struct AxisFace {
+    /// Indices into [AxisMesh::points], in the order of the quad.
+    pub vertices: [usize; 4],
+    /// The grid edge this face is dual to.
+    pub edge: (Index, Index),
+    /// Number of swaps across the grid edge.
+    pub num_swaps: usize,
+    /// The largest persistence of a swap across the edge, where the persistence of a swap is the
+    /// longer of the two lifetimes of the swapped simplices.  This is infinite if one of the
+    /// simplices is in an essential class, and `None` if the lifetimes aren't known.
+    pub max_persistence: Option<f64>,
+    /// Canonical indices of the pair of simplices of the most persistent swap, or the first swap
+    /// if the lifetimes aren't known.
+    pub pair: (CI, CI),
+    /// The point on the grid edge where the swap of [AxisFace::pair] happened.
+    pub crossing: Pos,
+    /// Radius of the medial ball at [AxisFace::crossing], which is the distance at which the
+    /// swapped simplices enter the filtration there.  `None` until [AxisMesh::compute_radii] is
+    /// called, or if the filtration isn't a distance.
+    pub radius: Option<f64>,
+}
+
+/// The medial axis of one dimension, as an indexed quad mesh.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct AxisMesh {
+    pub dim: usize,
+    pub points: Vec<Pos>,
+    pub faces: Vec<AxisFace>,
+}
+
+/// Collects dual quads, and welds their corners in [AxisMeshBuilder::finish].
+#[derive(Debug)]
+pub struct AxisMeshBuilder {
+    dim: usize,
+    corners: Vec<Pos>,
+    faces: Vec<AxisFace>,
+    shortest_side: f64,
+}
+
+impl AxisMeshBuilder {
+    pub fn new(dim: usize) -> Self {
+        Self {
+            dim,
+            corners: Vec::new(),
+            faces: Vec::new(),
+            shortest_side: f64::INFINITY,
+        }
+    }
+
+    /// Add the dual quad of the grid edge `a`-`b`, with the swaps across it and the persistence of
+    /// each swap, if known.  Edges without swaps are skipped.
+    pub fn push<'a, I>(&mut self, grid: &Grid, a: Index, b: Index, swaps: I)
+    where
+        I: IntoIterator<Item = (&'a Swap, Option<f64>)>,
+    {
+        let mut num_swaps = 0;
+        let mut best: Option<(&Swap, Option<f64>)> = None;
+        for (swap, persistence) in swaps {
+            num_swaps += 1;
+            let better = match (best, persistence) {
+                (None, _) => true,
+                (Some((_, Some(b))), Some(p)) => b < p,
+                (Some((_, None)), Some(_)) => true,
+                _ => false,
+            };
+            if better {
+                best = Some((swap, persistence));
+            }
+        }
+        let Some((swap, max_persistence)) = best else {
+            return;
+        };
+        let (pa, pb) = (coordinate(grid, a), coordinate(grid, b));
+        let crossing = swap.crossing(pa, pb);
+
+        let quad = match grid {
+            Grid::Regular(g) => g.dual_quad_points(a, b),
+            Grid::Mesh(g) => g.dual_quad_points(a, b),
+        };
+        for k in 0..4 {
+            let side = quad[k].dist(&quad[(k + 1) % 4]);
+            self.shortest_side = self.shortest_side.min(side);
+        }
+        let first = self.corners.len();
+        self.corners.extend_from_slice(&quad);
+        self.faces.push(AxisFace {
+            vertices: [first, first + 1, first + 2, first + 3],
+            edge: (a, b),
+            num_swaps,
+            max_persistence,
+            pair: (swap.i, swap.j),
+            crossing,
+            radius: None,
+        });
+    }
+
+    /// Weld the corners of the quads, and drop the faces that collapsed in the process.
+    pub fn finish(self) -> AxisMesh {
+        let distance = if self.shortest_side.is_finite() {
+            self.shortest_side * WELD_FRACTION
+        } else {
+            0.0
+        };
+        let (points, new_index) = merge_close_points(&self.corners, distance);
+        let faces = self
+            .faces
+            .into_iter()
+            .filter_map(|mut f| {
+                f.vertices = f.vertices.map(|v| new_index[v]);
+                let [a, b, c, d] = f.vertices;
+                let distinct = a != b && a != c && a != d && b != c && b != d && c != d;
+                distinct.then_some(f)
+            })
+            .collect();
+        AxisMesh {
+            dim: self.dim,
+            points,
+            faces,
+        }
+    }
+}
+
+impl AxisMesh {
+    /// The medial axis of dimension `dim` from the output of [crate::Mars::run_slim].
+    ///
+    /// A [crate::grid::VineyardsGridMesh] needs [crate::grid::VineyardsGridMesh::recompute_dim_dist]
+    /// to be called first, so that the quads have the right size.
+    pub fn from_slim_swaps(grid: &Grid, dim: usize, swaps: &SlimSwapList) -> Self {
+        let mut builder = AxisMeshBuilder::new(dim);
+        for (a, b, swaps) in swaps {
+            builder.push(
+                grid,
+                *a,
+                *b,
+                swaps.iter().map(|(s, li, lj)| (s, Some(li.max(*lj)))),
+            );
+        }
+        builder.finish()
+    }
+
+    /// The medial axis of dimension `dim` from a [SwapList], like [Vineyards::swaps] or a pruned
+    /// list.  The persistence of the swaps is computed from the reductions in `vineyards`, if they
+    /// are given.
+    pub fn from_swaps(
+        grid: &Grid,
+        dim: usize,
+        swaps: &SwapList,
+        vineyards: Option<(&Complex, &dyn Filtration, &Vineyards)>,
+    ) -> Self {
+        let mut builder = AxisMeshBuilder::new(dim);
+        for (a, b, swaps) in swaps {
+            let reductions = vineyards
+                .and_then(|(c, f, v)| Some((c, f, v.reductions.get(a)?, v.reductions.get(b)?)));
+            let persistence = |s: &Swap| {
+                let (c, f, ra, rb) = reductions?;
+                let li = ra.persistence(c, f, s.dim, s.i).lifetime();
+                let lj = rb.persistence(c, f, s.dim, s.j).lifetime();
+                Some(li.max(lj))
+            };
+            builder.push(grid, *a, *b, swaps.v.iter().map(|s| (s, persistence(s))));
+        }
+        builder.finish()
+    }
+
+    /// Move the vertices from the cell centers to the crossings of the swaps around them.
+    ///
+    /// Each face wants its vertices on the plane through [AxisFace::crossing] orthogonal to its
+    /// grid edge, and each vertex is moved to the least squares solution for the planes of its
+    /// faces.  In directions without any plane, like along a flat sheet, a small pull keeps the
+    /// vertex at the cell center, so the sheets don't shrink at their boundaries.
+    pub fn place_at_crossings(&mut self, grid: &Grid) {
+        let mut planes = vec![([[0.0; 3]; 3], [0.0; 3]); self.points.len()];
+        for f in &self.faces {
+            let (a, b) = (coordinate(grid, f.edge.0), coordinate(grid, f.edge.1));
+            let n = (b - a) / a.dist(&b);
+            let d = n.dot(&f.crossing);
+            for &v in &f.vertices {
+                let (m, r) = &mut planes[v];
+                for (row, (m_row, r_row)) in m.iter_mut().zip(r.iter_mut()).enumerate() {
+                    for (col, m_entry) in m_row.iter_mut().enumerate() {
+                        *m_entry += n.0[row] * n.0[col];
+                    }
+                    *r_row += n.0[row] * d;
+                }
+            }
+        }
+        for (p, (mut m, mut r)) in self.points.iter_mut().zip(planes) {
+            for k in 0..3 {
+                m[k][k] += CENTER_WEIGHT;
+                r[k] += CENTER_WEIGHT * p.0[k];
+            }
+            if let Some(x) = solve3(m, r) {
+                *p = Pos(x);
+            }
+        }
+    }
+
+    /// Set [AxisFace::radius] for all faces, from the values of the swapped pair at the crossing.
+    /// The two values are equal at the crossing up to the precision of [Swap::t], so we take their
+    /// mean.
+    pub fn compute_radii(&mut self, complex: &Complex, filtration: &dyn Filtration) {
+        for f in &mut self.faces {
+            let (i, j) = f.pair;
+            let vi = filtration.simplex_value(complex, self.dim, i, f.crossing);
+            let vj = filtration.simplex_value(complex, self.dim, j, f.crossing);
+            f.radius = filtration.radius((vi + vj) / 2.0);
+        }
+    }
+
+    /// The radius at each vertex, which is the mean radius of the faces around it.
+    pub fn vertex_radii(&self) -> Vec<Option<f64>> {
+        let mut sums = vec![(0.0, 0); self.points.len()];
+        for f in &self.faces {
+            let Some(r) = f.radius else {
+                continue;
+            };
+            for &v in &f.vertices {
+                sums[v].0 += r;
+                sums[v].1 += 1;
+            }
+        }
+        sums.into_iter()
+            .map(|(sum, n)| (0 < n).then(|| sum / n as f64))
+            .collect()
+    }
+
+    /// Average side length of the quads, or `0` if there are none.
+    pub fn average_side(&self) -> f64 {
+        let sides = self.faces.iter().flat_map(|f| {
+            let v = f.vertices;
+            (0..4).map(move |k| self.points[v[k]].dist(&self.points[v[(k + 1) % 4]]))
+        });
+        let (sum, n) = sides.fold((0.0, 0), |(sum, n), d| (sum + d, n + 1));
+        if n == 0 {
+            0.0
+        } else {
+            sum / n as f64
+        }
+    }
+
+    /// Number of faces per simplex pair, which is roughly one sheet of the axis each.
+    pub fn faces_per_pair(&self) -> BTreeMap<(CI, CI), usize> {
+        let mut ret = BTreeMap::new();
+        for f in &self.faces {
+            *ret.entry(f.pair).or_insert(0) += 1;
+        }
+        ret
+    }
+}
+
+fn coordinate(grid: &Grid, i: Index) -> Pos {
+    match grid {
+        Grid::Regular(g) => g.coordinate(i),
+        Grid::Mesh(g) => g.coordinate(i),
+    }
+}
+
+/// Solve `m x = r` by Cramer's rule.
+fn solve3(m: [[f64; 3]; 3], r: [f64; 3]) -> Option<[f64; 3]> {
+    let det = |m: [[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+    let d = det(m);
+    if d == 0.0 {
+        return None;
+    }
+    Some([0, 1, 2].map(|col| {
+        let mut mc = m;
+        for row in 0..3 {
+            mc[row][col] = r[row];
+        }
+        det(mc) / d
+    }))
+}
+
+/// Write the axes as an .obj file.  Each axis is an object `ma-dim-<dim>`, and its faces are
+/// grouped by the simplex pair of the face, in groups `pair-<i>-<j>`.
+pub fn write_axes_as_obj<W: std::io::Write>(mut w: W, axes: &[AxisMesh]) -> std::io::Result<()> {
+    let mut offset = 0;
+    for axis in axes {
+        writeln!(w, "o ma-dim-{}", axis.dim)?;
+        for p in &axis.points {
+            writeln!(w, "v {} {} {}", p.x(), p.y(), p.z())?;
+        }
+        let mut faces = axis.faces.iter().collect::<Vec<_>>();
+        faces.sort_by_key(|f| f.pair);
+        let mut group = None;
+        for f in faces {
+            if group != Some(f.pair) {
+                writeln!(w, "g pair-{}-{}", f.pair.0, f.pair.1)?;
+                group = Some(f.pair);
+            }
+            let [a, b, c, d] = f.vertices.map(|v| v + offset + 1);
+            writeln!(w, "f {} {} {} {}", a, b, c, d)?;
+        }
+        offset += axis.points.len();
+    }
+    Ok(())
+}
+
+/// Write the axes as an ASCII .ply file, with all dimensions in one mesh.  Faces have the
+/// properties `dim`, `swaps` (the number of swaps), `persistence` (the largest persistence, `-1`
+/// if it is unknown or infinite), `simplex_i` and `simplex_j` (the simplex pair), and `radius`
+/// (`-1` if it is unknown).  Vertices have the mean `radius` of their faces.
+pub fn write_axes_as_ply<W: std::io::Write>(mut w: W, axes: &[AxisMesh]) -> std::io::Result<()> {
+    let num_points = axes.iter().map(|a| a.points.len()).sum::<usize>();
+    let num_faces = axes.iter().map(|a| a.faces.len()).sum::<usize>();
+    writeln!(w, "ply")?;
+    writeln!(w, "format ascii 1.0")?;
+    writeln!(w, "comment medial axes from mars")?;
+    writeln!(w, "element vertex {}", num_points)?;
+    writeln!(w, "property double x")?;
+    writeln!(w, "property double y")?;
+    writeln!(w, "property double z")?;
+    writeln!(w, "property double radius")?;
+    writeln!(w, "element face {}", num_faces)?;
+    writeln!(w, "property list uchar int vertex_indices")?;
+    writeln!(w, "property uchar dim")?;
+    writeln!(w, "property int swaps")?;
+    writeln!(w, "property double persistence")?;
+    writeln!(w, "property int simplex_i")?;
+    writeln!(w, "property int simplex_j")?;
+    writeln!(w, "property double radius")?;
+    writeln!(w, "end_header")?;
+    for axis in axes {
+        for (p, r) in axis.points.iter().zip(axis.vertex_radii()) {
+            writeln!(w, "{} {} {} {}", p.x(), p.y(), p.z(), r.unwrap_or(-1.0))?;
+        }
+    }
+    let mut offset = 0;
+    for axis in axes {
+        for f in &axis.faces {
+            let [a, b, c, d] = f.vertices.map(|v| v + offset);
+            let persistence = f.max_persistence.filter(|p| p.is_finite()).unwrap_or(-1.0);
+            writeln!(
+                w,
+                "4 {} {} {} {} {} {} {} {} {} {}",
+                a,
+                b,
+                c,
+                d,
+                axis.dim,
+                f.num_swaps,
+                persistence,
+                f.pair.0,
+                f.pair.1,
+                f.radius.unwrap_or(-1.0)
+            )?;
+        }
+        offset += axis.points.len();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::VineyardsGrid;
+
+    fn swap(i: CI, j: CI) -> Swap {
+        Swap {
+            dim: 1,
+            i,
+            j,
+            t: 0.5,
+        }
+    }
+
+    #[test]
+    fn weld_neighboring_quads() {
+        let grid = Grid::Regular(VineyardsGrid::new(Pos([0.0; 3]), 0.5, [4, 4, 4]));
+        // Two neighboring grid edges, whose quads share a side, and an edge without swaps.
+        let late = Swap {
+            t: 0.8,
+            ..swap(5, 6)
+        };
+        let swaps: SlimSwapList = vec![
+            (
+                Index([1, 1, 1]),
+                Index([2, 1, 1]),
+                vec![(swap(3, 4), 0.5, 0.1), (late, 0.2, 2.0)],
+            ),
+            (
+                Index([1, 2, 1]),
+                Index([2, 2, 1]),
+                vec![(swap(3, 4), 0.5, f64::INFINITY)],
+            ),
+            (Index([0, 0, 0]), Index([1, 0, 0]), vec![]),
+        ];
+        let axis = AxisMesh::from_slim_swaps(&grid, 1, &swaps);
+        assert_eq!(axis.faces.len(), 2);
+        assert_eq!(axis.points.len(), 6);
+
+        assert_eq!(axis.faces[0].num_swaps, 2);
+        assert_eq!(axis.faces[0].max_persistence, Some(2.0));
+        assert_eq!(axis.faces[0].pair, (5, 6));
+        // The crossing is that of the most persistent swap.
+        assert!(axis.faces[0].crossing.dist(&Pos([0.9, 0.5, 0.5])) < 1e-12);
+        assert_eq!(axis.faces[1].max_persistence, Some(f64::INFINITY));
+        assert_eq!(
+            axis.faces_per_pair().into_iter().collect::<Vec<_>>(),
+            [((3, 4), 1), ((5, 6), 1)]
+        );
+
+        let mut obj = Vec::new();
+        write_axes_as_obj(&mut obj, std::slice::from_ref(&axis)).unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+        assert_eq!(obj.lines().filter(|l| l.starts_with("v ")).count(), 6);
+        assert!(obj.contains("g pair-5-6\n"));
+
+        let mut ply = Vec::new();
+        write_axes_as_ply(&mut ply, &[axis]).unwrap();
+        let ply = String::from_utf8(ply).unwrap();
+        assert!(ply.contains("element vertex 6\n"));
+        assert!(ply
+            .lines()
+            .any(|l| l.starts_with("4 ") && l.ends_with(" 1 1 -1 3 4 -1")));
+    }
+
+    #[test]
+    fn radius_at_crossings() {
+        let complex = Complex::from_cells(
+            vec![Pos([0.0, 0.0, 0.0]), Pos([2.0, 0.0, 0.0])],
+            &[vec![0, 1]],
+        )
+        .unwrap();
+        let grid = Grid::Regular(VineyardsGrid::new(Pos([0.5, -1.0, -1.0]), 1.0, [2, 3, 3]));
+        // The two vertices swap halfway between `x = 0.5` and `x = 1.5`.
+        let swap = Swap {
+            dim: 0,
+            i: 0,
+            j: 1,
+            t: 0.5,
+        };
+        let swaps: SlimSwapList =
+            vec![(Index([0, 1, 1]), Index([1, 1, 1]), vec![(swap, 1.0, 1.0)])];
+        let mut axis = AxisMesh::from_slim_swaps(&grid, 0, &swaps);
+        assert_eq!(axis.faces[0].radius, None);
+
+        axis.compute_radii(&complex, &crate::filtration::SquaredEuclidean);
+        assert_eq!(axis.faces[0].radius, Some(1.0));
+        assert_eq!(axis.vertex_radii(), [Some(1.0); 4]);
+    }
+
+    #[test]
+    fn place_vertices_at_crossings() {
+        let grid = Grid::Regular(VineyardsGrid::new(Pos([0.0; 3]), 1.0, [4, 4, 4]));
+        // The plane `x = 1.2 + 0.25 y` crosses the grid edges from `x = 1` to `x = 2`.
+        let swaps: SlimSwapList = (0..4)
+            .flat_map(|y| (0..4).map(move |z| (y, z)))
+            .map(|(y, z)| {
+                let t = 0.2 + 0.25 * y as f64;
+                let swap = Swap {
+                    dim: 1,
+                    i: 3,
+                    j: 4,
+                    t,
+                };
+                (Index([1, y, z]), Index([2, y, z]), vec![(swap, 0.1, 0.2)])
+            })
+            .collect();
+        let mut axis = AxisMesh::from_slim_swaps(&grid, 1, &swaps);
+        // The face of the edge at `y = 1, z = 1`.
+        assert!(axis.faces[5].crossing.dist(&Pos([1.45, 1.0, 1.0])) < 1e-12);
+        assert!(axis.points.iter().all(|p| p.x() == 1.5));
+
+        axis.place_at_crossings(&grid);
+        for p in &axis.points {
+            // The cells at the ends only see one row of crossings.
+            if 0.0 < p.y() && p.y() < 3.0 {
+                assert!((p.x() - (1.2 + 0.25 * p.y())).abs() < 1e-2, "{:?}", p);
+            }
+            assert!(0.0 < p.y() - p.y().floor() && 0.0 < p.z() - p.z().floor());
+        }
+    }
+}
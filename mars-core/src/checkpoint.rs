@@ -0,0 +1,276 @@
+//! Checkpoints for long slim runs over a [VineyardsGridMesh].
+//!
+//! A [SlimRun] is the state of [SubMars::run_slim] between two grid edges: the traversal
+//! frontier and the reductions that are still alive.  It can be serialized at any point, and
+//! resuming it gives the same swaps as an uninterrupted run.
+//!
+//! The swaps emitted so far are not part of the serialized state, since they grow with the run.
+//! Instead, [SlimRun::append_swaps] appends the swaps since the last checkpoint to a separate swap
+//! log, and the state records how long the log was at the checkpoint.  The log is a sequence of
+//! rmp encoded `(dim, a, b, swaps)` records, and edges without swaps are skipped.
+use std::io::{Read, Write};
+
+use crate::{
+    error::{MarsError, MarsResult},
+    grid::{Index, MeshTraversal, VineyardsGridMesh},
+    push_slim_swaps, Grid, Mars, SlimEdge, SlimSwapList, SubMars, Swap,
+};
+
+/// Version of the checkpoint layout.  Checkpoints are only meant to be resumed by the same
+/// version of mars, so there is no migration.
+///
+/// Version 4 moved the swaps out of the state into the swap log.
+pub const FORMAT_VERSION: u32 = 4;
+
+/// Everything in a checkpoint except the state of the sub-problems.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct CheckpointHeader {
+    pub format_version: u32,
+    /// The instance that is being run.
+    pub mars: Mars,
+    /// Number of parts passed to [Mars::split_into], so that the instance is split in the same way
+    /// when the run is resumed.
+    pub parts: usize,
+}
+
+impl CheckpointHeader {
+    pub fn new(mars: Mars, parts: usize) -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            mars,
+            parts,
+        }
+    }
+
+    pub fn check_version(&self) -> MarsResult<()> {
+        if self.format_version != FORMAT_VERSION {
+            return Err(MarsError::Invalid(format!(
+                "Checkpoint has format version {}, but this version of mars uses {}",
+                self.format_version, FORMAT_VERSION
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A slim run of a [SubMars] over a [VineyardsGridMesh] that can be stopped and resumed.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct SlimRun {
+    traversal: MeshTraversal,
+    /// The swaps of each dimension.  Not serialized; see [SlimRun::restore_swaps].
+    #[serde(skip)]
+    swaps: Vec<SlimSwapList>,
+    /// Number of entries of each dimension in `swaps` that have been appended to the swap log.
+    logged: Vec<usize>,
+    /// Length of the swap log in bytes.
+    log_len: u64,
+    done: bool,
+}
+
+fn mesh_of(sub: &SubMars) -> MarsResult<&VineyardsGridMesh> {
+    match sub.mars.grid {
+        Some(Grid::Mesh(ref mesh)) => Ok(mesh),
+        Some(Grid::Regular(_)) => Err(MarsError::Invalid(
+            "Checkpointing is only supported for grid meshes".to_string(),
+        )),
+        None => Err(MarsError::Invalid("SlimRun: no grid".to_string())),
+    }
+}
+
+impl SlimRun {
+    pub fn new(sub: &SubMars) -> MarsResult<Self> {
+        let Some(ref c) = sub.mars.complex else {
+            return Err(MarsError::Invalid("SlimRun::new: no complex".to_string()));
+        };
+        Ok(Self {
+            traversal: MeshTraversal::new(mesh_of(sub)?),
+            swaps: vec![Vec::new(); c.dim() + 1],
+            logged: vec![0; c.dim() + 1],
+            log_len: 0,
+            done: false,
+        })
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Number of grid edges crossed so far.
+    pub fn steps(&self) -> usize {
+        self.traversal.steps
+    }
+
+    /// Length in bytes of the swap log at this checkpoint.  A log that is longer has records from
+    /// after the checkpoint, which must be truncated before appending to it again.
+    pub fn log_len(&self) -> u64 {
+        self.log_len
+    }
+
+    /// Append the swaps emitted since the last call to `log`, which has to be at the end of the
+    /// log of the last checkpoint.
+    pub fn append_swaps<W: Write>(&mut self, mut log: W) -> MarsResult<()> {
+        for (dim, swaps) in self.swaps.iter().enumerate() {
+            for (a, b, s) in &swaps[self.logged[dim]..] {
+                if s.is_empty() {
+                    continue;
+                }
+                let bytes = rmp_serde::to_vec(&(dim, a, b, s))?;
+                log.write_all(&bytes)?;
+                self.log_len += bytes.len() as u64;
+            }
+            self.logged[dim] = swaps.len();
+        }
+        log.flush()?;
+        Ok(())
+    }
+
+    /// Read the swaps of a deserialized run back from the swap log.  Only the first
+    /// [SlimRun::log_len] bytes of `log` are read.
+    pub fn restore_swaps<R: Read>(&mut self, log: R) -> MarsResult<()> {
+        let mut bytes = Vec::new();
+        log.take(self.log_len).read_to_end(&mut bytes)?;
+        if (bytes.len() as u64) < self.log_len {
+            return Err(MarsError::Parse(format!(
+                "the swap log has {} bytes, but the checkpoint has {}",
+                bytes.len(),
+                self.log_len
+            )));
+        }
+
+        self.swaps = vec![Vec::new(); self.logged.len()];
+        let mut rest = bytes.as_slice();
+        while !rest.is_empty() {
+            let (dim, a, b, s): (usize, Index, Index, Vec<(Swap, f64, f64)>) =
+                rmp_serde::from_read(&mut rest)?;
+            let Some(swaps) = self.swaps.get_mut(dim) else {
+                return Err(MarsError::Parse(format!(
+                    "the swap log has swaps of dimension {}",
+                    dim
+                )));
+            };
+            swaps.push((a, b, s));
+        }
+        for (logged, swaps) in self.logged.iter_mut().zip(&self.swaps) {
+            *logged = swaps.len();
+        }
+        Ok(())
+    }
+
+    /// Run until done, and call `on_checkpoint` every `checkpoint_every` edges, and once at the
+    /// end.  If `on_checkpoint` fails, the run is stopped.
+    pub fn run<F: Fn(usize, usize), C: FnMut(&mut SlimRun) -> MarsResult<()>>(
+        &mut self,
+        sub: &SubMars,
+        progress: F,
+        checkpoint_every: usize,
+        mut on_checkpoint: C,
+    ) -> MarsResult<()> {
+        let Some(ref c) = sub.mars.complex else {
+            return Err(MarsError::Invalid("SlimRun::run: no complex".to_string()));
+        };
+        let f = &sub.mars.filtration;
+        let mesh = mesh_of(sub)?;
+        let num_edges = mesh.num_edges();
+        if self.swaps.len() != self.logged.len() {
+            return Err(MarsError::Invalid(
+                "SlimRun::run: the swaps have not been restored".to_string(),
+            ));
+        }
+
+        while !self.done {
+            let swaps = &mut self.swaps;
+            let on_edge = |edge: SlimEdge| push_slim_swaps(c, f, swaps, edge);
+            self.done = !mesh.step_vineyards_slim(c, f, &mut self.traversal, false, on_edge);
+            if self.done {
+                break;
+            }
+            progress(self.traversal.steps, num_edges);
+            if checkpoint_every > 0 && self.traversal.steps.is_multiple_of(checkpoint_every) {
+                on_checkpoint(self)?;
+            }
+        }
+
+        on_checkpoint(self)
+    }
+
+    /// The swaps emitted so far, mapped back to the coordinate system of the [Mars] instance the
+    /// [SubMars] came from.  Edges without swaps are left out, like in the swap log, so that a
+    /// resumed run gives the same swaps as one that wasn't stopped.
+    pub fn into_swaps(self, sub: &SubMars) -> Vec<SlimSwapList> {
+        let mut swaps_all_dims = self.swaps;
+        for swaps in &mut swaps_all_dims {
+            swaps.retain(|s| !s.2.is_empty());
+            for s in swaps {
+                s.0 += sub.offset;
+                s.1 += sub.offset;
+            }
+        }
+        swaps_all_dims
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{grid::GridRegion, test::test_complex_cube};
+
+    #[test]
+    fn resume_gives_same_swaps() {
+        let complex = test_complex_cube();
+        let grid = VineyardsGridMesh::around_complex(&complex, 2.0, GridRegion::Inside).unwrap();
+        let sub = SubMars {
+            mars: Mars {
+                complex: Some(complex),
+                grid: Some(Grid::Mesh(grid)),
+                ..Default::default()
+            },
+            offset: Index([0; 3]),
+        };
+
+        let expected = sub.run_slim(|_, _| {}).unwrap();
+
+        // Stop after the second checkpoint, and round trip the state through rmp.
+        let mut run = SlimRun::new(&sub).unwrap();
+        let mut log = Vec::new();
+        let mut saved = None;
+        let stopped = run.run(
+            &sub,
+            |_, _| {},
+            5,
+            |r| {
+                r.append_swaps(&mut log)?;
+                saved = Some(rmp_serde::to_vec(r).unwrap());
+                if r.steps() == 10 {
+                    return Err(MarsError::Invalid("stop".to_string()));
+                }
+                Ok(())
+            },
+        );
+        assert!(stopped.is_err());
+        // The state covers everything that was appended to the log.
+        assert!(run.log_len() == log.len() as u64 && !log.is_empty());
+
+        // Records after the checkpoint are ignored.
+        log.extend_from_slice(&[0xc1; 7]);
+        let mut resumed: SlimRun = rmp_serde::from_slice(&saved.unwrap()).unwrap();
+        assert_eq!(resumed.steps(), 10);
+        assert!(resumed.run(&sub, |_, _| {}, 0, |_| Ok(())).is_err());
+        resumed.restore_swaps(log.as_slice()).unwrap();
+        resumed.run(&sub, |_, _| {}, 0, |_| Ok(())).unwrap();
+        assert!(resumed.is_done());
+
+        // Edges without swaps are left out.
+        let got = resumed.into_swaps(&sub);
+        assert_eq!(got.len(), expected.len());
+        for dim in 0..got.len() {
+            let e = expected[dim].iter().filter(|e| !e.2.is_empty());
+            assert_eq!(got[dim].len(), e.clone().count());
+            for (g, e) in got[dim].iter().zip(e) {
+                assert_eq!((g.0, g.1), (e.0, e.1));
+                let g_swaps = g.2.iter().map(|s| (s.0.i, s.0.j, s.1, s.2));
+                let e_swaps = e.2.iter().map(|s| (s.0.i, s.0.j, s.1, s.2));
+                assert!(g_swaps.eq(e_swaps));
+            }
+        }
+    }
+}
@@ -0,0 +1,330 @@
+//! Chunked state files, for slim output that is too large to load in one go.
+//!
+//! The layout of a file is
+//!
+//! ```text
+//! MAGIC  FORMAT_VERSION:u32
+//! len:u64 header            (rmp encoded [ChunkedHeader])
+//! len:u64 record            (rmp encoded [SwapRecord]), repeated
+//! len:u64 index             (rmp encoded Vec<[RecordIndex]>)
+//! index_offset:u64 INDEX_MAGIC
+//! ```
+//!
+//! All integers are little endian.  Every record can be decoded on its own, so the file can be
+//! read sequentially without the index, or randomly by first reading the index from the footer.
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use crate::{
+    error::{MarsError, MarsResult},
+    grid::Index,
+    Mars, PruningParam, SlimSwapList, Swap,
+};
+
+/// The first bytes of a chunked file.
+pub const MAGIC: &[u8; 8] = b"MARSCHNK";
+/// The last bytes of a chunked file.
+const INDEX_MAGIC: &[u8; 8] = b"MARSIDX\0";
+/// Version of the chunked layout.  Bump this when the layout or the records change.
+///
+/// Version 2 added the filtration to the [Mars] in the header.  Version 1 files are read with the
+/// default filtration.
+///
+/// Version 3 added [Swap::t] to the swaps in the records.  Older records are read with the swaps
+/// at the midpoint of their grid edge.
+pub const FORMAT_VERSION: u32 = 3;
+
+/// Everything in the file except the swaps.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ChunkedHeader {
+    /// The complex and grid the swaps were computed for.
+    pub mars: Mars,
+    /// The pruning parameters, if the swaps have been pruned.
+    pub pruning: Option<Vec<PruningParam>>,
+}
+
+/// The swaps of one dimension across one grid edge.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct SwapRecord {
+    pub dim: usize,
+    pub a: Index,
+    pub b: Index,
+    pub swaps: Vec<(Swap, f64, f64)>,
+}
+
+/// Where in the file a [SwapRecord] is.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct RecordIndex {
+    pub dim: usize,
+    pub a: Index,
+    pub b: Index,
+    /// Number of swaps in the record.
+    pub num_swaps: usize,
+    /// Byte offset of the length prefix of the record.
+    pub offset: u64,
+}
+
+/// True if `bytes` starts like a chunked file.
+pub fn is_chunked(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+fn write_chunk<W: Write>(w: &mut W, bytes: &[u8]) -> std::io::Result<u64> {
+    w.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    w.write_all(bytes)?;
+    Ok(8 + bytes.len() as u64)
+}
+
+fn read_u64<R: Read>(r: &mut R) -> MarsResult<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Read a length prefixed chunk from a file of `file_len` bytes.  A length that goes past the end of
+/// the file, as in a truncated or corrupt file, is an error instead of a huge allocation.
+fn read_chunk<R: Read + Seek>(r: &mut R, file_len: u64) -> MarsResult<Vec<u8>> {
+    let len = read_u64(r)?;
+    let left = file_len.saturating_sub(r.stream_position()?);
+    if left < len {
+        return Err(MarsError::Parse(format!(
+            "Chunk of {} bytes, but only {} bytes are left in the file",
+            len, left
+        )));
+    }
+    let mut buf = vec![0; len as usize];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Writes a chunked file one [SwapRecord] at a time.  [ChunkedWriter::finish] must be called to
+/// write out the index.
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+    offset: u64,
+    index: Vec<RecordIndex>,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(mut inner: W, header: &ChunkedHeader) -> MarsResult<Self> {
+        inner.write_all(MAGIC)?;
+        inner.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        let bytes = rmp_serde::to_vec(header)?;
+        let n = write_chunk(&mut inner, &bytes)?;
+        Ok(Self {
+            inner,
+            offset: MAGIC.len() as u64 + 4 + n,
+            index: Vec::new(),
+        })
+    }
+
+    /// Write the swaps across one grid edge.  Empty swap lists are skipped.
+    pub fn write_record(
+        &mut self,
+        dim: usize,
+        a: Index,
+        b: Index,
+        swaps: Vec<(Swap, f64, f64)>,
+    ) -> MarsResult<()> {
+        if swaps.is_empty() {
+            return Ok(());
+        }
+        let record = SwapRecord { dim, a, b, swaps };
+        let bytes = rmp_serde::to_vec(&record)?;
+        self.index.push(RecordIndex {
+            dim,
+            a,
+            b,
+            num_swaps: record.swaps.len(),
+            offset: self.offset,
+        });
+        self.offset += write_chunk(&mut self.inner, &bytes)?;
+        Ok(())
+    }
+
+    /// Write all swaps for all dimensions.
+    pub fn write_all(&mut self, swaps: Vec<SlimSwapList>) -> MarsResult<()> {
+        for (dim, dim_swaps) in swaps.into_iter().enumerate() {
+            for (a, b, s) in dim_swaps {
+                self.write_record(dim, a, b, s)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the index and the footer, and return the inner writer.
+    pub fn finish(mut self) -> MarsResult<W> {
+        let index_offset = self.offset;
+        let bytes = rmp_serde::to_vec(&self.index)?;
+        write_chunk(&mut self.inner, &bytes)?;
+        self.inner.write_all(&index_offset.to_le_bytes())?;
+        self.inner.write_all(INDEX_MAGIC)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Reads a chunked file.  The header and index are read up front, and the records are read when
+/// asked for.
+pub struct ChunkedReader<R: Read + Seek> {
+    inner: R,
+    /// Length of the file in bytes.
+    len: u64,
+    header: ChunkedHeader,
+    index: Vec<RecordIndex>,
+}
+
+impl<R: Read + Seek> ChunkedReader<R> {
+    pub fn open(mut inner: R) -> MarsResult<Self> {
+        let len = inner.seek(SeekFrom::End(0))?;
+        inner.seek(SeekFrom::Start(0))?;
+        let mut magic = [0; 8];
+        inner.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(MarsError::Parse("Not a chunked state file".to_string()));
+        }
+        let mut version = [0; 4];
+        inner.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if !(1..=FORMAT_VERSION).contains(&version) {
+            return Err(MarsError::Parse(format!(
+                "Unsupported chunked format version {} (expected at most {})",
+                version, FORMAT_VERSION
+            )));
+        }
+        let header: ChunkedHeader = rmp_serde::from_slice(&read_chunk(&mut inner, len)?)?;
+
+        inner.seek(SeekFrom::End(-16))?;
+        let index_offset = read_u64(&mut inner)?;
+        inner.read_exact(&mut magic)?;
+        if &magic != INDEX_MAGIC {
+            return Err(MarsError::Parse(
+                "Chunked state file is missing its index; was it truncated?".to_string(),
+            ));
+        }
+        inner.seek(SeekFrom::Start(index_offset))?;
+        let index: Vec<RecordIndex> = rmp_serde::from_slice(&read_chunk(&mut inner, len)?)?;
+
+        Ok(Self {
+            inner,
+            len,
+            header,
+            index,
+        })
+    }
+
+    pub fn header(&self) -> &ChunkedHeader {
+        &self.header
+    }
+
+    pub fn into_header(self) -> ChunkedHeader {
+        self.header
+    }
+
+    /// Number of dimensions that there are swaps for.  This is at least 3, since files written
+    /// before tetrahedra were supported don't record the dimension of the complex.
+    pub fn num_dims(&self) -> usize {
+        let complex_dims = self.header.mars.complex.as_ref().map(|c| c.dim() + 1);
+        let record_dims = self.index.iter().map(|e| e.dim + 1).max();
+        complex_dims
+            .unwrap_or(0)
+            .max(record_dims.unwrap_or(0))
+            .max(3)
+    }
+
+    /// All records in the file, in the order they were written.
+    pub fn index(&self) -> &[RecordIndex] {
+        &self.index
+    }
+
+    /// Read the record for one entry of the index.
+    pub fn read_record(&mut self, entry: &RecordIndex) -> MarsResult<SwapRecord> {
+        self.inner.seek(SeekFrom::Start(entry.offset))?;
+        rmp_serde::from_slice(&read_chunk(&mut self.inner, self.len)?).map_err(MarsError::from)
+    }
+
+    /// Call `f` on every record of the given dimension, one at a time.
+    pub fn for_each_record<F: FnMut(SwapRecord) -> MarsResult<()>>(
+        &mut self,
+        dim: usize,
+        mut f: F,
+    ) -> MarsResult<()> {
+        let entries = self
+            .index
+            .iter()
+            .filter(|e| e.dim == dim)
+            .cloned()
+            .collect::<Vec<_>>();
+        for entry in &entries {
+            f(self.read_record(entry)?)?;
+        }
+        Ok(())
+    }
+
+    /// Read all swaps into memory.  There is one list per dimension of the complex in the header.
+    pub fn read_all(&mut self) -> MarsResult<Vec<SlimSwapList>> {
+        let num_dims = self.num_dims();
+        let mut ret = vec![Vec::new(); num_dims];
+        for (dim, list) in ret.iter_mut().enumerate() {
+            self.for_each_record(dim, |r| {
+                list.push((r.a, r.b, r.swaps));
+                Ok(())
+            })?;
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let header = ChunkedHeader {
+            mars: Mars::default(),
+            pruning: None,
+        };
+        let swap = |dim, i, j| (Swap { dim, i, j, t: 0.5 }, 0.5, 1.5);
+        let swaps = vec![
+            vec![(Index::fake(0), Index::fake(1), vec![swap(0, 1, 2)])],
+            vec![
+                (Index::fake(1), Index::fake(2), vec![]),
+                (
+                    Index::fake(2),
+                    Index::fake(3),
+                    vec![swap(1, 3, 4), swap(1, 5, 6)],
+                ),
+            ],
+            vec![],
+        ];
+
+        let mut writer = ChunkedWriter::new(Vec::new(), &header).unwrap();
+        writer.write_all(swaps).unwrap();
+        let bytes = writer.finish().unwrap();
+        assert!(is_chunked(&bytes));
+
+        let mut reader = ChunkedReader::open(std::io::Cursor::new(&bytes)).unwrap();
+        assert_eq!(reader.index().len(), 2);
+
+        let entry = reader.index()[1].clone();
+        let record = reader.read_record(&entry).unwrap();
+        assert_eq!(
+            (record.dim, record.a, record.b),
+            (1, Index::fake(2), Index::fake(3))
+        );
+        assert_eq!(record.swaps.len(), 2);
+
+        let all = reader.read_all().unwrap();
+        assert_eq!(all.iter().map(|v| v.len()).collect::<Vec<_>>(), [1, 1, 0]);
+
+        // A corrupt length prefix is a parse error, and doesn't allocate the claimed length.
+        let mut corrupt = bytes.clone();
+        let offset = entry.offset as usize;
+        corrupt[offset..offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        let mut reader = ChunkedReader::open(std::io::Cursor::new(&corrupt)).unwrap();
+        assert!(matches!(
+            reader.read_record(&entry),
+            Err(MarsError::Parse(_))
+        ));
+    }
+}
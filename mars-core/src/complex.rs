@@ -1,7 +1,17 @@
 use std::collections::{HashMap, HashSet};
 
-use crate::{sneaky_matrix::CI, SneakyMatrix};
+use crate::{
+    error::{MarsError, MarsResult},
+    filtration::Filtration,
+    sneaky_matrix::CI,
+    spatial::{find_close_pair, merge_close_points},
+    SneakyMatrix,
+};
 use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Input vertices that are closer than this are rejected as duplicates, unless they are merged.
+pub const MIN_VERTEX_DISTANCE: f64 = 1e-5;
 
 #[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct Pos(pub [f64; 3]);
@@ -30,6 +40,22 @@ impl Pos {
     pub fn dist(&self, other: &Pos) -> f64 {
         self.dist2(other).sqrt()
     }
+
+    pub fn dot(&self, other: &Pos) -> f64 {
+        let mut sum = 0.0;
+        for i in 0..3 {
+            sum += self.0[i] * other.0[i];
+        }
+        sum
+    }
+
+    pub fn cross(&self, other: &Pos) -> Pos {
+        Pos([
+            self.y() * other.z() - self.z() * other.y(),
+            self.z() * other.x() - self.x() * other.z(),
+            self.x() * other.y() - self.y() * other.x(),
+        ])
+    }
 }
 
 impl std::fmt::Debug for Pos {
@@ -108,22 +134,21 @@ impl Simplex {
     /// - point: itself
     /// - edge: midpoint of the edge
     /// - face: centroid of the face
+    /// - tetrahedron: centroid of the tetrahedron
+    ///
+    /// Each vertex is in equally many faces of the simplex, so the average of the center points of
+    /// the faces is the centroid.
     pub fn center_point(&self, complex: &Complex) -> Pos {
-        match self.dim() {
-            0 => self.coords.unwrap(),
-            1 => {
-                let a = &complex.simplices_per_dim[0][self.boundary[0] as usize];
-                let b = &complex.simplices_per_dim[0][self.boundary[1] as usize];
-                (a.coords.unwrap() + b.coords.unwrap()) * 0.5
-            }
-            2 => {
-                let a = &complex.simplices_per_dim[1][self.boundary[0] as usize];
-                let b = &complex.simplices_per_dim[1][self.boundary[1] as usize];
-                let c = &complex.simplices_per_dim[1][self.boundary[2] as usize];
-                (a.center_point(complex) + b.center_point(complex) + c.center_point(complex)) / 3.0
-            }
-            _ => panic!("Missing arms for simplex of dimension {}", self.dim()),
+        let dim = self.dim();
+        if dim == 0 {
+            return self.coords.unwrap();
         }
+        let sum = self
+            .boundary
+            .iter()
+            .map(|&b| complex.simplices_per_dim[dim as usize - 1][b as usize].center_point(complex))
+            .fold(Pos([0.0; 3]), |a, b| a + b);
+        sum / self.boundary.len() as f64
     }
 }
 
@@ -133,6 +158,21 @@ pub struct Complex {
 }
 
 impl Complex {
+    /// The largest dimension that the complex has room for, which is 2 for surfaces and 3 for
+    /// tetrahedral meshes.  There may be no simplices of this dimension.
+    pub fn dim(&self) -> usize {
+        self.simplices_per_dim.len() - 1
+    }
+
+    /// If all vertices lie in a plane `z = c`, return `c`.
+    pub fn planar_z(&self) -> Option<f64> {
+        let mut zs = self.simplices_per_dim[0]
+            .iter()
+            .filter_map(|s| s.coords.map(|p| p.z()));
+        let z = zs.next()?;
+        zs.all(|w| (w - z).abs() < 1e-9).then_some(z)
+    }
+
     pub fn num_simplices_of_dim(&self, dim: isize) -> usize {
         if dim == -1 {
             return 1;
@@ -187,144 +227,283 @@ impl Complex {
         tris
     }
 
-    pub fn read_from_obj_path<P: AsRef<std::path::Path>>(p: P) -> Result<Self, String> {
-        let input_str =
-            std::fs::read_to_string(p).map_err(|e| format!("Error reading file: {}", e))?;
+    /// Return the vertex positions of each triangle, in the order of [Complex::triangle_indices].
+    pub fn triangle_positions(&self) -> Vec<[Pos; 3]> {
+        self.triangle_indices()
+            .into_iter()
+            .map(|t| t.map(|v| self.simplices_per_dim[0][v as usize].coords.unwrap()))
+            .collect()
+    }
+
+    /// The average over all triangles of the length of its longest edge.
+    ///
+    /// Returns `0.0` if there are no triangles.
+    pub fn average_longest_edge(&self) -> f64 {
+        let tris = self.triangle_positions();
+        if tris.is_empty() {
+            return 0.0;
+        }
+        let sum = tris
+            .iter()
+            .map(|[a, b, c]| a.dist(b).max(b.dist(c)).max(c.dist(a)))
+            .sum::<f64>();
+        sum / tris.len() as f64
+    }
+
+    pub fn read_from_obj_path<P: AsRef<std::path::Path>>(p: P) -> MarsResult<Self> {
+        let input_str = std::fs::read_to_string(p)
+            .map_err(|e| MarsError::Io(format!("Error reading file: {}", e)))?;
         Self::read_from_obj_string(&input_str)
     }
 
-    pub fn read_from_obj_string(input_str: &str) -> Result<Self, String> {
+    /// Read a complex from an .obj file.
+    ///
+    /// Vertices (`v`), polylines (`l`) and faces (`f`) are read, and all other statements, like
+    /// normals, texture coordinates, groups and materials, are ignored.  Indices can be written as
+    /// `v`, `v/vt`, `v//vn` or `v/vt/vn`, and negative indices count from the last vertex read so
+    /// far.  Polygons are split into triangles around their first vertex.
+    pub fn read_from_obj_string(input_str: &str) -> MarsResult<Self> {
+        Self::read_from_obj_string_merging(input_str, None)
+    }
+
+    /// Like [Complex::read_from_obj_string], but if `merge_distance` is given, vertices that are
+    /// closer than it are merged instead of rejected.  Edges and faces that collapse are dropped.
+    pub fn read_from_obj_string_merging(
+        input_str: &str,
+        merge_distance: Option<f64>,
+    ) -> MarsResult<Self> {
         let mut vertices: Vec<Simplex> = Vec::new();
         let mut edges: Vec<Simplex> = Vec::new();
         let mut triangles: Vec<Simplex> = Vec::new();
+        // The line number of each edge and triangle, for error messages.
+        let mut edge_lines = Vec::new();
+        let mut triangle_lines = Vec::new();
 
         // Map (i, j) to edge simplex index.
         let mut edge_map = HashMap::<(CI, CI), CI>::new();
 
-        for line in input_str.lines() {
-            let line = line.trim();
-            if line.starts_with("#")
-                || line.starts_with("mtllib")
-                || line.starts_with("o")
-                || line.starts_with("s")
-            {
-                continue;
-            } else if line.starts_with("v") {
-                // v -0.039375 1.021144 0.000000
-                let groups = line.split_ascii_whitespace().collect::<Vec<_>>();
-                let x = groups
-                    .get(1)
-                    .ok_or("missing field".to_string())
-                    .and_then(|n| n.parse::<f64>().map_err(|e| e.to_string()))?;
-                let y = groups
-                    .get(2)
-                    .ok_or("missing field".to_string())
-                    .and_then(|n| n.parse::<f64>().map_err(|e| e.to_string()))?;
-                let z = groups
-                    .get(3)
-                    .ok_or("missing field".to_string())
-                    .and_then(|n| n.parse::<f64>().map_err(|e| e.to_string()))?;
-                let coords = Pos([x, y, z]);
-                assert!(
-                    vertices.len() <= CI::MAX as usize,
-                    "Too many vertices in input complex (got {}, max {})",
-                    vertices.len(),
-                    CI::MAX
-                );
-                vertices.push(Simplex {
-                    id: vertices.len() as CI,
-                    coords: Some(coords),
-                    boundary: vec![0],
-                });
-            } else if line.starts_with("l") {
-                // l 1 2
-                let groups = line.split_ascii_whitespace().collect::<Vec<_>>();
-                if groups.len() != 3 {
-                    return Err("An edge should have two vertices".into());
+        // Join lines that end with a backslash with the next line.
+        let mut statements: Vec<(usize, String)> = Vec::new();
+        let mut continued = false;
+        for (line_i, line) in input_str.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let (line, continues) = match line.trim_end().strip_suffix('\\') {
+                Some(l) => (l, true),
+                None => (line, false),
+            };
+            match statements.last_mut() {
+                Some((_, s)) if continued => {
+                    s.push(' ');
+                    s.push_str(line);
                 }
-                let a = groups
-                    .get(1)
-                    .ok_or("missing field".to_string())
-                    .and_then(|n| n.parse::<CI>().map_err(|e| e.to_string()))?
-                    - 1; // NOTE: .obj is 1-indexed
-
-                let b = groups
-                    .get(2)
-                    .ok_or("missing field".to_string())
-                    .and_then(|n| n.parse::<CI>().map_err(|e| e.to_string()))?
-                    - 1; // NOTE: .obj is 1-indexed
-
-                assert!(
-                    edges.len() <= CI::MAX as usize,
-                    "Too many edges in input complex (got {}, max {})",
-                    edges.len(),
-                    CI::MAX
-                );
-                let id = edges.len() as CI;
-
-                let (a, b) = (a.min(b), a.max(b));
+                _ => statements.push((line_i + 1, line.to_string())),
+            }
+            continued = continues;
+        }
 
-                if edge_map.contains_key(&(a, b)) {
-                    panic!("Duplicate edge entries in the .obj");
+        for (line_no, line) in &statements {
+            let err = |msg: String| MarsError::Parse(format!("line {}: {}", line_no, msg));
+            let mut fields = line.split_ascii_whitespace();
+            let Some(keyword) = fields.next() else {
+                continue;
+            };
+            // Parse a vertex reference `v`, `v/vt`, `v//vn` or `v/vt/vn` into a 0-based index.
+            let num_vertices = vertices.len();
+            let index = |field: &str| -> MarsResult<CI> {
+                let v = field.split('/').next().unwrap_or("");
+                let i = v
+                    .parse::<i64>()
+                    .map_err(|_| err(format!("invalid vertex index {:?}", field)))?;
+                // NOTE: .obj is 1-indexed, and negative indices are relative to the end.
+                let i = match i {
+                    0 => return Err(err("vertex index 0 is not allowed".into())),
+                    i if i < 0 => num_vertices as i64 + i,
+                    i => i - 1,
+                };
+                if i < 0 || CI::MAX as i64 <= i {
+                    return Err(err(format!("vertex index {} is out of range", field)));
                 }
+                Ok(i as CI)
+            };
 
-                edge_map.insert((a, b), id);
-
-                edges.push(Simplex {
-                    id,
-                    coords: None,
-                    boundary: vec![a, b],
-                });
-            } else if line.starts_with("f") {
-                // f 20 27 19
-                let groups = line.split_ascii_whitespace().collect::<Vec<_>>();
+            match keyword {
+                "v" => {
+                    // v -0.039375 1.021144 0.000000
+                    let coords = fields
+                        .take(3)
+                        .map(|n| {
+                            n.parse::<f64>()
+                                .map_err(|_| err(format!("invalid coordinate {:?}", n)))
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    if coords.len() != 3 {
+                        return Err(err("a vertex should have three coordinates".into()));
+                    }
+                    if !coords.iter().all(|c| c.is_finite()) {
+                        return Err(err(format!("non-finite vertex {:?}", coords)));
+                    }
+                    if CI::MAX as usize <= vertices.len() {
+                        return Err(MarsError::Capacity(format!(
+                            "line {}: too many vertices in input complex (max {})",
+                            line_no,
+                            CI::MAX
+                        )));
+                    }
+                    vertices.push(Simplex {
+                        id: vertices.len() as CI,
+                        coords: Some(Pos([coords[0], coords[1], coords[2]])),
+                        boundary: vec![0],
+                    });
+                }
+                "l" => {
+                    // l 1 2, or a polyline l 1 2 3 4
+                    let line_vertices = fields.map(index).collect::<Result<Vec<_>, _>>()?;
+                    if line_vertices.len() < 2 {
+                        return Err(err("an edge should have two vertices".into()));
+                    }
 
-                if groups.len() != 4 {
-                    return Err("A triangle should have three vertices".into());
+                    for w in line_vertices.windows(2) {
+                        if CI::MAX as usize <= edges.len() {
+                            return Err(MarsError::Capacity(format!(
+                                "line {}: too many edges in input complex (max {})",
+                                line_no,
+                                CI::MAX
+                            )));
+                        }
+                        let id = edges.len() as CI;
+                        let (a, b) = (w[0].min(w[1]), w[0].max(w[1]));
+                        if a == b {
+                            return Err(err(format!("edge from vertex {} to itself", a + 1)));
+                        }
+                        if edge_map.contains_key(&(a, b)) {
+                            return Err(err(format!("duplicate edge {} {}", a + 1, b + 1)));
+                        }
+                        edge_map.insert((a, b), id);
+                        edges.push(Simplex {
+                            id,
+                            coords: None,
+                            boundary: vec![a, b],
+                        });
+                        edge_lines.push(*line_no);
+                    }
                 }
-                let a = groups
-                    .get(1)
-                    .ok_or("missing field".to_string())
-                    .and_then(|n| n.parse::<CI>().map_err(|e| e.to_string()))?
-                    - 1;
-                let b = groups
-                    .get(2)
-                    .ok_or("missing field".to_string())
-                    .and_then(|n| n.parse::<CI>().map_err(|e| e.to_string()))?
-                    - 1;
-                let c = groups
-                    .get(3)
-                    .ok_or("missing field".to_string())
-                    .and_then(|n| n.parse::<CI>().map_err(|e| e.to_string()))?
-                    - 1;
-
-                assert!(
-                    triangles.len() <= CI::MAX as usize,
-                    "Too many triangles in input complex (got {}, max {})",
-                    triangles.len(),
-                    CI::MAX
-                );
-                triangles.push(Simplex {
-                    id: triangles.len() as CI,
-                    coords: None,
-                    boundary: vec![a, b, c], // NOTE: we insert vertex indices here, and fix them up later.
-                });
+                "f" => {
+                    // f 20 27 19, f 1/1/1 2/2/2 3/3/3 4/4/4, or f -3 -2 -1
+                    let polygon = fields.map(index).collect::<Result<Vec<_>, _>>()?;
+                    if polygon.len() < 3 {
+                        return Err(err("a face should have at least three vertices".into()));
+                    }
+                    let mut sorted = polygon.clone();
+                    sorted.sort();
+                    sorted.dedup();
+                    if sorted.len() != polygon.len() {
+                        return Err(err("a face has a repeated vertex".into()));
+                    }
+                    for k in 1..polygon.len() - 1 {
+                        if CI::MAX as usize <= triangles.len() {
+                            return Err(MarsError::Capacity(format!(
+                                "line {}: too many triangles in input complex (max {})",
+                                line_no,
+                                CI::MAX
+                            )));
+                        }
+                        triangles.push(Simplex {
+                            id: triangles.len() as CI,
+                            coords: None,
+                            // NOTE: we insert vertex indices here, and fix them up later.
+                            boundary: vec![polygon[0], polygon[k], polygon[k + 1]],
+                        });
+                        triangle_lines.push(*line_no);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Indices of polylines and faces may point to vertices later in the file.
+        let num_vertices = vertices.len() as CI;
+        for (e, line_no) in edges.iter().zip(&edge_lines) {
+            if e.boundary[1] >= num_vertices {
+                return Err(MarsError::Parse(format!(
+                    "line {}: edge has a missing vertex (have {} vertices)",
+                    line_no, num_vertices
+                )));
+            }
+        }
+        for (t, line_no) in triangles.iter().zip(&triangle_lines) {
+            if t.boundary.iter().any(|&v| v >= num_vertices) {
+                return Err(MarsError::Parse(format!(
+                    "line {}: face has a missing vertex (have {} vertices)",
+                    line_no, num_vertices
+                )));
             }
         }
 
         // Check that no two vertices are actually the same vertex
-        for i in 0..vertices.len() {
-            for j in (i + 1)..vertices.len() {
-                let p = vertices[i].coords.ok_or("Vertex should have coordinates")?;
-                let q = vertices[j].coords.ok_or("Vertex should have coordinates")?;
-                let dist = p.dist(&q);
-                if dist < 1e-5 {
-                    return Err(format!(
-                        "Two vertices are too close together: {} and {}",
-                        i, j
-                    ));
+        let points = vertices
+            .iter()
+            .map(|v| {
+                v.coords
+                    .ok_or_else(|| MarsError::Internal("Vertex should have coordinates".into()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(distance) = merge_distance {
+            let (kept, new_index) = merge_close_points(&points, distance);
+            if kept.len() < points.len() {
+                warn!(
+                    "Merged {} vertices that were closer than {}",
+                    points.len() - kept.len(),
+                    distance
+                );
+            }
+            vertices = simplices_of_points(kept);
+            let remap = |v: CI| new_index[v as usize] as CI;
+
+            // Edges that collapse to a point are dropped, and edges that end up between the same
+            // vertices are merged.
+            edge_map.clear();
+            for e in std::mem::take(&mut edges) {
+                let (a, b) = (remap(e.boundary[0]), remap(e.boundary[1]));
+                let (a, b) = (a.min(b), a.max(b));
+                if a != b && !edge_map.contains_key(&(a, b)) {
+                    edge_map.insert((a, b), edges.len() as CI);
+                    edges.push(Simplex {
+                        id: edges.len() as CI,
+                        coords: None,
+                        boundary: vec![a, b],
+                    });
+                }
+            }
+            // Triangles that collapse are dropped, but their remaining edge is kept.  Triangles
+            // that end up on the same vertices are merged, like in [Complex::from_cells_merging].
+            let mut triangle_set = HashSet::new();
+            for t in std::mem::take(&mut triangles) {
+                let bnd = t.boundary.iter().map(|&v| remap(v)).collect::<Vec<_>>();
+                let mut sorted = bnd.clone();
+                sorted.sort();
+                sorted.dedup();
+                match sorted[..] {
+                    [a, b, c] if !triangle_set.insert([a, b, c]) => {}
+                    [_, _, _] => {
+                        triangles.push(Simplex {
+                            id: triangles.len() as CI,
+                            coords: None,
+                            boundary: bnd,
+                        });
+                    }
+                    [a, b] if !edge_map.contains_key(&(a, b)) => {
+                        edge_map.insert((a, b), edges.len() as CI);
+                        edges.push(Simplex {
+                            id: edges.len() as CI,
+                            coords: None,
+                            boundary: vec![a, b],
+                        });
+                    }
+                    _ => {}
                 }
             }
+        } else {
+            check_distinct_vertices(&points)?;
         }
 
         // Replace vertex indices with the correct edge indices.
@@ -350,12 +529,359 @@ impl Complex {
                 tri.boundary[i] = id;
             }
         }
+        if CI::MAX as usize <= edges.len() {
+            return Err(MarsError::Capacity(format!(
+                "Too many edges in input complex (got {}, max {})",
+                edges.len(),
+                CI::MAX
+            )));
+        }
 
         Ok(Self {
             simplices_per_dim: vec![vertices, edges, triangles],
         })
     }
 
+    /// Read a complex from a file in any of the formats in [crate::formats::Format].  The format
+    /// is detected from the contents of the file.
+    pub fn read_from_path<P: AsRef<std::path::Path>>(p: P) -> MarsResult<Self> {
+        Self::read_from_path_merging(p, None)
+    }
+
+    /// Like [Complex::read_from_path], but if `merge_distance` is given, vertices that are closer
+    /// than it are merged instead of rejected.
+    pub fn read_from_path_merging<P: AsRef<std::path::Path>>(
+        p: P,
+        merge_distance: Option<f64>,
+    ) -> MarsResult<Self> {
+        let bytes =
+            std::fs::read(p).map_err(|e| MarsError::Io(format!("Error reading file: {}", e)))?;
+        Self::read_from_bytes_merging(&bytes, merge_distance)
+    }
+
+    /// Build a complex from vertex positions and cells.  Each cell is a list of 2, 3 or 4 vertex
+    /// indices for an edge, a triangle or a tetrahedron.  All faces of the cells are added, and
+    /// shared faces are only added once.
+    ///
+    /// The complex has room for tetrahedra only if there are any.
+    pub fn from_cells(points: Vec<Pos>, cells: &[Vec<CI>]) -> MarsResult<Self> {
+        Self::from_cells_merging(points, cells, None)
+    }
+
+    /// Like [Complex::from_cells], but if `merge_distance` is given, vertices that are closer
+    /// than it are merged instead of rejected.  Cells that lose vertices this way become the
+    /// lower dimensional cell on the remaining vertices.
+    pub fn from_cells_merging(
+        points: Vec<Pos>,
+        cells: &[Vec<CI>],
+        merge_distance: Option<f64>,
+    ) -> MarsResult<Self> {
+        if CI::MAX as usize <= points.len() {
+            return Err(MarsError::Capacity(format!(
+                "Too many vertices in input complex (got {}, max {})",
+                points.len(),
+                CI::MAX
+            )));
+        }
+        if let Some(i) = points
+            .iter()
+            .position(|p| !p.0.iter().all(|c| c.is_finite()))
+        {
+            return Err(MarsError::Parse(format!(
+                "Vertex {} has non-finite coordinates: {:?}",
+                i, points[i].0
+            )));
+        }
+        let (points, new_index) = match merge_distance {
+            Some(distance) => {
+                let (kept, new_index) = merge_close_points(&points, distance);
+                if kept.len() < points.len() {
+                    warn!(
+                        "Merged {} vertices that were closer than {}",
+                        points.len() - kept.len(),
+                        distance
+                    );
+                }
+                (kept, Some(new_index))
+            }
+            None => {
+                check_distinct_vertices(&points)?;
+                (points, None)
+            }
+        };
+
+        let top_dim = cells.iter().map(|c| c.len()).max().unwrap_or(0).max(3) - 1;
+        let mut simplices_per_dim = vec![Vec::new(); top_dim + 1];
+        simplices_per_dim[0] = simplices_of_points(points);
+
+        // Map the sorted vertices of a simplex to its index, per dimension.
+        let mut simplex_map: Vec<HashMap<Vec<CI>, CI>> = vec![HashMap::new(); top_dim + 1];
+
+        fn insert(
+            vertices: Vec<CI>,
+            simplices_per_dim: &mut [Vec<Simplex>],
+            simplex_map: &mut [HashMap<Vec<CI>, CI>],
+        ) -> MarsResult<CI> {
+            let dim = vertices.len() - 1;
+            if let Some(&id) = simplex_map[dim].get(&vertices) {
+                return Ok(id);
+            }
+            let boundary = if dim == 1 {
+                vertices.clone()
+            } else {
+                (0..vertices.len())
+                    .map(|skip| {
+                        let mut face = vertices.clone();
+                        face.remove(skip);
+                        insert(face, simplices_per_dim, simplex_map)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+            };
+            let id = simplices_per_dim[dim].len();
+            if CI::MAX as usize <= id {
+                return Err(MarsError::Capacity(format!(
+                    "Too many simplices of dim {} in input complex (max {})",
+                    dim,
+                    CI::MAX
+                )));
+            }
+            simplices_per_dim[dim].push(Simplex {
+                id: id as CI,
+                coords: None,
+                boundary,
+            });
+            simplex_map[dim].insert(vertices, id as CI);
+            Ok(id as CI)
+        }
+
+        let num_vertices = simplices_per_dim[0].len();
+        for cell in cells {
+            let mut vertices = cell.clone();
+            vertices.sort();
+            vertices.dedup();
+            if vertices.len() != cell.len() || !(2..=4).contains(&cell.len()) {
+                return Err(MarsError::Invalid(format!("Invalid cell {:?}", cell)));
+            }
+            if let Some(new_index) = &new_index {
+                if let Some(v) = vertices
+                    .iter()
+                    .find(|&&v| v < 0 || new_index.len() <= v as usize)
+                {
+                    return Err(MarsError::Invalid(format!(
+                        "Cell {:?} has a missing vertex {}",
+                        cell, v
+                    )));
+                }
+                vertices = vertices
+                    .iter()
+                    .map(|&v| new_index[v as usize] as CI)
+                    .collect();
+                vertices.sort();
+                vertices.dedup();
+                if vertices.len() < 2 {
+                    continue;
+                }
+            }
+            if let Some(v) = vertices
+                .iter()
+                .find(|&&v| v < 0 || num_vertices <= v as usize)
+            {
+                return Err(MarsError::Invalid(format!(
+                    "Cell {:?} has a missing vertex {}",
+                    cell, v
+                )));
+            }
+            insert(vertices, &mut simplices_per_dim, &mut simplex_map)?;
+        }
+
+        Ok(Self { simplices_per_dim })
+    }
+
+    /// Read a Medit `.mesh` file.  `Vertices`, `Edges`, `Triangles` and `Tetrahedra` are read,
+    /// and all other sections are skipped.
+    pub fn read_from_mesh_string(input_str: &str) -> MarsResult<Self> {
+        let (points, cells) = Self::mesh_cells(input_str)?;
+        Self::from_cells(points, &cells)
+    }
+
+    pub(crate) fn mesh_cells(input_str: &str) -> MarsResult<(Vec<Pos>, Vec<Vec<CI>>)> {
+        let mut tokens = input_str
+            .lines()
+            .map(|l| l.split('#').next().unwrap_or(""))
+            .flat_map(|l| l.split_ascii_whitespace());
+
+        fn next_num<'a, T: std::str::FromStr, I: Iterator<Item = &'a str>>(
+            tokens: &mut I,
+            what: &str,
+        ) -> MarsResult<T> {
+            let t = tokens.next().ok_or_else(|| {
+                MarsError::Parse(format!("Unexpected end of file while reading {}", what))
+            })?;
+            t.parse::<T>().map_err(|_| {
+                MarsError::Parse(format!("Expected a number for {}, got {:?}", what, t))
+            })
+        }
+
+        let mut points = Vec::new();
+        let mut cells = Vec::new();
+        while let Some(keyword) = tokens.next() {
+            match keyword {
+                "MeshVersionFormatted" | "Dimension" => {
+                    let n: usize = next_num(&mut tokens, keyword)?;
+                    if keyword == "Dimension" && n != 3 {
+                        return Err(MarsError::Parse(format!(
+                            "Only 3D .mesh files are supported, got {}",
+                            n
+                        )));
+                    }
+                }
+                "Vertices" => {
+                    let n: usize = next_num(&mut tokens, "number of vertices")?;
+                    for _ in 0..n {
+                        let x = next_num(&mut tokens, "vertex")?;
+                        let y = next_num(&mut tokens, "vertex")?;
+                        let z = next_num(&mut tokens, "vertex")?;
+                        let _reference: i64 = next_num(&mut tokens, "vertex reference")?;
+                        points.push(Pos([x, y, z]));
+                    }
+                }
+                "Edges" | "Triangles" | "Tetrahedra" => {
+                    let num_vertices = match keyword {
+                        "Edges" => 2,
+                        "Triangles" => 3,
+                        _ => 4,
+                    };
+                    let n: usize = next_num(&mut tokens, keyword)?;
+                    for _ in 0..n {
+                        let cell = (0..num_vertices)
+                            .map(|_| next_num::<CI, _>(&mut tokens, keyword).map(|v| v - 1)) // NOTE: .mesh is 1-indexed
+                            .collect::<Result<Vec<_>, _>>()?;
+                        let _reference: i64 = next_num(&mut tokens, "reference")?;
+                        cells.push(cell);
+                    }
+                }
+                "End" => break,
+                _ => {
+                    return Err(MarsError::Parse(format!(
+                        "Unsupported section {:?} in .mesh file",
+                        keyword
+                    )))
+                }
+            }
+        }
+
+        Ok((points, cells))
+    }
+
+    /// Read a legacy ASCII `.vtk` file with an unstructured grid.  Lines, triangles and
+    /// tetrahedra are read, and other cell types are skipped.
+    pub fn read_from_vtk_string(input_str: &str) -> MarsResult<Self> {
+        let (points, cells) = Self::vtk_cells(input_str)?;
+        Self::from_cells(points, &cells)
+    }
+
+    pub(crate) fn vtk_cells(input_str: &str) -> MarsResult<(Vec<Pos>, Vec<Vec<CI>>)> {
+        let mut lines = input_str
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty());
+        let mut tokens = Vec::new();
+        // The first two lines are the version and a free-form title.
+        if !lines
+            .next()
+            .map(|l| l.starts_with("# vtk DataFile"))
+            .unwrap_or(false)
+        {
+            return Err(MarsError::Parse("Not a legacy .vtk file".to_string()));
+        }
+        lines.next();
+        for line in lines {
+            tokens.extend(line.split_ascii_whitespace());
+        }
+        let mut tokens = tokens.into_iter();
+
+        let expect = |t: Option<&str>, what: &str| -> MarsResult<()> {
+            match t {
+                Some(t) if t.eq_ignore_ascii_case(what) => Ok(()),
+                t => Err(MarsError::Parse(format!(
+                    "Expected {} in .vtk file, got {:?}",
+                    what, t
+                ))),
+            }
+        };
+        let next_num = |t: Option<&str>| -> MarsResult<f64> {
+            t.ok_or(MarsError::Parse("Unexpected end of .vtk file".to_string()))?
+                .parse::<f64>()
+                .map_err(|e| MarsError::Parse(e.to_string()))
+        };
+
+        expect(tokens.next(), "ASCII")?;
+        expect(tokens.next(), "DATASET")?;
+        expect(tokens.next(), "UNSTRUCTURED_GRID")?;
+
+        let mut points = Vec::new();
+        let mut cells: Vec<Vec<CI>> = Vec::new();
+        let mut cell_types = Vec::new();
+        while let Some(keyword) = tokens.next() {
+            match keyword.to_ascii_uppercase().as_str() {
+                "POINTS" => {
+                    let n = next_num(tokens.next())? as usize;
+                    let _data_type = tokens.next();
+                    for _ in 0..n {
+                        let x = next_num(tokens.next())?;
+                        let y = next_num(tokens.next())?;
+                        let z = next_num(tokens.next())?;
+                        points.push(Pos([x, y, z]));
+                    }
+                }
+                "CELLS" => {
+                    let n = next_num(tokens.next())? as usize;
+                    let _size = tokens.next();
+                    for _ in 0..n {
+                        let k = next_num(tokens.next())? as usize;
+                        let cell = (0..k)
+                            .map(|_| next_num(tokens.next()).map(|v| v as CI))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        cells.push(cell);
+                    }
+                }
+                "CELL_TYPES" => {
+                    let n = next_num(tokens.next())? as usize;
+                    for _ in 0..n {
+                        cell_types.push(next_num(tokens.next())? as u8);
+                    }
+                }
+                // Point and cell data come after the geometry, and we don't need them.
+                "POINT_DATA" | "CELL_DATA" => break,
+                _ => {
+                    return Err(MarsError::Parse(format!(
+                        "Unsupported section {:?} in .vtk file",
+                        keyword
+                    )))
+                }
+            }
+        }
+
+        if cell_types.len() != cells.len() {
+            return Err(MarsError::Parse(format!(
+                "The .vtk file has {} cells but {} cell types",
+                cells.len(),
+                cell_types.len()
+            )));
+        }
+        // VTK_LINE = 3, VTK_TRIANGLE = 5, VTK_TETRA = 10.
+        let cells = cells
+            .into_iter()
+            .zip(cell_types)
+            .filter(|(_, t)| matches!(t, 3 | 5 | 10))
+            .map(|(c, _)| c)
+            .collect::<Vec<_>>();
+
+        Ok((points, cells))
+    }
+
+    /// Write the vertices and triangles of the complex, and the edges that aren't in any
+    /// triangle, as an .obj file.
     pub fn write_as_obj<W: std::io::Write>(&self, mut w: W) -> std::io::Result<()> {
         writeln!(w, "o complex")?;
 
@@ -364,7 +890,27 @@ impl Complex {
             writeln!(w, "v {} {} {}", c.x(), c.y(), c.z())?;
         }
 
+        let mut in_triangle = vec![false; self.simplices_per_dim[1].len()];
         for s in &self.simplices_per_dim[2] {
+            for &e in &s.boundary {
+                in_triangle[e as usize] = true;
+            }
+        }
+        for (e, _) in in_triangle.iter().enumerate().filter(|(_, &t)| !t) {
+            let [a, b] = [0, 1].map(|k| self.simplices_per_dim[1][e].boundary[k]);
+            writeln!(w, "l {} {}", a + 1, b + 1)?;
+        }
+
+        for [a, b, c] in self.triangles() {
+            writeln!(w, "f {} {} {}", a + 1, b + 1, c + 1)?;
+        }
+
+        Ok(())
+    }
+
+    /// The sorted vertex indices of every triangle.
+    pub fn triangles(&self) -> impl Iterator<Item = [CI; 3]> + '_ {
+        self.simplices_per_dim[2].iter().map(|s| {
             let mut vx = Vec::new();
             for ei in &s.boundary {
                 vx.extend_from_slice(&self.simplices_per_dim[1][*ei as usize].boundary);
@@ -372,44 +918,42 @@ impl Complex {
             vx.sort();
             vx.dedup();
             assert_eq!(vx.len(), 3, "a face should have =3 simplex indices");
-            writeln!(w, "f {} {} {}", vx[0] + 1, vx[1] + 1, vx[2] + 1)?;
-        }
-
-        Ok(())
+            [vx[0], vx[1], vx[2]]
+        })
     }
 
-    /// Compute the distances from a given point to all vertices, edges, and faces.
-    ///
-    /// The distance to a point is the squared euclidean distance.
+    /// Compute the filtration values of all simplices for a given key point, one list per
+    /// dimension.  For the distance filtrations these are the distances to the key point.
     ///
-    /// The distance to a higher simplex is the largest of the distances to its faces.
-    pub fn distances_to(&self, key_point: Pos) -> (Vec<f64>, Vec<f64>, Vec<f64>) {
-        let vertex_distances = self.simplices_per_dim[0]
-            .iter()
-            .map(|v| v.coords.unwrap().dist2(&key_point))
-            .collect::<Vec<_>>();
-
-        let edge_distances = self.simplices_per_dim[1]
-            .iter()
-            .map(|e| {
-                vertex_distances[e.boundary[0] as usize]
-                    .max(vertex_distances[e.boundary[1] as usize])
-            })
-            .collect::<Vec<_>>();
-
-        let triangle_distances = self.simplices_per_dim[2]
-            .iter()
-            .map(|f| {
-                edge_distances[f.boundary[0] as usize]
-                    .max(edge_distances[f.boundary[1] as usize])
-                    .max(edge_distances[f.boundary[2] as usize])
-            })
-            .collect::<Vec<_>>();
+    /// The value of a higher simplex is the largest of the values of its faces.
+    pub fn distances_to(&self, filtration: &dyn Filtration, key_point: Pos) -> Vec<Vec<f64>> {
+        filtration.values(self, key_point)
+    }
+}
 
-        (vertex_distances, edge_distances, triangle_distances)
+/// Check that no two vertices are actually the same vertex.
+fn check_distinct_vertices(points: &[Pos]) -> MarsResult<()> {
+    match find_close_pair(points, MIN_VERTEX_DISTANCE) {
+        Some((i, j)) => Err(MarsError::Invalid(format!(
+            "Two vertices are too close together: {} and {}",
+            i, j
+        ))),
+        None => Ok(()),
     }
 }
 
+fn simplices_of_points(points: Vec<Pos>) -> Vec<Simplex> {
+    points
+        .into_iter()
+        .enumerate()
+        .map(|(i, p)| Simplex {
+            id: i as CI,
+            coords: Some(p),
+            boundary: vec![0],
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -434,6 +978,113 @@ mod tests {
         }
     }
 
+    #[test]
+    fn read_obj_polygons() {
+        // A quad written with v/vt/vn references, and a triangle with negative indices.
+        let obj = "o thing\nv 0 0 0\nv 1 0 0 1.0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvn 0 0 1\ng group\nusemtl m\n\
+                   f 1/1/1 2/1/1 3//1 4/1\nv 0 0 1 # apex\nf -5 -4 \\\n -1\n";
+        let c = Complex::read_from_obj_string(obj).unwrap();
+        let counts = (0..3)
+            .map(|d| c.num_simplices_of_dim(d))
+            .collect::<Vec<_>>();
+        assert_eq!(counts, [5, 7, 3]);
+        let apex = &c.simplices_per_dim[2][2];
+        let verts = apex
+            .boundary
+            .iter()
+            .flat_map(|&e| c.simplices_per_dim[1][e as usize].boundary.clone())
+            .collect::<HashSet<_>>();
+        assert_eq!(verts, HashSet::from([0, 1, 4]));
+    }
+
+    #[test]
+    fn read_obj_errors() {
+        let square = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+        for (body, line) in [
+            ("l 1 2\nl 2 1\n", "line 6:"),
+            ("f 1 2 2\n", "line 5:"),
+            ("f 1 2\n", "line 5:"),
+            ("f 1 2 0\n", "line 5:"),
+            ("f 1 2 -5\n", "line 5:"),
+            ("f 1 2 9\n", "line 5:"),
+            ("v 0 0\n", "line 5:"),
+        ] {
+            let err = Complex::read_from_obj_string(&format!("{}{}", square, body)).unwrap_err();
+            assert_eq!(err.kind(), "parse");
+            assert!(err.message().starts_with(line), "{:?}: {}", body, err);
+        }
+        for v in ["v inf 0 0\n", "v 0 nan 0\n"] {
+            let err = Complex::read_from_obj_string(&format!("{}{}", square, v)).unwrap_err();
+            assert!(err.message().starts_with("line 5:"), "{:?}: {}", v, err);
+        }
+        let err = Complex::from_cells(vec![Pos([f64::NAN; 3])], &[]).unwrap_err();
+        assert_eq!(err.kind(), "parse");
+    }
+
+    #[test]
+    fn merge_close_vertices() {
+        // Two triangles that share an edge, but with the shared vertices repeated, and a sliver
+        // triangle that collapses into the shared edge.
+        let obj =
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 0 1e-7\nv 0 1 0\nv 1 1 0\nf 1 2 3\nf 4 6 5\nf 2 3 5\n";
+        assert!(Complex::read_from_obj_string(obj).is_err());
+        let c = Complex::read_from_obj_string_merging(obj, Some(MIN_VERTEX_DISTANCE)).unwrap();
+        let counts = (0..3)
+            .map(|d| c.num_simplices_of_dim(d))
+            .collect::<Vec<_>>();
+        assert_eq!(counts, [4, 5, 2]);
+
+        let points = c.simplices_per_dim[0]
+            .iter()
+            .map(|v| v.coords.unwrap())
+            .chain([Pos([1.0, 1.0, 1e-6])])
+            .collect::<Vec<_>>();
+        let cells = vec![vec![0, 1, 2], vec![1, 3, 2], vec![2, 4, 1, 3], vec![3, 4]];
+        assert!(Complex::from_cells(points.clone(), &cells).is_err());
+        let c = Complex::from_cells_merging(points, &cells, Some(MIN_VERTEX_DISTANCE)).unwrap();
+        let counts = (0..3)
+            .map(|d| c.num_simplices_of_dim(d))
+            .collect::<Vec<_>>();
+        assert_eq!(counts, [4, 5, 2]);
+
+        // The same triangle twice, once on copies of its vertices, becomes a single triangle.
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 1e-7 0\nv 1 0 1e-7\nv 0 1 0\nf 1 2 3\nf 5 6 4\n";
+        let c = Complex::read_from_obj_string_merging(obj, Some(MIN_VERTEX_DISTANCE)).unwrap();
+        let counts = (0..3)
+            .map(|d| c.num_simplices_of_dim(d))
+            .collect::<Vec<_>>();
+        assert_eq!(counts, [3, 3, 1]);
+        assert_eq!(c.simplices_per_dim[2][0].id, 0);
+    }
+
+    #[test]
+    fn read_tetrahedra() {
+        let mesh = "MeshVersionFormatted 1\nDimension 3\nVertices\n4\n0 0 0 1\n1 0 0 1\n0 1 0 1\n0 0 1 1\nTetrahedra\n1\n1 2 3 4 0\nEnd\n";
+        let vtk = "# vtk DataFile Version 2.0\ntet\nASCII\nDATASET UNSTRUCTURED_GRID\nPOINTS 4 float\n0 0 0\n1 0 0\n0 1 0\n0 0 1\nCELLS 1 5\n4 0 1 2 3\nCELL_TYPES 1\n10\n";
+        for c in [
+            Complex::read_from_mesh_string(mesh).unwrap(),
+            Complex::read_from_vtk_string(vtk).unwrap(),
+        ] {
+            assert_eq!(c.dim(), 3);
+            let counts = (0..4)
+                .map(|d| c.num_simplices_of_dim(d))
+                .collect::<Vec<_>>();
+            assert_eq!(counts, [4, 6, 4, 1]);
+            let center = c.simplices_per_dim[3][0].center_point(&c);
+            assert!(center.dist(&Pos([0.25; 3])) < 1e-12);
+        }
+    }
+
+    #[test]
+    fn tet_cube_shares_faces() {
+        let c = crate::test::test_complex_tet_cube();
+        // Euler characteristic of a ball.
+        let counts = (0..4)
+            .map(|d| c.num_simplices_of_dim(d))
+            .collect::<Vec<_>>();
+        assert_eq!(counts, [8, 19, 18, 6]);
+    }
+
     #[test]
     fn boundary_matrix() {
         let complex = crate::test::test_complex_cube();
@@ -0,0 +1,114 @@
+//! The error type of mars-core.
+use serde::Serialize;
+
+/// Everything that can go wrong in mars-core.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum MarsError {
+    /// Reading or writing a file failed.
+    Io(String),
+    /// The input isn't in the expected format, e.g. a malformed line in an .obj file or a
+    /// truncated state file.
+    Parse(String),
+    /// The input is well formed, but can't be used, e.g. vertices that are too close together, a
+    /// missing complex or grid, or out of range parameters.
+    Invalid(String),
+    /// The input has more simplices than fit in a [crate::sneaky_matrix::CI].
+    Capacity(String),
+    /// An invariant of the algorithm doesn't hold.  This is a bug in mars-core.
+    Internal(String),
+}
+
+impl MarsError {
+    /// A short name for the kind of error, like `"parse"`, for error objects in other languages.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MarsError::Io(_) => "io",
+            MarsError::Parse(_) => "parse",
+            MarsError::Invalid(_) => "invalid",
+            MarsError::Capacity(_) => "capacity",
+            MarsError::Internal(_) => "internal",
+        }
+    }
+
+    /// The message of the error, without the kind.
+    pub fn message(&self) -> &str {
+        match self {
+            MarsError::Io(m)
+            | MarsError::Parse(m)
+            | MarsError::Invalid(m)
+            | MarsError::Capacity(m)
+            | MarsError::Internal(m) => m,
+        }
+    }
+
+    /// Prefix the message with some context, like the path of the file that was read.
+    pub fn context(self, context: impl std::fmt::Display) -> Self {
+        let wrap = |m: String| format!("{}: {}", context, m);
+        match self {
+            MarsError::Io(m) => MarsError::Io(wrap(m)),
+            MarsError::Parse(m) => MarsError::Parse(wrap(m)),
+            MarsError::Invalid(m) => MarsError::Invalid(wrap(m)),
+            MarsError::Capacity(m) => MarsError::Capacity(wrap(m)),
+            MarsError::Internal(m) => MarsError::Internal(wrap(m)),
+        }
+    }
+}
+
+impl std::fmt::Display for MarsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MarsError::Io(m) => write!(f, "I/O error: {}", m),
+            MarsError::Parse(m) => write!(f, "Parse error: {}", m),
+            MarsError::Invalid(m) => write!(f, "Invalid input: {}", m),
+            MarsError::Capacity(m) => write!(f, "Too large: {}", m),
+            MarsError::Internal(m) => write!(f, "Internal error: {}", m),
+        }
+    }
+}
+
+impl std::error::Error for MarsError {}
+
+impl From<std::io::Error> for MarsError {
+    fn from(e: std::io::Error) -> Self {
+        MarsError::Io(e.to_string())
+    }
+}
+
+impl From<rmp_serde::encode::Error> for MarsError {
+    fn from(e: rmp_serde::encode::Error) -> Self {
+        MarsError::Io(format!("rmp_serde failed: {}", e))
+    }
+}
+
+impl From<rmp_serde::decode::Error> for MarsError {
+    fn from(e: rmp_serde::decode::Error) -> Self {
+        MarsError::Parse(format!("rmp_serde failed: {}", e))
+    }
+}
+
+/// Shorthand for results with a [MarsError].
+pub type MarsResult<T> = Result<T, MarsError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kinds_and_messages() {
+        let e = MarsError::Parse("line 3: expected 3 coordinates".to_string()).context("a.obj");
+        assert_eq!(e.kind(), "parse");
+        assert_eq!(e.message(), "a.obj: line 3: expected 3 coordinates");
+        assert_eq!(
+            e.to_string(),
+            "Parse error: a.obj: line 3: expected 3 coordinates"
+        );
+        assert_eq!(
+            serde_json::to_value(&e).unwrap(),
+            serde_json::json!({"kind": "parse", "message": "a.obj: line 3: expected 3 coordinates"})
+        );
+
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+        assert_eq!(MarsError::from(io).kind(), "io");
+    }
+}
@@ -0,0 +1,316 @@
+//! Local feature size and thickness on the input complex.
+//!
+//! The local feature size at a point of a shape is its distance to the medial axis.  We estimate it
+//! for every vertex of the complex as the distance to the nearest face of an [AxisMesh].  The
+//! thickness at a vertex is the diameter of the medial ball of that face, which for thin parts is
+//! the distance to the other side.
+use serde::Serialize;
+
+use crate::{
+    axis_mesh::AxisMesh,
+    complex::{Complex, Pos},
+    spatial::PointIndex,
+};
+
+/// Color of vertices without a value, in the outputs with colors.
+const UNKNOWN_COLOR: [u8; 3] = [128, 128, 128];
+
+/// Per-vertex local feature size and thickness, from the medial axis of one dimension.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct FeatureSize {
+    /// Dimension of the medial axis.
+    pub dim: usize,
+    /// Distance from each vertex of the complex to the nearest face of the axis.  `None` if the
+    /// axis is empty.
+    pub distance: Vec<Option<f64>>,
+    /// Twice the radius of the nearest face of the axis, for each vertex.  `None` if that face has
+    /// no radius.
+    pub thickness: Vec<Option<f64>>,
+}
+
+impl FeatureSize {
+    /// Find the nearest face of `axis` for all vertices of `complex`.  For the thickness,
+    /// [AxisMesh::compute_radii] has to be called first.
+    pub fn new(complex: &Complex, axis: &AxisMesh) -> Self {
+        let quads = axis
+            .faces
+            .iter()
+            .map(|f| f.vertices.map(|v| axis.points[v]))
+            .collect::<Vec<_>>();
+        let centers = quads
+            .iter()
+            .map(|q| q.iter().fold(Pos([0.0; 3]), |s, &p| s + p) / 4.0)
+            .collect::<Vec<_>>();
+        // No point of a quad is further than this from its center.
+        let max_extent = quads
+            .iter()
+            .zip(&centers)
+            .flat_map(|(q, c)| q.iter().map(|p| p.dist(c)))
+            .fold(0.0, f64::max);
+        let index = PointIndex::new(&centers, 2.0 * max_extent);
+
+        let mut size = Self {
+            dim: axis.dim,
+            ..Default::default()
+        };
+        for s in &complex.simplices_per_dim[0] {
+            let nearest = s.coords.and_then(|p| {
+                let (_, d) = index.nearest(p)?;
+                // The nearest quad is at most `d` away, so its center is within `d + max_extent`.
+                index
+                    .within(p, d + max_extent)
+                    .map(|f| (f, quad_distance(&quads[f], p)))
+                    .min_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)))
+            });
+            size.distance.push(nearest.map(|(_, d)| d));
+            size.thickness
+                .push(nearest.and_then(|(f, _)| axis.faces[f].radius.map(|r| 2.0 * r)));
+        }
+        size
+    }
+
+    /// The smallest and largest distance, or `None` if no vertex has one.
+    pub fn range(&self) -> Option<(f64, f64)> {
+        self.distance.iter().flatten().fold(None, |r, &d| match r {
+            None => Some((d, d)),
+            Some((lo, hi)) => Some((d.min(lo), d.max(hi))),
+        })
+    }
+
+    /// A color for each vertex, from red at the smallest distance to blue at the largest.
+    pub fn colors(&self) -> Vec<[u8; 3]> {
+        let (lo, hi) = self.range().unwrap_or((0.0, 0.0));
+        self.distance
+            .iter()
+            .map(|d| match d {
+                Some(d) if lo < hi => color_ramp((d - lo) / (hi - lo)),
+                Some(_) => color_ramp(0.0),
+                None => UNKNOWN_COLOR,
+            })
+            .collect()
+    }
+}
+
+/// Red at `0`, through green at `0.5`, to blue at `1`.
+fn color_ramp(t: f64) -> [u8; 3] {
+    let t = t.clamp(0.0, 1.0);
+    let (r, g, b) = if t < 0.5 {
+        (1.0 - 2.0 * t, 2.0 * t, 0.0)
+    } else {
+        (0.0, 2.0 - 2.0 * t, 2.0 * t - 1.0)
+    };
+    [r, g, b].map(|c| (255.0 * c).round() as u8)
+}
+
+/// Distance from `p` to the quad, split into two triangles along its first diagonal.
+fn quad_distance(q: &[Pos; 4], p: Pos) -> f64 {
+    let a = closest_on_triangle(p, q[0], q[1], q[2]).dist(&p);
+    let b = closest_on_triangle(p, q[0], q[2], q[3]).dist(&p);
+    a.min(b)
+}
+
+/// The point of the triangle `abc` closest to `p`, by the regions of the triangle's Voronoi
+/// diagram, as in Ericson's Real-Time Collision Detection.
+fn closest_on_triangle(p: Pos, a: Pos, b: Pos, c: Pos) -> Pos {
+    let (ab, ac, ap) = (b - a, c - a, p - a);
+    let (d1, d2) = (ab.dot(&ap), ac.dot(&ap));
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+    let bp = p - b;
+    let (d3, d4) = (ab.dot(&bp), ac.dot(&bp));
+    if 0.0 <= d3 && d4 <= d3 {
+        return b;
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && 0.0 <= d1 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+    let cp = p - c;
+    let (d5, d6) = (ab.dot(&cp), ac.dot(&cp));
+    if 0.0 <= d6 && d5 <= d6 {
+        return c;
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && 0.0 <= d2 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && 0.0 <= d4 - d3 && 0.0 <= d5 - d6 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+    let denom = va + vb + vc;
+    if denom == 0.0 {
+        // Degenerate triangle; all the edges were handled above, so this is a single point.
+        return a;
+    }
+    a + ab * (vb / denom) + ac * (vc / denom)
+}
+
+/// Write the complex as an ASCII .ply file with the feature sizes as vertex properties
+/// `distance_<dim>` and `thickness_<dim>` (`-1` if unknown), and the colors of the first of
+/// `sizes` as `red`, `green` and `blue`.
+pub fn write_feature_size_as_ply<W: std::io::Write>(
+    mut w: W,
+    complex: &Complex,
+    sizes: &[FeatureSize],
+) -> std::io::Result<()> {
+    let triangles = complex.triangles().collect::<Vec<_>>();
+    let colors = sizes.first().map(|s| s.colors());
+    writeln!(w, "ply")?;
+    writeln!(w, "format ascii 1.0")?;
+    writeln!(w, "comment local feature size from mars")?;
+    writeln!(w, "element vertex {}", complex.simplices_per_dim[0].len())?;
+    writeln!(w, "property double x")?;
+    writeln!(w, "property double y")?;
+    writeln!(w, "property double z")?;
+    for s in sizes {
+        writeln!(w, "property double distance_{}", s.dim)?;
+        writeln!(w, "property double thickness_{}", s.dim)?;
+    }
+    if colors.is_some() {
+        writeln!(w, "property uchar red")?;
+        writeln!(w, "property uchar green")?;
+        writeln!(w, "property uchar blue")?;
+    }
+    writeln!(w, "element face {}", triangles.len())?;
+    writeln!(w, "property list uchar int vertex_indices")?;
+    writeln!(w, "end_header")?;
+    for (v, s) in complex.simplices_per_dim[0].iter().enumerate() {
+        let p = s.coords.unwrap_or(Pos([0.0; 3]));
+        write!(w, "{} {} {}", p.x(), p.y(), p.z())?;
+        for size in sizes {
+            let d = size.distance[v].unwrap_or(-1.0);
+            let t = size.thickness[v].unwrap_or(-1.0);
+            write!(w, " {} {}", d, t)?;
+        }
+        if let Some(ref colors) = colors {
+            let [r, g, b] = colors[v];
+            write!(w, " {} {} {}", r, g, b)?;
+        }
+        writeln!(w)?;
+    }
+    for [a, b, c] in triangles {
+        writeln!(w, "3 {} {} {}", a, b, c)?;
+    }
+    Ok(())
+}
+
+/// Write the triangles of the complex as an .obj file with vertex colors from `size`, as
+/// `v x y z r g b` with the colors in `[0, 1]`, which most viewers read.
+pub fn write_feature_size_as_obj<W: std::io::Write>(
+    mut w: W,
+    complex: &Complex,
+    size: &FeatureSize,
+) -> std::io::Result<()> {
+    writeln!(w, "o lfs-dim-{}", size.dim)?;
+    for (s, [r, g, b]) in complex.simplices_per_dim[0].iter().zip(size.colors()) {
+        let p = s.coords.unwrap_or(Pos([0.0; 3]));
+        let [r, g, b] = [r, g, b].map(|c| c as f64 / 255.0);
+        writeln!(w, "v {} {} {} {} {} {}", p.x(), p.y(), p.z(), r, g, b)?;
+    }
+    for [a, b, c] in complex.triangles() {
+        writeln!(w, "f {} {} {}", a + 1, b + 1, c + 1)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        axis_mesh::AxisFace,
+        grid::{Index, VineyardsGrid},
+        test::test_complex_tet_cube,
+        Grid, Mars, SlimSwapList,
+    };
+
+    #[test]
+    fn closest_points_on_a_triangle() {
+        let [a, b, c] = [Pos([0.0; 3]), Pos([1.0, 0.0, 0.0]), Pos([0.0, 1.0, 0.0])];
+        let cases = [
+            (Pos([0.25, 0.25, 2.0]), 2.0),
+            (Pos([-1.0, -1.0, 0.0]), 2f64.sqrt()),
+            (Pos([0.5, -1.0, 0.0]), 1.0),
+            (Pos([1.0, 1.0, 0.0]), 0.5f64.sqrt()),
+            (Pos([3.0, 0.0, 0.0]), 2.0),
+        ];
+        for (p, d) in cases {
+            assert!((closest_on_triangle(p, a, b, c).dist(&p) - d).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn distance_to_a_sheet() {
+        // The cube is [-0.5, 0.5]^3, and the sheet is the square x = 0.2 across it.
+        let complex = test_complex_tet_cube();
+        let axis = AxisMesh {
+            dim: 0,
+            points: vec![
+                Pos([0.2, -1.0, -1.0]),
+                Pos([0.2, 1.0, -1.0]),
+                Pos([0.2, 1.0, 1.0]),
+                Pos([0.2, -1.0, 1.0]),
+            ],
+            faces: vec![AxisFace {
+                vertices: [0, 1, 2, 3],
+                edge: (Index([0, 0, 0]), Index([1, 0, 0])),
+                num_swaps: 1,
+                max_persistence: None,
+                pair: (0, 1),
+                crossing: Pos([0.2, 0.0, 0.0]),
+                radius: Some(0.3),
+            }],
+        };
+        let size = FeatureSize::new(&complex, &axis);
+        for (s, d) in complex.simplices_per_dim[0].iter().zip(&size.distance) {
+            let x = s.coords.unwrap().x();
+            assert!((d.unwrap() - (x - 0.2).abs()).abs() < 1e-12);
+        }
+        assert!(size.thickness.iter().all(|&t| t == Some(0.6)));
+        assert_eq!(size.range(), Some((0.3, 0.7)));
+        let colors = size.colors();
+        assert!(colors.contains(&[255, 0, 0]) && colors.contains(&[0, 0, 255]));
+
+        let empty = FeatureSize::new(&complex, &AxisMesh::default());
+        assert!(empty.distance.iter().all(Option::is_none));
+        assert!(empty.colors().iter().all(|&c| c == UNKNOWN_COLOR));
+    }
+
+    #[test]
+    fn feature_size_from_full_and_slim_runs() {
+        // This is what the wasm `feature_size` and `feature_size_colors` do, for both vineyards and
+        // a slim state that went through a serialization roundtrip.
+        let mars = Mars {
+            complex: Some(test_complex_tet_cube()),
+            grid: Some(Grid::Regular(VineyardsGrid::new(
+                Pos([-0.75; 3]),
+                0.5,
+                [4, 4, 4],
+            ))),
+            ..Default::default()
+        };
+        let (c, g, f) = (
+            mars.complex.as_ref().unwrap(),
+            mars.grid.as_ref().unwrap(),
+            &mars.filtration,
+        );
+        let vineyards = mars.run(|_, _| {}).unwrap();
+        let slim = mars.run_slim(|_, _| {}).unwrap();
+        let slim: Vec<SlimSwapList> =
+            rmp_serde::from_slice(&rmp_serde::to_vec(&slim).unwrap()).unwrap();
+
+        for dim in 0..3 {
+            let mut full =
+                AxisMesh::from_swaps(g, dim, &vineyards.swaps[dim], Some((c, f, &vineyards)));
+            let mut slim = AxisMesh::from_slim_swaps(g, dim, &slim[dim]);
+            full.compute_radii(c, f);
+            slim.compute_radii(c, f);
+            let (full, slim) = (FeatureSize::new(c, &full), FeatureSize::new(c, &slim));
+            assert_eq!(full.distance, slim.distance);
+            assert_eq!(full.colors(), slim.colors());
+            assert_eq!(full.colors().len(), c.simplices_per_dim[0].len());
+            assert!(full.range().is_some());
+        }
+    }
+}
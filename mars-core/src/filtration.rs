@@ -0,0 +1,307 @@
+//! Filtrations of a [Complex] around a key point.
+//!
+//! A [Filtration] gives every vertex a value for each key point, and a higher simplex enters the
+//! filtration when the last of its vertices does.  The vineyards, persistence, barcodes and
+//! pruning all use the filtration of the [crate::Mars] instance, which is squared Euclidean
+//! distance unless set otherwise.
+use crate::{
+    complex::{Complex, Pos},
+    error::{MarsError, MarsResult},
+    sneaky_matrix::CI,
+    spatial::PointIndex,
+};
+use std::sync::OnceLock;
+
+pub trait Filtration {
+    /// The value at which the vertex `v` enters the filtration at `key_point`.
+    fn vertex_value(&self, complex: &Complex, v: CI, key_point: Pos) -> f64;
+
+    /// The values of all vertices at `key_point`.
+    fn vertex_values(&self, complex: &Complex, key_point: Pos) -> Vec<f64> {
+        (0..complex.simplices_per_dim[0].len() as CI)
+            .map(|v| self.vertex_value(complex, v, key_point))
+            .collect()
+    }
+
+    /// The values of all simplices at `key_point`, one list per dimension.
+    fn values(&self, complex: &Complex, key_point: Pos) -> Vec<Vec<f64>> {
+        let mut values = vec![self.vertex_values(complex, key_point)];
+        for dim in 1..complex.simplices_per_dim.len() {
+            let faces = &values[dim - 1];
+            let dim_values = complex.simplices_per_dim[dim]
+                .iter()
+                .map(|s| {
+                    s.boundary
+                        .iter()
+                        .map(|&b| faces[b as usize])
+                        .fold(f64::NEG_INFINITY, f64::max)
+                })
+                .collect();
+            values.push(dim_values);
+        }
+        values
+    }
+
+    /// The value at which a single simplex enters the filtration at `key_point`.
+    ///
+    /// The `id` is a canonical index.
+    fn simplex_value(&self, complex: &Complex, dim: usize, id: CI, key_point: Pos) -> f64 {
+        if dim == 0 {
+            return self.vertex_value(complex, id, key_point);
+        }
+        complex.simplices_per_dim[dim][id as usize]
+            .boundary
+            .iter()
+            .map(|&b| self.simplex_value(complex, dim - 1, b, key_point))
+            .max_by(|a, b| a.total_cmp(b))
+            .unwrap()
+    }
+
+    /// The distance from the key point at which a vertex has the filtration value `value`, which
+    /// is the radius of the ball around the key point that the sublevel set is cut from.  `None`
+    /// if the sublevel sets aren't balls.
+    fn radius(&self, _value: f64) -> Option<f64> {
+        None
+    }
+}
+
+fn vertex_pos(complex: &Complex, v: CI) -> Pos {
+    complex.simplices_per_dim[0][v as usize]
+        .coords
+        .expect("Vertices should have coords")
+}
+
+/// Squared Euclidean distance to the key point.
+#[derive(Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct SquaredEuclidean;
+
+impl Filtration for SquaredEuclidean {
+    fn vertex_value(&self, complex: &Complex, v: CI, key_point: Pos) -> f64 {
+        vertex_pos(complex, v).dist2(&key_point)
+    }
+
+    fn radius(&self, value: f64) -> Option<f64> {
+        Some(value.max(0.0).sqrt())
+    }
+}
+
+/// Euclidean distance to the key point.  This gives the same vineyards as [SquaredEuclidean], but
+/// the persistence is measured in distance instead of squared distance.
+#[derive(Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct Euclidean;
+
+impl Filtration for Euclidean {
+    fn vertex_value(&self, complex: &Complex, v: CI, key_point: Pos) -> f64 {
+        vertex_pos(complex, v).dist2(&key_point).sqrt()
+    }
+
+    fn radius(&self, value: f64) -> Option<f64> {
+        Some(value)
+    }
+}
+
+/// Squared distance to the key point where each axis is scaled by a weight, so that
+/// the distance is `wx * dx^2 + wy * dy^2 + wz * dz^2`.
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Anisotropic {
+    pub weights: [f64; 3],
+}
+
+impl Filtration for Anisotropic {
+    fn vertex_value(&self, complex: &Complex, v: CI, key_point: Pos) -> f64 {
+        let p = vertex_pos(complex, v);
+        (0..3)
+            .map(|i| self.weights[i] * (p.0[i] - key_point.0[i]).powi(2))
+            .sum()
+    }
+}
+
+/// An arbitrary scalar field on the vertices that changes along the path of the key point.
+///
+/// `values[k][v]` is the value of vertex `v` when the key point is `key_points[k]`.  For any other
+/// key point the values of the closest sample are used, so the samples should include all grid
+/// points.  A field that does not change along the path has a single sample.
+///
+/// The closest sample is looked up in an index of the key points, which is built on the first
+/// lookup, so `key_points` shouldn't change after that.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ScalarField {
+    pub key_points: Vec<Pos>,
+    pub values: Vec<Vec<f64>>,
+    #[serde(skip)]
+    index: OnceLock<PointIndex<'static>>,
+}
+
+impl ScalarField {
+    pub fn new(key_points: Vec<Pos>, values: Vec<Vec<f64>>) -> Self {
+        Self {
+            key_points,
+            values,
+            index: OnceLock::new(),
+        }
+    }
+
+    /// Check that there is at least one sample, and one value per vertex in every sample.
+    pub fn validate(&self, complex: &Complex) -> MarsResult<()> {
+        if self.key_points.is_empty() {
+            return Err(MarsError::Invalid(
+                "Scalar field has no samples".to_string(),
+            ));
+        }
+        if self.key_points.len() != self.values.len() {
+            return Err(MarsError::Invalid(format!(
+                "Scalar field has {} key points but {} lists of values",
+                self.key_points.len(),
+                self.values.len()
+            )));
+        }
+        let num_vertices = complex.simplices_per_dim[0].len();
+        if let Some((k, v)) = self
+            .values
+            .iter()
+            .enumerate()
+            .find(|(_, v)| v.len() != num_vertices)
+        {
+            return Err(MarsError::Invalid(format!(
+                "Scalar field sample {} has {} values, but the complex has {} vertices",
+                k,
+                v.len(),
+                num_vertices
+            )));
+        }
+        Ok(())
+    }
+
+    /// The values of the sample closest to `key_point`.
+    fn sample(&self, key_point: Pos) -> &[f64] {
+        let index = self.index.get_or_init(|| {
+            // About one key point per bucket, if they are spread out in a box.
+            let (mut lo, mut hi) = ([f64::MAX; 3], [f64::MIN; 3]);
+            for p in self
+                .key_points
+                .iter()
+                .filter(|p| p.0.iter().all(|c| c.is_finite()))
+            {
+                for j in 0..3 {
+                    lo[j] = lo[j].min(p.0[j]);
+                    hi[j] = hi[j].max(p.0[j]);
+                }
+            }
+            let extent = (0..3).map(|j| hi[j] - lo[j]).fold(0.0, f64::max);
+            let cell_size = extent / (self.key_points.len() as f64).cbrt();
+            PointIndex::owned(self.key_points.clone(), cell_size)
+        });
+        let (k, _) = index
+            .nearest(key_point)
+            .expect("Scalar field should have samples");
+        &self.values[k]
+    }
+}
+
+impl Filtration for ScalarField {
+    fn vertex_value(&self, _complex: &Complex, v: CI, key_point: Pos) -> f64 {
+        self.sample(key_point)[v as usize]
+    }
+
+    fn vertex_values(&self, _complex: &Complex, key_point: Pos) -> Vec<f64> {
+        self.sample(key_point).to_vec()
+    }
+}
+
+/// One of the filtrations in this module.  This is what is stored in a [crate::Mars] instance.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub enum FiltrationKind {
+    #[default]
+    SquaredEuclidean,
+    Euclidean,
+    Anisotropic(Anisotropic),
+    ScalarField(ScalarField),
+}
+
+impl FiltrationKind {
+    fn inner(&self) -> &dyn Filtration {
+        match self {
+            FiltrationKind::SquaredEuclidean => &SquaredEuclidean,
+            FiltrationKind::Euclidean => &Euclidean,
+            FiltrationKind::Anisotropic(f) => f,
+            FiltrationKind::ScalarField(f) => f,
+        }
+    }
+}
+
+impl Filtration for FiltrationKind {
+    fn vertex_value(&self, complex: &Complex, v: CI, key_point: Pos) -> f64 {
+        self.inner().vertex_value(complex, v, key_point)
+    }
+
+    fn vertex_values(&self, complex: &Complex, key_point: Pos) -> Vec<f64> {
+        self.inner().vertex_values(complex, key_point)
+    }
+
+    fn radius(&self, value: f64) -> Option<f64> {
+        self.inner().radius(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::test_complex_cube;
+
+    #[test]
+    fn euclidean_orders_like_squared() {
+        let complex = test_complex_cube();
+        let p = Pos([0.1, 0.2, 0.3]);
+        let squared = SquaredEuclidean.values(&complex, p);
+        let euclidean = Euclidean.values(&complex, p);
+        for (a, b) in squared.iter().zip(&euclidean) {
+            for (x, y) in a.iter().zip(b) {
+                assert!((x.sqrt() - y).abs() < 1e-12);
+                assert_eq!(SquaredEuclidean.radius(*x), Euclidean.radius(*y));
+            }
+        }
+    }
+
+    #[test]
+    fn simplex_value_is_max_of_vertices() {
+        let complex = test_complex_cube();
+        let f = Anisotropic {
+            weights: [1.0, 0.0, 2.0],
+        };
+        let p = Pos([0.5, -0.5, 0.0]);
+        let values = f.values(&complex, p);
+        for (dim, values) in values.iter().enumerate().skip(1) {
+            for (id, value) in values.iter().enumerate() {
+                assert_eq!(*value, f.simplex_value(&complex, dim, id as CI, p));
+            }
+        }
+    }
+
+    #[test]
+    fn scalar_field_uses_closest_sample() {
+        let complex = test_complex_cube();
+        let n = complex.simplices_per_dim[0].len();
+        let field = ScalarField::new(
+            vec![Pos([0.0; 3]), Pos([1.0, 0.0, 0.0])],
+            vec![vec![0.0; n], (0..n).map(|v| v as f64).collect()],
+        );
+        field.validate(&complex).unwrap();
+        assert_eq!(field.vertex_value(&complex, 1, Pos([0.1, 0.0, 0.0])), 0.0);
+        assert_eq!(field.vertex_value(&complex, 1, Pos([0.9, 0.0, 0.0])), 1.0);
+        // A NaN key point gets some sample instead of a panic.
+        field.vertex_value(&complex, 1, Pos([f64::NAN, 0.0, 0.0]));
+
+        // Samples at the points of a grid.
+        let key_points = (0..1000)
+            .map(|i| Pos([i % 10, i / 10 % 10, i / 100].map(|c| c as f64 * 0.1)))
+            .collect::<Vec<_>>();
+        let values = (0..1000).map(|k| vec![k as f64; n]).collect();
+        let field = ScalarField::new(key_points, values);
+        assert_eq!(field.vertex_value(&complex, 0, Pos([0.0; 3])), 0.0);
+        assert_eq!(
+            field.vertex_value(&complex, 0, Pos([0.32, 0.48, 0.71])),
+            753.0
+        );
+        assert_eq!(field.vertex_value(&complex, 0, Pos([5.0, 5.0, 5.0])), 999.0);
+    }
+}
@@ -0,0 +1,722 @@
+//! Readers for the mesh formats other than .obj: PLY, STL and OFF, and detection of the format
+//! of a file from its contents.
+//!
+//! All readers collect the vertex positions and the cells, and build the [Complex] with
+//! [Complex::from_cells].  Polygons with more than three vertices are split into triangles around
+//! their first vertex.
+use std::collections::HashMap;
+
+use tracing::warn;
+
+use crate::{
+    complex::{Complex, Pos},
+    error::{MarsError, MarsResult},
+    sneaky_matrix::CI,
+};
+
+/// The file formats that we can read a [Complex] from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Obj,
+    Mesh,
+    Vtk,
+    Ply,
+    Stl,
+    Off,
+}
+
+impl Format {
+    /// Guess the format from the start of the file.  Anything that isn't recognized is assumed
+    /// to be an .obj file.
+    pub fn detect(bytes: &[u8]) -> Self {
+        if is_binary_stl(bytes) {
+            return Format::Stl;
+        }
+        let start = String::from_utf8_lossy(&bytes[..bytes.len().min(512)]);
+        let first_line = start.trim_start().lines().next().unwrap_or("").trim();
+        if first_line == "ply" {
+            Format::Ply
+        } else if first_line.starts_with("OFF") {
+            Format::Off
+        } else if first_line.starts_with("solid") {
+            Format::Stl
+        } else if first_line.starts_with("# vtk DataFile") {
+            Format::Vtk
+        } else if start.contains("MeshVersionFormatted") {
+            Format::Mesh
+        } else {
+            Format::Obj
+        }
+    }
+}
+
+/// Binary STL files have an 80 byte header, which may start with "solid" like ASCII STL files,
+/// followed by the number of triangles and 50 bytes per triangle.
+fn is_binary_stl(bytes: &[u8]) -> bool {
+    if bytes.len() < 84 {
+        return false;
+    }
+    let n = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+    n.checked_mul(50).and_then(|b| b.checked_add(84)) == Some(bytes.len())
+}
+
+/// Split the polygon into triangles around its first vertex.  Two vertices give an edge.
+fn push_polygon(cells: &mut Vec<Vec<CI>>, polygon: &[CI]) {
+    if polygon.len() == 2 {
+        cells.push(polygon.to_vec());
+    }
+    for k in 1..polygon.len().saturating_sub(1) {
+        cells.push(vec![polygon[0], polygon[k], polygon[k + 1]]);
+    }
+}
+
+fn to_ci(i: i64, num_vertices: usize) -> MarsResult<CI> {
+    if i < 0 || num_vertices as i64 <= i {
+        return Err(MarsError::Parse(format!(
+            "Vertex index {} is out of range (have {} vertices)",
+            i, num_vertices
+        )));
+    }
+    Ok(i as CI)
+}
+
+impl Complex {
+    /// Read a complex from the contents of a file in any of the supported formats.  The format
+    /// is detected with [Format::detect].
+    pub fn read_from_bytes(bytes: &[u8]) -> MarsResult<Self> {
+        Self::read_from_bytes_merging(bytes, None)
+    }
+
+    /// Like [Complex::read_from_bytes], but if `merge_distance` is given, vertices that are closer
+    /// than it are merged instead of rejected.  See [Complex::from_cells_merging].
+    pub fn read_from_bytes_merging(bytes: &[u8], merge_distance: Option<f64>) -> MarsResult<Self> {
+        let text = || {
+            std::str::from_utf8(bytes)
+                .map_err(|e| MarsError::Parse(format!("Invalid text file: {}", e)))
+        };
+        let (points, cells) = match Format::detect(bytes) {
+            Format::Obj => return Self::read_from_obj_string_merging(text()?, merge_distance),
+            Format::Mesh => Self::mesh_cells(text()?)?,
+            Format::Vtk => Self::vtk_cells(text()?)?,
+            Format::Ply => Self::ply_cells(bytes)?,
+            Format::Stl => Self::stl_cells(bytes)?,
+            Format::Off => Self::off_cells(text()?)?,
+        };
+        Self::from_cells_merging(points, &cells, merge_distance)
+    }
+
+    /// Read an OFF file.  Faces with two vertices are read as edges, and any colors after the
+    /// vertex indices of a face are ignored.
+    pub fn read_from_off_string(input_str: &str) -> MarsResult<Self> {
+        let (points, cells) = Self::off_cells(input_str)?;
+        Self::from_cells(points, &cells)
+    }
+
+    fn off_cells(input_str: &str) -> MarsResult<(Vec<Pos>, Vec<Vec<CI>>)> {
+        let mut lines = input_str
+            .lines()
+            .map(|l| l.split('#').next().unwrap_or("").trim())
+            .filter(|l| !l.is_empty());
+
+        let parse = |s: &str, what: &str| -> MarsResult<f64> {
+            s.parse::<f64>().map_err(|_| {
+                MarsError::Parse(format!("Expected a number for {}, got {:?}", what, s))
+            })
+        };
+
+        let header = lines
+            .next()
+            .ok_or(MarsError::Parse("Empty OFF file".to_string()))?;
+        if !header.starts_with("OFF") {
+            return Err(MarsError::Parse(format!(
+                "Expected OFF header, got {:?}",
+                header
+            )));
+        }
+        // The counts may be on the same line as the header.
+        let counts_line = match header["OFF".len()..].trim() {
+            "" => lines
+                .next()
+                .ok_or_else(|| MarsError::Parse("Missing vertex and face counts".to_string()))?,
+            rest => rest,
+        };
+        let counts = counts_line
+            .split_ascii_whitespace()
+            .map(|c| {
+                c.parse::<usize>()
+                    .map_err(|_| MarsError::Parse(format!("Expected a count, got {:?}", c)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let (&num_vertices, &num_faces) = match counts.as_slice() {
+            [v, f, ..] => (v, f),
+            _ => {
+                return Err(MarsError::Parse(
+                    "Expected vertex and face counts".to_string(),
+                ))
+            }
+        };
+
+        // The counts come from the file, so we don't trust them to preallocate.
+        let mut points = Vec::new();
+        for _ in 0..num_vertices {
+            let line = lines.next().ok_or(MarsError::Parse(
+                "Unexpected end of file in vertices".to_string(),
+            ))?;
+            let xyz = line
+                .split_ascii_whitespace()
+                .take(3)
+                .map(|c| parse(c, "vertex"))
+                .collect::<Result<Vec<_>, _>>()?;
+            if xyz.len() != 3 {
+                return Err(MarsError::Parse(format!(
+                    "A vertex should have three coordinates: {:?}",
+                    line
+                )));
+            }
+            points.push(Pos([xyz[0], xyz[1], xyz[2]]));
+        }
+
+        let mut cells = Vec::new();
+        for _ in 0..num_faces {
+            let line = lines.next().ok_or(MarsError::Parse(
+                "Unexpected end of file in faces".to_string(),
+            ))?;
+            let mut fields = line.split_ascii_whitespace();
+            let n = fields
+                .next()
+                .and_then(|n| n.parse::<usize>().ok())
+                .ok_or_else(|| {
+                    MarsError::Parse(format!(
+                        "Expected the number of vertices of a face: {:?}",
+                        line
+                    ))
+                })?;
+            let polygon = fields
+                .take(n)
+                .map(|i| {
+                    let i = i.parse::<i64>().map_err(|_| {
+                        MarsError::Parse(format!("Expected a vertex index, got {:?}", i))
+                    })?;
+                    to_ci(i, num_vertices)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if polygon.len() != n || n < 2 {
+                return Err(MarsError::Parse(format!("Invalid face: {:?}", line)));
+            }
+            push_polygon(&mut cells, &polygon);
+        }
+
+        Ok((points, cells))
+    }
+
+    /// Read an ASCII or binary STL file.
+    ///
+    /// STL files list the three corners of each triangle, so vertices that are shared between
+    /// triangles are repeated.  Corners with identical coordinates are welded into one vertex, and
+    /// triangles that become degenerate are skipped.
+    pub fn read_from_stl_bytes(bytes: &[u8]) -> MarsResult<Self> {
+        let (points, cells) = Self::stl_cells(bytes)?;
+        Self::from_cells(points, &cells)
+    }
+
+    fn stl_cells(bytes: &[u8]) -> MarsResult<(Vec<Pos>, Vec<Vec<CI>>)> {
+        let mut corners = Vec::new();
+        if is_binary_stl(bytes) {
+            for tri in bytes[84..].chunks_exact(50) {
+                // Skip the normal, and the attribute byte count at the end.
+                for k in 0..3 {
+                    let mut p = [0.0; 3];
+                    for (j, c) in p.iter_mut().enumerate() {
+                        let o = 12 + 12 * k + 4 * j;
+                        let b = [tri[o], tri[o + 1], tri[o + 2], tri[o + 3]];
+                        *c = f32::from_le_bytes(b) as f64;
+                    }
+                    corners.push(p);
+                }
+            }
+        } else {
+            let text = std::str::from_utf8(bytes)
+                .map_err(|e| MarsError::Parse(format!("Invalid text file: {}", e)))?;
+            for line in text.lines() {
+                let mut fields = line.split_ascii_whitespace();
+                if fields.next() != Some("vertex") {
+                    continue;
+                }
+                let xyz = fields
+                    .map(|c| {
+                        c.parse::<f64>().map_err(|_| {
+                            MarsError::Parse(format!("Expected a number for vertex, got {:?}", c))
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                if xyz.len() != 3 {
+                    return Err(MarsError::Parse(format!(
+                        "A vertex should have three coordinates: {:?}",
+                        line
+                    )));
+                }
+                corners.push([xyz[0], xyz[1], xyz[2]]);
+            }
+            if corners.len() % 3 != 0 {
+                return Err(MarsError::Parse(
+                    "The number of vertices is not a multiple of three".to_string(),
+                ));
+            }
+        }
+
+        let mut vertex_of: HashMap<[u64; 3], CI> = HashMap::new();
+        let mut points = Vec::new();
+        let mut cells = Vec::new();
+        let mut num_degenerate = 0;
+        for tri in corners.chunks_exact(3) {
+            let cell = tri
+                .iter()
+                .map(|p| {
+                    // Adding 0.0 turns -0.0 into 0.0, so that they are welded.
+                    *vertex_of
+                        .entry(p.map(|c| (c + 0.0).to_bits()))
+                        .or_insert_with(|| {
+                            points.push(Pos(*p));
+                            points.len() as CI - 1
+                        })
+                })
+                .collect::<Vec<_>>();
+            if cell[0] == cell[1] || cell[1] == cell[2] || cell[2] == cell[0] {
+                num_degenerate += 1;
+                continue;
+            }
+            cells.push(cell);
+        }
+        if 0 < num_degenerate {
+            warn!(
+                "Skipped {} degenerate triangles in STL file",
+                num_degenerate
+            );
+        }
+
+        Ok((points, cells))
+    }
+
+    /// Read an ASCII or binary PLY file.  The `vertex` element must have `x`, `y` and `z`
+    /// properties.  Faces are read from the `vertex_indices` (or `vertex_index`) list of the
+    /// `face` element, and edges from `vertex1` and `vertex2` of the `edge` element.  All other
+    /// elements and properties are skipped.
+    pub fn read_from_ply_bytes(bytes: &[u8]) -> MarsResult<Self> {
+        let (points, cells) = Self::ply_cells(bytes)?;
+        Self::from_cells(points, &cells)
+    }
+
+    fn ply_cells(bytes: &[u8]) -> MarsResult<(Vec<Pos>, Vec<Vec<CI>>)> {
+        let (header, body) = PlyHeader::parse(bytes)?;
+        let mut reader = match header.encoding {
+            PlyEncoding::Ascii => PlyReader::Ascii(
+                std::str::from_utf8(body)
+                    .map_err(|e| MarsError::Parse(format!("Invalid text in PLY body: {}", e)))?
+                    .split_ascii_whitespace(),
+            ),
+            PlyEncoding::BinaryLittleEndian => PlyReader::Binary {
+                bytes: body,
+                big_endian: false,
+            },
+            PlyEncoding::BinaryBigEndian => PlyReader::Binary {
+                bytes: body,
+                big_endian: true,
+            },
+        };
+
+        let mut points = Vec::new();
+        let mut polygons: Vec<Vec<i64>> = Vec::new();
+        for element in &header.elements {
+            let prop = |name: &str| element.properties.iter().position(|p| p.name == name);
+            let (xyz, indices, ends) = match element.name.as_str() {
+                "vertex" => {
+                    let xyz = [prop("x"), prop("y"), prop("z")];
+                    if xyz.iter().any(|p| p.is_none()) {
+                        return Err(MarsError::Parse(
+                            "The vertex element needs x, y and z properties".to_string(),
+                        ));
+                    }
+                    (Some(xyz.map(|p| p.unwrap())), None, None)
+                }
+                "face" => (
+                    None,
+                    prop("vertex_indices").or_else(|| prop("vertex_index")),
+                    None,
+                ),
+                "edge" => (None, None, prop("vertex1").zip(prop("vertex2"))),
+                _ => (None, None, None),
+            };
+
+            for _ in 0..element.count {
+                let mut values = Vec::with_capacity(element.properties.len());
+                let mut list = Vec::new();
+                for (k, p) in element.properties.iter().enumerate() {
+                    match p.count_type {
+                        Some(count_type) => {
+                            let n = reader.read(count_type)?;
+                            if n < 0.0 {
+                                return Err(MarsError::Parse(format!(
+                                    "Negative list length in {}",
+                                    element.name
+                                )));
+                            }
+                            let items = (0..n as usize)
+                                .map(|_| reader.read(p.value_type))
+                                .collect::<Result<Vec<_>, _>>()?;
+                            if Some(k) == indices {
+                                list = items;
+                            }
+                            values.push(0.0);
+                        }
+                        None => values.push(reader.read(p.value_type)?),
+                    }
+                }
+                if let Some([x, y, z]) = xyz {
+                    points.push(Pos([values[x], values[y], values[z]]));
+                }
+                if indices.is_some() {
+                    polygons.push(list.iter().map(|&i| i as i64).collect());
+                }
+                if let Some((a, b)) = ends {
+                    polygons.push(vec![values[a] as i64, values[b] as i64]);
+                }
+            }
+        }
+
+        let mut cells = Vec::new();
+        for polygon in polygons {
+            if polygon.len() < 2 {
+                return Err(MarsError::Parse(format!("Invalid face: {:?}", polygon)));
+            }
+            let polygon = polygon
+                .into_iter()
+                .map(|i| to_ci(i, points.len()))
+                .collect::<Result<Vec<_>, _>>()?;
+            push_polygon(&mut cells, &polygon);
+        }
+        Ok((points, cells))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum PlyEncoding {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+/// A scalar type of a PLY property.
+#[derive(Clone, Copy, Debug)]
+enum PlyType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyType {
+    fn parse(s: &str) -> MarsResult<Self> {
+        Ok(match s {
+            "char" | "int8" => PlyType::I8,
+            "uchar" | "uint8" => PlyType::U8,
+            "short" | "int16" => PlyType::I16,
+            "ushort" | "uint16" => PlyType::U16,
+            "int" | "int32" => PlyType::I32,
+            "uint" | "uint32" => PlyType::U32,
+            "float" | "float32" => PlyType::F32,
+            "double" | "float64" => PlyType::F64,
+            _ => return Err(MarsError::Parse(format!("Unknown PLY type {:?}", s))),
+        })
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            PlyType::I8 | PlyType::U8 => 1,
+            PlyType::I16 | PlyType::U16 => 2,
+            PlyType::I32 | PlyType::U32 | PlyType::F32 => 4,
+            PlyType::F64 => 8,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PlyProperty {
+    name: String,
+    /// The type of the length, if this is a list property.
+    count_type: Option<PlyType>,
+    value_type: PlyType,
+}
+
+#[derive(Debug)]
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+#[derive(Debug)]
+struct PlyHeader {
+    encoding: PlyEncoding,
+    elements: Vec<PlyElement>,
+}
+
+impl PlyHeader {
+    /// Parse the header, and return it with the rest of the file.
+    fn parse(bytes: &[u8]) -> MarsResult<(Self, &[u8])> {
+        const END: &[u8] = b"end_header";
+        let end = bytes
+            .windows(END.len())
+            .position(|w| w == END)
+            .ok_or(MarsError::Parse(
+                "Missing end_header in PLY file".to_string(),
+            ))?;
+        let body_start = bytes[end..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map(|p| end + p + 1)
+            .unwrap_or(bytes.len());
+        let text = std::str::from_utf8(&bytes[..end])
+            .map_err(|e| MarsError::Parse(format!("Invalid text in PLY header: {}", e)))?;
+
+        let mut lines = text.lines().map(str::trim);
+        if lines.next() != Some("ply") {
+            return Err(MarsError::Parse("Missing ply magic".to_string()));
+        }
+        let mut encoding = None;
+        let mut elements: Vec<PlyElement> = Vec::new();
+        for line in lines {
+            let fields = line.split_ascii_whitespace().collect::<Vec<_>>();
+            match fields.as_slice() {
+                [] | ["comment", ..] | ["obj_info", ..] => {}
+                ["format", f, _version] => {
+                    encoding = Some(match *f {
+                        "ascii" => PlyEncoding::Ascii,
+                        "binary_little_endian" => PlyEncoding::BinaryLittleEndian,
+                        "binary_big_endian" => PlyEncoding::BinaryBigEndian,
+                        _ => return Err(MarsError::Parse(format!("Unknown PLY format {:?}", f))),
+                    })
+                }
+                ["element", name, count] => elements.push(PlyElement {
+                    name: name.to_string(),
+                    count: count.parse().map_err(|_| {
+                        MarsError::Parse(format!("Invalid element count {:?}", count))
+                    })?,
+                    properties: Vec::new(),
+                }),
+                ["property", "list", count_type, value_type, name] => elements
+                    .last_mut()
+                    .ok_or(MarsError::Parse(
+                        "PLY property before any element".to_string(),
+                    ))?
+                    .properties
+                    .push(PlyProperty {
+                        name: name.to_string(),
+                        count_type: Some(PlyType::parse(count_type)?),
+                        value_type: PlyType::parse(value_type)?,
+                    }),
+                ["property", value_type, name] => elements
+                    .last_mut()
+                    .ok_or(MarsError::Parse(
+                        "PLY property before any element".to_string(),
+                    ))?
+                    .properties
+                    .push(PlyProperty {
+                        name: name.to_string(),
+                        count_type: None,
+                        value_type: PlyType::parse(value_type)?,
+                    }),
+                _ => {
+                    return Err(MarsError::Parse(format!(
+                        "Unexpected line in PLY header: {:?}",
+                        line
+                    )))
+                }
+            }
+        }
+        let encoding =
+            encoding.ok_or(MarsError::Parse("Missing format in PLY header".to_string()))?;
+        Ok((Self { encoding, elements }, &bytes[body_start..]))
+    }
+}
+
+/// Reads values from the body of a PLY file.
+enum PlyReader<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { bytes: &'a [u8], big_endian: bool },
+}
+
+impl PlyReader<'_> {
+    fn read(&mut self, ty: PlyType) -> MarsResult<f64> {
+        match self {
+            PlyReader::Ascii(tokens) => {
+                let t = tokens
+                    .next()
+                    .ok_or(MarsError::Parse("Unexpected end of PLY file".to_string()))?;
+                t.parse::<f64>().map_err(|_| {
+                    MarsError::Parse(format!("Expected a number in PLY file, got {:?}", t))
+                })
+            }
+            PlyReader::Binary { bytes, big_endian } => {
+                let n = ty.size();
+                if bytes.len() < n {
+                    return Err(MarsError::Parse("Unexpected end of PLY file".to_string()));
+                }
+                let mut b = [0u8; 8];
+                b[..n].copy_from_slice(&bytes[..n]);
+                if *big_endian {
+                    b[..n].reverse();
+                }
+                *bytes = &bytes[n..];
+                Ok(match ty {
+                    PlyType::I8 => b[0] as i8 as f64,
+                    PlyType::U8 => b[0] as f64,
+                    PlyType::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                    PlyType::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                    PlyType::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    PlyType::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    PlyType::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    PlyType::F64 => f64::from_le_bytes(b),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The corners and faces of a square pyramid, with the base as a quad.
+    const PYRAMID_POINTS: [[f32; 3]; 5] = [
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [0.5, 0.5, 1.0],
+    ];
+    const PYRAMID_FACES: [&[i32]; 5] = [
+        &[0, 3, 2, 1],
+        &[0, 1, 4],
+        &[1, 2, 4],
+        &[2, 3, 4],
+        &[3, 0, 4],
+    ];
+
+    fn counts(c: &Complex) -> [usize; 3] {
+        [0, 1, 2].map(|d| c.num_simplices_of_dim(d))
+    }
+
+    #[test]
+    fn read_off() {
+        let off = "OFF\n# pyramid\n5 5 0\n0 0 0\n1 0 0\n1 1 0\n0 1 0\n0.5 0.5 1\n4 0 3 2 1\n3 0 1 4 255 0 0\n3 1 2 4\n3 2 3 4\n3 3 0 4\n";
+        assert_eq!(Format::detect(off.as_bytes()), Format::Off);
+        let c = Complex::read_from_bytes(off.as_bytes()).unwrap();
+        assert_eq!(counts(&c), [5, 9, 6]);
+        assert!(Complex::read_from_off_string("OFF\n1 1 0\n0 0 0\n3 0 1 2\n").is_err());
+        // A truncated file with a huge vertex count is an error, not an allocation failure.
+        let truncated = Complex::read_from_off_string("OFF\n100000000000000 1 0\n0 0 0\n");
+        assert!(matches!(truncated, Err(MarsError::Parse(_))));
+    }
+
+    #[test]
+    fn read_ply() {
+        let mut ascii = String::from("ply\nformat ascii 1.0\ncomment pyramid\nelement vertex 5\nproperty float x\nproperty float y\nproperty float z\nproperty uchar red\nelement face 5\nproperty list uchar int vertex_indices\nend_header\n");
+        for p in PYRAMID_POINTS {
+            ascii += &format!("{} {} {} 255\n", p[0], p[1], p[2]);
+        }
+        for f in PYRAMID_FACES {
+            ascii += &format!("{}", f.len());
+            for i in f {
+                ascii += &format!(" {}", i);
+            }
+            ascii += "\n";
+        }
+        assert_eq!(Format::detect(ascii.as_bytes()), Format::Ply);
+        let c = Complex::read_from_bytes(ascii.as_bytes()).unwrap();
+        assert_eq!(counts(&c), [5, 9, 6]);
+
+        for big_endian in [false, true] {
+            let (format, word): (_, fn(f32) -> [u8; 4]) = if big_endian {
+                ("binary_big_endian", f32::to_be_bytes)
+            } else {
+                ("binary_little_endian", f32::to_le_bytes)
+            };
+            let mut binary = format!("ply\nformat {} 1.0\nelement vertex 5\nproperty float x\nproperty float y\nproperty float z\nelement face 5\nproperty list uchar int vertex_indices\nend_header\n", format).into_bytes();
+            for p in PYRAMID_POINTS {
+                for c in p {
+                    binary.extend(word(c));
+                }
+            }
+            for f in PYRAMID_FACES {
+                binary.push(f.len() as u8);
+                for &i in f {
+                    binary.extend(if big_endian {
+                        i.to_be_bytes()
+                    } else {
+                        i.to_le_bytes()
+                    });
+                }
+            }
+            let c = Complex::read_from_bytes(&binary).unwrap();
+            assert_eq!(counts(&c), [5, 9, 6]);
+            let apex = c.simplices_per_dim[0][4].coords.unwrap();
+            assert_eq!(apex.0, [0.5, 0.5, 1.0]);
+        }
+    }
+
+    #[test]
+    fn read_stl() {
+        let triangles = PYRAMID_FACES
+            .iter()
+            .flat_map(|f| (1..f.len() - 1).map(|k| [f[0], f[k], f[k + 1]]))
+            .collect::<Vec<_>>();
+
+        let mut ascii = String::from("solid pyramid\n");
+        for t in &triangles {
+            ascii += "facet normal 0 0 0\nouter loop\n";
+            for &i in t {
+                let p = PYRAMID_POINTS[i as usize];
+                ascii += &format!("vertex {} {} {}\n", p[0], p[1], p[2]);
+            }
+            ascii += "endloop\nendfacet\n";
+        }
+        ascii += "endsolid pyramid\n";
+        assert_eq!(Format::detect(ascii.as_bytes()), Format::Stl);
+        let c = Complex::read_from_bytes(ascii.as_bytes()).unwrap();
+        assert_eq!(counts(&c), [5, 9, 6]);
+
+        // Binary files may also start with "solid".
+        let mut binary = b"solid".to_vec();
+        binary.resize(80, 0);
+        binary.extend((triangles.len() as u32).to_le_bytes());
+        for t in &triangles {
+            binary.extend([0u8; 12]);
+            for &i in t {
+                for c in PYRAMID_POINTS[i as usize] {
+                    binary.extend(c.to_le_bytes());
+                }
+            }
+            binary.extend([0u8; 2]);
+        }
+        assert_eq!(Format::detect(&binary), Format::Stl);
+        let c = Complex::read_from_bytes(&binary).unwrap();
+        assert_eq!(counts(&c), [5, 9, 6]);
+    }
+
+    #[test]
+    fn detect_other_formats() {
+        assert_eq!(Format::detect(b"v 0 0 0\nv 1 0 0\nl 1 2\n"), Format::Obj);
+        assert_eq!(
+            Format::detect(b"# vtk DataFile Version 2.0\ntet\nASCII\n"),
+            Format::Vtk
+        );
+        assert_eq!(
+            Format::detect(b"MeshVersionFormatted 1\nDimension 3\n"),
+            Format::Mesh
+        );
+    }
+}
@@ -1,10 +1,14 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
 
 use tracing::{instrument, trace};
 
 use crate::{
-    complex::{Complex, Pos},
-    reduce_from_scratch, vineyards_step, Reduction, Swaps,
+    complex::{Complex, Pos, MIN_VERTEX_DISTANCE},
+    error::{MarsError, MarsResult},
+    filtration::Filtration,
+    reduce_from_scratch,
+    spatial::find_close_pair,
+    vineyards_step, Reduction, SlimEdge, Swaps,
 };
 
 #[derive(
@@ -83,7 +87,8 @@ impl VineyardsGrid {
     /// Construct a new [Grid] around the given [Complex]. The grid cells are of
     /// size `size`, and `buffer` is the smallest distance from the grid
     /// boundary to the complex.  This is tight at the min corner of the grid,
-    /// and is up to `buffer + size` as the max corner of the grid.
+    /// and is up to `buffer + size` as the max corner of the grid.  Along an axis
+    /// where the complex is flat and `buffer` is zero there is a single point.
     pub fn around_complex(complex: &Complex, size: f64, buffer: f64) -> Self {
         let (xmin, ymin, zmin) = complex.simplices_per_dim[0].iter().fold(
             (f64::MAX, f64::MAX, f64::MAX),
@@ -103,15 +108,35 @@ impl VineyardsGrid {
 
         let corner = Pos([xmin - buffer, ymin - buffer, zmin - buffer]);
 
+        // `n` points cover `n - 1` cells.
         let shape = [
-            ((xmax - xmin + 2.0 * buffer) / size).ceil() as isize,
-            ((ymax - ymin + 2.0 * buffer) / size).ceil() as isize,
-            ((zmax - zmin + 2.0 * buffer) / size).ceil() as isize,
+            ((xmax - xmin + 2.0 * buffer) / size).ceil() as isize + 1,
+            ((ymax - ymin + 2.0 * buffer) / size).ceil() as isize + 1,
+            ((zmax - zmin + 2.0 * buffer) / size).ceil() as isize + 1,
         ];
 
         Self::new(corner, size, shape)
     }
 
+    /// Construct a 2D [VineyardsGrid] around a planar [Complex], in the plane of the complex.  The
+    /// grid has a single layer of points, so that it only has grid edges along the x and y axes.
+    ///
+    /// Returns an error if the complex does not lie in a plane `z = c`.
+    pub fn around_planar_complex(complex: &Complex, size: f64, buffer: f64) -> MarsResult<Self> {
+        let z = complex.planar_z().ok_or(MarsError::Invalid(
+            "The complex does not lie in a plane z = c".to_string(),
+        ))?;
+        let mut grid = Self::around_complex(complex, size, buffer);
+        grid.corner.0[2] = z;
+        grid.shape.0[2] = 1;
+        Ok(grid)
+    }
+
+    /// True if this is a 2D grid, with a single layer of points along the z axis.
+    pub fn is_planar(&self) -> bool {
+        self.shape.z() == 1
+    }
+
     pub fn is_on_boundary(&self, i: Index) -> bool {
         for j in 0..3 {
             if i.0[j] == 0 || i.0[j] == self.shape.0[j] - 1 {
@@ -183,6 +208,32 @@ impl VineyardsGrid {
         }
     }
 
+    /// The dual of a grid edge in a 2D grid, which is the line segment between the centers of the
+    /// two grid cells next to the edge.
+    pub fn dual_segment_points(&self, a: Index, b: Index) -> [Pos; 2] {
+        self.dual_segment_cells(a, b).map(|c| {
+            let mut arr = self.corner.0;
+            arr[0] += self.size * c[0] as f64 / 2.0;
+            arr[1] += self.size * c[1] as f64 / 2.0;
+            Pos(arr)
+        })
+    }
+
+    /// The endpoints of [VineyardsGrid::dual_segment_points] as cell centers, in coordinates that
+    /// are twice the grid [Index].  Neighboring segments share endpoints exactly.
+    pub fn dual_segment_cells(&self, a: Index, b: Index) -> [[isize; 2]; 2] {
+        let (x, y) = (a.x() + b.x(), a.y() + b.y());
+        if a.z() != b.z() {
+            panic!("dual_segment_cells: edge along the z axis");
+        } else if a.x() != b.x() {
+            [[x, y - 1], [x, y + 1]]
+        } else if a.y() != b.y() {
+            [[x - 1, y], [x + 1, y]]
+        } else {
+            panic!("dual_segment_cells: a == b");
+        }
+    }
+
     /// Splits the grid into two along the longest axis.
     /// The [Index] returned is the offset of the second [Grid] wrt. the first [Grid].
     pub fn split_with_overlap(&self) -> (Self, Self, Index) {
@@ -236,6 +287,7 @@ impl VineyardsGrid {
     pub fn run_vineyards_in_grid<F: Fn(usize, usize)>(
         &self,
         complex: &Complex,
+        filtration: &dyn Filtration,
         i0: Index,
         state: Reduction,
         require_hom_birth_to_be_first: bool,
@@ -256,8 +308,13 @@ impl VineyardsGrid {
                     .get(&old_cell)
                     .expect("prev_cell should have state in the map.");
                 let p = self.coordinate(new_cell);
-                let (new_state, swaps) =
-                    vineyards_step(complex, old_state, p, require_hom_birth_to_be_first);
+                let (new_state, swaps) = vineyards_step(
+                    complex,
+                    filtration,
+                    old_state,
+                    p,
+                    require_hom_birth_to_be_first,
+                );
                 all_swaps.push((old_cell, new_cell, swaps));
                 hm.insert(new_cell, new_state);
             } else {
@@ -267,6 +324,78 @@ impl VineyardsGrid {
         (hm, all_swaps)
     }
 
+    /// Run vineyards across all edges of the grid without keeping all [Reduction]s in memory.
+    ///
+    /// Instead of returning the [Reduction]s and swaps, `on_edge` is called for every grid edge.  A
+    /// [Reduction] is dropped as soon as all of the edges out of its grid point have been visited.
+    /// Like [VineyardsGridMesh::run_vineyards_slim], the run starts with a reduction from scratch,
+    /// at the first grid point.
+    pub fn run_vineyards_in_grid_slim<F: Fn(usize, usize), G: FnMut(SlimEdge)>(
+        &self,
+        complex: &Complex,
+        filtration: &dyn Filtration,
+        require_hom_birth_to_be_first: bool,
+        on_visit: F,
+        mut on_edge: G,
+    ) {
+        let i0 = Index([0; 3]);
+        let state = reduce_from_scratch(complex, filtration, self.coordinate(i0), false);
+        let mut reductions: HashMap<Index, Reduction> = HashMap::new();
+        let mut visits_left: HashMap<Index, usize> = HashMap::new();
+
+        let num_grid_edges = self.number_of_grid_edges() as usize;
+        let mut edge_i = 0;
+
+        self.visit_edges(i0, |new_cell, old_cell| {
+            let Some(old_cell) = old_cell else {
+                reductions.insert(new_cell, state.clone());
+                visits_left.insert(new_cell, self.iter_neighbors(&new_cell).count());
+                return;
+            };
+
+            edge_i += 1;
+            on_visit(edge_i, num_grid_edges);
+
+            let old_state = reductions
+                .get(&old_cell)
+                .expect("prev_cell should have state in the map.");
+            let p = self.coordinate(new_cell);
+            let (new_state, swaps) = vineyards_step(
+                complex,
+                filtration,
+                old_state,
+                p,
+                require_hom_birth_to_be_first,
+            );
+
+            on_edge(SlimEdge {
+                from: old_cell,
+                to: new_cell,
+                from_red: old_state,
+                to_red: &new_state,
+                swaps,
+            });
+
+            if let Entry::Vacant(e) = reductions.entry(new_cell) {
+                e.insert(new_state);
+                visits_left.insert(new_cell, self.iter_neighbors(&new_cell).count());
+            }
+
+            for cell in [old_cell, new_cell] {
+                let counter = visits_left
+                    .get_mut(&cell)
+                    .expect("All visited cells should be in the map");
+                *counter -= 1;
+                if *counter == 0 {
+                    reductions
+                        .remove(&cell)
+                        .expect("missing reduction for cell");
+                    visits_left.remove(&cell);
+                }
+            }
+        });
+    }
+
     /// True if the index is contained in the grid.
     fn contains(&self, index: &Index) -> bool {
         let [x, y, z] = index.0;
@@ -293,6 +422,42 @@ impl VineyardsGrid {
         .filter(|i| self.contains(i))
     }
 
+    pub fn write_as_obj<W: std::io::Write>(&self, mut w: W) -> std::io::Result<()> {
+        writeln!(w, "o grid")?;
+
+        let [sx, sy, sz] = self.shape.0;
+        // .obj vertex number of each grid index, in the order we write them out.
+        let vertex_number = |i: Index| 1 + i.x() + sx * (i.y() + sy * i.z());
+
+        for z in 0..sz {
+            for y in 0..sy {
+                for x in 0..sx {
+                    let pt = self.coordinate(Index([x, y, z]));
+                    writeln!(w, "v {} {} {}", pt.x(), pt.y(), pt.z())?;
+                }
+            }
+        }
+
+        for z in 0..sz {
+            for y in 0..sy {
+                for x in 0..sx {
+                    let a = Index([x, y, z]);
+                    for b in [
+                        Index([x + 1, y, z]),
+                        Index([x, y + 1, z]),
+                        Index([x, y, z + 1]),
+                    ] {
+                        if self.contains(&b) {
+                            writeln!(w, "l {} {}", vertex_number(a), vertex_number(b))?;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     /// Visit each edge of the grid.
     ///
     /// The two endpoints of each edge is passed as parameters to `f`.  The first [Index] is a
@@ -347,6 +512,38 @@ impl Bbox {
     }
 }
 
+/// The state of a slim traversal of a [VineyardsGridMesh] between two calls to
+/// [VineyardsGridMesh::step_vineyards_slim].
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct MeshTraversal {
+    /// Reductions at the vertices that still have unvisited edges.
+    reductions: HashMap<Index, Reduction>,
+    seen_vx: HashSet<isize>,
+    /// Number of edges left to visit for each vertex.
+    visits_left: HashMap<isize, usize>,
+    /// Edges `(to, from)` to visit.
+    queue: VecDeque<(Index, Index)>,
+    /// Number of edges visited so far.
+    pub steps: usize,
+}
+
+impl MeshTraversal {
+    pub fn new(grid: &VineyardsGridMesh) -> Self {
+        Self {
+            reductions: HashMap::new(),
+            seen_vx: HashSet::new(),
+            visits_left: grid
+                .neighbors
+                .iter()
+                .enumerate()
+                .map(|(k, ns)| (k as isize, ns.len()))
+                .collect(),
+            queue: VecDeque::new(),
+            steps: 0,
+        }
+    }
+}
+
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct VineyardsGridMesh {
     pub points: Vec<Pos>,
@@ -499,6 +696,7 @@ impl VineyardsGridMesh {
     pub fn run_vineyards<F: Fn(usize, usize)>(
         &self,
         complex: &Complex,
+        filtration: &dyn Filtration,
         require_hom_birth_to_be_first: bool,
         record_progress: F,
     ) -> (HashMap<Index, Reduction>, Vec<(Index, Index, Swaps)>) {
@@ -522,7 +720,8 @@ impl VineyardsGridMesh {
         {
             seen_vx.insert(i0);
             let i0 = Index::fake(i0 as isize);
-            let reduction_at_0 = reduce_from_scratch(&complex, self.points[i0.x() as usize], false);
+            let reduction_at_0 =
+                reduce_from_scratch(complex, filtration, self.points[i0.x() as usize], false);
             reductions.insert(i0, reduction_at_0);
 
             let mut stack = self
@@ -548,12 +747,17 @@ impl VineyardsGridMesh {
 
                 let old_state = reductions.get(&from).expect("from should be in the map");
                 let p = self.coordinate(next);
-                let (new_state, swaps) =
-                    vineyards_step(complex, old_state, p, require_hom_birth_to_be_first);
+                let (new_state, swaps) = vineyards_step(
+                    complex,
+                    filtration,
+                    old_state,
+                    p,
+                    require_hom_birth_to_be_first,
+                );
                 all_swaps.push((from, next, swaps));
 
-                if !reductions.contains_key(&next) {
-                    reductions.insert(next, new_state);
+                if let Entry::Vacant(e) = reductions.entry(next) {
+                    e.insert(new_state);
                     for neighbor in self.neighbors.get(next.x() as usize).unwrap() {
                         if !seen_vx.contains(neighbor) {
                             stack.push((Index::fake(*neighbor), next));
@@ -566,154 +770,193 @@ impl VineyardsGridMesh {
         (reductions, all_swaps)
     }
 
-    pub fn run_vineyards_slim<
-        F: Fn(usize, usize),
-        G: FnMut(Index, Index, &Reduction, &Reduction, Swaps),
-    >(
+    pub fn run_vineyards_slim<F: Fn(usize, usize), G: FnMut(SlimEdge)>(
         &self,
         complex: &Complex,
+        filtration: &dyn Filtration,
         require_hom_birth_to_be_first: bool,
         record_progress: F,
         mut on_edge: G,
     ) {
-        let mut reductions: HashMap<Index, Reduction> = HashMap::new();
-
-        if self.points.len() == 0 {
-            return;
-        }
-        let mut seen_vx = HashSet::<isize>::new();
-        let mut visits_left = HashMap::<isize, usize>::new();
-        for (k, ns) in self.neighbors.iter().enumerate() {
-            visits_left.insert(k as isize, ns.len());
+        let mut traversal = MeshTraversal::new(self);
+        let num_edges = self.num_edges();
+        while self.step_vineyards_slim(
+            complex,
+            filtration,
+            &mut traversal,
+            require_hom_birth_to_be_first,
+            &mut on_edge,
+        ) {
+            record_progress(traversal.steps, num_edges);
         }
+    }
 
-        // Find a component in the meshgrid that we haven't reached yet.
-        // `i0` is any node in this component.
-        while let Some(i0) = self
-            .neighbors
-            .iter()
-            .enumerate()
-            .filter(|(v, _)| !seen_vx.contains(&(*v as isize)))
-            .find(|(_, n)| n.len() > 0)
-            .map(|(v, _)| v as isize)
-        {
-            seen_vx.insert(i0);
-            let i0 = Index::fake(i0 as isize);
-            let reduction_at_0 = reduce_from_scratch(&complex, self.points[i0.x() as usize], false);
-            reductions.insert(i0, reduction_at_0);
+    /// Number of edges in the mesh.
+    pub fn num_edges(&self) -> usize {
+        self.neighbors.iter().map(|v| v.len()).sum::<usize>() / 2
+    }
 
-            let mut stack = self
+    /// Run one step of [VineyardsGridMesh::run_vineyards_slim], that is, cross one edge.  Returns
+    /// `false` if the traversal is done.
+    ///
+    /// `traversal` can be serialized between steps, and the run can be resumed later by calling
+    /// this again with the deserialized traversal.
+    pub fn step_vineyards_slim<G: FnMut(SlimEdge)>(
+        &self,
+        complex: &Complex,
+        filtration: &dyn Filtration,
+        traversal: &mut MeshTraversal,
+        require_hom_birth_to_be_first: bool,
+        mut on_edge: G,
+    ) -> bool {
+        let MeshTraversal {
+            reductions,
+            seen_vx,
+            visits_left,
+            queue,
+            steps,
+        } = traversal;
+
+        if queue.is_empty() {
+            // Find a component in the meshgrid that we haven't reached yet.
+            // `i0` is any node in this component.
+            let Some(i0) = self
                 .neighbors
-                .get(i0.x() as usize)
-                .unwrap()
                 .iter()
-                .map(|n| (Index::fake(*n), i0))
-                .collect::<Vec<_>>();
+                .enumerate()
+                .filter(|(v, _)| !seen_vx.contains(&(*v as isize)))
+                .find(|(_, n)| !n.is_empty())
+                .map(|(v, _)| v as isize)
+            else {
+                return false;
+            };
 
-            let mut loop_i = 0;
-            let num_edges = self
-                .neighbors
-                .iter()
-                .map(|v| v.len() as usize)
-                .sum::<usize>()
-                / 2;
+            seen_vx.insert(i0);
+            let i0 = Index::fake(i0);
+            let reduction_at_0 =
+                reduce_from_scratch(complex, filtration, self.points[i0.x() as usize], false);
+            reductions.insert(i0, reduction_at_0);
 
-            while stack.len() > 0 {
-                let (next, from) = stack.remove(0);
-                seen_vx.insert(next.x());
-                loop_i += 1;
-                record_progress(loop_i, num_edges);
+            queue.extend(
+                self.neighbors
+                    .get(i0.x() as usize)
+                    .unwrap()
+                    .iter()
+                    .map(|n| (Index::fake(*n), i0)),
+            );
+        }
 
-                let old_state = reductions.get(&from).expect("from should be in the map");
-                let p = self.coordinate(next);
-                let (new_state, swaps) =
-                    vineyards_step(complex, old_state, p, require_hom_birth_to_be_first);
+        let (next, from) = queue.pop_front().expect("queue is not empty");
+        seen_vx.insert(next.x());
+        *steps += 1;
+
+        let old_state = reductions.get(&from).expect("from should be in the map");
+        let p = self.coordinate(next);
+        let (new_state, swaps) = vineyards_step(
+            complex,
+            filtration,
+            old_state,
+            p,
+            require_hom_birth_to_be_first,
+        );
 
-                on_edge(from, next, old_state, &new_state, swaps);
+        on_edge(SlimEdge {
+            from,
+            to: next,
+            from_red: old_state,
+            to_red: &new_state,
+            swaps,
+        });
 
-                if !reductions.contains_key(&next) {
-                    reductions.insert(next, new_state);
-                    for neighbor in self.neighbors.get(next.x() as usize).unwrap() {
-                        if !seen_vx.contains(neighbor) {
-                            stack.push((Index::fake(*neighbor), next));
-                        }
-                    }
+        if let Entry::Vacant(e) = reductions.entry(next) {
+            e.insert(new_state);
+            for neighbor in self.neighbors.get(next.x() as usize).unwrap() {
+                if !seen_vx.contains(neighbor) {
+                    queue.push_back((Index::fake(*neighbor), next));
                 }
+            }
+        }
 
-                let from_counter = visits_left
-                    .get_mut(&from.x())
-                    .expect("All vertices should be in the map");
-                *from_counter -= 1;
-                if *from_counter == 0 {
-                    reductions.remove(&from).expect("missing reduction in from");
-                }
+        let from_counter = visits_left
+            .get_mut(&from.x())
+            .expect("All vertices should be in the map");
+        *from_counter -= 1;
+        if *from_counter == 0 {
+            reductions.remove(&from).expect("missing reduction in from");
+        }
 
-                let next_counter = visits_left
-                    .get_mut(&next.x())
-                    .expect("All vertices should be in the map");
-                *next_counter -= 1;
-                if *next_counter == 0 {
-                    reductions.remove(&next).expect("missing reduction in next");
-                }
-            }
+        let next_counter = visits_left
+            .get_mut(&next.x())
+            .expect("All vertices should be in the map");
+        *next_counter -= 1;
+        if *next_counter == 0 {
+            reductions.remove(&next).expect("missing reduction in next");
         }
+
+        true
     }
 
-    pub fn read_from_obj_string(s: &str) -> Result<Self, String> {
+    pub fn read_from_obj_string(s: &str) -> MarsResult<Self> {
         let mut points: Vec<Pos> = Vec::new();
         let mut edges: Vec<(isize, isize)> = Vec::new();
 
-        for line in s.lines() {
-            let line = line.trim();
-            if line.starts_with("#")
-                || line.starts_with("mtllib")
-                || line.starts_with("o")
-                || line.starts_with("s")
-            {
-                continue;
-            } else if line.starts_with("v") {
-                let groups = line.split_ascii_whitespace().collect::<Vec<_>>();
-                let x = groups
-                    .get(1)
-                    .ok_or("missing field".to_string())
-                    .and_then(|n| n.parse::<f64>().map_err(|e| e.to_string()))?;
-                let y = groups
-                    .get(2)
-                    .ok_or("missing field".to_string())
-                    .and_then(|n| n.parse::<f64>().map_err(|e| e.to_string()))?;
-                let z = groups
-                    .get(3)
-                    .ok_or("missing field".to_string())
-                    .and_then(|n| n.parse::<f64>().map_err(|e| e.to_string()))?;
-                let coords = Pos([x, y, z]);
-                points.push(coords);
-            } else if line.starts_with("l") {
-                let groups = line.split_ascii_whitespace().collect::<Vec<_>>();
-                let from = groups
-                    .get(1)
-                    .ok_or("missing field".to_string())
-                    .and_then(|n| n.parse::<isize>().map_err(|e| e.to_string()))?;
-                let to = groups
-                    .get(2)
-                    .ok_or("missing field".to_string())
-                    .and_then(|n| n.parse::<isize>().map_err(|e| e.to_string()))?;
-                edges.push((from - 1, to - 1));
+        for (line_i, line) in s.lines().enumerate() {
+            let err = |msg: String| MarsError::Parse(format!("line {}: {}", line_i + 1, msg));
+            let mut fields = line
+                .split('#')
+                .next()
+                .unwrap_or("")
+                .split_ascii_whitespace();
+            match fields.next() {
+                Some("v") => {
+                    let coords = fields
+                        .take(3)
+                        .map(|n| {
+                            n.parse::<f64>()
+                                .map_err(|_| err(format!("invalid coordinate {:?}", n)))
+                        })
+                        .collect::<MarsResult<Vec<_>>>()?;
+                    if coords.len() != 3 {
+                        return Err(err("a vertex should have three coordinates".into()));
+                    }
+                    if !coords.iter().all(|c| c.is_finite()) {
+                        return Err(err(format!("non-finite vertex {:?}", coords)));
+                    }
+                    points.push(Pos([coords[0], coords[1], coords[2]]));
+                }
+                Some("l") => {
+                    let ends = fields
+                        .take(2)
+                        .map(|n| {
+                            n.parse::<isize>()
+                                .map_err(|_| err(format!("invalid vertex index {:?}", n)))
+                        })
+                        .collect::<MarsResult<Vec<_>>>()?;
+                    if ends.len() != 2 {
+                        return Err(err("an edge should have two vertices".into()));
+                    }
+                    edges.push((ends[0] - 1, ends[1] - 1));
+                }
+                _ => {}
             }
         }
+        if let Some((i, j)) = edges.iter().find(|(i, j)| {
+            !(0..points.len() as isize).contains(i) || !(0..points.len() as isize).contains(j)
+        }) {
+            return Err(MarsError::Parse(format!(
+                "grid edge {} {} has a missing vertex (have {} vertices)",
+                i + 1,
+                j + 1,
+                points.len()
+            )));
+        }
 
         // Check that no two vertices are actually the same vertex
-        for i in 0..points.len() {
-            for j in (i + 1)..points.len() {
-                let p = points[i];
-                let q = points[j];
-                let dist = p.dist(&q);
-                if dist < 1e-5 {
-                    return Err(format!(
-                        "Two grid vertices are too close together: {} and {}",
-                        i, j
-                    ));
-                }
-            }
+        if let Some((i, j)) = find_close_pair(&points, MIN_VERTEX_DISTANCE) {
+            return Err(MarsError::Invalid(format!(
+                "Two grid vertices are too close together: {} and {}",
+                i, j
+            )));
         }
 
         let xs = edges
@@ -798,7 +1041,24 @@ impl VineyardsGridMesh {
             None
         };
 
-        self.dim_dist = dim_dist;
+        // Grids with edges of different lengths, like the ones from adaptive refinement, aren't a
+        // subset of a regular grid.  Their dual quads are sized by their own edge instead.
+        let uniform = dim_dist.is_some_and(|(dx, dy, dz)| {
+            self.neighbors.iter().enumerate().all(|(i, n)| {
+                n.iter().all(|&j| {
+                    let (a, b) = (self.points[i], self.points[j as usize]);
+                    let expected = if (a.x() - b.x()).abs() > 1e-3 {
+                        dx
+                    } else if (a.y() - b.y()).abs() > 1e-3 {
+                        dy
+                    } else {
+                        dz
+                    };
+                    (a.dist(&b) - expected).abs() <= 1e-6 * expected
+                })
+            })
+        });
+        self.dim_dist = dim_dist.filter(|_| uniform);
     }
 
     pub fn write_as_obj<W: std::io::Write>(&self, mut w: W) -> std::io::Result<()> {
@@ -822,3 +1082,357 @@ impl VineyardsGridMesh {
         Ok(())
     }
 }
+
+/// Which part of space around a [Complex] a generated [VineyardsGridMesh] should cover.
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub enum GridRegion {
+    /// Grid points inside the closed triangle mesh.
+    Inside,
+    /// Grid points outside the triangle mesh, but within its bounding box padded by one grid cell.
+    Outside,
+    /// Grid points that are closer than the given distance to the triangle mesh, on either side.
+    Shell(f64),
+}
+
+impl VineyardsGridMesh {
+    /// Construct a grid mesh covering the given [GridRegion] of the triangle mesh in `complex`.
+    ///
+    /// The grid spacing is the average longest triangle edge divided by `density`, so that
+    /// `density = 2` gives on average two grid points per triangle edge length.  Grid points are
+    /// placed at the cell centers of a regular grid covering the bounding box of the complex, and
+    /// neighboring points that both are in the region are connected.
+    ///
+    /// Inside-ness is decided by ray casting along all three axes, and a point is inside only if
+    /// all three rays agree.
+    pub fn around_complex(complex: &Complex, density: f64, region: GridRegion) -> MarsResult<Self> {
+        if density.is_nan() || density <= 0.0 {
+            return Err(MarsError::Invalid(format!(
+                "Grid density must be positive, got {}",
+                density
+            )));
+        }
+        let avg_longest_edge = complex.average_longest_edge();
+        if avg_longest_edge == 0.0 {
+            return Err(MarsError::Invalid(
+                "No valid triangles found in the complex".to_string(),
+            ));
+        }
+        let spacing = avg_longest_edge / density;
+
+        let padding = match region {
+            GridRegion::Inside => 0.0,
+            GridRegion::Outside => spacing,
+            GridRegion::Shell(width) => width,
+        };
+        let mut min = Pos([f64::MAX; 3]);
+        let mut max = Pos([f64::MIN; 3]);
+        for s in &complex.simplices_per_dim[0] {
+            let p = s.coords.unwrap();
+            for j in 0..3 {
+                min.0[j] = min.0[j].min(p.0[j] - padding);
+                max.0[j] = max.0[j].max(p.0[j] + padding);
+            }
+        }
+
+        let shape = [0, 1, 2].map(|j| (((max.0[j] - min.0[j]) / spacing).ceil() as isize).max(1));
+        let grid = VineyardsGrid::new(min + Pos([spacing / 2.0; 3]), spacing, shape);
+
+        let triangles = complex.triangle_positions();
+        let keep: Vec<bool> = match region {
+            GridRegion::Inside => inside_mask(&grid, &triangles),
+            GridRegion::Outside => inside_mask(&grid, &triangles)
+                .into_iter()
+                .map(|b| !b)
+                .collect(),
+            GridRegion::Shell(width) => {
+                let dists = surface_distance2(&grid, &triangles, width);
+                dists.into_iter().map(|d| d < width * width).collect()
+            }
+        };
+
+        let mut points = Vec::new();
+        let mut vertex_of = vec![-1; keep.len()];
+        for (k, &b) in keep.iter().enumerate() {
+            if b {
+                vertex_of[k] = points.len() as isize;
+                points.push(grid.coordinate(linear_to_index(&grid, k)));
+            }
+        }
+
+        let mut neighbors: Vec<Vec<isize>> = vec![Vec::new(); points.len()];
+        for (k, &v) in vertex_of.iter().enumerate() {
+            if v < 0 {
+                continue;
+            }
+            let [x, y, z] = linear_to_index(&grid, k).0;
+            for n in [
+                Index([x + 1, y, z]),
+                Index([x, y + 1, z]),
+                Index([x, y, z + 1]),
+            ] {
+                if !grid.contains(&n) {
+                    continue;
+                }
+                let w = vertex_of[index_to_linear(&grid, n)];
+                if 0 <= w {
+                    neighbors[v as usize].push(w);
+                    neighbors[w as usize].push(v);
+                }
+            }
+        }
+
+        trace!(
+            "around_complex: spacing={} shape={:?} #points={}",
+            spacing,
+            grid.shape,
+            points.len()
+        );
+
+        Ok(Self {
+            points,
+            neighbors,
+            r#type: "meshgrid".to_string(),
+            dim_dist: Some((spacing, spacing, spacing)),
+        })
+    }
+}
+
+fn index_to_linear(grid: &VineyardsGrid, i: Index) -> usize {
+    let [w, h, _] = grid.shape.0;
+    let [x, y, z] = i.0;
+    (x + w * (y + h * z)) as usize
+}
+
+fn linear_to_index(grid: &VineyardsGrid, k: usize) -> Index {
+    let [w, h, _] = grid.shape.0;
+    let k = k as isize;
+    Index([k % w, (k / w) % h, k / (w * h)])
+}
+
+/// For each point of the grid, in the order of [index_to_linear], whether the point is inside the
+/// closed surface given by `triangles`.
+///
+/// For each axis we shoot one ray along every line of grid points, and collect where it crosses
+/// the surface.  A point is inside along that axis if an odd number of crossings are before it.
+fn inside_mask(grid: &VineyardsGrid, triangles: &[[Pos; 3]]) -> Vec<bool> {
+    let n = grid.volume() as usize;
+    let mut inside = vec![true; n];
+
+    for axis in 0..3 {
+        let (b, c) = ((axis + 1) % 3, (axis + 2) % 3);
+        let (nb, nc) = (grid.shape.0[b], grid.shape.0[c]);
+        let mut crossings: Vec<Vec<f64>> = vec![Vec::new(); (nb * nc) as usize];
+
+        for tri in triangles {
+            let lo_b = tri.iter().map(|p| p.0[b]).fold(f64::MAX, f64::min);
+            let hi_b = tri.iter().map(|p| p.0[b]).fold(f64::MIN, f64::max);
+            let lo_c = tri.iter().map(|p| p.0[c]).fold(f64::MAX, f64::min);
+            let hi_c = tri.iter().map(|p| p.0[c]).fold(f64::MIN, f64::max);
+
+            let to_line = |v: f64, j: usize| (v - grid.corner.0[j]) / grid.size;
+            let b0 = to_line(lo_b, b).ceil().max(0.0) as isize;
+            let b1 = to_line(hi_b, b).floor().min(nb as f64 - 1.0) as isize;
+            let c0 = to_line(lo_c, c).ceil().max(0.0) as isize;
+            let c1 = to_line(hi_c, c).floor().min(nc as f64 - 1.0) as isize;
+
+            for ib in b0..=b1 {
+                for ic in c0..=c1 {
+                    let pb = grid.corner.0[b] + grid.size * ib as f64;
+                    let pc = grid.corner.0[c] + grid.size * ic as f64;
+                    if let Some(t) = line_triangle_crossing(tri, axis, pb, pc) {
+                        crossings[(ib + nb * ic) as usize].push(t);
+                    }
+                }
+            }
+        }
+
+        for ib in 0..nb {
+            for ic in 0..nc {
+                let line = &mut crossings[(ib + nb * ic) as usize];
+                line.sort_by(|x, y| x.partial_cmp(y).unwrap());
+                // Hits on shared edges are reported by both triangles.
+                line.dedup_by(|x, y| (*x - *y).abs() < 1e-9);
+
+                for ia in 0..grid.shape.0[axis] {
+                    let mut arr = [0; 3];
+                    arr[axis] = ia;
+                    arr[b] = ib;
+                    arr[c] = ic;
+                    let i = Index(arr);
+                    let pa = grid.coordinate(i).0[axis];
+                    let before = line.iter().take_while(|&&t| t < pa).count();
+                    if before % 2 == 0 {
+                        inside[index_to_linear(grid, i)] = false;
+                    }
+                }
+            }
+        }
+    }
+
+    inside
+}
+
+/// Where the line parallel to `axis` through the coordinates `pb` and `pc` in the two other axes
+/// crosses the triangle, given as the coordinate along `axis`.
+fn line_triangle_crossing(tri: &[Pos; 3], axis: usize, pb: f64, pc: f64) -> Option<f64> {
+    let (b, c) = ((axis + 1) % 3, (axis + 2) % 3);
+    let [p, q, r] = tri;
+    let area = (q.0[b] - p.0[b]) * (r.0[c] - p.0[c]) - (r.0[b] - p.0[b]) * (q.0[c] - p.0[c]);
+    if area.abs() < 1e-12 {
+        // The triangle is parallel to the line.
+        return None;
+    }
+    let u = ((q.0[b] - pb) * (r.0[c] - pc) - (r.0[b] - pb) * (q.0[c] - pc)) / area;
+    let v = ((r.0[b] - pb) * (p.0[c] - pc) - (p.0[b] - pb) * (r.0[c] - pc)) / area;
+    let w = 1.0 - u - v;
+    if u < 0.0 || v < 0.0 || w < 0.0 {
+        return None;
+    }
+    Some(u * p.0[axis] + v * q.0[axis] + w * r.0[axis])
+}
+
+/// For each point of the grid, in the order of [index_to_linear], the squared distance to the
+/// closest triangle.  Only distances below `max_dist` are exact; other points get [f64::MAX] or
+/// an upper bound.
+fn surface_distance2(grid: &VineyardsGrid, triangles: &[[Pos; 3]], max_dist: f64) -> Vec<f64> {
+    let mut dists = vec![f64::MAX; grid.volume() as usize];
+    for tri in triangles {
+        let mut lo = [0; 3];
+        let mut hi = [0; 3];
+        for j in 0..3 {
+            let min = tri.iter().map(|p| p.0[j]).fold(f64::MAX, f64::min) - max_dist;
+            let max = tri.iter().map(|p| p.0[j]).fold(f64::MIN, f64::max) + max_dist;
+            lo[j] = ((min - grid.corner.0[j]) / grid.size).ceil().max(0.0) as isize;
+            hi[j] = ((max - grid.corner.0[j]) / grid.size)
+                .floor()
+                .min(grid.shape.0[j] as f64 - 1.0) as isize;
+        }
+        for x in lo[0]..=hi[0] {
+            for y in lo[1]..=hi[1] {
+                for z in lo[2]..=hi[2] {
+                    let i = Index([x, y, z]);
+                    let d = point_triangle_distance2(grid.coordinate(i), tri);
+                    let k = index_to_linear(grid, i);
+                    dists[k] = dists[k].min(d);
+                }
+            }
+        }
+    }
+    dists
+}
+
+/// Squared distance from `p` to the closest point on the triangle.
+///
+/// From "Real-Time Collision Detection" by Christer Ericson, section 5.1.5.
+fn point_triangle_distance2(p: Pos, [a, b, c]: &[Pos; 3]) -> f64 {
+    let (a, b, c) = (*a, *b, *c);
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d1 = ab.dot(&ap);
+    let d2 = ac.dot(&ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return p.dist2(&a);
+    }
+
+    let bp = p - b;
+    let d3 = ab.dot(&bp);
+    let d4 = ac.dot(&bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return p.dist2(&b);
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        let v = d1 / (d1 - d3);
+        return p.dist2(&(a + ab * v));
+    }
+
+    let cp = p - c;
+    let d5 = ab.dot(&cp);
+    let d6 = ac.dot(&cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return p.dist2(&c);
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        let w = d2 / (d2 - d6);
+        return p.dist2(&(a + ac * w));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+        let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+        return p.dist2(&(b + (c - b) * w));
+    }
+
+    let denom = 1.0 / (va + vb + vc);
+    let v = vb * denom;
+    let w = vc * denom;
+    p.dist2(&(a + ab * v + ac * w))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_mesh_with_non_finite_vertices() {
+        let obj = "v 0 0 0\nv inf 0 0\nl 1 2\n";
+        let err = VineyardsGridMesh::read_from_obj_string(obj).unwrap_err();
+        assert_eq!(err.kind(), "parse");
+        assert!(err.message().starts_with("line 2:"));
+    }
+
+    #[test]
+    fn regular_grid_reaches_the_max_corner() {
+        // The cube is [-0.5, 0.5]^3.
+        let grid = VineyardsGrid::around_complex(&crate::test::test_complex_tet_cube(), 0.1, 0.0);
+        assert_eq!(grid.shape, Index([11, 11, 11]));
+        let max = grid.coordinate(Index([10, 10, 10]));
+        assert!(max.0.iter().all(|&c| 0.5 - 1e-9 <= c));
+    }
+
+    #[test]
+    fn around_complex_inside_cube() {
+        let complex = crate::test::test_complex_cube();
+        let grid = VineyardsGridMesh::around_complex(&complex, 2.0, GridRegion::Inside).unwrap();
+
+        assert!(!grid.points.is_empty());
+        for p in &grid.points {
+            for j in 0..3 {
+                assert!(-1.0 < p.0[j] && p.0[j] < 1.0, "{:?} is outside the cube", p);
+            }
+        }
+        for (v, ns) in grid.neighbors.iter().enumerate() {
+            for &w in ns {
+                assert!(grid.neighbors[w as usize].contains(&(v as isize)));
+            }
+        }
+    }
+
+    #[test]
+    fn around_complex_outside_and_shell_cube() {
+        let complex = crate::test::test_complex_cube();
+        let outside =
+            VineyardsGridMesh::around_complex(&complex, 2.0, GridRegion::Outside).unwrap();
+        for p in &outside.points {
+            assert!(
+                (0..3).any(|j| p.0[j].abs() > 1.0),
+                "{:?} is inside the cube",
+                p
+            );
+        }
+
+        let shell =
+            VineyardsGridMesh::around_complex(&complex, 2.0, GridRegion::Shell(0.3)).unwrap();
+        assert!(!shell.points.is_empty());
+        for p in &shell.points {
+            let dist_to_surface = (0..3)
+                .map(|j| (1.0 - p.0[j].abs()).abs())
+                .fold(f64::MAX, f64::min);
+            assert!(dist_to_surface < 0.3 + 1e-9, "{:?} is far from the cube", p);
+        }
+    }
+}
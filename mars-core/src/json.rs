@@ -2,6 +2,7 @@ use serde::Serialize;
 
 use crate::{
     complex::{Complex, Pos, Simplex},
+    filtration::Filtration,
     grid::{Index, VineyardsGrid},
     permutation::Permutation,
     BirthDeathPair, Reduction, Swaps,
@@ -30,6 +31,7 @@ pub struct JsonOuput {
 
 pub fn json_output(
     complex: &Complex,
+    filtration: &dyn Filtration,
     reduction: &Reduction,
     grid: &VineyardsGrid,
     swapssssss: Vec<(Index, Index, Swaps)>,
@@ -42,10 +44,10 @@ pub fn json_output(
     let edge_ordering = reduction.stacks[1].ordering.clone();
     let triangle_ordering = reduction.stacks[2].ordering.clone();
 
-    let empty_barcode = reduction.barcode(complex, -1);
-    let vertex_barcode = reduction.barcode(complex, 0);
-    let edge_barcode = reduction.barcode(complex, 1);
-    let triangle_barcode = reduction.barcode(complex, 2);
+    let empty_barcode = reduction.barcode(complex, filtration, -1);
+    let vertex_barcode = reduction.barcode(complex, filtration, 0);
+    let edge_barcode = reduction.barcode(complex, filtration, 1);
+    let triangle_barcode = reduction.barcode(complex, filtration, 2);
 
     let json_output = JsonOuput {
         vertices,
@@ -5,25 +5,46 @@ use std::{
 };
 
 use complex::{Complex, Pos};
+use error::{MarsError, MarsResult};
+use filtration::{Filtration, FiltrationKind};
 use grid::{Index, VineyardsGrid, VineyardsGridMesh};
 use permutation::Permutation;
 use serde::{Deserialize, Serialize};
 use sneaky_matrix::{SneakyMatrix, CI};
 use tracing::{info, warn};
 
+pub mod adaptive;
+pub mod axis_mesh;
+pub mod checkpoint;
+pub mod chunked;
 pub mod complex;
+pub mod error;
+pub mod feature_size;
+pub mod filtration;
+pub mod formats;
 pub mod grid;
 pub mod json;
 pub mod permutation;
+pub mod planar;
+pub mod points;
+pub mod probe;
+pub mod reconstruct;
+pub mod skeleton;
 pub mod sneaky_matrix;
+pub mod spatial;
+pub mod state;
 pub mod stats;
 #[cfg(test)]
 pub mod test;
+pub mod validate;
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Mars {
     pub complex: Option<Complex>,
     pub grid: Option<Grid>,
+    /// The filtration used for the vineyards.  Defaults to squared Euclidean distance.
+    #[serde(default)]
+    pub filtration: FiltrationKind,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -32,11 +53,48 @@ pub enum Grid {
     Mesh(VineyardsGridMesh),
 }
 
+impl Grid {
+    /// Number of edges in the grid, which is the number of vineyards steps needed to run it.
+    pub fn number_of_edges(&self) -> usize {
+        match self {
+            Grid::Regular(grid) => grid.number_of_grid_edges().max(0) as usize,
+            Grid::Mesh(mesh) => mesh.num_edges(),
+        }
+    }
+
+    /// Split the grid in two along its longest axis.  The second [Index] is the offset of the
+    /// second half relative to the first.  Returns [None] if the grid is too small to split.
+    pub fn split_in_two(&self) -> Option<(Grid, Grid, Index)> {
+        match self {
+            Grid::Regular(grid) => {
+                if grid.shape.0.iter().max().copied().unwrap_or(0) < 3 {
+                    return None;
+                }
+                let (a, b, offset) = grid.split_with_overlap();
+                Some((Grid::Regular(a), Grid::Regular(b), offset))
+            }
+            Grid::Mesh(mesh) => {
+                let (a, b) = mesh.split_in_half();
+                if a.num_edges() == 0 || b.num_edges() == 0 {
+                    return None;
+                }
+                Some((Grid::Mesh(a), Grid::Mesh(b), Index::fake(0)))
+            }
+        }
+    }
+}
+
 impl Mars {
-    /// Load a complex from an .obj string into the state.
-    pub fn load_from_obj_str(&mut self, obj_str: &str) -> Result<(), String> {
-        info!("load_from_obj_str");
-        let cplx = Complex::read_from_obj_string(obj_str)?;
+    /// Load a complex from a string into the state.  Despite the name, the string can be in any
+    /// of the text formats in [formats::Format].
+    pub fn load_from_obj_str(&mut self, obj_str: &str) -> MarsResult<()> {
+        self.load_from_bytes(obj_str.as_bytes())
+    }
+
+    /// Load a complex from the contents of a file in any of the formats in [formats::Format].
+    pub fn load_from_bytes(&mut self, bytes: &[u8]) -> MarsResult<()> {
+        info!("load_from_bytes");
+        let cplx = Complex::read_from_bytes(bytes)?;
         info!(
             "read complex #v={} #e={} #t={}",
             cplx.simplices_per_dim[0].len(),
@@ -48,7 +106,7 @@ impl Mars {
     }
 
     /// Load a mesh grid from an .obj string into the state.
-    pub fn load_meshgrid_from_obj_str(&mut self, obj_str: &str) -> Result<(), String> {
+    pub fn load_meshgrid_from_obj_str(&mut self, obj_str: &str) -> MarsResult<()> {
         self.grid = Some(Grid::Mesh(VineyardsGridMesh::read_from_obj_string(
             obj_str,
         )?));
@@ -56,9 +114,11 @@ impl Mars {
     }
 
     /// Return [Err] if we don't have a grid.
-    pub fn split_into_4(&self) -> Result<[SubMars; 4], String> {
+    pub fn split_into_4(&self) -> MarsResult<[SubMars; 4]> {
         let Some(ref g) = self.grid else {
-            return Err("Mars::split_into_4: missing grid".to_string());
+            return Err(MarsError::Invalid(
+                "Mars::split_into_4: missing grid".to_string(),
+            ));
         };
 
         match g {
@@ -73,6 +133,7 @@ impl Mars {
                         mars: Mars {
                             complex: self.complex.clone(),
                             grid: Some(Grid::Regular(aa)),
+                            filtration: self.filtration.clone(),
                         },
                         offset: Index([0; 3]),
                     },
@@ -80,6 +141,7 @@ impl Mars {
                         mars: Mars {
                             complex: self.complex.clone(),
                             grid: Some(Grid::Regular(ab)),
+                            filtration: self.filtration.clone(),
                         },
                         offset: ab_offset,
                     },
@@ -87,6 +149,7 @@ impl Mars {
                         mars: Mars {
                             complex: self.complex.clone(),
                             grid: Some(Grid::Regular(ba)),
+                            filtration: self.filtration.clone(),
                         },
                         offset: b_offset,
                     },
@@ -94,6 +157,7 @@ impl Mars {
                         mars: Mars {
                             complex: self.complex.clone(),
                             grid: Some(Grid::Regular(bb)),
+                            filtration: self.filtration.clone(),
                         },
                         offset: b_offset + bb_offset,
                     },
@@ -110,6 +174,7 @@ impl Mars {
                         mars: Mars {
                             complex: self.complex.clone(),
                             grid: Some(Grid::Mesh(aa)),
+                            filtration: self.filtration.clone(),
                         },
                         offset: fake,
                     },
@@ -117,6 +182,7 @@ impl Mars {
                         mars: Mars {
                             complex: self.complex.clone(),
                             grid: Some(Grid::Mesh(ab)),
+                            filtration: self.filtration.clone(),
                         },
                         offset: fake,
                     },
@@ -124,6 +190,7 @@ impl Mars {
                         mars: Mars {
                             complex: self.complex.clone(),
                             grid: Some(Grid::Mesh(ba)),
+                            filtration: self.filtration.clone(),
                         },
                         offset: fake,
                     },
@@ -131,6 +198,7 @@ impl Mars {
                         mars: Mars {
                             complex: self.complex.clone(),
                             grid: Some(Grid::Mesh(bb)),
+                            filtration: self.filtration.clone(),
                         },
                         offset: fake,
                     },
@@ -140,24 +208,98 @@ impl Mars {
         }
     }
 
-    /// Run Vineyards across the instance.
-    pub fn run<F: Fn(usize, usize)>(&self, progress: F) -> Result<Vineyards, String> {
-        let Some(ref c) = self.complex else {
-            return Err("Vineyards::run: no complex")?;
+    /// Split the instance into at most `n` sub-problems with roughly the same number of grid
+    /// edges, by repeatedly splitting the largest one in two.  Fewer than `n` sub-problems are
+    /// returned if the grid is too small.
+    ///
+    /// Return [Err] if we don't have a grid.
+    pub fn split_into(&self, n: usize) -> MarsResult<Vec<SubMars>> {
+        let Some(ref g) = self.grid else {
+            return Err(MarsError::Invalid(
+                "Mars::split_into: missing grid".to_string(),
+            ));
         };
+        if n == 0 {
+            return Err(MarsError::Invalid(
+                "Mars::split_into: cannot split into 0 parts".to_string(),
+            ));
+        }
+
+        // Parts as (grid, offset, can be split).
+        let mut parts = vec![(g.clone(), Index([0; 3]), true)];
+        while parts.len() < n {
+            let Some(k) = (0..parts.len())
+                .filter(|&k| parts[k].2)
+                .max_by_key(|&k| parts[k].0.number_of_edges())
+            else {
+                break;
+            };
+            match parts[k].0.split_in_two() {
+                Some((a, b, b_offset)) => {
+                    let offset = parts[k].1;
+                    parts[k] = (a, offset, true);
+                    parts.insert(k + 1, (b, offset + b_offset, true));
+                }
+                None => parts[k].2 = false,
+            }
+        }
+
+        Ok(parts
+            .into_iter()
+            .map(|(grid, offset, _)| SubMars {
+                mars: Mars {
+                    complex: self.complex.clone(),
+                    grid: Some(grid),
+                    filtration: self.filtration.clone(),
+                },
+                offset,
+            })
+            .collect())
+    }
 
+    /// The complex and the grid, if we have both and the complex can be used.
+    fn checked_inputs(&self) -> MarsResult<(&Complex, &Grid)> {
+        let Some(ref c) = self.complex else {
+            return Err(MarsError::Invalid("Vineyards::run: no complex".to_string()));
+        };
         let Some(ref g) = self.grid else {
-            return Err("Vineyards::run: no grid")?;
+            return Err(MarsError::Invalid("Vineyards::run: no grid".to_string()));
         };
+        for (dim, simplices) in c.simplices_per_dim.iter().enumerate() {
+            if CI::MAX as usize <= simplices.len() {
+                return Err(MarsError::Capacity(format!(
+                    "{} simplices of dim {} (max {})",
+                    simplices.len(),
+                    dim,
+                    CI::MAX
+                )));
+            }
+        }
+        let report = c.validate();
+        if report.has_errors() {
+            return Err(MarsError::Invalid(format!(
+                "the complex has missing faces or malformed simplices:\n{}",
+                report
+            )));
+        }
+        if let FiltrationKind::ScalarField(field) = &self.filtration {
+            field.validate(c)?;
+        }
+        Ok((c, g))
+    }
+
+    /// Run Vineyards across the instance.
+    pub fn run<F: Fn(usize, usize)>(&self, progress: F) -> MarsResult<Vineyards> {
+        let (c, g) = self.checked_inputs()?;
 
         let (reductions, all_swaps) = match g {
             Grid::Regular(r) => {
                 let i0 = Index([0; 3]);
                 let p = r.coordinate(i0);
-                let s0 = reduce_from_scratch(&c, p, false);
-                r.run_vineyards_in_grid(c, i0, s0, false, progress)
+                let s0 = reduce_from_scratch(c, &self.filtration, p, false);
+                r.run_vineyards_in_grid(c, &self.filtration, i0, s0, false, progress)
             }
-            Grid::Mesh(m) => m.run_vineyards(&c, false, progress),
+            Grid::Mesh(m) => m.run_vineyards(c, &self.filtration, false, progress),
         };
 
         // Split up the big list of swaps into one list per dimension, since this is always the
@@ -172,70 +314,79 @@ impl Mars {
                 .collect()
         }
 
-        let swaps = [
-            filter_dim(&all_swaps, 0),
-            filter_dim(&all_swaps, 1),
-            filter_dim(&all_swaps, 2),
-        ];
+        let swaps = (0..=c.dim())
+            .map(|dim| filter_dim(&all_swaps, dim))
+            .collect();
 
         Ok(Vineyards { reductions, swaps })
     }
 
     /// Run slim Vineyards across the instance.
-    pub fn run_slim<F: Fn(usize, usize)>(
-        &self,
-        progress: F,
-    ) -> Result<[Vec<(Index, Index, Vec<(Swap, f64, f64)>)>; 3], String> {
-        let Some(ref c) = self.complex else {
-            return Err("Vineyards::run: no complex")?;
-        };
+    pub fn run_slim<F: Fn(usize, usize)>(&self, progress: F) -> MarsResult<Vec<SlimSwapList>> {
+        let (c, g) = self.checked_inputs()?;
 
-        let Some(ref g) = self.grid else {
-            return Err("Vineyards::run: no grid")?;
-        };
+        let mut ret = vec![Vec::new(); c.dim() + 1];
 
-        let mut ret = [Vec::new(), Vec::new(), Vec::new()];
+        let on_edge = |edge: SlimEdge| push_slim_swaps(c, &self.filtration, &mut ret, edge);
 
         match g {
-            Grid::Regular(_) => {
-                todo!();
-            }
-            Grid::Mesh(m) => {
-                m.run_vineyards_slim(&c, false, progress, |from, to, from_red, to_red, swaps| {
-                    let mut inner_swaps = [Vec::new(), Vec::new(), Vec::new()];
-                    for s in swaps.v {
-                        let from_p = from_red
-                            .persistence(c, s.dim, s.i)
-                            .map(|b| b.lifetime())
-                            .expect(".persistence should always return Some");
-                        let to_p = to_red
-                            .persistence(c, s.dim, s.j)
-                            .map(|b| b.lifetime())
-                            .expect(".persistence should always return Some");
-
-                        inner_swaps[s.dim as usize].push((s, from_p, to_p));
-                    }
-                    for dim in 0..3 {
-                        ret[dim].push((from, to, std::mem::take(&mut inner_swaps[dim])));
-                    }
-                })
+            Grid::Regular(r) => {
+                r.run_vineyards_in_grid_slim(c, &self.filtration, false, progress, on_edge)
             }
+            Grid::Mesh(m) => m.run_vineyards_slim(c, &self.filtration, false, progress, on_edge),
         };
 
         Ok(ret)
     }
 }
 
+/// A grid edge crossed by a slim run: the two endpoints, their [Reduction]s, and the swaps across
+/// the edge.  This is what the `on_edge` callbacks of the slim runs get.
+pub struct SlimEdge<'a> {
+    pub from: Index,
+    pub to: Index,
+    pub from_red: &'a Reduction,
+    pub to_red: &'a Reduction,
+    pub swaps: Swaps,
+}
+
+/// Add the swaps across the edge to `ret`, together with the persistence lifetimes of the swapped
+/// simplices.  This is the `on_edge` callback of the slim runs.
+pub(crate) fn push_slim_swaps(
+    c: &Complex,
+    filtration: &dyn Filtration,
+    ret: &mut [SlimSwapList],
+    edge: SlimEdge,
+) {
+    let SlimEdge {
+        from,
+        to,
+        from_red,
+        to_red,
+        swaps,
+    } = edge;
+    let mut inner_swaps = vec![Vec::new(); ret.len()];
+    for s in swaps.v {
+        let from_p = from_red.persistence(c, filtration, s.dim, s.i).lifetime();
+        let to_p = to_red.persistence(c, filtration, s.dim, s.j).lifetime();
+
+        inner_swaps[s.dim as usize].push((s, from_p, to_p));
+    }
+    for (dim_ret, dim_swaps) in ret.iter_mut().zip(inner_swaps) {
+        dim_ret.push((from, to, dim_swaps));
+    }
+}
+
 /// Output data from running the Vineyard algorithm for an entire domain.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Vineyards {
     /// A [Reduction] for every [Index] of the grid we ran on.
     pub reductions: HashMap<Index, Reduction>,
 
-    /// Swaps for each dimension. This is a list of adjacent grid index pairs, together with
-    /// another [Vec]  of [Swap] objects, for each pair of simplices that were swapped in a
-    /// Faustian swap.  Empty pairs are not included.
-    pub swaps: [Vec<(Index, Index, Swaps)>; 3],
+    /// Swaps for each dimension of the complex. This is a list of adjacent grid index pairs,
+    /// together with another [Vec]  of [Swap] objects, for each pair of simplices that were
+    /// swapped in a Faustian swap.  Empty pairs are not included.
+    pub swaps: Vec<SwapList>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -250,16 +401,31 @@ pub struct PruningParam {
 
 impl Vineyards {
     /// Prune the swaps for a given dimension and return a new list of swaps.
+    ///
+    /// `filtration` must be the one the vineyards were computed with.
     pub fn prune_dim<F: Fn(usize, usize)>(
         &self,
         dim: usize,
         params: &PruningParam,
         complex: &Complex,
+        filtration: &dyn Filtration,
         progress: F,
-    ) -> Vec<(Index, Index, Swaps)> {
+    ) -> MarsResult<Vec<(Index, Index, Swaps)>> {
+        let Some(swaps) = self.swaps.get(dim) else {
+            return Err(MarsError::Invalid(format!(
+                "No swaps for dimension {} (have {})",
+                dim,
+                self.swaps.len()
+            )));
+        };
+        let reduction_at = |index: &Index| {
+            self.reductions.get(index).ok_or_else(|| {
+                MarsError::Invalid(format!("No reduction for grid point {:?}", index))
+            })
+        };
         let mut pruned = Vec::new();
-        let n = self.swaps[dim].len();
-        for (i, s) in self.swaps[dim].iter().enumerate() {
+        let n = swaps.len();
+        for (i, s) in swaps.iter().enumerate() {
             progress(i, n);
             if s.2.v.len() == 0 {
                 continue;
@@ -288,11 +454,15 @@ impl Vineyards {
 
             if params.persistence {
                 if let Some(dist) = params.persistence_threshold {
-                    let grid_index_a = s.0;
-                    let reduction_at_a = self.reductions.get(&grid_index_a).unwrap();
-                    let grid_index_b = s.1;
-                    let reduction_at_b = self.reductions.get(&grid_index_b).unwrap();
-                    dim_swaps.prune_persistence(&complex, reduction_at_a, reduction_at_b, dist)
+                    let reduction_at_a = reduction_at(&s.0)?;
+                    let reduction_at_b = reduction_at(&s.1)?;
+                    dim_swaps.prune_persistence(
+                        complex,
+                        filtration,
+                        reduction_at_a,
+                        reduction_at_b,
+                        dist,
+                    )
                 } else {
                     warn!(
                         "params dim {}: persistence was true but threshold was None",
@@ -308,7 +478,7 @@ impl Vineyards {
             pruned.push((s.0, s.1, dim_swaps));
         }
 
-        pruned
+        Ok(pruned)
     }
 
     /// Add the swaps from another [Vineyards] instance.  The indices of the other instance is
@@ -319,7 +489,10 @@ impl Vineyards {
         }
 
         // Add swaps
-        for dim in 0..3 {
+        if self.swaps.len() < other.swaps.len() {
+            self.swaps.resize(other.swaps.len(), Vec::new());
+        }
+        for dim in 0..other.swaps.len() {
             // Properly merge in swaps for existing grid index pairs so that we don't get
             // duplicates.
             let swaps = &mut self.swaps[dim];
@@ -342,6 +515,10 @@ impl Vineyards {
 
 pub type SwapList = Vec<(Index, Index, Swaps)>;
 
+/// Like [SwapList], but for the output of [Mars::run_slim].  Each [Swap] also has the persistence
+/// lifetimes of the two swapped simplices.
+pub type SlimSwapList = Vec<(Index, Index, Vec<(Swap, f64, f64)>)>;
+
 /// Sub problems for a [Mars] instance.  This is just like a regular instance, except that we have
 /// a offset for the grid which we need to map the swaps we compute here to the right "coordinate
 /// system"  in the [Mars] instance from which the [SubMars] was created.
@@ -355,7 +532,7 @@ pub struct SubMars {
 impl SubMars {
     /// Run Vineyards, and map the result swaps back to the original coorinate system of the [Mars]
     /// instance this [SubMars] instance came from.
-    pub fn run<F: Fn(usize, usize)>(&self, progress: F) -> Result<Vineyards, String> {
+    pub fn run<F: Fn(usize, usize)>(&self, progress: F) -> MarsResult<Vineyards> {
         let inner = self.mars.run(progress)?;
 
         let reductions = inner
@@ -364,7 +541,7 @@ impl SubMars {
             .map(|(index, reduction)| (index + self.offset, reduction))
             .collect();
 
-        let mut swaps = inner
+        let swaps = inner
             .swaps
             .into_iter()
             .map(|s| {
@@ -372,24 +549,14 @@ impl SubMars {
                     .map(|(i, j, swaps)| (i + self.offset, j + self.offset, swaps))
                     .collect()
             })
-            .collect::<Vec<_>>();
-
-        let swaps2 = swaps.pop().unwrap();
-        let swaps1 = swaps.pop().unwrap();
-        let swaps0 = swaps.pop().unwrap();
+            .collect();
 
-        Ok(Vineyards {
-            reductions,
-            swaps: [swaps0, swaps1, swaps2],
-        })
+        Ok(Vineyards { reductions, swaps })
     }
 
     /// Run Vineyards, and map the result swaps back to the original coorinate system of the [Mars]
     /// instance this [SubMars] instance came from.
-    pub fn run_slim<F: Fn(usize, usize)>(
-        &self,
-        progress: F,
-    ) -> Result<[Vec<(Index, Index, Vec<(Swap, f64, f64)>)>; 3], String> {
+    pub fn run_slim<F: Fn(usize, usize)>(&self, progress: F) -> MarsResult<Vec<SlimSwapList>> {
         let mut swaps_all_dims = self.mars.run_slim(progress)?;
 
         for swaps in &mut swaps_all_dims {
@@ -412,6 +579,23 @@ pub struct Swap {
     pub i: CI,
     /// Canonical index of the second simplex.
     pub j: CI,
+    /// Where along the vineyards step the two simplices changed order, from `0` at the old key
+    /// point to `1` at the new one.  Swaps read from files written before this was recorded are
+    /// put at the midpoint.
+    #[serde(default = "Swap::midpoint")]
+    pub t: f64,
+}
+
+impl Swap {
+    fn midpoint() -> f64 {
+        0.5
+    }
+
+    /// The point on the segment from `a` to `b` where the swap happened, if `a` and `b` are the
+    /// key points of the vineyards step.
+    pub fn crossing(&self, a: Pos, b: Pos) -> Pos {
+        a + (b - a) * self.t
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -440,7 +624,7 @@ impl Swaps {
     pub fn prune_common_face(&mut self, complex: &Complex) {
         let mut simp_to_vertices: HashMap<(usize, CI), HashSet<CI>> = HashMap::new();
 
-        for dim in 1..3 {
+        for dim in 1..complex.simplices_per_dim.len() {
             for (s, i) in complex.simplices_per_dim[dim].iter().zip(0..) {
                 if dim == 1 {
                     let set = simp_to_vertices
@@ -481,7 +665,7 @@ impl Swaps {
         // (dim, id) to [id].
         let mut coboundary: HashMap<(usize, CI), HashSet<CI>> = HashMap::new();
 
-        for dim in 1..3 {
+        for dim in 1..complex.simplices_per_dim.len() {
             for (s, parent_i) in complex.simplices_per_dim[dim].iter().zip(0..) {
                 for face_i in &s.boundary {
                     let v = coboundary
@@ -493,7 +677,7 @@ impl Swaps {
         }
 
         self.v.retain(|swap| {
-            if swap.dim == 2 {
+            if swap.dim == complex.dim() {
                 return true;
             }
 
@@ -518,23 +702,15 @@ impl Swaps {
     pub fn prune_persistence(
         &mut self,
         complex: &Complex,
+        filtration: &dyn Filtration,
         reduction_from: &Reduction,
         reduction_to: &Reduction,
         lifetime: f64,
     ) {
         self.v.retain(|swap| {
-            let persistence_i = reduction_from.persistence(complex, swap.dim, swap.i);
-            let persistence_j = reduction_to.persistence(complex, swap.dim, swap.j);
-            match (persistence_i, persistence_j) {
-                (Some(p), Some(q)) => {
-                    if p.lifetime() < lifetime && q.lifetime() < lifetime {
-                        false
-                    } else {
-                        true
-                    }
-                }
-                _ => true,
-            }
+            let p = reduction_from.persistence(complex, filtration, swap.dim, swap.i);
+            let q = reduction_to.persistence(complex, filtration, swap.dim, swap.j);
+            !(p.lifetime() < lifetime && q.lifetime() < lifetime)
         });
     }
 
@@ -625,7 +801,7 @@ pub mod prune {
     pub fn prune_common_face(swaps: &mut SlimSwap, complex: &Complex) {
         let mut simp_to_vertices: HashMap<(usize, CI), HashSet<CI>> = HashMap::new();
 
-        for dim in 1..3 {
+        for dim in 1..complex.simplices_per_dim.len() {
             for (s, i) in complex.simplices_per_dim[dim].iter().zip(0..) {
                 if dim == 1 {
                     let set = simp_to_vertices
@@ -666,7 +842,7 @@ pub mod prune {
         // (dim, id) to [id].
         let mut coboundary: HashMap<(usize, CI), HashSet<CI>> = HashMap::new();
 
-        for dim in 1..3 {
+        for dim in 1..complex.simplices_per_dim.len() {
             for (s, parent_i) in complex.simplices_per_dim[dim].iter().zip(0..) {
                 for face_i in &s.boundary {
                     let v = coboundary
@@ -678,7 +854,7 @@ pub mod prune {
         }
 
         swaps.retain(|(swap, _, _)| {
-            if swap.dim == 2 {
+            if swap.dim == complex.dim() {
                 return true;
             }
 
@@ -754,26 +930,25 @@ impl BirthDeathPair {
 pub struct Reduction {
     /// Key point around which the reduction is done.
     pub key_point: Pos,
-    /// Matrices for three dimensions.
-    pub stacks: [Stack; 3],
+    /// Matrices for each dimension of the complex.
+    pub stacks: Vec<Stack>,
 }
 
 impl Reduction {
     /// "Bake" all matrices for all dimensions. This means running
     /// [`SneakyMatrix::bake_in_permutations`] for all matrices.
     pub fn bake_all_matrices(&mut self) {
-        for dim in 0..3 {
-            self.stacks[dim].U_t.bake_in_permutations();
-            self.stacks[dim].R.bake_in_permutations();
-            self.stacks[dim].D.bake_in_permutations();
+        for stack in &mut self.stacks {
+            stack.U_t.bake_in_permutations();
+            stack.R.bake_in_permutations();
+            stack.D.bake_in_permutations();
         }
     }
 
-    /// Returns the Betti numbers for dimensions 0, 1, and 2.
+    /// Returns the Betti numbers for each dimension of the complex.
     pub fn betti_numbers(&self) -> Vec<i8> {
-        let mut bettis = vec![0; 3];
-        for dim in 0..3 {
-            let stack = &self.stacks[dim];
+        let mut bettis = vec![0; self.stacks.len()];
+        for (dim, stack) in self.stacks.iter().enumerate() {
             for c in 0..stack.R.cols() {
                 if stack.R.col_is_empty(c) {
                     bettis[dim] += 1;
@@ -785,22 +960,17 @@ impl Reduction {
         bettis
     }
 
-    /// Compute the entering value of the given simplex.
-    ///
-    /// Uses squared Euclidian distance.
+    /// Compute the entering value of the given simplex in the filtration at the key point.
     ///
     /// The `id` is a canonical index.
-    pub fn simplex_entering_value(&self, complex: &Complex, dim: usize, id: CI) -> f64 {
-        let simplex = &complex.simplices_per_dim[dim][id as usize];
-        if dim == 0 {
-            return simplex.coords.unwrap().dist2(&self.key_point);
-        }
-        simplex
-            .boundary
-            .iter()
-            .map(|&b| self.simplex_entering_value(complex, dim - 1, b))
-            .max_by(|a, b| a.partial_cmp(b).unwrap())
-            .unwrap()
+    pub fn simplex_entering_value(
+        &self,
+        complex: &Complex,
+        filtration: &dyn Filtration,
+        dim: usize,
+        id: CI,
+    ) -> f64 {
+        filtration.simplex_value(complex, dim, id, self.key_point)
     }
 
     /// Find the killer of the given "simplex", if any.
@@ -838,36 +1008,58 @@ impl Reduction {
     /// Compute the persistence of the given "simplex".
     /// `id` is the canonical index.
     ///
-    /// Returns [None] if the "simplex" is not killed.
-    pub fn persistence(&self, complex: &Complex, dim: usize, id: CI) -> Option<BirthDeathPair> {
+    /// If the simplex gives birth to a class, this is the pair of that class, and otherwise it is
+    /// the pair of the class that the simplex kills.
+    pub fn persistence(
+        &self,
+        complex: &Complex,
+        filtration: &dyn Filtration,
+        dim: usize,
+        id: CI,
+    ) -> BirthDeathPair {
         let killer = self.find_killer(dim, id);
         if let Some(killer) = killer {
-            let dist = self.simplex_entering_value(complex, dim, id);
-            let killer_dist = self.simplex_entering_value(complex, dim + 1, killer);
-            Some(BirthDeathPair {
+            let dist = self.simplex_entering_value(complex, filtration, dim, id);
+            let killer_dist = self.simplex_entering_value(complex, filtration, dim + 1, killer);
+            BirthDeathPair {
                 dim: dim as isize,
                 birth: Some((dist, id)),
                 death: Some((killer_dist, killer)),
-            })
+            }
         } else {
             // If we're the top dimension we will never be killed, but we might
             // have births. Check if column is zero.
             let ord_i = self.stacks[dim].ordering.map(id);
             if self.stacks[dim].R.col_is_empty(ord_i) {
-                let dist = self.simplex_entering_value(complex, dim, id);
-                Some(BirthDeathPair {
+                let dist = self.simplex_entering_value(complex, filtration, dim, id);
+                BirthDeathPair {
                     dim: dim as isize,
                     birth: Some((dist, id)),
                     death: None,
-                })
+                }
             } else {
-                panic!("we are in here after all!");
-                None
+                // The simplex kills a class of one dimension lower, so it is the death of that
+                // class.  The vertex that kills the empty simplex has no birth.
+                let death = self.simplex_entering_value(complex, filtration, dim, id);
+                let birth = self.find_victim(dim, id).map(|victim| {
+                    let birth = self.simplex_entering_value(complex, filtration, dim - 1, victim);
+                    (birth, victim)
+                });
+                BirthDeathPair {
+                    dim: dim as isize - 1,
+                    birth,
+                    death: Some((death, id)),
+                }
             }
         }
     }
 
-    pub fn barcode(&self, complex: &Complex, dim: isize) -> Vec<BirthDeathPair> {
+    pub fn barcode(
+        &self,
+        complex: &Complex,
+        filtration: &dyn Filtration,
+        dim: isize,
+    ) -> Vec<BirthDeathPair> {
         let mut ret = Vec::new();
 
         if dim == -1 {
@@ -876,7 +1068,10 @@ impl Reduction {
                 ret.push(BirthDeathPair {
                     dim: -1,
                     birth: None,
-                    death: Some((self.simplex_entering_value(complex, 0, first), first)),
+                    death: Some((
+                        self.simplex_entering_value(complex, filtration, 0, first),
+                        first,
+                    )),
                 });
             }
             return ret;
@@ -890,9 +1085,7 @@ impl Reduction {
             if !R.gives_birth(ordering.map(simplex.id)) {
                 continue;
             }
-            if let Some(persistence) = self.persistence(complex, dim as usize, simplex.id) {
-                ret.push(persistence);
-            }
+            ret.push(self.persistence(complex, filtration, dim as usize, simplex.id));
         }
         ret
     }
@@ -902,107 +1095,57 @@ impl Reduction {
 impl Reduction {
     pub fn D(&self, dim: isize) -> &SneakyMatrix {
         assert!(0 <= dim);
-        assert!(dim <= 2);
+        assert!((dim as usize) < self.stacks.len());
         &self.stacks[dim as usize].D
     }
 
     pub fn R(&self, dim: isize) -> &SneakyMatrix {
         assert!(0 <= dim);
-        assert!(dim <= 2);
+        assert!((dim as usize) < self.stacks.len());
         &self.stacks[dim as usize].R
     }
 
     pub fn U_t(&self, dim: isize) -> &SneakyMatrix {
         assert!(0 <= dim);
-        assert!(dim <= 2);
+        assert!((dim as usize) < self.stacks.len());
         &self.stacks[dim as usize].U_t
     }
 
     pub fn ordering(&self, dim: isize) -> &Permutation {
         assert!(0 <= dim);
-        assert!(dim <= 2);
+        assert!((dim as usize) < self.stacks.len());
         &self.stacks[dim as usize].ordering
     }
 
     /// Checks that the ordering is consistent.
-    pub fn assert_ordering(&self, complex: &Complex) {
-        let mut vertex_distance = HashMap::new();
-
-        let mut vertex_order = (0..complex.simplices_per_dim[0].len() as CI)
-            .map(|i| {
-                let s = &complex.simplices_per_dim[0][i as usize];
-                let coords = s.coords.unwrap();
-                let dist = coords.dist2(&self.key_point);
-                vertex_distance.insert(i, dist);
-                let sorted_order = self.stacks[0].ordering.map(i);
-                (float_ord::FloatOrd(dist), sorted_order)
-            })
-            .collect::<Vec<_>>();
-        vertex_order.sort();
-
-        for t in vertex_order.windows(2) {
-            let a = t[0];
-            let b = t[1];
-            assert!(a.1 <= b.1, "a[1] = {:?}, b[1] = {:?}", a.1, b.1);
-        }
-
-        let mut edge_dist = HashMap::new();
-        let mut edge_order = (0..complex.simplices_per_dim[1].len() as CI)
-            .map(|i| {
-                let s = &complex.simplices_per_dim[1][i as usize];
-                let ai = s.boundary[0];
-                let bi = s.boundary[1];
-
-                let adist = *vertex_distance.get(&ai).unwrap();
-                let bdist = *vertex_distance.get(&bi).unwrap();
-
-                let dist = adist.max(bdist);
-                edge_dist.insert(i, dist);
-
-                let sorted_order = self.stacks[1].ordering.map(i);
-                (float_ord::FloatOrd(dist), sorted_order)
-            })
-            .collect::<Vec<_>>();
-        edge_order.sort();
-
-        for t in edge_order.windows(2) {
-            let a = t[0];
-            let b = t[1];
-            assert!(a.1 <= b.1, "a.1 = {:?}, b.1 = {:?}", a, b);
-        }
-
-        let mut tri_order = (0..complex.simplices_per_dim[2].len() as CI)
-            .map(|i| {
-                let s = &complex.simplices_per_dim[2][i as usize];
-
-                let ai = s.boundary[0];
-                let bi = s.boundary[1];
-                let ci = s.boundary[1];
-
-                let adist = *edge_dist.get(&ai).unwrap();
-                let bdist = *edge_dist.get(&bi).unwrap();
-                let cdist = *edge_dist.get(&ci).unwrap();
-
-                let dist = adist.max(bdist).max(cdist);
-
-                let sorted_order = self.stacks[2].ordering.map(i);
-                (float_ord::FloatOrd(dist), sorted_order)
-            })
-            .collect::<Vec<_>>();
-        tri_order.sort();
+    pub fn assert_ordering(&self, complex: &Complex, filtration: &dyn Filtration) {
+        for (dim, values) in filtration
+            .values(complex, self.key_point)
+            .into_iter()
+            .enumerate()
+        {
+            let mut order = values
+                .into_iter()
+                .enumerate()
+                .map(|(i, dist)| {
+                    let sorted_order = self.stacks[dim].ordering.map(i as CI);
+                    (float_ord::FloatOrd(dist), sorted_order)
+                })
+                .collect::<Vec<_>>();
+            order.sort();
 
-        for t in tri_order.windows(2) {
-            let a = t[0];
-            let b = t[1];
-            assert!(a.0 <= b.0, "a[0] = {:?}, b[0] = {:?}", a.0, b.0); // This will always pass
-            assert!(a.1 <= b.1, "a[1] = {:?}, b[1] = {:?}", a.1, b.1);
+            for t in order.windows(2) {
+                let a = t[0];
+                let b = t[1];
+                assert!(a.1 <= b.1, "dim {}: a = {:?}, b = {:?}", dim, a, b);
+            }
         }
     }
 }
 
 /// The permutations returned are such that when you go forwards through the
-/// permutation, you get the simplices in sorted order based on their distance
-/// to the key point.
+/// permutation, you get the simplices in sorted order based on their value in
+/// the filtration at the key point.  There is one permutation per dimension.
 ///
 /// I.e, `v_perm[0]` is the canonical index of the closest vertex, and
 /// `v_perm[1]` is the canonical index of the second closest index.
@@ -1010,163 +1153,117 @@ impl Reduction {
 /// In other words, `v_perm.map` takes a "sorted" index and returns a "canonical" index.
 fn compute_permutations(
     complex: &Complex,
+    filtration: &dyn Filtration,
     key_point: Pos,
-) -> (Permutation, Permutation, Permutation) {
-    let vertex_distances = complex.simplices_per_dim[0]
-        .iter()
-        .map(|v| float_ord::FloatOrd(v.coords.unwrap().dist2(&key_point)))
-        .collect::<Vec<_>>();
-
-    let edge_distances = complex.simplices_per_dim[1]
-        .iter()
-        .map(|e| {
-            let dist_a = vertex_distances[e.boundary[0] as usize];
-            let dist_b = vertex_distances[e.boundary[1] as usize];
-            dist_a.max(dist_b)
-        })
-        .collect::<Vec<_>>();
-
-    let triangle_distances = complex.simplices_per_dim[2]
-        .iter()
-        .map(|f| {
-            let dist_a = edge_distances[f.boundary[0] as usize];
-            let dist_b = edge_distances[f.boundary[1] as usize];
-            let dist_c = edge_distances[f.boundary[2] as usize];
-            dist_a.max(dist_b).max(dist_c)
+) -> Vec<Permutation> {
+    filtration
+        .values(complex, key_point)
+        .into_iter()
+        .map(|values| {
+            let distances = values
+                .into_iter()
+                .map(float_ord::FloatOrd)
+                .collect::<Vec<_>>();
+            Permutation::from_ord(&distances)
         })
-        .collect::<Vec<_>>();
-
-    let v_perm = Permutation::from_ord(&vertex_distances);
-    let e_perm = Permutation::from_ord(&edge_distances);
-    let t_perm = Permutation::from_ord(&triangle_distances);
-
-    (v_perm, e_perm, t_perm)
+        .collect()
 }
 
 /// Run Vineyards from a previous [`Reduction`] to a new key point.
 pub fn vineyards_step(
     complex: &Complex,
+    filtration: &dyn Filtration,
     reduction: &Reduction,
     key_point: Pos,
     require_hom_birth_to_be_first: bool,
 ) -> (Reduction, Swaps) {
-    let (mut v_perm, mut e_perm, mut t_perm) = compute_permutations(complex, key_point);
+    let perms = compute_permutations(complex, filtration, key_point);
 
-    let mut stack0 = reduction.stacks[0].clone();
-    let mut stack1 = reduction.stacks[1].clone();
-    let mut stack2 = reduction.stacks[2].clone();
+    let mut stacks = reduction.stacks.clone();
 
     let mut faustian_swap_simplices = Vec::new();
 
-    if 0 < t_perm.len() {
-        t_perm.reverse();
-        let vine_ordering2 = Permutation::from_to(&stack2.ordering, &t_perm);
-        let (swap_is2, simplices_that_got_swapped2) =
-            compute_transpositions(vine_ordering2.clone().into_forwards());
-        for (swap_i, &i) in swap_is2.iter().enumerate() {
-            let res = perform_one_swap_top_dim(i, &mut stack2);
-            stack2.D.swap_cols(i, i + 1);
-            if let Some(true) = res {
-                let (i, j) = simplices_that_got_swapped2[swap_i];
-                let cann_i = t_perm.inv(i);
-                let cann_j = t_perm.inv(j);
-
-                faustian_swap_simplices.push(Swap {
-                    dim: 2,
-                    i: cann_i,
-                    j: cann_j,
-                });
-            }
+    // Go from the top dimension and down, since the swaps in one dimension permute the rows of the
+    // matrices in the dimension above.
+    for (dim, mut perm) in perms.into_iter().enumerate().rev() {
+        // The vertices always have to be sorted, since we need the permutation for the next step.
+        if 0 < dim && perm.len() == 0 {
+            continue;
         }
 
-        stack2.ordering = t_perm;
-    }
-
-    if 0 < e_perm.len() {
-        e_perm.reverse();
-        let vine_ordering1 = Permutation::from_to(&stack1.ordering, &e_perm);
-        let (swap_is1, simplices_that_got_swapped1) =
-            compute_transpositions(vine_ordering1.clone().into_forwards());
+        // NOTE: we need the permutation from cannonical to sorted, so that we can
+        // get the permutation that takes us from the `b` point to the `a` point, so
+        // that, in turn, we can bubble sort from `a` to `b`.
+        perm.reverse();
+
+        // perm[0] = 12            this is the column at which s0 is at in the ordering.
+        // stack.ordering[0] = 14  this is the column at which s0 is at in the stack ordering.
+        // if vine_ordering[12] == 14, then
+        // vine_ordering[perm[0]] == stack.ordering[0]  means that
+        // s0 is column 12 at this ordering, and column 14 at the old ordering.
+        // Which means that vine_ordering takes a new column index and produces an old column index.
+
+        // This should map old to new indices.
+        let (lower, upper) = stacks.split_at_mut(dim + 1);
+        let stack = &mut lower[dim];
+        let vine_ordering = Permutation::from_to(&stack.ordering, &perm);
+
+        //  0 . 1 . 2 . 3 . 4 ...   <--- old indices
+        // [0,  3,  1,  4,  2, ...] <------- new indices
+
+        // First swap:
+        //      1,  3
+        // This was at index i=1, and corresponds to simplices at (3) and (1).
+
+        // swap_is has to contain ordered indices from the OLD ordering.
+        let (swap_is, simplices_that_got_swapped) =
+            compute_transpositions(vine_ordering.into_forwards());
+
+        let mut up = upper.first_mut().map(|up| {
+            let up_U_t = &mut up.U_t;
+            let up_cwi = ColWithInv::new(&mut up.R);
+            (up_cwi, up_U_t, &mut up.D)
+        });
 
-        let up_U_t = &mut stack2.U_t;
-        let mut up_cwi = ColWithInv::new(&mut stack2.R);
+        for (swap_i, &i) in swap_is.iter().enumerate() {
+            let res = match up {
+                Some((ref mut up_cwi, ref mut up_U_t, ref mut up_D)) => {
+                    let res = perform_one_swap(i, stack, up_cwi, up_U_t);
+                    up_D.swap_rows(i, i + 1);
+                    res
+                }
+                None => perform_one_swap_top_dim(i, stack),
+            };
+            stack.D.swap_cols(i, i + 1);
 
-        for (swap_i, &i) in swap_is1.iter().enumerate() {
-            let res = perform_one_swap(i, &mut stack1, &mut up_cwi, up_U_t);
-            stack1.D.swap_cols(i, i + 1);
-            stack2.D.swap_rows(i, i + 1);
             if let Some(true) = res {
-                let (i, j) = simplices_that_got_swapped1[swap_i];
-                let cann_i = e_perm.inv(i);
-                let cann_j = e_perm.inv(j);
+                // These are indices of simplices that we said were the 0,1,2... order
+                // in the bubble sort (compute_transpositions).  This is the order
+                // of the simplices at `a`.
+                let (i, j) = simplices_that_got_swapped[swap_i];
+                let cann_i = perm.inv(i);
+                let cann_j = perm.inv(j);
 
                 faustian_swap_simplices.push(Swap {
-                    dim: 1,
+                    dim,
                     i: cann_i,
                     j: cann_j,
+                    t: crossing_time(
+                        complex,
+                        filtration,
+                        dim,
+                        (cann_i, cann_j),
+                        reduction.key_point,
+                        key_point,
+                    ),
                 });
             }
         }
 
-        stack1.ordering = e_perm;
-    }
-
-    // NOTE: we need the permutation from cannonical to sorted, so that we can
-    // get the permutation that takes us from the `b` point to the `a` point, so
-    // that, in turn, we can bubble sort from `a` to `b`.
-    v_perm.reverse();
-
-    // v_perm[0] = 12          this is the column at which v0 is at in the ordering.
-    // stack0.ordering[0] = 14 this is the column at which v0 is at in the stack0 ordering.
-    // if vine_ordering0[12] == 14, then
-    // vine_ordering0[v_perm[0]] == stack0.ordering[0]  means that
-    // v0 is column 12 at this ordering, and column 14 at the old ordering.
-    // Which means that vine_ordering0 takes a new column index and produces an old column index.
-
-    // This should map old to new indices.
-    let vine_ordering0 = Permutation::from_to(&stack0.ordering, &v_perm);
-
-    //  0 . 1 . 2 . 3 . 4 ...   <--- old indices
-    // [0,  3,  1,  4,  2, ...] <------- new indices
-
-    // First swap:
-    //      1,  3
-    // This was at index i=1, and corresponds to simplices at (3) and (1).
-
-    // swap_is0 has to contain ordered indices from the OLD ordering.
-    let (swap_is0, simplices_that_got_swapped0) =
-        compute_transpositions(vine_ordering0.clone().into_forwards());
-
-    let up_U_t = &mut stack1.U_t;
-    let mut up_cwi = ColWithInv::new(&mut stack1.R);
-
-    for (swap_i, &i) in swap_is0.iter().enumerate() {
-        let res = perform_one_swap(i, &mut stack0, &mut up_cwi, up_U_t);
-        stack0.D.swap_cols(i, i + 1);
-        stack1.D.swap_rows(i, i + 1);
-
-        if let Some(true) = res {
-            // These are indices of simplices that we said were the 0,1,2... order
-            // in the bubble sort (compute_transpositions).  This is the order
-            // of the simplices at `a`.
-            let (i, j) = simplices_that_got_swapped0[swap_i];
-            let cann_i = v_perm.inv(i);
-            let cann_j = v_perm.inv(j);
-
-            faustian_swap_simplices.push(Swap {
-                dim: 0,
-                i: cann_i,
-                j: cann_j,
-            });
-        }
+        stack.ordering = perm;
     }
 
-    stack0.ordering = v_perm;
-
-    let state = Reduction {
-        key_point,
-        stacks: [stack0, stack1, stack2],
-    };
+    let state = Reduction { key_point, stacks };
 
     if require_hom_birth_to_be_first {
         // We now have a bunch of faustian swaps.  However, we only want the first "real" cycle for
@@ -1182,7 +1279,12 @@ pub fn vineyards_step(
         /// Finds the simplex that creates the first homology class in a dimension.
         ///
         /// Returns the canonical index of the simplex.
-        fn find_interesting(reduction: &Reduction, complex: &Complex, dim: usize) -> Option<CI> {
+        fn find_interesting(
+            reduction: &Reduction,
+            complex: &Complex,
+            filtration: &dyn Filtration,
+            dim: usize,
+        ) -> Option<CI> {
             #[allow(non_snake_case)]
             let R = &reduction.stacks[dim].R;
 
@@ -1191,9 +1293,7 @@ pub fn vineyards_step(
                     continue;
                 }
                 let can_i = reduction.stacks[dim].ordering.inv(ord_i);
-                let Some(p) = reduction.persistence(complex, dim, can_i) else {
-                    continue;
-                };
+                let p = reduction.persistence(complex, filtration, dim, can_i);
                 if 1e-6 < p.lifetime() {
                     return Some(can_i);
                 }
@@ -1207,8 +1307,8 @@ pub fn vineyards_step(
 
         // TODO: range 0..3
         for dim in 1..2 {
-            let old_interesting_edge = find_interesting(reduction, complex, dim);
-            let new_interesting_edge = find_interesting(&state, complex, dim);
+            let old_interesting_edge = find_interesting(reduction, complex, filtration, dim);
+            let new_interesting_edge = find_interesting(&state, complex, filtration, dim);
 
             if let Some((min, max)) = old_interesting_edge
                 .zip(new_interesting_edge)
@@ -1230,107 +1330,57 @@ pub fn vineyards_step(
 
 /// Compute the reduction at a key point from scratch.
 #[allow(non_snake_case)]
-pub fn reduce_from_scratch(complex: &Complex, key_point: Pos, noisy: bool) -> Reduction {
+pub fn reduce_from_scratch(
+    complex: &Complex,
+    filtration: &dyn Filtration,
+    key_point: Pos,
+    noisy: bool,
+) -> Reduction {
     info!("reduce from scratch");
-    let (mut v_perm, mut e_perm, mut t_perm) = compute_permutations(complex, key_point);
-
-    let mut boundary_0 = complex.boundary_matrix(0);
-    // Order the cols and rows of the three boundary matrices by their distance
-    // to the key point. This permutation is computed above, so we just need to
-    // set the fields.
-    boundary_0.col_perm = Some(v_perm.clone());
-    let D0 = boundary_0.clone();
-
-    let mut boundary_1 = complex.boundary_matrix(1);
-    boundary_1.col_perm = Some(e_perm.clone());
-    boundary_1.row_perm = Some(v_perm.clone());
-    let D1 = boundary_1.clone();
-
-    let mut boundary_2 = complex.boundary_matrix(2);
-    boundary_2.col_perm = Some(t_perm.clone());
-    boundary_2.row_perm = Some(e_perm.clone());
-    let D2 = boundary_2.clone();
-
-    if noisy {
-        print!("Reduce dim0 ... ");
-    }
-    let adds0 = boundary_0.reduce();
-    if noisy {
-        print!("done\nReduce dim1 ... ");
-    }
-    let adds1 = boundary_1.reduce();
-    if noisy {
-        print!("done\nReduce dim2 ... ");
-    }
-    let adds2 = boundary_2.reduce();
-    if noisy {
-        println!("done");
-    }
-
-    let mut V0 = SneakyMatrix::eye(boundary_0.cols());
-    for (target, other) in adds0 {
-        V0.add_cols(target, other);
-    }
-
-    let mut V1 = SneakyMatrix::eye(boundary_1.cols());
-    for (target, other) in adds1 {
-        V1.add_cols(target, other);
-    }
-
-    let mut V2 = SneakyMatrix::eye(boundary_2.cols());
-    for (target, other) in adds2 {
-        V2.add_cols(target, other);
-    }
-
-    if noisy {
-        print!("Invert V0 ... ");
-    }
-    let U_t0 = V0.inverse_gauss_jordan().transpose();
-    if noisy {
-        print!("done\nInvert V1 ... ");
-    }
-    let U_t1 = V1.inverse_gauss_jordan().transpose();
-    if noisy {
-        print!("done\nInvert V2 ... ");
-    }
-    let U_t2 = V2.inverse_gauss_jordan().transpose();
-    if noisy {
-        println!("done");
-    }
-
-    let R0 = boundary_0;
-    let R1 = boundary_1;
-    let R2 = boundary_2;
-
-    v_perm.reverse();
-    e_perm.reverse();
-    t_perm.reverse();
-
-    let ret = Reduction {
-        key_point,
-        stacks: [
-            Stack {
-                D: D0,
-                R: R0,
-                U_t: U_t0,
-                ordering: v_perm,
-            },
-            Stack {
-                D: D1,
-                R: R1,
-                U_t: U_t1,
-                ordering: e_perm,
-            },
-            Stack {
-                D: D2,
-                R: R2,
-                U_t: U_t2,
-                ordering: t_perm,
-            },
-        ],
-    };
+    let perms = compute_permutations(complex, filtration, key_point);
+
+    let mut stacks = Vec::with_capacity(perms.len());
+    for (dim, perm) in perms.iter().enumerate() {
+        // Order the cols and rows of the boundary matrices by their distance
+        // to the key point. This permutation is computed above, so we just need to
+        // set the fields.
+        let mut boundary = complex.boundary_matrix(dim as isize);
+        boundary.col_perm = Some(perm.clone());
+        if 0 < dim {
+            boundary.row_perm = Some(perms[dim - 1].clone());
+        }
+        let D = boundary.clone();
+
+        if noisy {
+            print!("Reduce dim{} ... ", dim);
+        }
+        let adds = boundary.reduce();
+        if noisy {
+            print!("done\nInvert V{} ... ", dim);
+        }
+
+        let mut V = SneakyMatrix::eye(boundary.cols());
+        for (target, other) in adds {
+            V.add_cols(target, other);
+        }
+        let U_t = V.inverse_gauss_jordan().transpose();
+        if noisy {
+            println!("done");
+        }
 
-    ret.assert_ordering(&complex);
+        let mut ordering = perm.clone();
+        ordering.reverse();
+        stacks.push(Stack {
+            D,
+            R: boundary,
+            U_t,
+            ordering,
+        });
+    }
+
+    let ret = Reduction { key_point, stacks };
+
+    ret.assert_ordering(complex, filtration);
     info!("reduce from scratch done");
 
     ret
@@ -1721,6 +1771,79 @@ fn perform_one_swap_top_dim(i: CI, stack: &mut Stack) -> Option<bool> {
     panic!("This should never happen: no cases matched.");
 }
 
+/// Number of bisection steps in [crossing_time], which gives the crossing to within `2^-30` of
+/// the step.
+const CROSSING_STEPS: usize = 30;
+
+/// Find where along the segment from `a` to `b` the simplices `i` and `j` of dimension `dim`
+/// change order, as the parameter `t` in `[0, 1]`.
+///
+/// The filtration values aren't linear along the segment in general, so we bisect on the order of
+/// the two simplices, with ties broken by the canonical index like in [compute_permutations].  If
+/// the order is the same at both ends, the midpoint is returned.  A simplex enters with the last
+/// of its vertices, so each step only looks up the values of the vertices of the two simplices.
+fn crossing_time(
+    complex: &Complex,
+    filtration: &dyn Filtration,
+    dim: usize,
+    (i, j): (CI, CI),
+    a: Pos,
+    b: Pos,
+) -> f64 {
+    let vertices_of = |id: CI| {
+        let mut ids = vec![id];
+        for d in (1..=dim).rev() {
+            ids = ids
+                .iter()
+                .flat_map(|&s| complex.simplices_per_dim[d][s as usize].boundary.iter())
+                .copied()
+                .collect();
+            ids.sort();
+            ids.dedup();
+        }
+        ids
+    };
+    let (vertices_i, vertices_j) = (vertices_of(i), vertices_of(j));
+    let mut vertices = [vertices_i.as_slice(), vertices_j.as_slice()].concat();
+    vertices.sort();
+    vertices.dedup();
+    let slot = |v: &CI| vertices.binary_search(v).unwrap();
+    let (slots_i, slots_j) = (
+        vertices_i.iter().map(slot).collect::<Vec<_>>(),
+        vertices_j.iter().map(slot).collect::<Vec<_>>(),
+    );
+
+    let i_first = |t: f64| {
+        let p = a + (b - a) * t;
+        let values = vertices
+            .iter()
+            .map(|&v| filtration.vertex_value(complex, v, p))
+            .collect::<Vec<_>>();
+        let value = |slots: &[usize]| {
+            let v = slots
+                .iter()
+                .map(|&k| values[k])
+                .fold(f64::NEG_INFINITY, f64::max);
+            float_ord::FloatOrd(v)
+        };
+        (value(&slots_i), i) < (value(&slots_j), j)
+    };
+    let at_a = i_first(0.0);
+    if at_a == i_first(1.0) {
+        return 0.5;
+    }
+    let (mut lo, mut hi) = (0.0, 1.0);
+    for _ in 0..CROSSING_STEPS {
+        let mid = (lo + hi) / 2.0;
+        if i_first(mid) == at_a {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    (lo + hi) / 2.0
+}
+
 /// Compute the transpositions required to swap a permutation to become `0..n`.
 ///
 /// Returns a `Vec<usize>` where each element `i` correspond to the transposition
@@ -1758,6 +1881,58 @@ pub fn compute_transpositions(mut b: Vec<CI>) -> (Vec<CI>, Vec<(CI, CI)>) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use filtration::SquaredEuclidean;
+
+    #[test]
+    fn split_into_balances_edges() {
+        let mars = Mars {
+            complex: None,
+            grid: Some(Grid::Regular(test::test_grid_for_cube())),
+            ..Default::default()
+        };
+        let total = mars.grid.as_ref().unwrap().number_of_edges();
+
+        for n in 1..=6 {
+            let parts = mars.split_into(n).unwrap();
+            assert_eq!(parts.len(), n);
+            let edges = parts
+                .iter()
+                .map(|p| p.mars.grid.as_ref().unwrap().number_of_edges())
+                .collect::<Vec<_>>();
+            // The parts overlap, so there are at least as many edges in total.
+            assert!(total <= edges.iter().sum());
+            assert!(*edges.iter().max().unwrap() <= total / n * 2 + 1);
+        }
+
+        // A 5x5x5 grid can only be split so far.
+        assert!(mars.split_into(1000).unwrap().len() < 1000);
+    }
+
+    #[test]
+    fn swaps_are_at_the_crossings() {
+        let grid = test::test_grid_for_cube();
+        let mars = Mars {
+            complex: Some(test::test_complex_cube()),
+            grid: Some(Grid::Regular(grid.clone())),
+            ..Default::default()
+        };
+        let vineyards = mars.run(|_, _| {}).unwrap();
+        let complex = mars.complex.as_ref().unwrap();
+
+        let mut num_swaps = 0;
+        for (a, b, swaps) in vineyards.swaps.iter().flatten() {
+            let (pa, pb) = (grid.coordinate(*a), grid.coordinate(*b));
+            for s in &swaps.v {
+                num_swaps += 1;
+                assert!((0.0..=1.0).contains(&s.t));
+                let p = s.crossing(pa, pb);
+                let vi = mars.filtration.simplex_value(complex, s.dim, s.i, p);
+                let vj = mars.filtration.simplex_value(complex, s.dim, s.j, p);
+                assert!((vi - vj).abs() < 1e-6, "{:?} {} {}", s, vi, vj);
+            }
+        }
+        assert!(0 < num_swaps);
+    }
 
     #[test]
     fn test_compute_transpositions() {
@@ -1797,7 +1972,7 @@ mod tests {
         let complex = test_complex_cube();
 
         fn test(complex: &Complex, pos: complex::Pos) {
-            let mut reduction = reduce_from_scratch(complex, pos, false);
+            let mut reduction = reduce_from_scratch(complex, &SquaredEuclidean, pos, false);
             reduction.bake_all_matrices();
 
             for dim in 0..3 {
@@ -1812,6 +1987,94 @@ mod tests {
         test(&complex, complex::Pos([0.0, -0.5, 0.25]));
     }
 
+    #[test]
+    fn persistence_of_a_killing_simplex_is_its_victims_pair() {
+        let complex = test_complex_tet_cube();
+        let reduction = reduce_from_scratch(&complex, &SquaredEuclidean, Pos([0.1; 3]), false);
+
+        let mut num_killers = 0;
+        for dim in 1..complex.simplices_per_dim.len() {
+            for s in &complex.simplices_per_dim[dim] {
+                let Some(victim) = reduction.find_victim(dim, s.id) else {
+                    continue;
+                };
+                num_killers += 1;
+                let p = reduction.persistence(&complex, &SquaredEuclidean, dim, s.id);
+                let q = reduction.persistence(&complex, &SquaredEuclidean, dim - 1, victim);
+                assert_eq!(p.dim, dim as isize - 1);
+                assert_eq!(p.death.map(|d| d.1), Some(s.id));
+                assert_eq!(p.birth.map(|b| b.1), Some(victim));
+                assert_eq!(p.birth, q.birth);
+                assert_eq!(p.death, q.death);
+            }
+        }
+        assert!(0 < num_killers);
+    }
+
+    #[test]
+    fn run_slim_regular_grid_matches_run() {
+        let mars = Mars {
+            complex: Some(test_complex_cube()),
+            grid: Some(Grid::Regular(test_grid_for_cube())),
+            ..Default::default()
+        };
+
+        let full = mars.run(|_, _| {}).expect("failed to run mars");
+        let slim = mars.run_slim(|_, _| {}).expect("failed to run slim mars");
+
+        assert_eq!(full.swaps.len(), slim.len());
+        for (full, slim) in full.swaps.iter().zip(&slim) {
+            let mut full_pairs = full
+                .iter()
+                .map(|(i, j, s)| ((*i).min(*j), (*i).max(*j), s.v.len()))
+                .collect::<Vec<_>>();
+            let mut slim_pairs = slim
+                .iter()
+                .filter(|t| !t.2.is_empty())
+                .map(|(i, j, s)| ((*i).min(*j), (*i).max(*j), s.len()))
+                .collect::<Vec<_>>();
+            full_pairs.sort();
+            slim_pairs.sort();
+            assert_eq!(full_pairs, slim_pairs);
+        }
+    }
+
+    #[test]
+    fn tetrahedra_run_slim_matches_run() {
+        let complex = test_complex_tet_cube();
+        let reduction = reduce_from_scratch(&complex, &SquaredEuclidean, Pos([0.1; 3]), false);
+        // Reduced homology, since the vertices have the empty simplex as boundary.
+        assert_eq!(reduction.betti_numbers(), [0, 0, 0, 0]);
+
+        let mars = Mars {
+            complex: Some(complex),
+            grid: Some(Grid::Regular(test_grid_for_cube())),
+            ..Default::default()
+        };
+        let full = mars.run(|_, _| {}).expect("failed to run mars");
+        let slim = mars.run_slim(|_, _| {}).expect("failed to run slim mars");
+        assert_eq!(full.swaps.len(), 4);
+        assert_eq!(slim.len(), 4);
+
+        assert_eq!(full.swaps.len(), slim.len());
+        for (full, slim) in full.swaps.iter().zip(&slim) {
+            let full_count = full.iter().map(|s| s.2.v.len()).sum::<usize>();
+            let slim_count = slim.iter().map(|s| s.2.len()).sum::<usize>();
+            assert_eq!(full_count, slim_count);
+        }
+
+        // The swaps of the tetrahedra can be pruned like any other dimension.
+        let complex = mars.complex.as_ref().unwrap();
+        let params = default_pruning_param(3);
+        let full = full
+            .prune_dim(3, &params, complex, &mars.filtration, |_, _| {})
+            .unwrap();
+        let slim = prune::prune_dim(&slim[3], 3, &params, complex, |_, _| {});
+        let full_count = full.iter().map(|s| s.2.v.len()).sum::<usize>();
+        let slim_count = slim.iter().map(|s| s.2.len()).sum::<usize>();
+        assert_eq!(full_count, slim_count);
+    }
+
     #[test]
     fn snapshot_medial_axes_for_grid() {
         let complex = test_complex_cube();
@@ -1820,15 +2083,24 @@ mod tests {
         let mars = Mars {
             complex: Some(complex),
             grid: Some(Grid::Regular(grid)),
+            ..Default::default()
         };
 
         let no_progress = |_, _| {};
 
-        let vin = mars.run(&no_progress).expect("failed to run mars");
+        let vin = mars.run(no_progress).expect("failed to run mars");
 
         for dim in 0..3 {
             let params = default_pruning_param(dim);
-            let pruned = vin.prune_dim(dim, &params, mars.complex.as_ref().unwrap(), no_progress);
+            let pruned = vin
+                .prune_dim(
+                    dim,
+                    &params,
+                    mars.complex.as_ref().unwrap(),
+                    &mars.filtration,
+                    no_progress,
+                )
+                .unwrap();
             let mut pairs = pruned
                 .into_iter()
                 .filter(|t| t.2.v.len() > 0)
@@ -0,0 +1,290 @@
+//! Medial axes of planar complexes.
+//!
+//! If the complex lies in a plane `z = c`, we can run Vineyards on a 2D [VineyardsGrid] in the
+//! same plane (see [VineyardsGrid::around_planar_complex]).  The dual of a grid edge is then a
+//! line segment instead of a quad, and the medial axes are curves, which we output as polylines.
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    complex::{Complex, Pos},
+    grid::{Index, VineyardsGrid},
+};
+
+/// A set of polylines with shared vertices.
+#[derive(Clone, Debug, Default)]
+pub struct Polylines {
+    pub points: Vec<Pos>,
+    /// Each polyline is a list of indices into `points`.  Closed polylines start and end with the
+    /// same index.
+    pub lines: Vec<Vec<usize>>,
+}
+
+impl Polylines {
+    /// Join the duals of the given grid edges into polylines.  The polylines are split at the
+    /// points where more than two segments meet.
+    pub fn from_grid_edges<I: IntoIterator<Item = (Index, Index)>>(
+        grid: &VineyardsGrid,
+        edges: I,
+    ) -> Self {
+        let mut node_of: HashMap<[isize; 2], usize> = HashMap::new();
+        let mut points = Vec::new();
+        let mut segments = Vec::new();
+
+        for (a, b) in edges {
+            let cells = grid.dual_segment_cells(a, b);
+            let pts = grid.dual_segment_points(a, b);
+            let [u, v] = [0, 1].map(|k| {
+                *node_of.entry(cells[k]).or_insert_with(|| {
+                    points.push(pts[k]);
+                    points.len() - 1
+                })
+            });
+            segments.push((u, v));
+        }
+
+        Self::from_segments(points, segments)
+    }
+
+    /// Join line segments between `points` into polylines.  The polylines are split at the points
+    /// where more than two segments meet, or where a polyline ends.  Repeated segments are only
+    /// used once.
+    pub fn from_segments<I: IntoIterator<Item = (usize, usize)>>(
+        points: Vec<Pos>,
+        segments: I,
+    ) -> Self {
+        let mut neighbors: Vec<Vec<usize>> = vec![Vec::new(); points.len()];
+        let mut seen = HashSet::new();
+        for (u, v) in segments {
+            if u != v && seen.insert((u.min(v), u.max(v))) {
+                neighbors[u].push(v);
+                neighbors[v].push(u);
+            }
+        }
+
+        let mut used = HashSet::new();
+        let mut lines = Vec::new();
+        // Follow the axis from `start` through `next` until we get to a point that isn't on exactly
+        // two segments, or back to `start`.
+        let walk = |start: usize, next: usize, used: &mut HashSet<(usize, usize)>| {
+            let mut line = vec![start, next];
+            let (mut prev, mut cur) = (start, next);
+            used.insert((prev.min(cur), prev.max(cur)));
+            while neighbors[cur].len() == 2 && cur != start {
+                let n = if neighbors[cur][0] == prev {
+                    neighbors[cur][1]
+                } else {
+                    neighbors[cur][0]
+                };
+                if !used.insert((cur.min(n), cur.max(n))) {
+                    break;
+                }
+                (prev, cur) = (cur, n);
+                line.push(cur);
+            }
+            line
+        };
+
+        // Open polylines go between points where the axis ends or branches.
+        for (v, ns) in neighbors.iter().enumerate() {
+            if ns.len() == 2 {
+                continue;
+            }
+            for &n in ns {
+                if !used.contains(&(v.min(n), v.max(n))) {
+                    lines.push(walk(v, n, &mut used));
+                }
+            }
+        }
+        // Everything left is a closed loop.
+        for (v, ns) in neighbors.iter().enumerate() {
+            for &n in ns {
+                if !used.contains(&(v.min(n), v.max(n))) {
+                    lines.push(walk(v, n, &mut used));
+                }
+            }
+        }
+
+        Self { points, lines }
+    }
+
+    /// Total number of line segments in all polylines.
+    pub fn num_segments(&self) -> usize {
+        self.lines.iter().map(|l| l.len() - 1).sum()
+    }
+
+    /// Write the polylines as `v` and `l` lines of an .obj file.  `vertex_offset` is the number of
+    /// vertices that are already written to the file.
+    pub fn write_as_obj<W: std::io::Write>(
+        &self,
+        mut w: W,
+        vertex_offset: usize,
+    ) -> std::io::Result<()> {
+        for p in &self.points {
+            writeln!(w, "v {} {} {}", p.x(), p.y(), p.z())?;
+        }
+        for line in &self.lines {
+            write!(w, "l")?;
+            for i in line {
+                write!(w, " {}", vertex_offset + i + 1)?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write the edges of the complex and the medial axes, one [Polylines] per dimension, as an .svg
+/// image of the xy plane.
+pub fn write_svg<W: std::io::Write>(
+    mut w: W,
+    complex: &Complex,
+    axes: &[Polylines],
+) -> std::io::Result<()> {
+    const COLORS: [&str; 4] = ["#d62728", "#1f77b4", "#2ca02c", "#9467bd"];
+
+    let vertex = |v: usize| complex.simplices_per_dim[0][v].coords.unwrap();
+    let all_points = complex.simplices_per_dim[0]
+        .iter()
+        .filter_map(|s| s.coords)
+        .chain(axes.iter().flat_map(|a| a.points.iter().copied()));
+    let (mut min, mut max) = ([f64::MAX; 2], [f64::MIN; 2]);
+    for p in all_points {
+        for j in 0..2 {
+            min[j] = min[j].min(p.0[j]);
+            max[j] = max[j].max(p.0[j]);
+        }
+    }
+    if max[0] < min[0] {
+        (min, max) = ([0.0; 2], [1.0; 2]);
+    }
+    let span = (max[0] - min[0]).max(max[1] - min[1]).max(f64::EPSILON);
+    let pad = span * 0.05;
+    let stroke = span / 400.0;
+
+    writeln!(
+        w,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}">"#,
+        min[0] - pad,
+        -max[1] - pad,
+        max[0] - min[0] + 2.0 * pad,
+        max[1] - min[1] + 2.0 * pad
+    )?;
+    // SVG has y pointing down.
+    writeln!(
+        w,
+        r#"<g transform="scale(1,-1)" fill="none" stroke-width="{}" stroke-linecap="round" stroke-linejoin="round">"#,
+        stroke
+    )?;
+
+    write!(w, r#"<path id="complex" stroke="black" d=""#)?;
+    for e in &complex.simplices_per_dim[1] {
+        let (a, b) = (
+            vertex(e.boundary[0] as usize),
+            vertex(e.boundary[1] as usize),
+        );
+        write!(w, "M{} {}L{} {}", a.x(), a.y(), b.x(), b.y())?;
+    }
+    writeln!(w, r#""/>"#)?;
+
+    for (dim, axis) in axes.iter().enumerate() {
+        writeln!(
+            w,
+            r#"<g id="ma-dim-{}" stroke="{}">"#,
+            dim,
+            COLORS[dim % COLORS.len()]
+        )?;
+        for line in &axis.lines {
+            write!(w, r#"<polyline points=""#)?;
+            for (k, &i) in line.iter().enumerate() {
+                let p = axis.points[i];
+                let sep = if k == 0 { "" } else { " " };
+                write!(w, "{}{},{}", sep, p.x(), p.y())?;
+            }
+            writeln!(w, r#""/>"#)?;
+        }
+        writeln!(w, "</g>")?;
+    }
+
+    writeln!(w, "</g>")?;
+    writeln!(w, "</svg>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test::test_complex_square, Grid, Mars};
+
+    #[test]
+    fn square_outline_axis() {
+        let complex = test_complex_square();
+        let grid = VineyardsGrid::around_planar_complex(&complex, 0.1, 0.05).unwrap();
+        assert!(grid.is_planar());
+        assert_eq!(grid.corner.z(), 0.0);
+
+        let mars = Mars {
+            complex: Some(complex),
+            grid: Some(Grid::Regular(grid.clone())),
+            ..Default::default()
+        };
+        let swaps = mars.run_slim(|_, _| {}).unwrap();
+        let axes = swaps
+            .iter()
+            .map(|dim_swaps| {
+                Polylines::from_grid_edges(
+                    &grid,
+                    dim_swaps
+                        .iter()
+                        .filter(|(_, _, s)| !s.is_empty())
+                        .map(|(a, b, _)| (*a, *b)),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // The filtration is given by the corners, so the 0-dimensional axis is the Voronoi diagram
+        // of the corners: the x and y axes, joined at the origin.
+        let axis = &axes[0];
+        assert!(axis.points.iter().all(|p| p.z() == 0.0));
+        for p in &axis.points {
+            assert!(
+                p.x().abs() < 1e-9 || p.y().abs() < 1e-9,
+                "{:?} is off axis",
+                p
+            );
+        }
+        assert_eq!(axis.lines.len(), 4);
+        let origin = axis.lines[0].last().unwrap();
+        assert!(axis.points[*origin].dist(&Pos([0.0; 3])) < 1e-9);
+        assert!(axis.lines.iter().all(|l| l.contains(origin)));
+
+        let mut svg = Vec::new();
+        write_svg(&mut svg, mars.complex.as_ref().unwrap(), &axes).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(svg.contains(r#"<g id="ma-dim-1""#));
+        assert!(svg.contains("<polyline"));
+    }
+
+    #[test]
+    fn segments_are_joined() {
+        let grid = VineyardsGrid::new(Pos([0.0; 3]), 1.0, [4, 4, 1]);
+        // A straight line of three segments, and a loop around the point (1, 1).
+        let edges = [
+            (Index([1, 1, 0]), Index([1, 2, 0])),
+            (Index([2, 1, 0]), Index([2, 2, 0])),
+            (Index([3, 1, 0]), Index([3, 2, 0])),
+        ];
+        let line = Polylines::from_grid_edges(&grid, edges);
+        assert_eq!(line.lines.len(), 1);
+        assert_eq!(line.lines[0].len(), 4);
+
+        let edges = [
+            (Index([1, 1, 0]), Index([2, 1, 0])),
+            (Index([1, 1, 0]), Index([0, 1, 0])),
+            (Index([1, 1, 0]), Index([1, 2, 0])),
+            (Index([1, 1, 0]), Index([1, 0, 0])),
+        ];
+        let square = Polylines::from_grid_edges(&grid, edges);
+        assert_eq!(square.lines.len(), 1);
+        assert_eq!(square.points.len(), 4);
+        assert_eq!(square.lines[0].first(), square.lines[0].last());
+    }
+}
@@ -0,0 +1,782 @@
+//! Complexes built from point clouds.
+//!
+//! Scanned inputs often only have points, without edges or faces.  We build a [Complex] on the
+//! points, either as a Vietoris–Rips complex or as an alpha complex, both truncated to the
+//! 2-skeleton.
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+use tracing::warn;
+
+use crate::{
+    complex::{Complex, Pos},
+    error::{MarsError, MarsResult},
+    sneaky_matrix::CI,
+    spatial::PointIndex,
+};
+
+/// How to build a [Complex] from a point cloud.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum PointComplex {
+    /// An edge between all points closer than `radius`, and a triangle for any three points that
+    /// are pairwise connected.
+    Rips { radius: f64 },
+    /// The simplices of the Delaunay triangulation that have an empty circumsphere with radius
+    /// at most `radius`.
+    Alpha { radius: f64 },
+}
+
+impl PointComplex {
+    /// Build the complex on the given points.
+    pub fn build(&self, points: Vec<Pos>) -> MarsResult<Complex> {
+        match *self {
+            PointComplex::Rips { radius } => rips_complex(points, radius),
+            PointComplex::Alpha { radius } => alpha_complex(points, radius),
+        }
+    }
+}
+
+/// Read a point cloud.  Each line is either an .obj vertex `v x y z`, or three coordinates
+/// `x y z`, separated by whitespace or commas, possibly followed by more columns like normals or
+/// colors, which are ignored.  Empty lines, comments starting with `#` and all other .obj lines are
+/// skipped.
+pub fn read_points_from_str(input_str: &str) -> MarsResult<Vec<Pos>> {
+    let mut points = Vec::new();
+    for (line_i, line) in input_str.lines().enumerate() {
+        let line = line.trim();
+        let mut fields = line
+            .split(|c: char| c.is_ascii_whitespace() || c == ',')
+            .filter(|f| !f.is_empty())
+            .peekable();
+        match fields.peek() {
+            None => continue,
+            Some(&"v") => {
+                fields.next();
+            }
+            Some(f) if f.parse::<f64>().is_err() => continue,
+            Some(_) => {}
+        }
+        let mut coord = || -> MarsResult<f64> {
+            let f = fields.next().ok_or_else(|| {
+                MarsError::Parse(format!("line {}: expected three coordinates", line_i + 1))
+            })?;
+            f.parse::<f64>()
+                .map_err(|e| MarsError::Parse(format!("line {}: {:?}: {}", line_i + 1, f, e)))
+        };
+        points.push(Pos([coord()?, coord()?, coord()?]));
+    }
+    if points.is_empty() {
+        return Err(MarsError::Parse("No points in input".to_string()));
+    }
+    Ok(points)
+}
+
+/// Build the Vietoris–Rips complex of the points with the given radius, truncated to the
+/// 2-skeleton.
+pub fn rips_complex(points: Vec<Pos>, radius: f64) -> MarsResult<Complex> {
+    if radius.is_nan() || radius <= 0.0 {
+        return Err(MarsError::Invalid(format!(
+            "The radius must be positive, got {}",
+            radius
+        )));
+    }
+    // Neighbors with a higher index, sorted.
+    let index = PointIndex::new(&points, radius);
+    let neighbors = points
+        .iter()
+        .enumerate()
+        .map(|(i, &p)| {
+            let mut ns = index
+                .within(p, radius)
+                .filter(|&j| i < j)
+                .collect::<Vec<_>>();
+            ns.sort();
+            ns
+        })
+        .collect::<Vec<_>>();
+
+    let mut cells = Vec::new();
+    for (i, ns) in neighbors.iter().enumerate() {
+        for (k, &j) in ns.iter().enumerate() {
+            cells.push(vec![i as CI, j as CI]);
+            for &l in &ns[k + 1..] {
+                if neighbors[j].binary_search(&l).is_ok() {
+                    cells.push(vec![i as CI, j as CI, l as CI]);
+                }
+            }
+        }
+    }
+    Complex::from_cells(points, &cells)
+}
+
+/// Build the alpha complex of the points with the given radius, truncated to the 2-skeleton.
+///
+/// A simplex of the Delaunay triangulation is included if its smallest circumsphere has radius at
+/// most `radius` and has no other points inside, or if it is the face of an included simplex.
+/// Included tetrahedra are collapsed, so that the complex has the same homotopy type as the full
+/// alpha complex.  Points that all lie in a plane are triangulated in that plane.
+pub fn alpha_complex(points: Vec<Pos>, radius: f64) -> MarsResult<Complex> {
+    if radius.is_nan() || radius <= 0.0 {
+        return Err(MarsError::Invalid(format!(
+            "The radius must be positive, got {}",
+            radius
+        )));
+    }
+    if points.len() < 3 {
+        return Err(MarsError::Invalid(
+            "The alpha complex needs at least three points".to_string(),
+        ));
+    }
+    // The opposite vertex of each coface, for all triangles and edges.  The alpha values are
+    // computed for the perturbed points, so that they are consistent with the triangulation.
+    let mut triangles: BTreeMap<[usize; 3], Vec<usize>> = BTreeMap::new();
+    let (tets, perturbed) = match plane_coordinates(&points) {
+        Some(flat) => {
+            let (tris, perturbed) = delaunay_2d(&flat);
+            for mut t in tris {
+                t.sort();
+                triangles.insert(t, Vec::new());
+            }
+            (Vec::new(), perturbed)
+        }
+        None => {
+            let (tets, perturbed) = delaunay(&points);
+            for t in &tets {
+                for (face, opposite) in faces_of_tet(*t) {
+                    triangles.entry(face).or_default().push(opposite);
+                }
+            }
+            (tets, perturbed)
+        }
+    };
+    if triangles.is_empty() {
+        return Err(MarsError::Invalid(
+            "Failed to triangulate the points".to_string(),
+        ));
+    }
+
+    let mut edges: BTreeMap<[usize; 2], Vec<usize>> = BTreeMap::new();
+    for &[a, b, c] in triangles.keys() {
+        edges.entry([a, b]).or_default().push(c);
+        edges.entry([a, c]).or_default().push(b);
+        edges.entry([b, c]).or_default().push(a);
+    }
+
+    // The squared alpha value of a simplex is the squared radius of its smallest circumsphere,
+    // unless the circumsphere contains the opposite vertex of a coface.  Then the simplex enters
+    // with its first coface.
+    let pos = |vs: &[usize]| vs.iter().map(|&v| perturbed[v]).collect::<Vec<_>>();
+    let tet_alpha = tets
+        .iter()
+        .map(|t| {
+            let mut key = *t;
+            key.sort();
+            (key, circumsphere(&pos(t)).1)
+        })
+        .collect::<HashMap<_, _>>();
+    let alpha = |vs: &[usize], opposite: &[usize], coface_alpha: &dyn Fn(usize) -> f64| {
+        let (center, r2) = circumsphere(&pos(vs));
+        let attached = opposite.iter().any(|&o| perturbed[o].dist2(&center) < r2);
+        if attached {
+            opposite
+                .iter()
+                .map(|&o| coface_alpha(o))
+                .fold(f64::MAX, f64::min)
+        } else {
+            r2
+        }
+    };
+
+    let mut triangle_alpha = HashMap::new();
+    for (tri, opposite) in &triangles {
+        let a = alpha(tri, opposite, &|o| {
+            let mut key = [tri[0], tri[1], tri[2], o];
+            key.sort();
+            tet_alpha[&key]
+        });
+        triangle_alpha.insert(*tri, a);
+    }
+
+    let r2 = radius * radius;
+    let removed = collapse_tets(tets.iter().filter(|t| {
+        let mut key = **t;
+        key.sort();
+        tet_alpha[&key] <= r2
+    }));
+
+    let mut cells = Vec::new();
+    for (edge, opposite) in &edges {
+        let a = alpha(edge, opposite, &|o| {
+            let mut key = [edge[0], edge[1], o];
+            key.sort();
+            triangle_alpha[&key]
+        });
+        if a <= r2 {
+            cells.push(edge.map(|v| v as CI).to_vec());
+        }
+    }
+    for tri in triangles.keys() {
+        if triangle_alpha[tri] <= r2 && !removed.contains(tri) {
+            cells.push(tri.map(|v| v as CI).to_vec());
+        }
+    }
+    Complex::from_cells(points, &cells)
+}
+
+/// The coordinates of the points in the plane `z = 0`, if they all lie in one plane, up to
+/// rounding.  Their tetrahedra would all be flat, so we triangulate them in 2D instead.
+fn plane_coordinates(points: &[Pos]) -> Option<Vec<Pos>> {
+    // The plane through the first point, the point farthest from it, and the point farthest from
+    // the line through those two.
+    let a = points[0];
+    let b = *points
+        .iter()
+        .max_by(|p, q| a.dist2(p).total_cmp(&a.dist2(q)))?;
+    let ab = b - a;
+    let off_line = |p: &Pos| {
+        let n = ab.cross(&(*p - a));
+        n.dot(&n)
+    };
+    let c = *points
+        .iter()
+        .max_by(|p, q| off_line(p).total_cmp(&off_line(q)))?;
+    let normal = ab.cross(&(c - a));
+    let area = normal.dot(&normal).sqrt();
+    let extent = ab.dot(&ab).sqrt();
+    if area <= 1e-12 * extent * extent {
+        // All points are on a line, which we can't triangulate at all.
+        return None;
+    }
+    let normal = normal / area;
+    if points
+        .iter()
+        .any(|p| 1e-6 * extent < normal.dot(&(*p - a)).abs())
+    {
+        return None;
+    }
+    let u = ab / extent;
+    let v = normal.cross(&u);
+    Some(
+        points
+            .iter()
+            .map(|p| Pos([u.dot(&(*p - a)), v.dot(&(*p - a)), 0.0]))
+            .collect(),
+    )
+}
+
+/// The four triangles of a tetrahedron, sorted, each with the opposite vertex.
+fn faces_of_tet(t: [usize; 4]) -> [([usize; 3], usize); 4] {
+    [0, 1, 2, 3].map(|skip| {
+        let mut face = [t[(skip + 1) % 4], t[(skip + 2) % 4], t[(skip + 3) % 4]];
+        face.sort();
+        (face, t[skip])
+    })
+}
+
+/// Collapse the given tetrahedra: repeatedly remove a tetrahedron together with a triangle that
+/// is not a face of any other remaining tetrahedron.  Returns the removed triangles.
+///
+/// Leaving out the tetrahedra would leave a hollow shell of triangles, which has a 2-dimensional
+/// hole that the alpha complex doesn't have.  Collapsing instead keeps the homotopy type.  Some
+/// sets of tetrahedra can't be collapsed all the way; the rest are left hollow, and we warn about
+/// it.
+fn collapse_tets<'a, I: Iterator<Item = &'a [usize; 4]>>(tets: I) -> HashSet<[usize; 3]> {
+    let tets = tets.copied().collect::<Vec<_>>();
+    let mut cofaces: HashMap<[usize; 3], Vec<usize>> = HashMap::new();
+    for (i, t) in tets.iter().enumerate() {
+        for (face, _) in faces_of_tet(*t) {
+            cofaces.entry(face).or_default().push(i);
+        }
+    }
+
+    let mut removed = HashSet::new();
+    let mut alive = vec![true; tets.len()];
+    let mut queue = (0..tets.len()).collect::<VecDeque<_>>();
+    while let Some(i) = queue.pop_front() {
+        if !alive[i] {
+            continue;
+        }
+        let faces = faces_of_tet(tets[i]).map(|(f, _)| f);
+        let free = faces
+            .iter()
+            .find(|f| !removed.contains(*f) && cofaces[*f].iter().all(|&j| j == i || !alive[j]));
+        let Some(free) = free else {
+            continue;
+        };
+        removed.insert(*free);
+        alive[i] = false;
+        for f in &faces {
+            queue.extend(cofaces[f].iter().filter(|&&j| alive[j]));
+        }
+    }
+    let stuck = alive.iter().filter(|&&a| a).count();
+    if stuck > 0 {
+        warn!(
+            "{} tetrahedra of the alpha complex have no free face, so they are left hollow",
+            stuck
+        );
+    }
+    removed
+}
+
+/// The center and the squared radius of the smallest sphere through two, three or four points.
+fn circumsphere(pts: &[Pos]) -> (Pos, f64) {
+    let a = pts[0];
+    match pts.len() {
+        2 => ((a + pts[1]) / 2.0, a.dist2(&pts[1]) / 4.0),
+        3 => {
+            let (ab, ac) = (pts[1] - a, pts[2] - a);
+            let n = ab.cross(&ac);
+            let offset =
+                (n.cross(&ab) * ac.dot(&ac) + ac.cross(&n) * ab.dot(&ab)) / (2.0 * n.dot(&n));
+            (a + offset, offset.dot(&offset))
+        }
+        4 => {
+            let (ab, ac, ad) = (pts[1] - a, pts[2] - a, pts[3] - a);
+            let det = 2.0 * ab.dot(&ac.cross(&ad));
+            let offset = (ac.cross(&ad) * ab.dot(&ab)
+                + ad.cross(&ab) * ac.dot(&ac)
+                + ab.cross(&ac) * ad.dot(&ad))
+                / det;
+            (a + offset, offset.dot(&offset))
+        }
+        n => panic!("circumsphere of {} points", n),
+    }
+}
+
+/// Compute the Delaunay tetrahedralization of the points with the Bowyer–Watson algorithm.
+///
+/// To avoid degenerate configurations like four points on a circle, which are common in
+/// inputs sampled on a grid, the points are perturbed by a tiny amount.  The tetrahedralization is
+/// returned with the perturbed points.  Coplanar points give very flat tetrahedra, so
+/// [alpha_complex] uses [delaunay_2d] for them instead.
+fn delaunay(points: &[Pos]) -> (Vec<[usize; 4]>, Vec<Pos>) {
+    let (mut pts, center, diag) = perturbed(points, 3);
+
+    // A tetrahedron that contains all points.  Its vertices come after the input points.
+    let s = 20.0 * diag;
+    for d in [
+        [1.0, 1.0, 1.0],
+        [1.0, -1.0, -1.0],
+        [-1.0, 1.0, -1.0],
+        [-1.0, -1.0, 1.0],
+    ] {
+        pts.push(center + Pos(d) * s);
+    }
+    let tets = bowyer_watson::<4>(&pts, points.len());
+    pts.truncate(points.len());
+    (tets, pts)
+}
+
+/// Compute the Delaunay triangulation of points in the plane `z = 0`, like [delaunay].
+fn delaunay_2d(points: &[Pos]) -> (Vec<[usize; 3]>, Vec<Pos>) {
+    let (mut pts, center, diag) = perturbed(points, 2);
+
+    // A triangle that contains all points.
+    let s = 20.0 * diag;
+    for d in [[0.0, 2.0, 0.0], [-2.0, -1.0, 0.0], [2.0, -1.0, 0.0]] {
+        pts.push(center + Pos(d) * s);
+    }
+    let tris = bowyer_watson::<3>(&pts, points.len());
+    pts.truncate(points.len());
+    (tris, pts)
+}
+
+/// The points moved by a deterministic pseudo-random amount along their first `axes`
+/// coordinates, with the center and the diagonal of their bounding box.
+fn perturbed(points: &[Pos], axes: usize) -> (Vec<Pos>, Pos, f64) {
+    let (mut lo, mut hi) = ([f64::MAX; 3], [f64::MIN; 3]);
+    for p in points {
+        for j in 0..3 {
+            lo[j] = lo[j].min(p.0[j]);
+            hi[j] = hi[j].max(p.0[j]);
+        }
+    }
+    let (lo, hi) = (Pos(lo), Pos(hi));
+    let diag = lo.dist(&hi).max(f64::MIN_POSITIVE);
+
+    let mut state = 0x9e3779b97f4a7c15u64;
+    let mut jitter = || {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((state >> 11) as f64 / (1u64 << 53) as f64 - 0.5) * diag * 1e-9
+    };
+    let pts = points
+        .iter()
+        .map(|p| {
+            let mut p = *p;
+            for c in &mut p.0[..axes] {
+                *c += jitter();
+            }
+            p
+        })
+        .collect();
+    (pts, (lo + hi) / 2.0, diag)
+}
+
+/// The Bowyer–Watson algorithm for simplices with `N` vertices, so tetrahedra or triangles.  The
+/// first `n` points are inserted into the simplex made of the remaining `N` points, which must
+/// contain them all.  Returns the simplices without any of the outer vertices.
+///
+/// Each point is located by walking from the last inserted simplex towards it, and only the
+/// simplices in its cavity, whose circumspheres contain the point, are replaced.
+fn bowyer_watson<const N: usize>(pts: &[Pos], n: usize) -> Vec<[usize; N]> {
+    let mut mesh = SimplexMesh::<N>::default();
+    mesh.add(Simplex::new(std::array::from_fn(|k| n + k), pts));
+
+    let mut last = 0;
+    for i in 0..n {
+        let p = pts[i];
+        let Some(start) = mesh.locate(pts, last, p) else {
+            continue;
+        };
+
+        // Find the cavity, and its boundary faces as the cavity simplex, the index of the face
+        // in it, and the simplex on the other side.
+        let mut cavity = vec![start];
+        mesh.simplices[start].alive = false;
+        let mut boundary = Vec::new();
+        let mut k = 0;
+        while k < cavity.len() {
+            let t = cavity[k];
+            k += 1;
+            for j in 0..N {
+                match mesh.simplices[t].adj[j] {
+                    Some(u) if !mesh.simplices[u].alive => {}
+                    Some(u) if mesh.simplices[u].conflicts(p) => {
+                        mesh.simplices[u].alive = false;
+                        cavity.push(u);
+                    }
+                    outside => boundary.push((t, j, outside)),
+                }
+            }
+        }
+
+        // Connect the boundary faces to the point, by replacing the vertex opposite of the face.
+        // The new simplices are neighbors across the ridges of the boundary, which we key by their
+        // sorted vertices, padded for triangles.
+        let mut open_ridges: HashMap<[usize; 2], (usize, usize)> = HashMap::new();
+        for (t, j, outside) in boundary {
+            let mut v = mesh.simplices[t].v;
+            v[j] = i;
+            let mut simplex = Simplex::new(v, pts);
+            if !simplex.r2.is_finite() {
+                if let Some(u) = outside {
+                    mesh.unlink(u, t);
+                }
+                continue;
+            }
+            simplex.adj[j] = outside;
+            let new = mesh.add(simplex);
+            if let Some(u) = outside {
+                mesh.relink(u, t, new);
+            }
+            for e in (0..N).filter(|&e| e != j) {
+                let mut ridge = [usize::MAX; 2];
+                for (r, k) in (0..N).filter(|&k| k != j && k != e).enumerate() {
+                    ridge[r] = v[k];
+                }
+                ridge.sort();
+                if let Some((other, other_e)) = open_ridges.remove(&ridge) {
+                    mesh.simplices[new].adj[e] = Some(other);
+                    mesh.simplices[other].adj[other_e] = Some(new);
+                } else {
+                    open_ridges.insert(ridge, (new, e));
+                }
+            }
+            last = new;
+        }
+        mesh.free.extend(cavity);
+    }
+
+    mesh.simplices
+        .into_iter()
+        .filter(|t| t.alive && t.v.iter().all(|&i| i < n))
+        .map(|t| t.v)
+        .collect()
+}
+
+/// A simplex of [bowyer_watson], with its circumsphere.
+struct Simplex<const N: usize> {
+    v: [usize; N],
+    /// The simplex across the face opposite of each vertex, if any.
+    adj: [Option<usize>; N],
+    center: Pos,
+    r2: f64,
+    alive: bool,
+}
+
+impl<const N: usize> Simplex<N> {
+    fn new(v: [usize; N], pts: &[Pos]) -> Self {
+        let (center, r2) = circumsphere(&v.map(|i| pts[i]));
+        Self {
+            v,
+            adj: [None; N],
+            center,
+            r2,
+            alive: true,
+        }
+    }
+
+    /// True if `p` is inside the circumsphere.
+    fn conflicts(&self, p: Pos) -> bool {
+        p.dist2(&self.center) < self.r2
+    }
+}
+
+/// The simplices of [bowyer_watson].  Removed simplices stay in place until their slot is reused.
+struct SimplexMesh<const N: usize> {
+    simplices: Vec<Simplex<N>>,
+    free: Vec<usize>,
+}
+
+impl<const N: usize> Default for SimplexMesh<N> {
+    fn default() -> Self {
+        Self {
+            simplices: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+}
+
+impl<const N: usize> SimplexMesh<N> {
+    fn add(&mut self, simplex: Simplex<N>) -> usize {
+        match self.free.pop() {
+            Some(t) => {
+                self.simplices[t] = simplex;
+                t
+            }
+            None => {
+                self.simplices.push(simplex);
+                self.simplices.len() - 1
+            }
+        }
+    }
+
+    /// Make `u` point to `new` where it pointed to `old`.
+    fn relink(&mut self, u: usize, old: usize, new: usize) {
+        for a in &mut self.simplices[u].adj {
+            if *a == Some(old) {
+                *a = Some(new);
+            }
+        }
+    }
+
+    fn unlink(&mut self, u: usize, old: usize) {
+        for a in &mut self.simplices[u].adj {
+            if *a == Some(old) {
+                *a = None;
+            }
+        }
+    }
+
+    /// Find a simplex whose circumsphere contains `p`.  We walk from `start` through the faces
+    /// that `p` is behind until we reach the simplex that contains `p`.  If the walk gets lost,
+    /// which can only happen for nearly degenerate simplices, we look at all of them.
+    fn locate(&self, pts: &[Pos], start: usize, p: Pos) -> Option<usize> {
+        // The orientation of the face opposite of vertex `j` and a point, for tetrahedra and for
+        // triangles in the plane `z = 0`.
+        let orient = |v: &[Pos; N], j: usize, d: Pos| {
+            let [a, b, c] = [1, 2, 3].map(|o| v[(j + o) % N]);
+            if N == 4 {
+                (b - a).dot(&(c - a).cross(&(d - a)))
+            } else {
+                (b - a).cross(&(d - a)).z()
+            }
+        };
+        let mut t = start;
+        'walk: for step in 0..self.simplices.len() {
+            let simplex = &self.simplices[t];
+            for k in 0..N {
+                // Start at a different face in every step, so that we don't walk in circles.
+                let j = (step + k) % N;
+                let v = simplex.v.map(|i| pts[i]);
+                if 0.0 < orient(&v, j, v[j]) * orient(&v, j, p) {
+                    continue;
+                }
+                match simplex.adj[j] {
+                    Some(u) => {
+                        t = u;
+                        continue 'walk;
+                    }
+                    None => break 'walk,
+                }
+            }
+            if simplex.conflicts(p) {
+                return Some(t);
+            }
+            break;
+        }
+        self.simplices
+            .iter()
+            .position(|simplex| simplex.alive && simplex.conflicts(p))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Points spread evenly on the unit sphere.
+    fn fibonacci_sphere(n: usize) -> Vec<Pos> {
+        let golden = std::f64::consts::PI * (3.0 - 5f64.sqrt());
+        (0..n)
+            .map(|i| {
+                let y = 1.0 - 2.0 * (i as f64 + 0.5) / n as f64;
+                let r = (1.0 - y * y).sqrt();
+                let theta = golden * i as f64;
+                Pos([r * theta.cos(), y, r * theta.sin()])
+            })
+            .collect()
+    }
+
+    fn euler_characteristic(c: &Complex) -> isize {
+        (0..3)
+            .map(|d| (-1isize).pow(d as u32) * c.num_simplices_of_dim(d) as isize)
+            .sum()
+    }
+
+    #[test]
+    fn read_points() {
+        let points =
+            read_points_from_str("# scan\nv 0 0 0\nvn 0 0 1\n1,2,3\n\n4 5 6 0.1 0.2 0.3\n")
+                .unwrap();
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[2].0, [4.0, 5.0, 6.0]);
+        assert!(read_points_from_str("1 2\n").is_err());
+    }
+
+    #[test]
+    fn rips_square() {
+        let square = vec![
+            Pos([0.0, 0.0, 0.0]),
+            Pos([1.0, 0.0, 0.0]),
+            Pos([1.0, 1.0, 0.0]),
+            Pos([0.0, 1.0, 0.0]),
+        ];
+        let c = rips_complex(square.clone(), 1.1).unwrap();
+        assert_eq!(c.num_simplices_of_dim(1), 4);
+        assert_eq!(c.num_simplices_of_dim(2), 0);
+        let c = rips_complex(square, 1.5).unwrap();
+        assert_eq!(c.num_simplices_of_dim(1), 6);
+        assert_eq!(c.num_simplices_of_dim(2), 4);
+    }
+
+    #[test]
+    fn delaunay_has_empty_circumspheres() {
+        let mut state = 1u64;
+        let mut rand = || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 11) as f64 / (1u64 << 53) as f64
+        };
+        let mut points = (0..300)
+            .map(|_| Pos([rand(), rand(), rand()]))
+            .collect::<Vec<_>>();
+        for c in 0..8 {
+            points.push(Pos([0, 1, 2].map(|d| (c >> d & 1) as f64)));
+        }
+
+        let (tets, pts) = delaunay(&points);
+        let mut volume = 0.0;
+        for t in &tets {
+            let v = t.map(|i| pts[i]);
+            let (center, r2) = circumsphere(&v);
+            for (i, p) in pts.iter().enumerate() {
+                assert!(t.contains(&i) || r2 * (1.0 - 1e-9) < p.dist2(&center));
+            }
+            volume += (v[1] - v[0])
+                .dot(&(v[2] - v[0]).cross(&(v[3] - v[0])))
+                .abs()
+                / 6.0;
+        }
+        // The tetrahedra don't overlap, and fill most of the convex hull, which is the unit cube.
+        // Flat tetrahedra at the hull can be lost to the tetrahedron around all points.
+        assert!(0.99 < volume && volume < 1.0 + 1e-6, "{}", volume);
+    }
+
+    #[test]
+    fn alpha_grid_points() {
+        // Points on a grid are very degenerate, since the corners of each cube are on a sphere.
+        let mut points = Vec::new();
+        for x in 0..3 {
+            for y in 0..3 {
+                for z in 0..3 {
+                    points.push(Pos([x as f64, y as f64, z as f64]));
+                }
+            }
+        }
+        // The edges of the grid.
+        let c = alpha_complex(points.clone(), 0.6).unwrap();
+        assert_eq!(c.num_simplices_of_dim(1), 54);
+        assert_eq!(euler_characteristic(&c), 27 - 54);
+        // The squares of the grid are filled, and the cubes are empty.
+        let c = alpha_complex(points.clone(), 0.8).unwrap();
+        assert_eq!(euler_characteristic(&c), 1 + 8);
+
+        let planar = points.into_iter().filter(|p| p.z() == 0.0).collect();
+        let c = alpha_complex(planar, 0.8).unwrap();
+        assert_eq!(euler_characteristic(&c), 1);
+    }
+
+    #[test]
+    fn alpha_planar_points() {
+        // Coplanar points only have flat tetrahedra, so they are triangulated in their plane.
+        let mut points = vec![
+            Pos([0.0, 0.0, 0.0]),
+            Pos([1.0, 0.0, 0.0]),
+            Pos([0.0, 1.0, 0.0]),
+            Pos([0.8, 0.9, 0.0]),
+        ];
+        let c = alpha_complex(points.clone(), 10.0).unwrap();
+        assert_eq!(c.num_simplices_of_dim(1), 5);
+        assert_eq!(c.num_simplices_of_dim(2), 2);
+        points.push(Pos([0.3, 0.3, 0.0]));
+        let c = alpha_complex(points.clone(), 10.0).unwrap();
+        assert_eq!(c.num_simplices_of_dim(2), 4);
+        assert_eq!(euler_characteristic(&c), 1);
+
+        // Irregular points in a tilted plane.
+        let points = (1..60)
+            .map(|i| {
+                let x = (i as f64 * 0.7548776662).fract() * 3.0;
+                let y = (i as f64 * 0.5698402910).fract() * 2.0;
+                Pos([x, y, 0.3 * x - 0.5 * y + 2.0])
+            })
+            .collect::<Vec<_>>();
+        let (tris, pts) = delaunay_2d(&plane_coordinates(&points).unwrap());
+        for t in &tris {
+            let (center, r2) = circumsphere(&t.map(|i| pts[i]));
+            for (i, p) in pts.iter().enumerate() {
+                if !t.contains(&i) {
+                    assert!(r2 * (1.0 - 1e-9) <= p.dist2(&center));
+                }
+            }
+        }
+        let c = alpha_complex(points.clone(), 10.0).unwrap();
+        assert_eq!(c.num_simplices_of_dim(2), tris.len());
+        assert_eq!(euler_characteristic(&c), 1);
+        let c = alpha_complex(points, 0.01).unwrap();
+        assert_eq!(c.num_simplices_of_dim(1), 0);
+    }
+
+    #[test]
+    fn alpha_sphere() {
+        let points = fibonacci_sphere(200);
+        let c = alpha_complex(points.clone(), 0.4).unwrap();
+        assert_eq!(euler_characteristic(&c), 2);
+        // Every point is on the surface, so the surface triangles are all of the complex.
+        for e in 0..c.num_simplices_of_dim(1) {
+            let cofaces = c.simplices_per_dim[2]
+                .iter()
+                .filter(|t| t.boundary.contains(&(e as CI)))
+                .count();
+            assert_eq!(cofaces, 2);
+        }
+
+        // A large radius gives all of the 2-skeleton of the convex hull.
+        let c = alpha_complex(points, 10.0).unwrap();
+        assert!(c.num_simplices_of_dim(2) > 2 * 200 - 4);
+    }
+}
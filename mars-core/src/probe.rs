@@ -0,0 +1,178 @@
+//! Persistence at arbitrary points.
+//!
+//! [Vineyards] only has reductions at the grid points.  To get the barcodes somewhere else, we
+//! start from the reduction at the closest grid point and do a single [vineyards_step] to the
+//! point, which also gives the Faustian swaps between the two.  Without any reductions, for
+//! instance for slim runs, we reduce from scratch.
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::{
+    complex::Pos,
+    error::{MarsError, MarsResult},
+    grid::Index,
+    reduce_from_scratch, vineyards_step, BirthDeathPair, Grid, Mars, Reduction, Swap, Vineyards,
+};
+
+/// The persistence at a point, from [Mars::probe].
+#[derive(Clone, Debug, Serialize)]
+pub struct Probe {
+    /// The point we probed.
+    pub point: Pos,
+    /// The grid point with a reduction that we started from, and its position.  `None` if we
+    /// reduced from scratch.
+    pub from: Option<(Index, Pos)>,
+    /// The barcodes for each dimension, starting with `-1` for the empty simplex.
+    pub barcodes: BTreeMap<isize, Vec<BirthDeathPair>>,
+    /// The reduced Betti numbers of the complex for each dimension from `0`, which is the number
+    /// of bars that never die.
+    pub betti: Vec<usize>,
+    /// The Faustian swaps between `from` and `point`.
+    pub swaps: Vec<Swap>,
+    #[serde(skip)]
+    pub reduction: Reduction,
+}
+
+impl Probe {
+    /// The reduced Betti numbers of the sublevel set at `value`, which is the number of bars alive
+    /// at `value` for each dimension from `0`.
+    pub fn betti_at(&self, value: f64) -> Vec<usize> {
+        self.betti_dims()
+            .map(|dim| {
+                self.barcodes[&dim]
+                    .iter()
+                    .filter(|b| {
+                        let born = b.birth.is_none_or(|(t, _)| t <= value);
+                        let alive = b.death.is_none_or(|(t, _)| value < t);
+                        born && alive
+                    })
+                    .count()
+            })
+            .collect()
+    }
+
+    fn betti_dims(&self) -> impl Iterator<Item = isize> + '_ {
+        self.barcodes.keys().copied().filter(|&d| 0 <= d)
+    }
+}
+
+impl Mars {
+    /// The barcodes at `point`.  If `vineyards` has reductions, we start at the closest grid
+    /// point that has one, and otherwise we reduce from scratch.
+    pub fn probe(&self, vineyards: Option<&Vineyards>, point: Pos) -> MarsResult<Probe> {
+        let complex = self
+            .complex
+            .as_ref()
+            .ok_or_else(|| MarsError::Invalid("Missing complex".to_string()))?;
+
+        let start = match (self.grid.as_ref(), vineyards) {
+            (Some(grid), Some(v)) => closest_reduction(grid, v, point),
+            _ => None,
+        };
+        let (from, reduction, swaps) = match start {
+            Some((index, pos, reduction)) => {
+                let (reduction, swaps) =
+                    vineyards_step(complex, &self.filtration, reduction, point, false);
+                (Some((index, pos)), reduction, swaps.v)
+            }
+            None => (
+                None,
+                reduce_from_scratch(complex, &self.filtration, point, false),
+                Vec::new(),
+            ),
+        };
+
+        let barcodes = (-1..reduction.stacks.len() as isize)
+            .map(|dim| (dim, reduction.barcode(complex, &self.filtration, dim)))
+            .collect::<BTreeMap<_, _>>();
+        let mut probe = Probe {
+            point,
+            from,
+            barcodes,
+            betti: Vec::new(),
+            swaps,
+            reduction,
+        };
+        probe.betti = probe
+            .betti_dims()
+            .map(|dim| {
+                probe.barcodes[&dim]
+                    .iter()
+                    .filter(|b| b.death.is_none())
+                    .count()
+            })
+            .collect();
+        Ok(probe)
+    }
+}
+
+/// The grid point closest to `point` that has a reduction in `vineyards`.
+fn closest_reduction<'a>(
+    grid: &Grid,
+    vineyards: &'a Vineyards,
+    point: Pos,
+) -> Option<(Index, Pos, &'a Reduction)> {
+    vineyards
+        .reductions
+        .iter()
+        .map(|(index, reduction)| {
+            let pos = match grid {
+                Grid::Regular(g) => g.coordinate(*index),
+                Grid::Mesh(g) => g.coordinate(*index),
+            };
+            (*index, pos, reduction)
+        })
+        .min_by(|a, b| {
+            let (da, db) = (a.1.dist2(&point), b.1.dist2(&point));
+            da.total_cmp(&db).then_with(|| a.0.cmp(&b.0))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{grid::VineyardsGrid, test::test_complex_tet_cube};
+
+    #[test]
+    fn probe_matches_reduction_from_scratch() {
+        let complex = test_complex_tet_cube();
+        let grid = VineyardsGrid::new(Pos([-0.5; 3]), 1.0, [3, 3, 3]);
+        let mars = Mars {
+            complex: Some(complex),
+            grid: Some(Grid::Regular(grid)),
+            ..Default::default()
+        };
+        let vineyards = mars.run(|_, _| {}).unwrap();
+
+        let point = Pos([0.3, 0.45, 0.6]);
+        let probe = mars.probe(Some(&vineyards), point).unwrap();
+        assert_eq!(probe.from.unwrap().0, Index([1, 1, 1]));
+        let scratch = mars.probe(None, point).unwrap();
+        assert!(scratch.from.is_none());
+        assert!(scratch.swaps.is_empty());
+
+        for dim in -1..=3 {
+            let lifetimes = |p: &Probe| {
+                let mut l = p.barcodes[&dim]
+                    .iter()
+                    .map(|b| (b.birth.map(|t| t.0), b.death.map(|t| t.0)))
+                    .map(|(b, d)| format!("{:?} {:?}", b, d))
+                    .collect::<Vec<_>>();
+                l.sort();
+                l
+            };
+            assert_eq!(lifetimes(&probe), lifetimes(&scratch));
+        }
+        // The cube is contractible.
+        assert_eq!(probe.betti, [0, 0, 0, 0]);
+        assert_eq!(probe.betti_at(f64::INFINITY), probe.betti);
+        assert_eq!(probe.betti_at(-1.0), [0, 0, 0, 0]);
+
+        // The wasm `probe` goes through JSON, so the barcodes are keyed by strings.
+        let json = serde_json::to_value(&probe).unwrap();
+        for dim in ["-1", "0", "1", "2"] {
+            assert!(json["barcodes"][dim].is_array());
+        }
+    }
+}
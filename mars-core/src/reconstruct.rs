@@ -0,0 +1,395 @@
+//! Approximate shapes from medial axes.
+//!
+//! A medial axis together with the radius at every point is the medial axis transform, and the
+//! union of the medial balls is the shape it came from.  [UnionOfBalls] rebuilds the shape from the
+//! faces of [AxisMesh]es and meshes its boundary, so that an axis can be judged by how well it
+//! gives back the input.
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::{
+    axis_mesh::AxisMesh,
+    complex::{Complex, Pos},
+    error::{MarsError, MarsResult},
+    spatial::PointIndex,
+};
+
+/// Each cube of the sampling grid is split into six tetrahedra around its diagonal from corner `0`
+/// to corner `7`, where corner `k` is offset along `x`, `y` and `z` by the bits of `k`.  All cubes
+/// use the same diagonal, so the tetrahedra of neighboring cubes share their faces.
+const CUBE_TETS: [[usize; 4]; 6] = [
+    [0, 1, 3, 7],
+    [0, 3, 2, 7],
+    [0, 2, 6, 7],
+    [0, 6, 4, 7],
+    [0, 4, 5, 7],
+    [0, 5, 1, 7],
+];
+
+/// Sampling grids with more points than this are refused.
+const MAX_SAMPLES: usize = 1 << 27;
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Ball {
+    pub center: Pos,
+    pub radius: f64,
+}
+
+/// A union of balls.
+#[derive(Clone, Debug, Default)]
+pub struct UnionOfBalls {
+    pub balls: Vec<Ball>,
+}
+
+/// A triangle mesh, with the triangles oriented so that their normals point out of the shape.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TriangleMesh {
+    pub points: Vec<Pos>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+/// How far the vertices of a complex are from the boundary of a [UnionOfBalls].  The distance of
+/// a point `p` is estimated as `min |p - c| - r` over the balls, which is exact outside the union.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct SurfaceError {
+    /// Mean of the absolute distances.
+    pub mean: f64,
+    /// Largest absolute distance.
+    pub max: f64,
+    /// Number of vertices that are inside the union, which means that the balls are too large.
+    pub inside: usize,
+}
+
+impl UnionOfBalls {
+    /// One ball per face of the axes that has a radius, centered at [crate::axis_mesh::AxisFace::crossing].
+    /// [AxisMesh::compute_radii] has to be called first.
+    pub fn from_axes<'a>(axes: impl IntoIterator<Item = &'a AxisMesh>) -> Self {
+        let balls = axes
+            .into_iter()
+            .flat_map(|a| &a.faces)
+            .filter_map(|f| {
+                f.radius.map(|radius| Ball {
+                    center: f.crossing,
+                    radius,
+                })
+            })
+            .collect();
+        Self { balls }
+    }
+
+    /// Mesh the boundary of the union by sampling the distance to it on a regular grid with
+    /// spacing `cell_size`, using marching tetrahedra.
+    pub fn mesh(&self, cell_size: f64) -> MarsResult<TriangleMesh> {
+        if !(cell_size.is_finite() && 0.0 < cell_size) {
+            return Err(MarsError::Invalid(format!(
+                "Invalid cell size {} for the reconstruction",
+                cell_size
+            )));
+        }
+        if self.balls.is_empty() {
+            return Ok(TriangleMesh::default());
+        }
+
+        let mut lo = [f64::INFINITY; 3];
+        let mut hi = [f64::NEG_INFINITY; 3];
+        for b in &self.balls {
+            for k in 0..3 {
+                lo[k] = lo[k].min(b.center.0[k] - b.radius - cell_size);
+                hi[k] = hi[k].max(b.center.0[k] + b.radius + cell_size);
+            }
+        }
+        let n = [0, 1, 2].map(|k| ((hi[k] - lo[k]) / cell_size).ceil() as usize + 1);
+        let num_samples = n[0].saturating_mul(n[1]).saturating_mul(n[2]);
+        if MAX_SAMPLES < num_samples {
+            return Err(MarsError::Capacity(format!(
+                "The reconstruction would need {} samples (max {}); use a larger cell size",
+                num_samples, MAX_SAMPLES
+            )));
+        }
+        let position = |id: usize| {
+            let (x, y, z) = (id % n[0], id / n[0] % n[1], id / (n[0] * n[1]));
+            Pos([
+                lo[0] + x as f64 * cell_size,
+                lo[1] + y as f64 * cell_size,
+                lo[2] + z as f64 * cell_size,
+            ])
+        };
+
+        // The edges of the tetrahedra are at most `sqrt(3) * cell_size` long, so if the distance
+        // is further than that from zero, on either side, we only need its sign.
+        let reach = 3f64.sqrt() * cell_size;
+        let centers = self.centers();
+        let query = Query::new(&self.balls, &centers, reach);
+        let values = (0..num_samples)
+            .map(|id| query.distance_within(position(id), reach).unwrap_or(reach))
+            .collect::<Vec<_>>();
+
+        let mean = |ids: &[usize]| {
+            ids.iter().fold(Pos([0.0; 3]), |s, &id| s + position(id)) / ids.len() as f64
+        };
+        // The interpolated points can coincide when a sample is exactly on the boundary, so the
+        // triangles are oriented by the midpoints of the edges instead, which are never degenerate.
+        let mid = |(i, o): (usize, usize)| (position(i) + position(o)) / 2.0;
+
+        let mut mesh = TriangleMesh::default();
+        let mut edge_points = HashMap::new();
+        let mut edge_point = |mesh: &mut TriangleMesh, a: usize, b: usize| {
+            *edge_points.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                let t = values[a] / (values[a] - values[b]);
+                let (pa, pb) = (position(a), position(b));
+                mesh.points.push(pa + (pb - pa) * t);
+                mesh.points.len() - 1
+            })
+        };
+        for z in 0..n[2] - 1 {
+            for y in 0..n[1] - 1 {
+                for x in 0..n[0] - 1 {
+                    let corners = [0, 1, 2, 3, 4, 5, 6, 7].map(|c| {
+                        (x + (c & 1)) + n[0] * ((y + ((c >> 1) & 1)) + n[1] * (z + (c >> 2)))
+                    });
+                    for tet in CUBE_TETS {
+                        let tet = tet.map(|c| corners[c]);
+                        let (inside, outside): (Vec<usize>, Vec<usize>) =
+                            tet.iter().partition(|&&id| values[id] < 0.0);
+                        let polygon = match (inside.as_slice(), outside.as_slice()) {
+                            (&[i], &[a, b, c]) => vec![(i, a), (i, b), (i, c)],
+                            (&[a, b, c], &[o]) => vec![(a, o), (b, o), (c, o)],
+                            (&[i, j], &[a, b]) => vec![(i, a), (i, b), (j, b), (j, a)],
+                            _ => continue,
+                        };
+                        let out = mean(&outside) - mean(&inside);
+                        for k in 1..polygon.len() - 1 {
+                            let [a, b, c] = [polygon[0], polygon[k], polygon[k + 1]];
+                            let normal = (mid(b) - mid(a)).cross(&(mid(c) - mid(a)));
+                            let [a, b, c] = [a, b, c].map(|e| edge_point(&mut mesh, e.0, e.1));
+                            if normal.dot(&out) < 0.0 {
+                                mesh.triangles.push([a, c, b]);
+                            } else {
+                                mesh.triangles.push([a, b, c]);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(mesh)
+    }
+
+    /// How far the vertices of `complex` are from the boundary of the union.
+    pub fn surface_error(&self, complex: &Complex) -> SurfaceError {
+        let centers = self.centers();
+        // How far the vertices are is not known up front, so there is a single level.
+        let max_radius = self.balls.iter().map(|b| b.radius).fold(0.0, f64::max);
+        let query = Query::new(&self.balls, &centers, max_radius);
+        let mut error = SurfaceError::default();
+        let mut n = 0;
+        for s in &complex.simplices_per_dim[0] {
+            let Some(p) = s.coords else {
+                continue;
+            };
+            let Some(d) = query.distance(p) else {
+                continue;
+            };
+            n += 1;
+            error.mean += d.abs();
+            error.max = error.max.max(d.abs());
+            if d < 0.0 {
+                error.inside += 1;
+            }
+        }
+        if 0 < n {
+            error.mean /= n as f64;
+        }
+        error
+    }
+
+    fn centers(&self) -> Vec<Pos> {
+        self.balls.iter().map(|b| b.center).collect()
+    }
+}
+
+impl TriangleMesh {
+    /// The volume enclosed by the mesh, if it is closed.
+    pub fn volume(&self) -> f64 {
+        self.triangles
+            .iter()
+            .map(|t| {
+                let [a, b, c] = t.map(|v| self.points[v]);
+                a.dot(&b.cross(&c)) / 6.0
+            })
+            .sum()
+    }
+
+    pub fn write_as_obj<W: std::io::Write>(&self, mut w: W) -> std::io::Result<()> {
+        writeln!(w, "o union-of-balls")?;
+        for p in &self.points {
+            writeln!(w, "v {} {} {}", p.x(), p.y(), p.z())?;
+        }
+        for [a, b, c] in &self.triangles {
+            writeln!(w, "f {} {} {}", a + 1, b + 1, c + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// Distance queries on a set of balls.  The balls are indexed in levels by radius, so that a
+/// query only looks as far around the small balls as they reach, and not as far as the largest
+/// ball does.
+struct Query<'a> {
+    balls: &'a [Ball],
+    /// The index of the centers in each nonempty level, and the largest radius in it.
+    levels: Vec<(PointIndex<'a>, f64)>,
+}
+
+impl<'a> Query<'a> {
+    /// `centers` are the centers of `balls`.  Level `k` has the radii in `(r / 2^(k + 1), r / 2^k]`
+    /// for the largest radius `r`, and buckets as large as those radii, but not smaller than
+    /// `min_cell_size`, which should be about the reach of the queries.  The balls that are smaller
+    /// than that are all in the last level.
+    fn new(balls: &'a [Ball], centers: &'a [Pos], min_cell_size: f64) -> Self {
+        let max_radius = balls.iter().map(|b| b.radius).fold(0.0, f64::max);
+        let num_levels = if min_cell_size < max_radius {
+            (max_radius / min_cell_size).log2().ceil() as usize + 1
+        } else {
+            1
+        };
+        let mut levels: Vec<Option<(PointIndex, f64)>> = vec![None; num_levels];
+        for (i, b) in balls.iter().enumerate() {
+            // Balls of radius zero go to the last level.
+            let k = ((max_radius / b.radius).log2().floor() as usize).min(num_levels - 1);
+            let (index, radius) = levels[k].get_or_insert_with(|| {
+                let cell_size = (max_radius / 2f64.powi(k as i32)).max(min_cell_size);
+                (PointIndex::empty(centers, cell_size), 0.0)
+            });
+            index.insert(i);
+            *radius = radius.max(b.radius);
+        }
+        Self {
+            balls,
+            levels: levels.into_iter().flatten().collect(),
+        }
+    }
+
+    /// `|p - c| - r` for ball `i`.
+    fn ball_distance(&self, i: usize, p: Pos) -> f64 {
+        self.balls[i].center.dist(&p) - self.balls[i].radius
+    }
+
+    /// `min |p - c| - r` over the balls, if it is at most `reach`, and at least `-reach`: as soon
+    /// as `p` is that deep inside a ball, the other balls don't matter.  The largest balls are
+    /// looked at first, since they are the most likely to have `p` deep inside.
+    fn distance_within(&self, p: Pos, reach: f64) -> Option<f64> {
+        let mut best: Option<f64> = None;
+        for (index, radius) in &self.levels {
+            for i in index.within(p, reach + radius) {
+                let d = self.ball_distance(i, p);
+                if d <= -reach {
+                    return Some(-reach);
+                }
+                if d <= reach && best.is_none_or(|b| d < b) {
+                    best = Some(d);
+                }
+            }
+        }
+        best
+    }
+
+    /// `min |p - c| - r` over the balls, or `None` if there are none.
+    fn distance(&self, p: Pos) -> Option<f64> {
+        let nearest = self
+            .levels
+            .iter()
+            .filter_map(|(index, _)| index.nearest(p).map(|(i, _)| i))
+            .collect::<Vec<_>>();
+        let bound = nearest
+            .iter()
+            .map(|&i| self.ball_distance(i, p))
+            .min_by(f64::total_cmp)?;
+        // A ball can only beat the bound by being at most its radius further away.
+        self.levels
+            .iter()
+            .flat_map(|(index, radius)| index.within(p, (bound + radius).max(0.0)))
+            .chain(nearest)
+            .map(|i| self.ball_distance(i, p))
+            .min_by(f64::total_cmp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::test_complex_tet_cube;
+
+    fn ball(radius: f64) -> UnionOfBalls {
+        UnionOfBalls {
+            balls: vec![Ball {
+                center: Pos([0.0; 3]),
+                radius,
+            }],
+        }
+    }
+
+    #[test]
+    fn mesh_of_a_ball() {
+        let mesh = ball(1.0).mesh(0.1).unwrap();
+        assert!(mesh
+            .points
+            .iter()
+            .all(|p| (p.dist(&Pos([0.0; 3])) - 1.0).abs() < 0.01));
+        let volume = 4.0 / 3.0 * std::f64::consts::PI;
+        assert!((mesh.volume() - volume).abs() < 0.03 * volume);
+
+        // Every edge is in exactly two triangles, once in each direction.
+        let mut edges = HashMap::new();
+        for t in &mesh.triangles {
+            for k in 0..3 {
+                *edges.entry((t[k], t[(k + 1) % 3])).or_insert(0) += 1;
+            }
+        }
+        assert!(edges
+            .iter()
+            .all(|(&(a, b), &n)| n == 1 && edges.get(&(b, a)) == Some(&1)));
+    }
+
+    #[test]
+    fn surface_error_of_the_circumscribed_ball() {
+        let complex = test_complex_tet_cube();
+        let error = ball(0.75f64.sqrt()).surface_error(&complex);
+        assert!(error.max < 1e-12);
+        assert_eq!(ball(0.9).surface_error(&complex).inside, 8);
+        assert!(ball(0.0).mesh(-1.0).is_err());
+    }
+
+    #[test]
+    fn query_balls_of_different_sizes() {
+        // One large ball, and a row of small ones of decreasing radius, down to zero.
+        let mut balls = vec![Ball {
+            center: Pos([0.0; 3]),
+            radius: 2.0,
+        }];
+        for i in 0..40 {
+            balls.push(Ball {
+                center: Pos([2.5 + 0.1 * i as f64, 0.05 * i as f64, 0.0]),
+                radius: (0.1 / (i + 1) as f64 - 0.0025).max(0.0),
+            });
+        }
+        let centers = balls.iter().map(|b| b.center).collect::<Vec<_>>();
+        let brute = |p: Pos| {
+            balls
+                .iter()
+                .map(|b| b.center.dist(&p) - b.radius)
+                .fold(f64::INFINITY, f64::min)
+        };
+        for min_cell_size in [0.05, 0.5, 5.0] {
+            let query = Query::new(&balls, &centers, min_cell_size);
+            for i in 0..100 {
+                let p = Pos([-3.0 + 0.08 * i as f64, 0.3, 0.02 * (i % 7) as f64]);
+                assert_eq!(query.distance(p), Some(brute(p)));
+                let within = query.distance_within(p, 0.05);
+                let expected = Some(brute(p)).filter(|&d| d <= 0.05);
+                assert_eq!(within, expected.map(|d| d.max(-0.05)));
+            }
+        }
+    }
+}
@@ -0,0 +1,642 @@
+//! Skeletons of medial axes.
+//!
+//! The medial axes of dimension 1 and 2 are often thin sheets of dual quads, while what we want is
+//! a 1D curve skeleton.  We get it by topological thinning of the welded quad mesh ([AxisMesh]):
+//! quads are collapsed through their free edges, and dangling edges through their free vertices,
+//! which doesn't change the homotopy type.  The collapses are done in rounds, peeling one layer
+//! off all boundaries at a time, so that what is left is centered in the sheets.
+//!
+//! Closed sheets, like the axis around a cavity, have no free edges to start from, so when the
+//! collapses get stuck we punch a hole in each of them, at the quad closest to its center.  This
+//! changes the homotopy type.  The collapses from the hole meet on the far side of the sheet and
+//! leave curves there.  Small sheets can still be thinned away completely; for these we keep the
+//! center as an isolated point of the skeleton, and warn.
+//!
+//! An edge that is left after all quads around it are collapsed is part of a curve.  Curves are
+//! eaten from their free ends too, but only for `isolation` rounds after they are left bare, so
+//! that the short hairs from the thinning disappear and the long curves stay.
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+use tracing::warn;
+
+use crate::{axis_mesh::AxisMesh, complex::Pos, planar::Polylines};
+
+/// Parameters for [Skeleton::from_axis].
+#[derive(Clone, Debug, Serialize, serde::Deserialize)]
+pub struct ThinningParams {
+    /// For how many rounds after an edge is left without quads it may still be collapsed from a
+    /// free end.  Larger values remove more of the curve ends.
+    pub isolation: usize,
+    /// Branches from a branch point to a free end that are shorter than this are removed after
+    /// the thinning.  The length is in units of the average side length of the quads, so that
+    /// it doesn't depend on the grid size.  The thinning leaves short branches towards the
+    /// corners of the sheets, which this removes.
+    pub min_branch_length: f64,
+}
+
+impl Default for ThinningParams {
+    fn default() -> Self {
+        Self {
+            isolation: 2,
+            min_branch_length: 3.0,
+        }
+    }
+}
+
+/// The curve skeleton of a medial axis.
+#[derive(Clone, Debug)]
+pub struct Skeleton {
+    pub dim: usize,
+    /// The curves of the skeleton.  Curves are split at branch points.
+    pub curves: Polylines,
+    /// The centers of closed sheets that were thinned away completely, like the axis around a
+    /// cavity.
+    pub isolated_points: Vec<Pos>,
+}
+
+/// A branch point or an end point of a [Skeleton].
+#[derive(Clone, Debug, Serialize)]
+pub struct SkeletonNode {
+    pub position: Pos,
+    /// Number of curves that meet at the node.  This is 0 for the
+    /// [Skeleton::isolated_points].
+    pub degree: usize,
+}
+
+/// A curve of a [Skeleton] between two nodes.
+#[derive(Clone, Debug, Serialize)]
+pub struct SkeletonCurve {
+    /// Indices into [SkeletonGraph::nodes].  Both are `None` for closed curves without any
+    /// branch points.
+    pub from: Option<usize>,
+    pub to: Option<usize>,
+    /// All points on the curve, including the end points.
+    pub points: Vec<Pos>,
+    pub length: f64,
+}
+
+/// A [Skeleton] as a graph of nodes and curves, for writing as JSON.
+#[derive(Clone, Debug, Serialize)]
+pub struct SkeletonGraph {
+    pub dim: usize,
+    pub nodes: Vec<SkeletonNode>,
+    pub curves: Vec<SkeletonCurve>,
+}
+
+/// The cells of the welded quad mesh, with the bookkeeping for collapsing them.
+struct Thinning {
+    points: Vec<Pos>,
+    edge_vertices: Vec<[usize; 2]>,
+    quad_edges: Vec<[usize; 4]>,
+    edge_quads: Vec<Vec<usize>>,
+    vertex_edges: Vec<Vec<usize>>,
+
+    quad_alive: Vec<bool>,
+    edge_alive: Vec<bool>,
+    /// Number of live quads around each edge.
+    edge_count: Vec<usize>,
+    /// Number of live edges around each vertex.
+    vertex_count: Vec<usize>,
+    /// The round in which the last quad around the edge was collapsed.
+    bare_since: Vec<usize>,
+
+    /// Edges and vertices whose neighborhood changed in this round.  Only these can become free
+    /// in the next round.
+    touched_edges: Vec<usize>,
+    touched_vertices: Vec<usize>,
+
+    /// The center and the vertices of every closed sheet that we punched a hole in.
+    punched: Vec<(Pos, Vec<usize>)>,
+}
+
+impl Thinning {
+    fn new(axis: &AxisMesh) -> Self {
+        let mut edge_of: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut edge_vertices = Vec::new();
+        let mut quad_edges = Vec::new();
+        for f in &axis.faces {
+            let v = f.vertices;
+            quad_edges.push([0, 1, 2, 3].map(|k| {
+                let (a, b) = (v[k], v[(k + 1) % 4]);
+                *edge_of.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    edge_vertices.push([a, b]);
+                    edge_vertices.len() - 1
+                })
+            }));
+        }
+
+        let mut edge_quads = vec![Vec::new(); edge_vertices.len()];
+        for (q, edges) in quad_edges.iter().enumerate() {
+            for &e in edges {
+                edge_quads[e].push(q);
+            }
+        }
+        let mut vertex_edges = vec![Vec::new(); axis.points.len()];
+        for (e, [a, b]) in edge_vertices.iter().enumerate() {
+            vertex_edges[*a].push(e);
+            vertex_edges[*b].push(e);
+        }
+
+        Self {
+            quad_alive: vec![true; quad_edges.len()],
+            edge_alive: vec![true; edge_vertices.len()],
+            edge_count: edge_quads.iter().map(|q| q.len()).collect(),
+            vertex_count: vertex_edges.iter().map(|e| e.len()).collect(),
+            bare_since: vec![0; edge_vertices.len()],
+            touched_edges: (0..edge_vertices.len()).collect(),
+            touched_vertices: (0..axis.points.len()).collect(),
+            punched: Vec::new(),
+            points: axis.points.clone(),
+            edge_vertices,
+            quad_edges,
+            edge_quads,
+            vertex_edges,
+        }
+    }
+
+    fn remove_quad(&mut self, q: usize, round: usize) {
+        self.quad_alive[q] = false;
+        for e in self.quad_edges[q] {
+            self.edge_count[e] -= 1;
+            self.touched_edges.push(e);
+            if self.edge_count[e] == 0 {
+                self.bare_since[e] = round;
+                self.touched_vertices.extend(self.edge_vertices[e]);
+            }
+        }
+    }
+
+    fn remove_edge(&mut self, e: usize) {
+        self.edge_alive[e] = false;
+        for v in self.edge_vertices[e] {
+            self.vertex_count[v] -= 1;
+        }
+        self.touched_vertices.extend(self.edge_vertices[e]);
+    }
+
+    /// The only live edge at `v`, if `v` is a free vertex of a bare edge.
+    fn free_edge_of_vertex(&self, v: usize) -> Option<usize> {
+        if self.vertex_count[v] != 1 {
+            return None;
+        }
+        let e = *self.vertex_edges[v].iter().find(|&&e| self.edge_alive[e])?;
+        (self.edge_count[e] == 0).then_some(e)
+    }
+
+    /// Punch a hole in every sheet of live quads, at the quad closest to the center of the sheet.
+    /// This is only called when nothing can be collapsed, so all sheets left are closed.  Returns
+    /// false if there are no quads left.
+    fn punch_closed_sheets(&mut self, round: usize) -> bool {
+        let mut seen = self.quad_alive.iter().map(|a| !a).collect::<Vec<_>>();
+        let mut any = false;
+        for start in 0..self.quad_alive.len() {
+            if seen[start] {
+                continue;
+            }
+            seen[start] = true;
+            let mut sheet = vec![start];
+            let mut k = 0;
+            while k < sheet.len() {
+                let q = sheet[k];
+                k += 1;
+                for e in self.quad_edges[q] {
+                    for &r in &self.edge_quads[e] {
+                        if !seen[r] {
+                            seen[r] = true;
+                            sheet.push(r);
+                        }
+                    }
+                }
+            }
+            sheet.sort_unstable();
+
+            let mut vertices = sheet
+                .iter()
+                .flat_map(|&q| {
+                    self.quad_edges[q]
+                        .iter()
+                        .flat_map(|&e| self.edge_vertices[e])
+                })
+                .collect::<Vec<_>>();
+            vertices.sort_unstable();
+            vertices.dedup();
+            let mean = |vs: &mut dyn Iterator<Item = usize>| {
+                let (sum, n) = vs.fold((Pos([0.0; 3]), 0), |(sum, n), v| {
+                    (sum + self.points[v], n + 1)
+                });
+                sum / n as f64
+            };
+            let center = mean(&mut vertices.iter().copied());
+            // Every vertex of a quad is on two of its edges, so this is the center of the quad.
+            let quad_center = |q: usize| {
+                mean(
+                    &mut self.quad_edges[q]
+                        .iter()
+                        .flat_map(|&e| self.edge_vertices[e]),
+                )
+            };
+            let q = *sheet
+                .iter()
+                .min_by(|&&a, &&b| {
+                    quad_center(a)
+                        .dist2(&center)
+                        .total_cmp(&quad_center(b).dist2(&center))
+                })
+                .expect("a sheet has a quad");
+            self.remove_quad(q, round);
+            self.punched.push((center, vertices));
+            any = true;
+        }
+        any
+    }
+
+    fn run(&mut self, isolation: usize) {
+        let mut round = 0;
+        loop {
+            round += 1;
+            let mut free_edges = std::mem::take(&mut self.touched_edges);
+            free_edges.sort_unstable();
+            free_edges.dedup();
+            free_edges.retain(|&e| self.edge_alive[e] && self.edge_count[e] == 1);
+            let mut free_vertices = std::mem::take(&mut self.touched_vertices);
+            free_vertices.sort_unstable();
+            free_vertices.dedup();
+            free_vertices.retain(|&v| {
+                self.free_edge_of_vertex(v)
+                    .is_some_and(|e| round - self.bare_since[e] <= isolation)
+            });
+
+            if free_edges.is_empty() && free_vertices.is_empty() {
+                // Closed sheets have no free edges, so we punch a hole in them.  This is the
+                // only step that changes the homotopy type.
+                if !self.punch_closed_sheets(round) {
+                    break;
+                }
+                continue;
+            }
+
+            for e in free_edges {
+                if !self.edge_alive[e] || self.edge_count[e] != 1 {
+                    continue;
+                }
+                let q = self.edge_quads[e]
+                    .iter()
+                    .copied()
+                    .find(|&q| self.quad_alive[q])
+                    .expect("an edge with a live quad count has a live quad");
+                self.remove_quad(q, round);
+                self.remove_edge(e);
+            }
+            for v in free_vertices {
+                if let Some(e) = self.free_edge_of_vertex(v) {
+                    self.remove_edge(e);
+                }
+            }
+        }
+    }
+}
+
+impl Skeleton {
+    /// Thin the quads of `axis` to a curve skeleton.
+    pub fn from_axis(axis: &AxisMesh, params: &ThinningParams) -> Self {
+        let mut thinning = Thinning::new(axis);
+        thinning.run(params.isolation);
+
+        let segments = (0..thinning.edge_alive.len())
+            .filter(|&e| thinning.edge_alive[e])
+            .map(|e| (thinning.edge_vertices[e][0], thinning.edge_vertices[e][1]))
+            .collect::<Vec<_>>();
+        // A punched sheet with nothing left of it was a sphere, which has no curve skeleton.
+        let isolated_points = thinning
+            .punched
+            .iter()
+            .filter(|(_, vertices)| vertices.iter().all(|&v| thinning.vertex_count[v] == 0))
+            .map(|(center, _)| *center)
+            .collect::<Vec<_>>();
+        if !isolated_points.is_empty() {
+            warn!(
+                dim = axis.dim,
+                "{} closed sheets were thinned away completely; keeping their centers as points",
+                isolated_points.len()
+            );
+        }
+        let mut skeleton = Self {
+            dim: axis.dim,
+            curves: compact_polylines(&axis.points, segments),
+            isolated_points,
+        };
+        if 0.0 < params.min_branch_length {
+            skeleton.prune_branches(params.min_branch_length * axis.average_side());
+        }
+        skeleton
+    }
+
+    /// Number of curves that end at each point.  This is 2 for the inner points of the curves.
+    fn degrees(&self) -> Vec<usize> {
+        let mut degree = vec![0; self.curves.points.len()];
+        for line in &self.curves.lines {
+            for &i in &line[1..line.len() - 1] {
+                degree[i] += 2;
+            }
+            degree[line[0]] += 1;
+            degree[line[line.len() - 1]] += 1;
+        }
+        degree
+    }
+
+    /// Remove branches from a branch point to a free end that are shorter than `min_length`, in
+    /// the units of the points.
+    /// Only the shortest such branch at a branch point is removed at a time, since the others
+    /// might be part of a longer curve once it is gone.
+    pub fn prune_branches(&mut self, min_length: f64) {
+        loop {
+            let degree = self.degrees();
+            // The shortest short branch at each branch point.
+            let mut shortest: HashMap<usize, (f64, usize)> = HashMap::new();
+            for (k, line) in self.curves.lines.iter().enumerate() {
+                let (a, b) = (line[0], line[line.len() - 1]);
+                let branch_point = match (degree[a], degree[b]) {
+                    (1, d) if 2 < d => b,
+                    (d, 1) if 2 < d => a,
+                    _ => continue,
+                };
+                let length = polyline_length(&self.curves.points, line);
+                if min_length <= length {
+                    continue;
+                }
+                let best = shortest.entry(branch_point).or_insert((length, k));
+                if length < best.0 {
+                    *best = (length, k);
+                }
+            }
+            if shortest.is_empty() {
+                return;
+            }
+
+            let removed = shortest.values().map(|&(_, k)| k).collect::<HashSet<_>>();
+            let segments = (0..self.curves.lines.len())
+                .filter(|k| !removed.contains(k))
+                .flat_map(|k| self.curves.lines[k].windows(2).map(|w| (w[0], w[1])))
+                .collect::<Vec<_>>();
+            self.curves = compact_polylines(&self.curves.points, segments);
+        }
+    }
+
+    /// The skeleton as a graph of branch and end points, and the curves between them.
+    pub fn graph(&self) -> SkeletonGraph {
+        let degree = self.degrees();
+        let mut node_of = HashMap::new();
+        let mut nodes = Vec::new();
+        for (i, &d) in degree.iter().enumerate() {
+            if d != 2 {
+                node_of.insert(i, nodes.len());
+                nodes.push(SkeletonNode {
+                    position: self.curves.points[i],
+                    degree: d,
+                });
+            }
+        }
+        nodes.extend(self.isolated_points.iter().map(|&position| SkeletonNode {
+            position,
+            degree: 0,
+        }));
+        let curves = self
+            .curves
+            .lines
+            .iter()
+            .map(|line| SkeletonCurve {
+                from: node_of.get(&line[0]).copied(),
+                to: node_of.get(&line[line.len() - 1]).copied(),
+                points: line.iter().map(|&i| self.curves.points[i]).collect(),
+                length: polyline_length(&self.curves.points, line),
+            })
+            .collect();
+        SkeletonGraph {
+            dim: self.dim,
+            nodes,
+            curves,
+        }
+    }
+}
+
+fn polyline_length(points: &[Pos], line: &[usize]) -> f64 {
+    line.windows(2)
+        .map(|w| points[w[0]].dist(&points[w[1]]))
+        .sum()
+}
+
+/// [Polylines::from_segments] with only the points that are used by the segments.
+fn compact_polylines(points: &[Pos], segments: Vec<(usize, usize)>) -> Polylines {
+    let mut new_index = HashMap::new();
+    let mut used = Vec::new();
+    let mut index = |i: usize| {
+        *new_index.entry(i).or_insert_with(|| {
+            used.push(points[i]);
+            used.len() - 1
+        })
+    };
+    let segments = segments
+        .into_iter()
+        .map(|(a, b)| (index(a), index(b)))
+        .collect::<Vec<_>>();
+    Polylines::from_segments(used, segments)
+}
+
+/// Write the skeletons as polylines in an .obj file, with one object `skeleton-dim-<dim>` each.
+/// The isolated points are written as point elements.
+pub fn write_skeletons_as_obj<W: std::io::Write>(
+    mut w: W,
+    skeletons: &[Skeleton],
+) -> std::io::Result<()> {
+    let mut offset = 0;
+    for s in skeletons {
+        writeln!(w, "o skeleton-dim-{}", s.dim)?;
+        s.curves.write_as_obj(&mut w, offset)?;
+        offset += s.curves.points.len();
+        for p in &s.isolated_points {
+            writeln!(w, "v {} {} {}", p.x(), p.y(), p.z())?;
+            offset += 1;
+            writeln!(w, "p {}", offset)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the skeletons as a JSON list of [SkeletonGraph]s.
+pub fn write_skeletons_as_json<W: std::io::Write>(
+    w: W,
+    skeletons: &[Skeleton],
+) -> serde_json::Result<()> {
+    let graphs = skeletons.iter().map(|s| s.graph()).collect::<Vec<_>>();
+    serde_json::to_writer_pretty(w, &graphs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::axis_mesh::AxisFace;
+    use crate::grid::Index;
+
+    /// A flat `w` by `h` grid of unit quads in the xy plane.
+    fn sheet(w: usize, h: usize) -> AxisMesh {
+        let points = (0..=h)
+            .flat_map(|y| (0..=w).map(move |x| Pos([x as f64, y as f64, 0.0])))
+            .collect();
+        let v = |x: usize, y: usize| y * (w + 1) + x;
+        let faces = (0..h)
+            .flat_map(|y| (0..w).map(move |x| (x, y)))
+            .map(|(x, y)| AxisFace {
+                vertices: [v(x, y), v(x + 1, y), v(x + 1, y + 1), v(x, y + 1)],
+                edge: (Index([0; 3]), Index([0; 3])),
+                num_swaps: 1,
+                max_persistence: None,
+                pair: (0, 1),
+                crossing: Pos([x as f64 + 0.5, y as f64 + 0.5, 0.0]),
+                radius: None,
+            })
+            .collect();
+        AxisMesh {
+            dim: 1,
+            points,
+            faces,
+        }
+    }
+
+    #[test]
+    fn strip_thins_to_a_centered_curve() {
+        let skeleton = Skeleton::from_axis(&sheet(20, 4), &ThinningParams::default());
+        let graph = skeleton.graph();
+        assert_eq!(graph.curves.len(), 1);
+        let curve = &graph.curves[0];
+        // The ends may bend into one of the corners, but the curve is in the middle of the strip.
+        let inner = &curve.points[1..curve.points.len() - 1];
+        assert!(inner.iter().all(|p| p.y() == 2.0));
+        assert!(10.0 < curve.length);
+        assert_eq!(graph.nodes.len(), 2);
+        assert!(graph.nodes.iter().all(|n| n.degree == 1));
+    }
+
+    #[test]
+    fn loops_are_kept() {
+        // A ring of quads around a hole can't be thinned to less than a loop.
+        let mut ring = sheet(9, 9);
+        ring.faces.retain(|f| {
+            !(3..6).contains(&(f.vertices[0] % 10)) || !(3..6).contains(&(f.vertices[0] / 10))
+        });
+        let skeleton = Skeleton::from_axis(&ring, &ThinningParams::default());
+        let graph = skeleton.graph();
+        assert_eq!(graph.curves.len(), 1);
+        assert_eq!(graph.curves[0].from, None);
+        let points = &graph.curves[0].points;
+        assert_eq!(points[0].0, points[points.len() - 1].0);
+        assert!(graph.nodes.is_empty());
+
+        let mut json = Vec::new();
+        write_skeletons_as_json(&mut json, &[skeleton]).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json[0]["dim"], 1);
+        assert_eq!(json[0]["curves"].as_array().unwrap().len(), 1);
+    }
+
+    /// The surfaces of `n` cubes with their lowest corners at the `corners`.
+    fn cube_surfaces(n: usize, corners: &[usize]) -> AxisMesh {
+        let mut points = Vec::new();
+        let mut ids = HashMap::new();
+        let mut faces = Vec::new();
+        for &corner in corners {
+            for axis in 0..3 {
+                for side in [0, n] {
+                    for a in 0..n {
+                        for b in 0..n {
+                            let vertices =
+                                [(a, b), (a + 1, b), (a + 1, b + 1), (a, b + 1)].map(|(a, b)| {
+                                    let mut p = [corner; 3];
+                                    p[axis] += side;
+                                    p[(axis + 1) % 3] += a;
+                                    p[(axis + 2) % 3] += b;
+                                    *ids.entry(p).or_insert_with(|| {
+                                        points.push(Pos(p.map(|c| c as f64)));
+                                        points.len() - 1
+                                    })
+                                });
+                            faces.push(AxisFace {
+                                vertices,
+                                edge: (Index([0; 3]), Index([0; 3])),
+                                num_swaps: 1,
+                                max_persistence: None,
+                                pair: (0, 1),
+                                crossing: Pos([0.0; 3]),
+                                radius: None,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        AxisMesh {
+            dim: 2,
+            points,
+            faces,
+        }
+    }
+
+    #[test]
+    fn closed_sheets_are_punched() {
+        // Like the axes around two cavities.  Each cube gets a hole in the middle of a face, and
+        // the collapses from it meet on the opposite face, where they leave curves.
+        let skeleton = Skeleton::from_axis(&cube_surfaces(6, &[0, 10]), &ThinningParams::default());
+        assert!(skeleton.isolated_points.is_empty());
+        let graph = skeleton.graph();
+        for corner in [0.0, 10.0] {
+            assert!(graph
+                .curves
+                .iter()
+                .any(|c| c.points.iter().all(|p| p.x() == corner + 6.0)));
+        }
+        assert!(graph
+            .curves
+            .iter()
+            .flat_map(|c| &c.points)
+            .all(|p| p.x() == 6.0 || p.x() == 16.0));
+
+        // A small cube is thinned away completely, so its center is kept instead.
+        let skeleton = Skeleton::from_axis(&cube_surfaces(1, &[0]), &ThinningParams::default());
+        assert!(skeleton.curves.lines.is_empty());
+        assert_eq!(skeleton.isolated_points.len(), 1);
+        let graph = skeleton.graph();
+        assert_eq!(graph.nodes.len(), 1);
+        assert_eq!(graph.nodes[0].degree, 0);
+        assert_eq!(graph.nodes[0].position.0, [0.5; 3]);
+
+        let mut obj = Vec::new();
+        write_skeletons_as_obj(&mut obj, &[skeleton]).unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+        assert!(obj.ends_with("v 0.5 0.5 0.5\np 1\n"), "{}", obj);
+    }
+
+    #[test]
+    fn short_branches_are_pruned() {
+        // A T of two strips, where the stem is short.
+        let mut t = sheet(20, 9);
+        t.faces.retain(|f| {
+            let (x, y) = (f.vertices[0] % 21, f.vertices[0] / 21);
+            y < 3 || (9..12).contains(&x)
+        });
+        let unpruned = ThinningParams {
+            min_branch_length: 0.0,
+            ..Default::default()
+        };
+        let unpruned = Skeleton::from_axis(&t, &unpruned).graph();
+        assert!(unpruned.nodes.iter().any(|n| n.degree == 3));
+
+        let params = ThinningParams {
+            min_branch_length: 100.0,
+            ..Default::default()
+        };
+        let pruned = Skeleton::from_axis(&t, &params).graph();
+        assert_eq!(pruned.curves.len(), 1);
+        assert_eq!(pruned.nodes.len(), 2);
+        assert!(pruned.nodes.iter().all(|n| n.degree == 1));
+    }
+}
@@ -1222,7 +1222,10 @@ mod tests {
         let key_point = complex::Pos([0.1, 0.2, 0.3]);
 
         let complex = crate::test::test_complex_cube();
-        let (v_perm, e_perm, _) = crate::compute_permutations(&complex, key_point);
+        let mut perms =
+            crate::compute_permutations(&complex, &crate::filtration::SquaredEuclidean, key_point);
+        let e_perm = perms.swap_remove(1);
+        let v_perm = perms.swap_remove(0);
         let mut boundary_0 = complex.boundary_matrix(0);
         boundary_0.col_perm = Some(v_perm.clone());
         let adds0 = boundary_0.reduce();
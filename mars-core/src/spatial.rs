@@ -0,0 +1,337 @@
+//! A spatial hash for proximity queries on sets of points.
+//!
+//! Points are put in buckets, which are cubes of a fixed side length, so that the points near a
+//! position are found by only looking in the buckets around it.  This is used to find (and merge)
+//! vertices that are too close together when reading a complex, and to find neighbors when
+//! building complexes from point clouds.
+use std::{borrow::Cow, collections::HashMap};
+
+use crate::complex::Pos;
+
+/// A spatial hash of a set of points.
+#[derive(Clone, Debug)]
+pub struct PointIndex<'a> {
+    points: Cow<'a, [Pos]>,
+    cell_size: f64,
+    buckets: HashMap<[i64; 3], Vec<usize>>,
+}
+
+impl<'a> PointIndex<'a> {
+    /// Index all the points, with buckets of side `cell_size`.  Queries are fastest when the
+    /// radius is around the cell size.
+    pub fn new(points: &'a [Pos], cell_size: f64) -> Self {
+        Self::with_all(Cow::Borrowed(points), cell_size)
+    }
+
+    /// Like [PointIndex::new], but the index owns the points, so that it can be kept around.
+    pub fn owned(points: Vec<Pos>, cell_size: f64) -> PointIndex<'static> {
+        PointIndex::with_all(Cow::Owned(points), cell_size)
+    }
+
+    fn with_all(points: Cow<'a, [Pos]>, cell_size: f64) -> Self {
+        let mut index = Self::with_none(points, cell_size);
+        for i in 0..index.points.len() {
+            index.insert(i);
+        }
+        index
+    }
+
+    /// An index over `points` that doesn't contain any of them yet.  Points are added with
+    /// [PointIndex::insert].
+    pub fn empty(points: &'a [Pos], cell_size: f64) -> Self {
+        Self::with_none(Cow::Borrowed(points), cell_size)
+    }
+
+    fn with_none(points: Cow<'a, [Pos]>, cell_size: f64) -> Self {
+        let cell_size = if cell_size.is_finite() && 0.0 < cell_size {
+            cell_size
+        } else {
+            1.0
+        };
+        Self {
+            points,
+            cell_size,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// The bucket of `p`.  The cast saturates, so infinite coordinates end up in the outermost
+    /// buckets and NaNs in bucket 0, and all arithmetic on buckets has to saturate too.
+    fn bucket_of(&self, p: &Pos) -> [i64; 3] {
+        p.0.map(|c| (c / self.cell_size).floor() as i64)
+    }
+
+    /// Add the point with index `i` to the index.
+    pub fn insert(&mut self, i: usize) {
+        let b = self.bucket_of(&self.points[i]);
+        self.buckets.entry(b).or_default().push(i);
+    }
+
+    /// The indexed points at most `radius` away from `p`, in no particular order.
+    pub fn within(&self, p: Pos, radius: f64) -> impl Iterator<Item = usize> + '_ {
+        let r = (radius / self.cell_size).ceil().max(0.0) as i64;
+        let b = self.bucket_of(&p);
+        let r2 = radius * radius;
+        (-r..=r)
+            .flat_map(move |dx| (-r..=r).flat_map(move |dy| (-r..=r).map(move |dz| [dx, dy, dz])))
+            .filter_map(move |d| {
+                let key = [0, 1, 2].map(|j| b[j].saturating_add(d[j]));
+                self.buckets.get(&key)
+            })
+            .flatten()
+            .copied()
+            .filter(move |&i| self.points[i].dist2(&p) <= r2)
+    }
+
+    /// The indexed point closest to `p`, and its distance, or `None` if the index is empty.  Ties,
+    /// and NaN distances, go to the point with the lowest index.
+    pub fn nearest(&self, p: Pos) -> Option<(usize, f64)> {
+        if self.buckets.is_empty() {
+            return None;
+        }
+        let (lo, hi) =
+            self.buckets
+                .keys()
+                .fold(([i64::MAX; 3], [i64::MIN; 3]), |(mut lo, mut hi), b| {
+                    for j in 0..3 {
+                        lo[j] = lo[j].min(b[j]);
+                        hi[j] = hi[j].max(b[j]);
+                    }
+                    (lo, hi)
+                });
+        let b = self.bucket_of(&p);
+        // The rings between these are the ones that overlap the bounding box of the buckets.
+        let first_ring = (0..3)
+            .map(|j| {
+                lo[j]
+                    .saturating_sub(b[j])
+                    .max(b[j].saturating_sub(hi[j]))
+                    .max(0)
+            })
+            .max()
+            .unwrap_or(0);
+        let last_ring = (0..3)
+            .map(|j| {
+                let (a, c) = (b[j].saturating_sub(lo[j]), hi[j].saturating_sub(b[j]));
+                a.saturating_abs().max(c.saturating_abs())
+            })
+            .max()
+            .unwrap_or(0);
+
+        let mut best: Option<(usize, f64)> = None;
+        for ring in first_ring..=last_ring {
+            // Points in this ring, or further out, are more than `(ring - 1) * cell_size` away.
+            if let Some((_, d)) = best {
+                if d <= (ring - 1) as f64 * self.cell_size {
+                    break;
+                }
+            }
+            for key in ring_buckets(b, ring, lo, hi) {
+                let Some(bucket) = self.buckets.get(&key) else {
+                    continue;
+                };
+                for &i in bucket {
+                    let d = self.points[i].dist(&p);
+                    if best.is_none_or(|(bi, bd)| d.total_cmp(&bd).then(i.cmp(&bi)).is_lt()) {
+                        best = Some((i, d));
+                    }
+                }
+            }
+        }
+        best
+    }
+}
+
+/// The buckets at Chebyshev distance exactly `ring` from `center` that are inside the box from `lo`
+/// to `hi`.  Only the six faces of the ring are visited, not the whole cube inside it.
+fn ring_buckets(
+    center: [i64; 3],
+    ring: i64,
+    lo: [i64; 3],
+    hi: [i64; 3],
+) -> impl Iterator<Item = [i64; 3]> {
+    let range = move |j: usize| {
+        center[j].saturating_sub(ring).max(lo[j])..=center[j].saturating_add(ring).min(hi[j])
+    };
+    let on_shell = move |j: usize, c: i64| c.saturating_sub(center[j]).saturating_abs() == ring;
+    range(0).flat_map(move |x| {
+        range(1).flat_map(move |y| {
+            let zs: Box<dyn Iterator<Item = i64>> = if on_shell(0, x) || on_shell(1, y) {
+                Box::new(range(2))
+            } else {
+                // Only the two caps along `z`, if they are inside the box.
+                let caps = if ring == 0 {
+                    vec![center[2]]
+                } else {
+                    vec![
+                        center[2].saturating_sub(ring),
+                        center[2].saturating_add(ring),
+                    ]
+                };
+                Box::new(caps.into_iter().filter(move |z| range(2).contains(z)))
+            };
+            zs.map(move |z| [x, y, z])
+        })
+    })
+}
+
+/// Find two points that are closer than `distance`.  The pair with the smallest first index, and
+/// then the smallest second index, is returned.
+pub fn find_close_pair(points: &[Pos], distance: f64) -> Option<(usize, usize)> {
+    let index = PointIndex::new(points, distance);
+    points.iter().enumerate().find_map(|(i, &p)| {
+        index
+            .within(p, distance)
+            .filter(|&j| i < j && points[j].dist(&p) < distance)
+            .min()
+            .map(|j| (i, j))
+    })
+}
+
+/// Merge points that are closer than `distance`.  Points are visited in order, and a point that is
+/// close to a point that was kept is merged into it.  Returns the kept points, and for each input
+/// point the index of the kept point it was merged into.
+pub fn merge_close_points(points: &[Pos], distance: f64) -> (Vec<Pos>, Vec<usize>) {
+    let mut index = PointIndex::empty(points, distance);
+    let mut kept = Vec::new();
+    let mut new_index = vec![0; points.len()];
+    for (i, &p) in points.iter().enumerate() {
+        let close = index
+            .within(p, distance)
+            .filter(|&j| points[j].dist(&p) < distance)
+            .min();
+        match close {
+            Some(j) => new_index[i] = new_index[j],
+            None => {
+                new_index[i] = kept.len();
+                kept.push(p);
+                index.insert(i);
+            }
+        }
+    }
+    (kept, new_index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn brute_force_close_pair(points: &[Pos], distance: f64) -> Option<(usize, usize)> {
+        (0..points.len()).find_map(|i| {
+            (i + 1..points.len())
+                .find(|&j| points[i].dist(&points[j]) < distance)
+                .map(|j| (i, j))
+        })
+    }
+
+    #[test]
+    fn close_pairs_match_brute_force() {
+        // A jittered grid, with a few points moved close to others.
+        let mut points = (0..1000)
+            .map(|i| {
+                let h = (i as f64 * 0.618_033_988_7).fract() * 0.1;
+                Pos([
+                    (i % 10) as f64 + h,
+                    ((i / 10) % 10) as f64,
+                    (i / 100) as f64 - h,
+                ])
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(find_close_pair(&points, 0.5), None);
+        assert_eq!(brute_force_close_pair(&points, 0.5), None);
+
+        points[700] = points[123] + Pos([0.0, 0.3, 0.0]);
+        points[456] = points[789] + Pos([-0.2, 0.0, 0.1]);
+        for d in [0.01, 0.25, 0.5, 1.0, 1.5] {
+            assert_eq!(
+                find_close_pair(&points, d),
+                brute_force_close_pair(&points, d)
+            );
+        }
+    }
+
+    #[test]
+    fn nearest_and_within() {
+        let points = (0..5)
+            .map(|i| Pos([i as f64, 0.0, 0.0]))
+            .collect::<Vec<_>>();
+        let index = PointIndex::new(&points, 0.5);
+        assert_eq!(index.nearest(Pos([2.2, 0.1, 0.0])).unwrap().0, 2);
+        let (i, d) = index.nearest(Pos([100.0, 0.0, 0.0])).unwrap();
+        assert_eq!((i, d), (4, 96.0));
+
+        let mut near = index.within(Pos([1.5, 0.0, 0.0]), 1.5).collect::<Vec<_>>();
+        near.sort();
+        assert_eq!(near, [0, 1, 2, 3]);
+
+        assert_eq!(PointIndex::new(&[], 1.0).nearest(Pos([0.0; 3])), None);
+
+        // Infinite coordinates are in the outermost buckets, and looking around them doesn't
+        // overflow.
+        let far = [
+            Pos([f64::INFINITY, 0.0, 0.0]),
+            Pos([f64::NEG_INFINITY; 3]),
+            Pos([0.0; 3]),
+        ];
+        assert_eq!(find_close_pair(&far, 1.0), None);
+    }
+
+    #[test]
+    fn ring_buckets_are_the_shell() {
+        let big = ([i64::MIN; 3], [i64::MAX; 3]);
+        for ring in 0..4 {
+            let mut keys = ring_buckets([1, -2, 3], ring, big.0, big.1).collect::<Vec<_>>();
+            let n = keys.len();
+            keys.sort();
+            keys.dedup();
+            assert_eq!(keys.len(), n);
+            let side = 2 * ring as usize + 1;
+            let inner = side.saturating_sub(2);
+            assert_eq!(n, side.pow(3) - if ring == 0 { 0 } else { inner.pow(3) });
+            assert!(keys
+                .iter()
+                .all(|k| { (0..3).map(|j| (k[j] - [1, -2, 3][j]).abs()).max() == Some(ring) }));
+        }
+        // Clipped to a box that only touches one face of the ring.
+        let keys = ring_buckets([0; 3], 2, [2, -1, -1], [5, 1, 1]).collect::<Vec<_>>();
+        assert_eq!(keys.len(), 9);
+    }
+
+    #[test]
+    fn nearest_matches_brute_force() {
+        // A fixed pseudo-random cloud, with queries inside and far outside of it.
+        let mut state = 12345u64;
+        let mut next = || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 11) as f64 / (1u64 << 53) as f64
+        };
+        let points = (0..200)
+            .map(|_| Pos([next(), next(), next()]))
+            .collect::<Vec<_>>();
+        let index = PointIndex::new(&points, 0.05);
+        for k in 0..100 {
+            let scale = if k % 2 == 0 { 1.0 } else { 50.0 };
+            let p = Pos([next() * scale, next() * scale, -next() * scale]);
+            let brute = (0..points.len())
+                .map(|i| (i, points[i].dist(&p)))
+                .min_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+            assert_eq!(index.nearest(p), brute);
+        }
+    }
+
+    #[test]
+    fn merge_points() {
+        let points = [
+            Pos([0.0, 0.0, 0.0]),
+            Pos([1.0, 0.0, 0.0]),
+            Pos([0.0, 0.0, 1e-7]),
+            Pos([1.0, 1e-7, 0.0]),
+            Pos([2.0, 0.0, 0.0]),
+        ];
+        let (kept, new_index) = merge_close_points(&points, 1e-5);
+        assert_eq!(kept.len(), 3);
+        assert_eq!(new_index, [0, 1, 0, 1, 2]);
+    }
+}
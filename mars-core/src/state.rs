@@ -0,0 +1,287 @@
+//! Versioned state files, as written by `mars-cli run` and `mars-cli prune`.
+//!
+//! The layout of a file is
+//!
+//! ```text
+//! MAGIC  FORMAT_VERSION:u32
+//! len:u64 header            (rmp encoded [StateHeader])
+//! payload                   (rmp encoded `(Mars, Vineyards)` or `(Vec<SlimSwapList>, Mars)`)
+//! ```
+//!
+//! Files written before the header was introduced are just the payload.  [read_state] detects
+//! these and migrates them, so that all callers only have to deal with the current version.
+//!
+//! Chunked files (see [crate::chunked]) have their own header and are not handled here.
+use std::io::Write;
+
+use crate::{
+    chunked,
+    error::{MarsError, MarsResult},
+    Mars, PruningParam, SlimSwapList, Vineyards,
+};
+
+/// The first bytes of a state file.
+pub const MAGIC: &[u8; 8] = b"MARSSTAT";
+/// Version of the state file layout.  Bump this when the layout or any of the serialized types
+/// change, and add a migration to [read_state].
+///
+/// Version 2 added the filtration to [Mars].  It is a trailing field with a default, so version 1
+/// files are read as version 2 files with the default filtration.
+///
+/// Version 3 added [crate::Swap::t].  It is also a trailing field with a default, so older swaps are
+/// put at the midpoint of their grid edge.
+pub const FORMAT_VERSION: u32 = 3;
+
+/// What the payload of a state file is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum StateKind {
+    /// The full [Vineyards], including all matrices.
+    Full,
+    /// Only the swaps, from [Mars::run_slim].
+    Slim,
+}
+
+/// The parameters of the run that produced a state file.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct RunParams {
+    /// The pruning parameters, if the swaps have been pruned.
+    pub pruning: Option<Vec<PruningParam>>,
+    /// The refinement depth, for adaptive runs.
+    pub refine: Option<usize>,
+}
+
+/// Everything in a state file except the payload.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct StateHeader {
+    pub kind: StateKind,
+    /// Version of `mars_core` that wrote the file, or `None` for migrated files.
+    pub crate_version: Option<String>,
+    pub params: RunParams,
+}
+
+impl StateHeader {
+    /// A header for a file written by this version of `mars_core`.
+    pub fn new(kind: StateKind, params: RunParams) -> Self {
+        Self {
+            kind,
+            crate_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            params,
+        }
+    }
+}
+
+/// The payload of a state file.
+#[derive(Debug)]
+pub enum State {
+    Full(Mars, Vineyards),
+    Slim(Vec<SlimSwapList>, Mars),
+}
+
+impl State {
+    pub fn kind(&self) -> StateKind {
+        match self {
+            State::Full(..) => StateKind::Full,
+            State::Slim(..) => StateKind::Slim,
+        }
+    }
+
+    pub fn mars(&self) -> &Mars {
+        match self {
+            State::Full(mars, _) => mars,
+            State::Slim(_, mars) => mars,
+        }
+    }
+}
+
+/// Write a state file.  The kind in the header must match the payload.
+pub fn write_state<W: Write>(mut w: W, header: &StateHeader, state: &State) -> MarsResult<()> {
+    if header.kind != state.kind() {
+        return Err(MarsError::Invalid(format!(
+            "Header kind {:?} does not match payload kind {:?}",
+            header.kind,
+            state.kind()
+        )));
+    }
+    w.write_all(MAGIC)?;
+    w.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    let header_bytes = rmp_serde::to_vec(header)?;
+    w.write_all(&(header_bytes.len() as u64).to_le_bytes())?;
+    w.write_all(&header_bytes)?;
+    match state {
+        State::Full(mars, vin) => rmp_serde::encode::write(&mut w, &(mars, vin)),
+        State::Slim(swaps, mars) => rmp_serde::encode::write(&mut w, &(swaps, mars)),
+    }?;
+    w.flush().map_err(MarsError::from)
+}
+
+/// Read a state file of any version.
+pub fn read_state(bytes: &[u8]) -> MarsResult<(StateHeader, State)> {
+    if chunked::is_chunked(bytes) {
+        return Err(MarsError::Invalid(
+            "This is a chunked state file; read it with ChunkedReader".to_string(),
+        ));
+    }
+    if !bytes.starts_with(MAGIC) {
+        return read_legacy(bytes);
+    }
+
+    let rest = &bytes[MAGIC.len()..];
+    let (version, rest) = split_u32(rest)?;
+    match version {
+        1..=3 => read_current(rest),
+        v if v > FORMAT_VERSION => Err(MarsError::Parse(format!(
+            "State file has format version {}, but this version of mars only supports up to {}",
+            v, FORMAT_VERSION
+        ))),
+        v => Err(MarsError::Parse(format!(
+            "Unknown state file format version {}",
+            v
+        ))),
+    }
+}
+
+fn split_u32(bytes: &[u8]) -> MarsResult<(u32, &[u8])> {
+    if bytes.len() < 4 {
+        return Err(MarsError::Parse("State file is truncated".to_string()));
+    }
+    let (n, rest) = bytes.split_at(4);
+    Ok((u32::from_le_bytes(n.try_into().unwrap()), rest))
+}
+
+fn read_current(bytes: &[u8]) -> MarsResult<(StateHeader, State)> {
+    if bytes.len() < 8 {
+        return Err(MarsError::Parse("State file is truncated".to_string()));
+    }
+    let (len, rest) = bytes.split_at(8);
+    let len = u64::from_le_bytes(len.try_into().unwrap()) as usize;
+    if rest.len() < len {
+        return Err(MarsError::Parse("State file is truncated".to_string()));
+    }
+    let (header_bytes, payload) = rest.split_at(len);
+    let header: StateHeader = rmp_serde::from_slice(header_bytes)
+        .map_err(|e| MarsError::Parse(format!("Failed to read state header: {}", e)))?;
+    let state = match header.kind {
+        StateKind::Full => {
+            let (mars, vin) = rmp_serde::from_slice(payload)
+                .map_err(|e| MarsError::Parse(format!("Failed to read full state: {}", e)))?;
+            State::Full(mars, vin)
+        }
+        StateKind::Slim => {
+            let (swaps, mars) = rmp_serde::from_slice(payload)
+                .map_err(|e| MarsError::Parse(format!("Failed to read slim state: {}", e)))?;
+            State::Slim(swaps, mars)
+        }
+    };
+    Ok((header, state))
+}
+
+/// Migrate a file from before the header was introduced.  These are either a full or a slim
+/// payload, and we don't know which, so we try both.
+fn read_legacy(bytes: &[u8]) -> MarsResult<(StateHeader, State)> {
+    let state = match rmp_serde::from_slice::<(Mars, Vineyards)>(bytes) {
+        Ok((mars, vin)) => State::Full(mars, vin),
+        Err(full_err) => match rmp_serde::from_slice::<(Vec<SlimSwapList>, Mars)>(bytes) {
+            Ok((swaps, mars)) => State::Slim(swaps, mars),
+            Err(slim_err) => {
+                return Err(MarsError::Parse(format!(
+                    "Not a state file. Reading as a full state failed with \"{}\", and as a slim state with \"{}\"",
+                    full_err, slim_err
+                )))
+            }
+        },
+    };
+    let header = StateHeader {
+        kind: state.kind(),
+        crate_version: None,
+        params: RunParams::default(),
+    };
+    Ok((header, state))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{grid::Index, Swap};
+
+    fn slim_swaps() -> Vec<SlimSwapList> {
+        let swap = (
+            Swap {
+                dim: 1,
+                i: 3,
+                j: 4,
+                t: 0.25,
+            },
+            0.5,
+            1.5,
+        );
+        vec![
+            vec![],
+            vec![(Index::fake(0), Index::fake(1), vec![swap])],
+            vec![],
+        ]
+    }
+
+    #[test]
+    fn roundtrip_slim() {
+        let header = StateHeader::new(StateKind::Slim, RunParams::default());
+        let mut bytes = Vec::new();
+        write_state(
+            &mut bytes,
+            &header,
+            &State::Slim(slim_swaps(), Mars::default()),
+        )
+        .unwrap();
+        assert!(bytes.starts_with(MAGIC));
+
+        let (header, state) = read_state(&bytes).unwrap();
+        assert_eq!(header.kind, StateKind::Slim);
+        assert_eq!(
+            header.crate_version.as_deref(),
+            Some(env!("CARGO_PKG_VERSION"))
+        );
+        let State::Slim(swaps, _) = state else {
+            panic!("expected slim state");
+        };
+        assert_eq!(swaps.iter().map(|s| s.len()).collect::<Vec<_>>(), [0, 1, 0]);
+        assert_eq!(swaps[1][0].2[0].0.t, 0.25);
+    }
+
+    #[test]
+    fn read_swaps_without_crossing() {
+        // Before version 3 a swap was just `(dim, i, j)`.
+        let swap = ((1usize, 3, 4), 0.5, 1.5);
+        let old = vec![
+            vec![],
+            vec![(Index::fake(0), Index::fake(1), vec![swap])],
+            vec![],
+        ];
+        let bytes = rmp_serde::to_vec(&(old, Mars::default())).unwrap();
+        let State::Slim(swaps, _) = read_state(&bytes).unwrap().1 else {
+            panic!("expected slim state");
+        };
+        assert_eq!(swaps[1][0].2[0].0.t, 0.5);
+    }
+
+    #[test]
+    fn migrate_legacy_slim() {
+        let bytes = rmp_serde::to_vec(&(slim_swaps(), Mars::default())).unwrap();
+        let (header, state) = read_state(&bytes).unwrap();
+        assert_eq!(header.kind, StateKind::Slim);
+        assert_eq!(header.crate_version, None);
+        assert_eq!(state.kind(), StateKind::Slim);
+    }
+
+    #[test]
+    fn reject_newer_version() {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert!(read_state(&bytes).is_err());
+    }
+
+    #[test]
+    fn reject_mismatched_kind() {
+        let header = StateHeader::new(StateKind::Full, RunParams::default());
+        let state = State::Slim(slim_swaps(), Mars::default());
+        assert!(write_state(Vec::new(), &header, &state).is_err());
+    }
+}
@@ -80,31 +80,29 @@ impl std::iter::Sum for StackMem {
 
 #[derive(Debug, Default)]
 pub struct ReductionMem {
-    pub stacks: [StackMem; 3],
+    pub stacks: Vec<StackMem>,
 }
 
 impl Add for ReductionMem {
     type Output = ReductionMem;
 
     fn add(self, rhs: Self) -> Self::Output {
-        Self {
-            stacks: [
-                self.stacks[0].clone() + rhs.stacks[0].clone(),
-                self.stacks[1].clone() + rhs.stacks[1].clone(),
-                self.stacks[2].clone() + rhs.stacks[2].clone(),
-            ],
+        let (mut long, short) = if rhs.stacks.len() <= self.stacks.len() {
+            (self.stacks, rhs.stacks)
+        } else {
+            (rhs.stacks, self.stacks)
+        };
+        for (a, b) in long.iter_mut().zip(short) {
+            *a = a.clone() + b;
         }
+        Self { stacks: long }
     }
 }
 
 impl Into<ReductionMem> for &crate::Reduction {
     fn into(self) -> ReductionMem {
         ReductionMem {
-            stacks: [
-                (&self.stacks[0]).into(),
-                (&self.stacks[1]).into(),
-                (&self.stacks[2]).into(),
-            ],
+            stacks: self.stacks.iter().map(|s| s.into()).collect(),
         }
     }
 }
@@ -138,7 +136,7 @@ impl std::iter::Sum for SwapsMem {
 #[derive(Debug, Default)]
 pub struct VineyardsMem {
     pub reductions: HashMap<crate::Index, ReductionMem>,
-    pub swaps: [usize; 3],
+    pub swaps: Vec<usize>,
 }
 
 impl Into<VineyardsMem> for &crate::Vineyards {
@@ -149,20 +147,16 @@ impl Into<VineyardsMem> for &crate::Vineyards {
                 .iter()
                 .map(|(k, v)| (*k, v.into()))
                 .collect(),
-            swaps: [
-                self.swaps[0]
-                    .iter()
-                    .map(|(_, _, v)| Into::<SwapsMem>::into(v).v)
-                    .sum::<usize>(),
-                self.swaps[1]
-                    .iter()
-                    .map(|(_, _, v)| Into::<SwapsMem>::into(v).v)
-                    .sum::<usize>(),
-                self.swaps[2]
-                    .iter()
-                    .map(|(_, _, v)| Into::<SwapsMem>::into(v).v)
-                    .sum::<usize>(),
-            ],
+            swaps: self
+                .swaps
+                .iter()
+                .map(|dim_swaps| {
+                    dim_swaps
+                        .iter()
+                        .map(|(_, _, v)| Into::<SwapsMem>::into(v).v)
+                        .sum::<usize>()
+                })
+                .collect(),
         }
     }
 }
@@ -9,6 +9,38 @@ pub fn test_complex_cube() -> Complex {
     Complex::read_from_obj_path(&d).expect("Failed to read test input")
 }
 
+/// Construct a planar test [Complex] of the outline of the square `[-0.5, 0.5]^2` in the plane
+/// `z = 0`.
+pub fn test_complex_square() -> Complex {
+    let obj = "v -0.5 -0.5 0\nv 0.5 -0.5 0\nv 0.5 0.5 0\nv -0.5 0.5 0\nl 1 2 3 4 1\n";
+    Complex::read_from_obj_string(obj).expect("Failed to read test input")
+}
+
+/// Construct a test [Complex] of the solid cube, split into six tetrahedra around the diagonal
+/// from `(-0.5, -0.5, -0.5)` to `(0.5, 0.5, 0.5)`.
+pub fn test_complex_tet_cube() -> Complex {
+    // Vertex `i` is the corner with coordinates given by the bits of `i`.
+    let points = (0..8)
+        .map(|i| Pos([0, 1, 2].map(|b| ((i >> b) & 1) as f64 - 0.5)))
+        .collect();
+    let tets = [
+        [0, 1, 2],
+        [0, 2, 1],
+        [1, 0, 2],
+        [1, 2, 0],
+        [2, 0, 1],
+        [2, 1, 0],
+    ]
+    .iter()
+    .map(|axes| {
+        let a = 1 << axes[0];
+        let b = a | 1 << axes[1];
+        vec![0, a, b, 7]
+    })
+    .collect::<Vec<_>>();
+    Complex::from_cells(points, &tets).expect("Failed to build test complex")
+}
+
 pub fn test_grid_for_cube() -> grid::VineyardsGrid {
     grid::VineyardsGrid {
         corner: Pos([-0.5, -0.5, -0.5]),
@@ -22,8 +54,8 @@ pub fn default_pruning_param(dim: usize) -> PruningParam {
     match dim {
         0 => default_pruning_param_dim0(),
         1 => default_pruning_param_dim1(),
-        2 => default_pruning_param_dim2(),
-        _ => panic!("Dimension too high: {}", dim),
+        // Tetrahedra are pruned like triangles.
+        _ => default_pruning_param_dim2(),
     }
 }
 
@@ -0,0 +1,371 @@
+//! Checking a [Complex] for problems before running Vineyards on it, and repairing it.
+//!
+//! The readers catch most problems in the input files, but complexes can also be built or
+//! deserialized directly, and some problems, like repeated or flat triangles, are valid input that
+//! usually points to a broken mesh.
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::{
+    complex::{Complex, Pos},
+    error::{MarsError, MarsResult},
+    sneaky_matrix::CI,
+};
+
+/// The number of indices of each kind that [ValidationReport]'s `Display` lists.
+const MAX_LISTED: usize = 10;
+
+/// The problems found by [Complex::validate].  Simplices are given as `(dim, index)`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ValidationReport {
+    /// Vertices with infinite or NaN coordinates.
+    pub non_finite_vertices: Vec<usize>,
+    /// Simplices with a face that doesn't exist.
+    pub out_of_range: Vec<(usize, usize)>,
+    /// Simplices whose faces don't make up a simplex, e.g. a triangle whose edges don't have
+    /// exactly three vertices between them.
+    pub malformed: Vec<(usize, usize)>,
+    /// Triangles with zero area.
+    pub degenerate_triangles: Vec<usize>,
+    /// Simplices with the same vertices as an earlier simplex, as `(dim, index, earlier index)`.
+    pub repeated: Vec<(usize, usize, usize)>,
+    /// Simplices of dimension one below the top dimension that are a face of more than two top
+    /// dimensional simplices, e.g. edges in more than two triangles of a surface.
+    pub non_manifold: Vec<(usize, usize)>,
+    /// Vertices that aren't in any edge.
+    pub isolated_vertices: Vec<usize>,
+    /// The number of connected components.
+    pub num_components: usize,
+}
+
+impl ValidationReport {
+    /// Whether the complex has problems that would break the algorithm: vertices that aren't
+    /// finite, and simplices that are out of range or malformed.
+    pub fn has_errors(&self) -> bool {
+        !self.non_finite_vertices.is_empty()
+            || !self.out_of_range.is_empty()
+            || !self.malformed.is_empty()
+    }
+
+    /// Whether no problems were found at all.  A complex with more than one component is fine.
+    pub fn is_clean(&self) -> bool {
+        !self.has_errors()
+            && self.degenerate_triangles.is_empty()
+            && self.repeated.is_empty()
+            && self.non_manifold.is_empty()
+            && self.isolated_vertices.is_empty()
+    }
+}
+
+fn write_list<T: std::fmt::Debug>(
+    f: &mut std::fmt::Formatter<'_>,
+    what: &str,
+    list: &[T],
+) -> std::fmt::Result {
+    if list.is_empty() {
+        return Ok(());
+    }
+    write!(f, "{} {}: ", list.len(), what)?;
+    for (i, x) in list.iter().take(MAX_LISTED).enumerate() {
+        let sep = if i == 0 { "" } else { ", " };
+        write!(f, "{}{:?}", sep, x)?;
+    }
+    if MAX_LISTED < list.len() {
+        write!(f, ", ...")?;
+    }
+    writeln!(f)
+}
+
+impl std::fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_list(
+            f,
+            "vertices with non-finite coordinates",
+            &self.non_finite_vertices,
+        )?;
+        write_list(
+            f,
+            "simplices with missing faces (dim, index)",
+            &self.out_of_range,
+        )?;
+        write_list(f, "malformed simplices (dim, index)", &self.malformed)?;
+        write_list(f, "degenerate triangles", &self.degenerate_triangles)?;
+        write_list(
+            f,
+            "repeated simplices (dim, index, first index)",
+            &self.repeated,
+        )?;
+        write_list(f, "non-manifold simplices (dim, index)", &self.non_manifold)?;
+        write_list(f, "isolated vertices", &self.isolated_vertices)?;
+        writeln!(f, "{} connected component(s)", self.num_components)?;
+        if self.is_clean() {
+            writeln!(f, "No problems found")?;
+        }
+        Ok(())
+    }
+}
+
+/// Find the root of `i`, with path halving.
+fn find(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+impl Complex {
+    /// The sorted vertices of each simplex, per dimension, or `None` for simplices that are out of
+    /// range or malformed, or have a face that is.
+    fn simplex_vertices(&self, report: &mut ValidationReport) -> Vec<Vec<Option<Vec<CI>>>> {
+        let mut vertices: Vec<Vec<Option<Vec<CI>>>> = vec![(0..self.simplices_per_dim[0].len()
+            as CI)
+            .map(|v| Some(vec![v]))
+            .collect()];
+        for dim in 1..self.simplices_per_dim.len() {
+            let faces = &vertices[dim - 1];
+            let mut this_dim = Vec::new();
+            for (i, s) in self.simplices_per_dim[dim].iter().enumerate() {
+                if s.boundary
+                    .iter()
+                    .any(|&f| f < 0 || faces.len() <= f as usize)
+                {
+                    report.out_of_range.push((dim, i));
+                    this_dim.push(None);
+                    continue;
+                }
+                let mut vs = Vec::new();
+                let mut ok = s.boundary.len() == dim + 1;
+                for &f in &s.boundary {
+                    match &faces[f as usize] {
+                        Some(f) => vs.extend_from_slice(f),
+                        None => ok = false,
+                    }
+                }
+                vs.sort();
+                vs.dedup();
+                let mut boundary = s.boundary.clone();
+                boundary.sort();
+                boundary.dedup();
+                if ok && (vs.len() != dim + 1 || boundary.len() != dim + 1) {
+                    report.malformed.push((dim, i));
+                    ok = false;
+                }
+                this_dim.push(ok.then_some(vs));
+            }
+            vertices.push(this_dim);
+        }
+        vertices
+    }
+
+    /// Check the complex for problems.  See [ValidationReport] for what is checked.
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        let vertices = self.simplex_vertices(&mut report);
+        let pos = |v: CI| self.simplices_per_dim[0][v as usize].coords;
+
+        report.non_finite_vertices = (self.simplices_per_dim[0].iter().enumerate())
+            .filter(|(_, s)| s.coords.is_some_and(|p| !p.0.iter().all(|c| c.is_finite())))
+            .map(|(v, _)| v)
+            .collect();
+
+        if let Some(triangles) = vertices.get(2) {
+            for (i, vs) in triangles.iter().enumerate() {
+                let Some(vs) = vs else { continue };
+                let (Some(a), Some(b), Some(c)) = (pos(vs[0]), pos(vs[1]), pos(vs[2])) else {
+                    continue;
+                };
+                let (u, v) = (b - a, c - a);
+                let longest = u.dist2(&Pos([0.0; 3])).max(v.dist2(&Pos([0.0; 3])));
+                if u.cross(&v).dist(&Pos([0.0; 3])) <= 1e-12 * longest {
+                    report.degenerate_triangles.push(i);
+                }
+            }
+        }
+
+        for (dim, simplices) in vertices.iter().enumerate().skip(1) {
+            let mut first = HashMap::new();
+            for (i, vs) in simplices.iter().enumerate() {
+                let Some(vs) = vs else { continue };
+                if let Some(&j) = first.get(vs) {
+                    report.repeated.push((dim, i, j));
+                } else {
+                    first.insert(vs, i);
+                }
+            }
+        }
+
+        if let Some(top) = (2..vertices.len())
+            .rev()
+            .find(|&d| !self.simplices_per_dim[d].is_empty())
+        {
+            let mut cofaces = vec![0; self.simplices_per_dim[top - 1].len()];
+            for (s, vs) in self.simplices_per_dim[top].iter().zip(&vertices[top]) {
+                if vs.is_some() {
+                    for &f in &s.boundary {
+                        cofaces[f as usize] += 1;
+                    }
+                }
+            }
+            report.non_manifold = (cofaces.iter().enumerate())
+                .filter(|(_, &n)| 2 < n)
+                .map(|(i, _)| (top - 1, i))
+                .collect();
+        }
+
+        let num_vertices = self.simplices_per_dim[0].len();
+        let mut parent = (0..num_vertices).collect::<Vec<_>>();
+        let mut in_edge = vec![false; num_vertices];
+        for vs in vertices[1].iter().flatten() {
+            let (a, b) = (vs[0] as usize, vs[1] as usize);
+            in_edge[a] = true;
+            in_edge[b] = true;
+            let (ra, rb) = (find(&mut parent, a), find(&mut parent, b));
+            parent[ra] = rb;
+        }
+        report.isolated_vertices = (0..num_vertices).filter(|&v| !in_edge[v]).collect();
+        report.num_components = (0..num_vertices)
+            .filter(|&v| find(&mut parent, v) == v)
+            .count();
+
+        report
+    }
+
+    /// A copy of the complex without the problems that [Complex::validate] finds, where that is
+    /// possible: vertices that aren't finite and simplices that are out of range, malformed,
+    /// repeated or degenerate are removed, along with their cofaces, and so are isolated vertices.
+    /// Non-manifold simplices are kept.
+    pub fn repaired(&self) -> MarsResult<Self> {
+        let vertices = self.simplex_vertices(&mut ValidationReport::default());
+        let validation = self.validate();
+        let mut dropped = validation
+            .repeated
+            .iter()
+            .map(|&(dim, i, _)| (dim, i))
+            .chain(validation.degenerate_triangles.iter().map(|&i| (2, i)))
+            .chain(validation.non_finite_vertices.iter().map(|&v| (0, v)))
+            .collect::<std::collections::HashSet<_>>();
+
+        // Every simplex with a dropped face is dropped, and every remaining simplex is a cell.
+        let mut cells = Vec::new();
+        let mut used = vec![false; self.simplices_per_dim[0].len()];
+        for (dim, simplices) in self.simplices_per_dim.iter().enumerate().skip(1) {
+            for (i, (s, vs)) in simplices.iter().zip(&vertices[dim]).enumerate() {
+                let Some(vs) = vs else {
+                    continue;
+                };
+                if dropped.contains(&(dim, i))
+                    || s.boundary
+                        .iter()
+                        .any(|&f| dropped.contains(&(dim - 1, f as usize)))
+                {
+                    dropped.insert((dim, i));
+                    continue;
+                }
+                for &v in vs {
+                    used[v as usize] = true;
+                }
+                cells.push(vs.clone());
+            }
+        }
+
+        let mut new_index = vec![0; used.len()];
+        let mut points = Vec::new();
+        for (v, s) in self.simplices_per_dim[0].iter().enumerate() {
+            if used[v] {
+                new_index[v] = points.len() as CI;
+                points.push(s.coords.ok_or_else(|| {
+                    MarsError::Internal("Vertex should have coordinates".to_string())
+                })?);
+            }
+        }
+        for cell in &mut cells {
+            for v in cell.iter_mut() {
+                *v = new_index[*v as usize];
+            }
+        }
+        Self::from_cells(points, &cells)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{complex::Simplex, test::test_complex_tet_cube};
+
+    #[test]
+    fn clean_complexes() {
+        let report = test_complex_tet_cube().validate();
+        assert!(report.is_clean(), "{}", report);
+        assert_eq!(report.num_components, 1);
+
+        let c = Complex::read_from_obj_string(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\nf 1 2 3\nf 1 2 4\nf 1 3 4\nf 2 3 4\n",
+        )
+        .unwrap();
+        assert!(c.validate().is_clean());
+    }
+
+    #[test]
+    fn find_and_repair_problems() {
+        // Four triangles on one edge, a repeated triangle, a flat triangle, an isolated vertex,
+        // and a separate edge.  The edges of the flat triangle are kept when repairing.
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 -1 0\nv 0 0 1\nv 0 0 -1\nv 2 0 0\nv 9 9 9\nv 5 5 5\nv 6 5 5\n\
+                   f 1 2 3\nf 1 2 4\nf 1 2 5\nf 1 2 6\nf 2 1 3\nf 1 2 7\nl 9 10\n";
+        let mut c = Complex::read_from_obj_string(obj).unwrap();
+        let report = c.validate();
+        assert!(!report.has_errors());
+        assert_eq!(report.repeated, [(2, 4, 0)]);
+        assert_eq!(report.degenerate_triangles, [5]);
+        assert_eq!(report.isolated_vertices, [7]);
+        assert_eq!(report.num_components, 3);
+        let edge = c.simplices_per_dim[1]
+            .iter()
+            .position(|e| e.boundary == [0, 1])
+            .unwrap();
+        assert_eq!(report.non_manifold, [(1, edge)]);
+        assert!(report.to_string().contains("1 repeated simplices"));
+
+        let repaired = c.repaired().unwrap();
+        let counts = (0..3)
+            .map(|d| repaired.num_simplices_of_dim(d))
+            .collect::<Vec<_>>();
+        assert_eq!(counts, [9, 12, 4]);
+        assert_eq!(repaired.validate().non_manifold.len(), 1);
+
+        // A triangle whose edges don't close up, and one with an edge that doesn't exist.
+        c.simplices_per_dim[2].push(Simplex {
+            id: 6,
+            coords: None,
+            boundary: vec![0, 1, edge as CI],
+        });
+        c.simplices_per_dim[2].push(Simplex {
+            id: 7,
+            coords: None,
+            boundary: vec![0, 1, 1000],
+        });
+        let report = c.validate();
+        assert!(report.has_errors());
+        assert_eq!(report.malformed, [(2, 6)]);
+        assert_eq!(report.out_of_range, [(2, 7)]);
+        assert_eq!(c.repaired().unwrap().num_simplices_of_dim(2), 4);
+    }
+
+    #[test]
+    fn non_finite_vertices_are_errors() {
+        let mut c = test_complex_tet_cube();
+        c.simplices_per_dim[0][3].coords = Some(Pos([0.0, f64::NAN, 0.0]));
+        let report = c.validate();
+        assert_eq!(report.non_finite_vertices, [3]);
+        assert!(report.has_errors());
+        assert!(report
+            .to_string()
+            .contains("1 vertices with non-finite coordinates"));
+
+        // The vertex is dropped with all of its cofaces.
+        let repaired = c.repaired().unwrap();
+        assert_eq!(repaired.num_simplices_of_dim(0), 7);
+        assert!(repaired.validate().is_clean());
+    }
+}
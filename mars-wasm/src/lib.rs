@@ -1,14 +1,23 @@
 #![allow(non_snake_case)]
 use anyhow::{bail, Result};
-use mars_core::complex::Complex;
+use mars_core::axis_mesh::AxisMesh;
+use mars_core::chunked::ChunkedReader;
+use mars_core::complex::{Complex, Pos};
+use mars_core::error::MarsError;
+use mars_core::feature_size::FeatureSize;
+use mars_core::filtration::{Filtration, FiltrationKind};
 use mars_core::grid::{Index, VineyardsGridMesh};
-use mars_core::{BirthDeathPair, Grid, Mars, PruningParam, SubMars, Vineyards};
-use serde::{Serialize, Serializer};
+use mars_core::points::PointComplex;
+use mars_core::state::State;
+use mars_core::{BirthDeathPair, Grid, Mars, PruningParam, SlimSwapList, SubMars, Swap, Vineyards};
+use serde::{Deserialize, Serialize, Serializer};
 use wasm_bindgen::prelude::*;
 
 use mars_core::{grid::VineyardsGrid, SwapList};
 use tracing::{debug, error, info, trace};
 
+use std::collections::BTreeMap;
+use std::io::Cursor;
 use std::panic;
 
 use std::alloc::{GlobalAlloc, Layout, System};
@@ -88,12 +97,44 @@ fn mb(u: usize) -> f64 {
     (u as f64) / 1024.0 / 1024.0
 }
 
+/// Turns a [MarsError] into a JS `Error` named `"MarsError"`, with the kind of error in `kind`.
+fn js_error(e: MarsError) -> JsValue {
+    let err = js_sys::Error::new(&e.to_string());
+    err.set_name("MarsError");
+    let _ = js_sys::Reflect::set(&err, &"kind".into(), &e.kind().into());
+    err.into()
+}
+
+/// Converting between Rust and JS values failed.  Arguments from JS with the wrong shape end up
+/// here, so this is [MarsError::Invalid].
+fn js_serde_error(e: serde_wasm_bindgen::Error) -> JsValue {
+    js_error(MarsError::Invalid(format!(
+        "serde_wasm_bindgen failed: {}",
+        e
+    )))
+}
+
+/// Convert an argument from JS.
+fn from_js<T: serde::de::DeserializeOwned>(value: JsValue) -> Result<T, JsValue> {
+    serde_wasm_bindgen::from_value(value).map_err(js_serde_error)
+}
+
+/// Convert a return value to JS.
+fn to_js<T: Serialize + ?Sized>(value: &T) -> Result<JsValue, JsValue> {
+    serde_wasm_bindgen::to_value(value).map_err(js_serde_error)
+}
+
 #[wasm_bindgen(skip_typescript)]
 #[derive(Default)]
 pub struct Api {
     core: mars_core::Mars,
     vineyards: Option<mars_core::Vineyards>,
-    pruned_swaps: [Option<(PruningParam, mars_core::SwapList)>; 3],
+    /// Swaps from a slim state file, which we have instead of `vineyards`.
+    slim_swaps: Option<Vec<SlimSwapList>>,
+    /// A chunked state file that fills in `slim_swaps` one dimension at a time.
+    chunked: Option<ChunkedSwaps>,
+    /// Pruned swaps per dimension, one for each dimension of simplices in the complex.
+    pruned_swaps: Vec<Option<(PruningParam, PrunedSwaps)>>,
 
     // Callbacks
     on_complex_change: Option<js_sys::Function>,
@@ -102,7 +143,109 @@ pub struct Api {
     on_pruned_change: Option<js_sys::Function>,
 }
 
+/// A chunked state file, kept in memory, with the dimensions that have been read so far.
+struct ChunkedSwaps {
+    reader: ChunkedReader<Cursor<Vec<u8>>>,
+    loaded: Vec<bool>,
+}
+
+/// Pruned swaps of one dimension, from either [Vineyards] or slim swaps.
+#[derive(Debug, Serialize, Deserialize)]
+enum PrunedSwaps {
+    Full(SwapList),
+    Slim(SlimSwapList),
+}
+
+/// The swaps of one dimension, from either a full or a slim run.
+#[derive(Clone, Copy)]
+enum DimSwaps<'a> {
+    Full(&'a SwapList),
+    Slim(&'a SlimSwapList),
+}
+
+impl<'a> DimSwaps<'a> {
+    /// The grid edges with at least one swap.  These are the faces of the medial axis.
+    fn edges(self) -> Box<dyn Iterator<Item = (Index, Index)> + 'a> {
+        match self {
+            DimSwaps::Full(swaps) => Box::new(
+                swaps
+                    .iter()
+                    .filter(|s| !s.2.v.is_empty())
+                    .map(|s| (s.0, s.1)),
+            ),
+            DimSwaps::Slim(swaps) => {
+                Box::new(swaps.iter().filter(|s| !s.2.is_empty()).map(|s| (s.0, s.1)))
+            }
+        }
+    }
+
+    /// The `k`th grid edge from [DimSwaps::edges], with its swaps.
+    fn edge_swaps(self, k: usize) -> Option<(Index, Index, Vec<Swap>)> {
+        match self {
+            DimSwaps::Full(swaps) => swaps
+                .iter()
+                .filter(|s| !s.2.v.is_empty())
+                .nth(k)
+                .map(|s| (s.0, s.1, s.2.v.clone())),
+            DimSwaps::Slim(swaps) => swaps.iter().filter(|s| !s.2.is_empty()).nth(k).map(|s| {
+                (
+                    s.0,
+                    s.1,
+                    s.2.iter().map(|(swap, _, _)| swap.clone()).collect(),
+                )
+            }),
+        }
+    }
+}
+
 impl Api {
+    /// The swaps of dimension `dim` to show: the pruned swaps if we have them, and otherwise all
+    /// swaps from the vineyards or the slim state.
+    fn dim_swaps(&self, dim: usize) -> Option<DimSwaps<'_>> {
+        match self.pruned_swaps.get(dim) {
+            Some(Some((_, PrunedSwaps::Full(s)))) => return Some(DimSwaps::Full(s)),
+            Some(Some((_, PrunedSwaps::Slim(s)))) => return Some(DimSwaps::Slim(s)),
+            _ => {}
+        }
+        if let Some(ref v) = self.vineyards {
+            return v.swaps.get(dim).map(DimSwaps::Full);
+        }
+        self.slim_swaps
+            .as_ref()
+            .and_then(|s| s.get(dim))
+            .map(DimSwaps::Slim)
+    }
+
+    /// The welded medial axis of dimension `dim`, with radii.
+    fn axis_mesh(&mut self, dim: usize, sub_cell: bool) -> Option<AxisMesh> {
+        if let Some(Grid::Mesh(ref mut grid)) = self.core.grid {
+            grid.recompute_dim_dist();
+        }
+        let (Some(c), Some(g), Some(swaps)) = (
+            self.core.complex.as_ref(),
+            self.core.grid.as_ref(),
+            self.dim_swaps(dim),
+        ) else {
+            return None;
+        };
+
+        let mut mesh = match swaps {
+            DimSwaps::Full(swaps) => {
+                let vineyards = self
+                    .vineyards
+                    .as_ref()
+                    .map(|v| (c, &self.core.filtration as &dyn Filtration, v));
+                AxisMesh::from_swaps(g, dim, swaps, vineyards)
+            }
+            DimSwaps::Slim(swaps) => AxisMesh::from_slim_swaps(g, dim, swaps),
+        };
+        if sub_cell {
+            mesh.place_at_crossings(g);
+        }
+        mesh.compute_radii(c, &self.core.filtration);
+        Some(mesh)
+    }
+
     fn notify_complex_change(&self) {
         if let Some(ref f) = self.on_complex_change {
             let _ = f.call0(&JsValue::null());
@@ -148,18 +291,92 @@ impl Api {
 
     fn set_vineyards(&mut self, v: Option<mars_core::Vineyards>) {
         self.vineyards = v;
+        self.slim_swaps = None;
+        self.chunked = None;
+        self.notify_vineyards_change();
+        self.reset_pruned_swaps();
+    }
+
+    fn set_slim_swaps(&mut self, s: Option<Vec<SlimSwapList>>) {
+        self.slim_swaps = s;
+        self.vineyards = None;
+        self.chunked = None;
+        self.notify_vineyards_change();
+        self.reset_pruned_swaps();
+    }
+
+    /// Use the swaps of a chunked file.  Nothing is read until [Api::load_chunked_dim] asks for a
+    /// dimension.
+    fn set_chunked(&mut self, reader: ChunkedReader<Cursor<Vec<u8>>>) {
+        let num_dims = reader.num_dims();
+        self.slim_swaps = Some(vec![Vec::new(); num_dims]);
+        self.vineyards = None;
+        self.chunked = Some(ChunkedSwaps {
+            reader,
+            loaded: vec![false; num_dims],
+        });
         self.notify_vineyards_change();
-        self.set_pruned_swaps([None, None, None]);
+        self.reset_pruned_swaps();
+    }
+
+    /// Read the swaps of dimension `dim` from the chunked file into `slim_swaps`, if we have a
+    /// chunked file and haven't read them yet.
+    fn load_chunked_dim(&mut self, dim: usize) -> Result<(), JsValue> {
+        let (Some(chunked), Some(slim)) = (self.chunked.as_mut(), self.slim_swaps.as_mut()) else {
+            return Ok(());
+        };
+        if chunked.loaded.get(dim) != Some(&false) {
+            return Ok(());
+        }
+        let mut list = Vec::new();
+        chunked
+            .reader
+            .for_each_record(dim, |r| {
+                list.push((r.a, r.b, r.swaps));
+                Ok(())
+            })
+            .map_err(js_error)?;
+        debug!(
+            "load_chunked_dim: {} records for dimension {}",
+            list.len(),
+            dim
+        );
+        slim[dim] = list;
+        chunked.loaded[dim] = true;
+        Ok(())
     }
 
-    fn set_pruned_swaps(&mut self, ps: [Option<(PruningParam, SwapList)>; 3]) {
-        self.pruned_swaps = ps;
+    /// Clear the pruned swaps, and make room for every dimension of the complex.
+    fn reset_pruned_swaps(&mut self) {
+        let num_dims = self
+            .core
+            .complex
+            .as_ref()
+            .map_or(0, |c| c.simplices_per_dim.len());
+        self.pruned_swaps = (0..num_dims).map(|_| None).collect();
         self.notify_pruned_change();
     }
 
-    fn set_one_pruned_swaps(&mut self, i: usize, s: Option<(PruningParam, SwapList)>) {
+    fn check_pruned_dim(&self, dim: usize) -> Result<(), JsValue> {
+        if self.pruned_swaps.len() <= dim {
+            return Err(js_error(MarsError::Invalid(format!(
+                "No pruned swaps for dimension {} (have {})",
+                dim,
+                self.pruned_swaps.len()
+            ))));
+        }
+        Ok(())
+    }
+
+    fn set_one_pruned_swaps(
+        &mut self,
+        i: usize,
+        s: Option<(PruningParam, PrunedSwaps)>,
+    ) -> Result<(), JsValue> {
+        self.check_pruned_dim(i)?;
         self.pruned_swaps[i] = s;
         self.notify_pruned_change();
+        Ok(())
     }
 }
 
@@ -193,68 +410,114 @@ impl Api {
         self.on_pruned_change = Some(f);
     }
 
-    pub fn load_complex(&mut self, obj_str: String) -> Result<(), String> {
-        let cplx = mars_core::complex::Complex::read_from_obj_string(&obj_str)?;
+    pub fn load_complex(&mut self, obj_str: String) -> Result<(), JsValue> {
+        let cplx =
+            mars_core::complex::Complex::read_from_bytes(obj_str.as_bytes()).map_err(js_error)?;
+        self.set_complex(Some(cplx));
+        Ok(())
+    }
+
+    /// Like [Api::load_complex], but for binary formats like binary PLY or STL files.
+    pub fn load_complex_bytes(&mut self, bytes: Vec<u8>) -> Result<(), JsValue> {
+        let cplx = mars_core::complex::Complex::read_from_bytes(&bytes).map_err(js_error)?;
+        self.set_complex(Some(cplx));
+        Ok(())
+    }
+
+    /// Build the complex from a point cloud.  `method` is either `"rips"` or `"alpha"`.
+    pub fn load_complex_from_points(
+        &mut self,
+        points_str: String,
+        method: String,
+        radius: f64,
+    ) -> Result<(), JsValue> {
+        let points = mars_core::points::read_points_from_str(&points_str).map_err(js_error)?;
+        let method = match method.as_str() {
+            "rips" => PointComplex::Rips { radius },
+            "alpha" => PointComplex::Alpha { radius },
+            _ => {
+                return Err(js_error(MarsError::Invalid(format!(
+                    "Unknown method {:?}",
+                    method
+                ))))
+            }
+        };
+        let cplx = method.build(points).map_err(js_error)?;
         self.set_complex(Some(cplx));
         Ok(())
     }
 
     #[wasm_bindgen(getter)]
-    pub fn complex(&self) -> Result<JsValue, String> {
+    pub fn complex(&self) -> Result<JsValue, JsValue> {
         let Some(ref c) = self.core.complex else {
             return Ok(JsValue::undefined());
         };
-        serde_wasm_bindgen::to_value(&c).map_err(|e| e.to_string())
+        to_js(&c)
     }
 
-    pub fn load_mesh_grid(&mut self, obj_str: String) -> Result<(), String> {
-        let grid = Grid::Mesh(VineyardsGridMesh::read_from_obj_string(&obj_str)?);
+    pub fn load_mesh_grid(&mut self, obj_str: String) -> Result<(), JsValue> {
+        let grid = Grid::Mesh(VineyardsGridMesh::read_from_obj_string(&obj_str).map_err(js_error)?);
         self._set_grid(Some(grid));
         Ok(())
     }
 
     #[wasm_bindgen(getter)]
-    pub fn grid(&self) -> Result<JsValue, String> {
+    pub fn grid(&self) -> Result<JsValue, JsValue> {
         let Some(ref g) = self.core.grid else {
             return Ok(JsValue::undefined());
         };
 
         match g {
-            mars_core::Grid::Regular(g) => serde_wasm_bindgen::to_value(&g),
-            mars_core::Grid::Mesh(g) => serde_wasm_bindgen::to_value(&g),
+            mars_core::Grid::Regular(g) => to_js(&g),
+            mars_core::Grid::Mesh(g) => to_js(&g),
         }
-        .map_err(|e| e.to_string())
     }
 
     #[wasm_bindgen(setter)]
-    pub fn set_grid(&mut self, grid: JsValue) -> Result<(), String> {
-        let grid: VineyardsGrid =
-            serde_wasm_bindgen::from_value(grid).map_err(|e| e.to_string())?;
+    pub fn set_grid(&mut self, grid: JsValue) -> Result<(), JsValue> {
+        let grid: VineyardsGrid = from_js(grid)?;
         self._set_grid(Some(Grid::Regular(grid)));
         Ok(())
     }
 
-    pub fn lifetimes_for_simplices(&self, index: JsValue) -> Result<JsValue, String> {
-        let index: Index = serde_wasm_bindgen::from_value(index).map_err(|e| e.to_string())?;
+    #[wasm_bindgen(getter)]
+    pub fn filtration(&self) -> Result<JsValue, JsValue> {
+        to_js(&self.core.filtration)
+    }
+
+    /// Set the filtration.  A scalar field is checked against the complex, if one is loaded.  The
+    /// vineyards are cleared, since they were computed for the old filtration.
+    #[wasm_bindgen(setter)]
+    pub fn set_filtration(&mut self, value: JsValue) -> Result<(), JsValue> {
+        let filtration: FiltrationKind = from_js(value)?;
+        if let (FiltrationKind::ScalarField(f), Some(c)) = (&filtration, &self.core.complex) {
+            f.validate(c).map_err(js_error)?;
+        }
+        self.core.filtration = filtration;
+        self.set_vineyards(None);
+        Ok(())
+    }
+
+    pub fn lifetimes_for_simplices(&self, index: JsValue) -> Result<JsValue, JsValue> {
+        let index: Index = from_js(index)?;
         let Some(ref c) = self.core.complex else {
-            return Err("No complex set")?;
+            return Err(js_error(MarsError::Invalid("No complex set".into())));
         };
 
         let Some(ref g) = self.core.grid else {
-            return Err("No grid set")?;
+            return Err(js_error(MarsError::Invalid("No grid set".into())));
         };
         let pt = match g {
             Grid::Regular(g) => g.coordinate(index),
             Grid::Mesh(g) => g.coordinate(index),
         };
 
-        let (vd, ed, td) = c.distances_to(pt);
-        let ret = vec![vd, ed, td];
-        serde_wasm_bindgen::to_value(&ret).map_err(|e| e.to_string())
+        let ret = self.core.filtration.values(c, pt);
+        to_js(&ret)
     }
 
     /// Flattened coordinates for every vertex of the complex, GL style.
-    pub fn vertex_positions(&self) -> Result<Vec<f64>, String> {
+    pub fn vertex_positions(&self) -> Result<Vec<f64>, JsValue> {
         let Some(ref c) = self.core.complex else {
             return Ok(vec![]);
         };
@@ -266,7 +529,7 @@ impl Api {
     }
 
     /// Flattened coordinates for every edge of the complex, GL style.
-    pub fn edge_positions(&self) -> Result<Vec<f64>, String> {
+    pub fn edge_positions(&self) -> Result<Vec<f64>, JsValue> {
         let Some(ref c) = self.core.complex else {
             return Ok(vec![]);
         };
@@ -282,7 +545,7 @@ impl Api {
     }
 
     /// Flattened coordinates for every face of the complex, GL style.
-    pub fn face_positions(&self) -> Result<Vec<f64>, String> {
+    pub fn face_positions(&self) -> Result<Vec<f64>, JsValue> {
         let mut ret = Vec::new();
         let Some(ref c) = self.core.complex else {
             return Ok(vec![]);
@@ -314,38 +577,23 @@ impl Api {
     }
 
     /// Flattened coordinates for every face of the computed medial axes, GL style.
-    pub fn medial_axes_face_positions(&mut self, dim: usize) -> Result<Vec<f32>, String> {
+    pub fn medial_axes_face_positions(&mut self, dim: usize) -> Result<Vec<f32>, JsValue> {
+        self.load_chunked_dim(dim)?;
         let mut out: Vec<f64> = Vec::new();
-        let Some(ref mut g) = self.core.grid else {
-            return Ok(Vec::new());
-        };
-        let Some(ref v) = self.vineyards else {
+        if let Some(Grid::Mesh(ref mut grid)) = self.core.grid {
+            grid.recompute_dim_dist();
+        }
+        let (Some(g), Some(swaps)) = (self.core.grid.as_ref(), self.dim_swaps(dim)) else {
             return Ok(Vec::new());
         };
 
-        let swaps = self.pruned_swaps[dim].as_ref().map(|(_, s)| s);
-        let swaps = swaps.unwrap_or(&v.swaps[dim]);
-
-        match g {
-            Grid::Regular(grid) => {
-                for s in swaps {
-                    if 0 < s.2.v.len() {
-                        let [a, b, c, d] = grid.dual_quad_points(s.0, s.1);
-                        for p in &[a, b, c, a, c, d] {
-                            out.extend_from_slice(&[p.x(), p.y(), p.z()]);
-                        }
-                    }
-                }
-            }
-            Grid::Mesh(ref mut grid) => {
-                for s in swaps {
-                    if 0 < s.2.v.len() {
-                        let [a, b, c, d] = grid.dual_quad_points(s.0, s.1);
-                        for p in &[a, b, c, a, c, d] {
-                            out.extend_from_slice(&[p.x(), p.y(), p.z()]);
-                        }
-                    }
-                }
+        for (a, b) in swaps.edges() {
+            let [a, b, c, d] = match g {
+                Grid::Regular(grid) => grid.dual_quad_points(a, b),
+                Grid::Mesh(grid) => grid.dual_quad_points(a, b),
+            };
+            for p in &[a, b, c, a, c, d] {
+                out.extend_from_slice(&[p.x(), p.y(), p.z()]);
             }
         }
         info_mem();
@@ -353,44 +601,87 @@ impl Api {
         Ok(out.into_iter().map(|n| n as f32).collect())
     }
 
+    /// The medial axis of dimension `dim` as a welded mesh, with the attributes of each face.  With
+    /// `sub_cell`, the vertices are placed at the swap crossings instead of the cell centers.  Each
+    /// face has the radius of the medial ball at its crossing, if the filtration is a distance.
+    pub fn medial_axes_mesh(
+        &mut self,
+        dim: usize,
+        sub_cell: Option<bool>,
+    ) -> Result<JsValue, JsValue> {
+        self.load_chunked_dim(dim)?;
+        match self.axis_mesh(dim, sub_cell.unwrap_or(false)) {
+            Some(mesh) => to_js(&mesh),
+            None => Ok(JsValue::undefined()),
+        }
+    }
+
+    /// The distance from every vertex of the complex to the medial axis of dimension `dim`, which
+    /// is the local feature size, and the thickness there.  See [Api::medial_axes_mesh] for
+    /// `sub_cell`.
+    pub fn feature_size(&mut self, dim: usize, sub_cell: Option<bool>) -> Result<JsValue, JsValue> {
+        self.load_chunked_dim(dim)?;
+        let Some(mesh) = self.axis_mesh(dim, sub_cell.unwrap_or(false)) else {
+            return Ok(JsValue::undefined());
+        };
+        let Some(c) = self.core.complex.as_ref() else {
+            return Ok(JsValue::undefined());
+        };
+        let size = FeatureSize::new(c, &mesh);
+        let serializer = serde_wasm_bindgen::Serializer::new().serialize_missing_as_null(true);
+        size.serialize(&serializer).map_err(js_serde_error)
+    }
+
+    /// Colors for every vertex of the complex from [Api::feature_size], flattened as RGB in
+    /// `[0, 1]`, GL style.  Red is the smallest distance and blue the largest.
+    pub fn feature_size_colors(
+        &mut self,
+        dim: usize,
+        sub_cell: Option<bool>,
+    ) -> Result<Vec<f32>, JsValue> {
+        self.load_chunked_dim(dim)?;
+        let (Some(mesh), Some(c)) = (
+            self.axis_mesh(dim, sub_cell.unwrap_or(false)),
+            self.core.complex.as_ref(),
+        ) else {
+            return Ok(Vec::new());
+        };
+        Ok(FeatureSize::new(c, &mesh)
+            .colors()
+            .into_iter()
+            .flat_map(|rgb| rgb.map(|c| c as f32 / 255.0))
+            .collect())
+    }
+
     /// Return the [SwapList] for that corresponded to the face at `face_index` in the output of [medial_axes_face_positions].
     pub fn swaplist_from_face_index(
-        &self,
+        &mut self,
         dim: usize,
         face_index: usize,
-    ) -> Result<JsValue, String> {
-        let Some(ref v) = self.vineyards else {
-            return Err("Missing vineyards")?;
+    ) -> Result<JsValue, JsValue> {
+        self.load_chunked_dim(dim)?;
+        let Some(swaps) = self.dim_swaps(dim) else {
+            return Err(js_error(MarsError::Invalid("Missing vineyards".into())));
         };
-        let face_index = face_index & !1;
-
-        let swaps = self.pruned_swaps[dim].as_ref().map(|(_, s)| s);
-        let swaps = swaps.unwrap_or(&v.swaps[dim]);
-
-        let mut i = 0;
-        for s in swaps {
-            if 0 < s.2.v.len() {
-                if i == face_index || i + 1 == face_index {
-                    return serde_wasm_bindgen::to_value(&(s.0, s.1, &s.2.v))
-                        .map_err(|e| e.to_string());
-                }
-                i += 2;
-            }
-        }
-
-        return Err("No matching face index")?;
+        // Every quad is two triangles.
+        let Some(edge) = swaps.edge_swaps(face_index / 2) else {
+            return Err(js_error(MarsError::Invalid(
+                "No matching face index".into(),
+            )));
+        };
+        to_js(&edge)
     }
 
     pub fn subproblems(&self) -> Result<Vec<JsValue>, JsValue> {
-        let subs = self.core.split_into_4()?;
+        let subs = self.core.split_into_4().map_err(js_error)?;
 
         let mut buffers = Vec::new();
 
         for i in 0..4 {
-            let bytes = rmp_serde::to_vec(&subs[i]).map_err(|e| e.to_string())?;
+            let bytes = rmp_serde::to_vec(&subs[i]).map_err(|e| js_error(e.into()))?;
             debug!("serialize_core: {:.2} MB", mb(bytes.len()));
             let serializer = serde_wasm_bindgen::Serializer::new();
-            let ret = serializer.serialize_bytes(&bytes)?;
+            let ret = serializer.serialize_bytes(&bytes).map_err(js_serde_error)?;
             buffers.push(ret);
         }
 
@@ -398,17 +689,16 @@ impl Api {
     }
 
     pub fn serialize_core(&self) -> Result<JsValue, JsValue> {
-        let bytes = rmp_serde::to_vec(&self.core).map_err(|e| e.to_string())?;
+        let bytes = rmp_serde::to_vec(&self.core).map_err(|e| js_error(e.into()))?;
         debug!("serialize_core: {:.2} MB", mb(bytes.len()));
         let serializer = serde_wasm_bindgen::Serializer::new();
-        let ret = serializer.serialize_bytes(&bytes)?;
+        let ret = serializer.serialize_bytes(&bytes).map_err(js_serde_error)?;
         Ok(ret)
     }
 
     pub fn deserialize_core(&mut self, value: JsValue) -> Result<(), JsValue> {
-        let bytes: serde_bytes::ByteBuf = serde_wasm_bindgen::from_value(value)?;
-        let core: Mars = rmp_serde::from_slice(&bytes)
-            .map_err(|e| format!("rmp_serde failed: {}", e.to_string()))?;
+        let bytes: serde_bytes::ByteBuf = from_js(value)?;
+        let core: Mars = rmp_serde::from_slice(&bytes).map_err(|e| js_error(e.into()))?;
         debug!("deserialize_core: {:.2} MB", mb(bytes.len()));
         self.set_mars(core);
         Ok(())
@@ -419,17 +709,16 @@ impl Api {
             info!("serialize_vineyards: no vineyards");
             return Ok(JsValue::undefined());
         };
-        let bytes = rmp_serde::to_vec(vineyards).map_err(|e| e.to_string())?;
+        let bytes = rmp_serde::to_vec(vineyards).map_err(|e| js_error(e.into()))?;
         debug!("serialize_vineyards: {:.2} MB", mb(bytes.len()));
         let serializer = serde_wasm_bindgen::Serializer::new();
-        let ret = serializer.serialize_bytes(&bytes)?;
+        let ret = serializer.serialize_bytes(&bytes).map_err(js_serde_error)?;
         Ok(ret)
     }
 
     pub fn deserialize_vineyards(&mut self, value: JsValue) -> Result<(), JsValue> {
-        let bytes: serde_bytes::ByteBuf = serde_wasm_bindgen::from_value(value)?;
-        let vineyards: Vineyards = rmp_serde::from_slice(&bytes)
-            .map_err(|e| format!("rmp_serde failed: {}", e.to_string()))?;
+        let bytes: serde_bytes::ByteBuf = from_js(value)?;
+        let vineyards: Vineyards = rmp_serde::from_slice(&bytes).map_err(|e| js_error(e.into()))?;
         debug!("deserialize_vineyards: {:.2} MB", mb(bytes.len()));
         self.set_vineyards(Some(vineyards));
         Ok(())
@@ -437,9 +726,8 @@ impl Api {
 
     /// Deserialize [Vineyards] data and load it into the current vineyards instance.
     pub fn deserialize_vineyards_load(&mut self, value: JsValue) -> Result<(), JsValue> {
-        let bytes: serde_bytes::ByteBuf = serde_wasm_bindgen::from_value(value)?;
-        let vineyards: Vineyards = rmp_serde::from_slice(&bytes)
-            .map_err(|e| format!("rmp_serde failed: {}", e.to_string()))?;
+        let bytes: serde_bytes::ByteBuf = from_js(value)?;
+        let vineyards: Vineyards = rmp_serde::from_slice(&bytes).map_err(|e| js_error(e.into()))?;
         debug!("deserialize_vineyards_load: {:.2} MB", mb(bytes.len()));
 
         if let Some(ref mut v) = self.vineyards {
@@ -449,35 +737,102 @@ impl Api {
         }
 
         self.notify_vineyards_change();
-        self.set_pruned_swaps([None, None, None]);
+        self.reset_pruned_swaps();
+        Ok(())
+    }
+
+    /// Serialize the slim swaps.  With `dim`, the other dimensions are left empty, which is all the
+    /// pruning worker needs and doesn't read all of a chunked file.
+    pub fn serialize_slim_swaps(&mut self, dim: Option<usize>) -> Result<JsValue, JsValue> {
+        match dim {
+            Some(dim) => self.load_chunked_dim(dim)?,
+            None => {
+                for dim in 0..self.slim_swaps.as_ref().map_or(0, |s| s.len()) {
+                    self.load_chunked_dim(dim)?;
+                }
+            }
+        }
+        let Some(ref slim) = self.slim_swaps else {
+            return Ok(JsValue::undefined());
+        };
+        let bytes = match dim {
+            Some(dim) => {
+                let only_dim = (0..slim.len())
+                    .map(|d| {
+                        if d == dim {
+                            slim[d].clone()
+                        } else {
+                            Vec::new()
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                rmp_serde::to_vec(&only_dim)
+            }
+            None => rmp_serde::to_vec(slim),
+        }
+        .map_err(|e| js_error(e.into()))?;
+        debug!("serialize_slim_swaps: {:.2} MB", mb(bytes.len()));
+        let serializer = serde_wasm_bindgen::Serializer::new();
+        let ret = serializer.serialize_bytes(&bytes).map_err(js_serde_error)?;
+        Ok(ret)
+    }
+
+    pub fn deserialize_slim_swaps(&mut self, value: JsValue) -> Result<(), JsValue> {
+        let bytes: serde_bytes::ByteBuf = from_js(value)?;
+        let slim: Vec<SlimSwapList> =
+            rmp_serde::from_slice(&bytes).map_err(|e| js_error(e.into()))?;
+        debug!("deserialize_slim_swaps: {:.2} MB", mb(bytes.len()));
+        self.set_slim_swaps(Some(slim));
         Ok(())
     }
 
     pub fn serialize_pruned_swaps(&self, dim: usize) -> Result<JsValue, JsValue> {
-        let bytes = rmp_serde::to_vec(&self.pruned_swaps[dim]).map_err(|e| e.to_string())?;
+        self.check_pruned_dim(dim)?;
+        let bytes = rmp_serde::to_vec(&self.pruned_swaps[dim]).map_err(|e| js_error(e.into()))?;
         debug!("serialize_pruned_swaps: {:.2} MB", mb(bytes.len()));
         let serializer = serde_wasm_bindgen::Serializer::new();
-        let ret = serializer.serialize_bytes(&bytes)?;
+        let ret = serializer.serialize_bytes(&bytes).map_err(js_serde_error)?;
         Ok(ret)
     }
 
     pub fn deserialize_pruned_swaps(&mut self, dim: usize, buffer: JsValue) -> Result<(), JsValue> {
-        let bytes: serde_bytes::ByteBuf = serde_wasm_bindgen::from_value(buffer)?;
-        let pruned: Option<(PruningParam, SwapList)> = rmp_serde::from_slice(&bytes)
-            .map_err(|e| format!("rmp_serde failed: {}", e.to_string()))?;
+        let bytes: serde_bytes::ByteBuf = from_js(buffer)?;
+        let pruned: Option<(PruningParam, PrunedSwaps)> =
+            rmp_serde::from_slice(&bytes).map_err(|e| js_error(e.into()))?;
         debug!("deserialize_vineyards: {:.2} MB", mb(bytes.len()));
-        self.set_one_pruned_swaps(dim, pruned);
-        Ok(())
+        self.set_one_pruned_swaps(dim, pruned)
     }
 
     pub fn deserialize_from_cli(&mut self, buffer: JsValue) -> Result<(), JsValue> {
-        let bytes: serde_bytes::ByteBuf = serde_wasm_bindgen::from_value(buffer)?;
-        let (mars, vineyards): (Mars, Vineyards) = rmp_serde::from_slice(&bytes)
-            .map_err(|e| format!("rmp_serde failed: {}", e.to_string()))?;
+        let bytes: serde_bytes::ByteBuf = from_js(buffer)?;
+        match mars_core::state::read_state(&bytes).map_err(js_error)?.1 {
+            State::Full(mars, vineyards) => {
+                self.set_mars(mars);
+                self.set_vineyards(Some(vineyards));
+            }
+            State::Slim(swaps, mars) => {
+                self.set_mars(mars);
+                self.set_slim_swaps(Some(swaps));
+            }
+        }
 
-        self.set_mars(mars);
-        self.set_vineyards(Some(vineyards));
+        Ok(())
+    }
+
+    /// True if `buffer` starts like a chunked state file.  Only the first few bytes are needed.
+    pub fn is_chunked(&self, buffer: JsValue) -> Result<bool, JsValue> {
+        let bytes: serde_bytes::ByteBuf = from_js(buffer)?;
+        Ok(mars_core::chunked::is_chunked(&bytes))
+    }
 
+    /// Load a chunked file computed from the CLI.  Only the header and the index are read here;
+    /// the swaps of a dimension are read the first time they are needed.
+    pub fn open_chunked(&mut self, buffer: JsValue) -> Result<(), JsValue> {
+        let bytes: serde_bytes::ByteBuf = from_js(buffer)?;
+        debug!("open_chunked: {:.2} MB", mb(bytes.len()));
+        let reader = ChunkedReader::open(Cursor::new(bytes.into_vec())).map_err(js_error)?;
+        self.set_mars(reader.header().mars.clone());
+        self.set_chunked(reader);
         Ok(())
     }
 
@@ -500,7 +855,7 @@ impl Api {
             }
         };
 
-        let v = self.core.run(progress)?;
+        let v = self.core.run(progress).map_err(js_error)?;
         self.vineyards = Some(v);
         self.notify_vineyards_change();
 
@@ -514,17 +869,17 @@ impl Api {
         params: JsValue,
         on_progress: Option<js_sys::Function>,
     ) -> Result<(), JsValue> {
-        let params: PruningParam = serde_wasm_bindgen::from_value(params)?;
+        let params: PruningParam = from_js(params)?;
+        self.check_pruned_dim(dim)?;
+        self.load_chunked_dim(dim)?;
 
         let Some(ref c) = self.core.complex else {
-            return Err("Need a complex before pruning.".to_string())?;
-        };
-
-        let Some(ref mut v) = self.vineyards else {
-            return Err("Need to compute vineyards before pruning.".to_string())?;
+            return Err(js_error(MarsError::Invalid(
+                "Need a complex before pruning.".into(),
+            )));
         };
 
-        let pruned = v.prune_dim(dim, &params, c, |i, n| {
+        let progress = |i: usize, n: usize| {
             if i % 127 == 0 {
                 if let Some(ref f) = on_progress {
                     let _ = f
@@ -539,10 +894,30 @@ impl Api {
                         });
                 }
             }
-        });
+        };
 
-        self.set_one_pruned_swaps(dim, Some((params, pruned)));
-        Ok(())
+        let pruned = if let Some(ref mut v) = self.vineyards {
+            let pruned = v
+                .prune_dim(dim, &params, c, &self.core.filtration, progress)
+                .map_err(js_error)?;
+            PrunedSwaps::Full(pruned)
+        } else if let Some(ref slim) = self.slim_swaps {
+            let Some(swaps) = slim.get(dim) else {
+                return Err(js_error(MarsError::Invalid(format!(
+                    "The state has no swaps for dimension {}",
+                    dim
+                ))));
+            };
+            PrunedSwaps::Slim(mars_core::prune::prune_dim(
+                swaps, dim, &params, c, progress,
+            ))
+        } else {
+            return Err(js_error(MarsError::Invalid(
+                "Need to compute vineyards before pruning.".into(),
+            )));
+        };
+
+        self.set_one_pruned_swaps(dim, Some((params, pruned)))
     }
 
     pub fn has_vineyards(&self) -> bool {
@@ -550,37 +925,51 @@ impl Api {
     }
 
     pub fn barcode_for_index(&self, index: JsValue) -> Result<JsValue, JsValue> {
-        let index: Index = serde_wasm_bindgen::from_value(index)?;
+        let index: Index = from_js(index)?;
+        // Barcodes are keyed by dimension, from -1 for the empty simplex, and JS object keys have
+        // to be strings.
+        let serializer = serde_wasm_bindgen::Serializer::new().serialize_maps_as_objects(true);
 
         let (Some(c), Some(v)) = (self.core.complex.as_ref(), self.vineyards.as_ref()) else {
-            return Ok(serde_wasm_bindgen::to_value(&serde_json::json!({
-                "-1": [],
-                "0": [],
-                "1": [],
-                "2": [],
-            }))?);
+            let num_dims = self
+                .core
+                .complex
+                .as_ref()
+                .map_or(0, |c| c.simplices_per_dim.len());
+            return (-1..num_dims as isize)
+                .map(|dim| (dim.to_string(), Vec::<BirthDeathPair>::new()))
+                .collect::<BTreeMap<_, _>>()
+                .serialize(&serializer)
+                .map_err(js_serde_error);
         };
 
-        let reduction = v.reductions.get(&index).ok_or("Index not in map")?;
-
-        #[derive(Serialize)]
-        struct Barcode {
-            #[serde(rename = "-1")]
-            a: Vec<BirthDeathPair>,
-            #[serde(rename = "0")]
-            b: Vec<BirthDeathPair>,
-            #[serde(rename = "1")]
-            c: Vec<BirthDeathPair>,
-            #[serde(rename = "2")]
-            d: Vec<BirthDeathPair>,
-        }
+        let reduction = v.reductions.get(&index).ok_or_else(|| {
+            js_error(MarsError::Invalid(format!(
+                "No reduction for grid point {:?}",
+                index
+            )))
+        })?;
+        let f = &self.core.filtration;
+        (-1..reduction.stacks.len() as isize)
+            .map(|dim| (dim.to_string(), reduction.barcode(c, f, dim)))
+            .collect::<BTreeMap<_, _>>()
+            .serialize(&serializer)
+            .map_err(js_serde_error)
+    }
 
-        return Ok(serde_wasm_bindgen::to_value(&Barcode {
-            a: reduction.barcode(c, -1),
-            b: reduction.barcode(c, 0),
-            c: reduction.barcode(c, 1),
-            d: reduction.barcode(c, 2),
-        })?);
+    /// The barcodes, Betti numbers and swaps at any point, starting from the closest grid point
+    /// with a reduction, or from scratch if there are none.
+    pub fn probe(&self, point: JsValue) -> Result<JsValue, JsValue> {
+        let point: Pos = from_js(point)?;
+        let probe = self
+            .core
+            .probe(self.vineyards.as_ref(), point)
+            .map_err(js_error)?;
+        // Barcodes are keyed by dimension, and JS object keys have to be strings.
+        let value = serde_json::to_value(&probe)
+            .map_err(|e| js_error(MarsError::Internal(format!("serde_json failed: {}", e))))?;
+        let serializer = serde_wasm_bindgen::Serializer::new().serialize_maps_as_objects(true);
+        value.serialize(&serializer).map_err(js_serde_error)
     }
 }
 
@@ -594,9 +983,8 @@ pub fn run_sub_mars(
     submars: JsValue,
     on_progress: Option<js_sys::Function>,
 ) -> Result<JsValue, JsValue> {
-    let bytes: serde_bytes::ByteBuf = serde_wasm_bindgen::from_value(submars)?;
-    let submars: SubMars = rmp_serde::from_slice(&bytes)
-        .map_err(|e| format!("rmp_serde failed: {}", e.to_string()))?;
+    let bytes: serde_bytes::ByteBuf = from_js(submars)?;
+    let submars: SubMars = rmp_serde::from_slice(&bytes).map_err(|e| js_error(e.into()))?;
 
     let progress = |i: usize, n: usize| {
         if i % 15 == 0 {
@@ -611,12 +999,12 @@ pub fn run_sub_mars(
         }
     };
 
-    let vineyards = submars.run(progress)?;
+    let vineyards = submars.run(progress).map_err(js_error)?;
 
-    let bytes = rmp_serde::to_vec(&vineyards).map_err(|e| e.to_string())?;
+    let bytes = rmp_serde::to_vec(&vineyards).map_err(|e| js_error(e.into()))?;
     debug!("run_sub_mars: {:.2} MB", mb(bytes.len()));
     let serializer = serde_wasm_bindgen::Serializer::new();
-    let ret = serializer.serialize_bytes(&bytes)?;
+    let ret = serializer.serialize_bytes(&bytes).map_err(js_serde_error)?;
     Ok(ret)
 }
 
@@ -640,6 +1028,52 @@ export type VineyardsGridMesh = {
 }
 
 
+/** Errors from mars-core are thrown as `Error`s with the name `"MarsError"`. */
+export type MarsError = Error & {
+  name: "MarsError";
+  kind: "io" | "parse" | "invalid" | "capacity" | "internal";
+};
+
+/** A Faustian swap, which happened at `t` along its grid edge, from 0 at the first grid point
+ * to 1 at the second. */
+export type Swap = {dim: number, i: number, j: number, t: number};
+
+export type AxisFace = {
+  vertices: [number, number, number, number];
+  edge: [Index, Index];
+  num_swaps: number;
+  /** Largest persistence of a swap across the edge, or null if unknown. */
+  max_persistence: number | null;
+  /** Canonical indices of the simplices of the most persistent swap. */
+  pair: [number, number];
+  /** Point on the grid edge where the swap of `pair` happened. */
+  crossing: Point;
+  /** Radius of the medial ball at `crossing`, or null if the filtration is not a distance. */
+  radius: number | null;
+};
+
+export type AxisMesh = {
+  dim: number;
+  points: Point[];
+  faces: AxisFace[];
+};
+
+/** Per vertex of the complex, the distance to the nearest face of the medial axis of dimension
+ * `dim`, and twice the radius of that face. */
+export type FeatureSize = {
+  dim: number;
+  distance: (number | null)[];
+  thickness: (number | null)[];
+};
+
+/** The filtration the vineyards are computed for.  `values[k][v]` of a scalar field is the value
+ * of vertex `v` when the key point is `key_points[k]`. */
+export type Filtration =
+  | "SquaredEuclidean"
+  | "Euclidean"
+  | {Anisotropic: {weights: Point}}
+  | {ScalarField: {key_points: Point[], values: number[][]}};
+
 export type BirthDeathPair = {
   dim: number;
   /** [Birth time, simplex index] */
@@ -648,11 +1082,18 @@ export type BirthDeathPair = {
   death: [number, number] | null;
 };
 
-export type Barcode = {
-    "-1": BirthDeathPair[],
-    "0": BirthDeathPair[],
-    "1": BirthDeathPair[],
-    "2": BirthDeathPair[],
+/** Bars keyed by dimension, from "-1" for the empty simplex up to the dimension of the complex. */
+export type Barcode = Record<string, BirthDeathPair[]>;
+
+export type Probe = {
+  point: Point;
+  /** The grid point we started from, or null if the reduction was done from scratch. */
+  from: [Index, Point] | null;
+  barcodes: Barcode;
+  /** Reduced Betti numbers, from dimension 0. */
+  betti: number[];
+  /** Faustian swaps between `from` and `point`. */
+  swaps: Swap[];
 };
 
 export class Api {
@@ -665,6 +1106,8 @@ export class Api {
   set_on_pruned_change(f: () => void): void;
 
   load_complex(obj: string): void;
+  /** Load a complex in any supported format, including binary PLY and STL. */
+  load_complex_bytes(bytes: Uint8Array): void;
   load_mesh_grid(obj: string): void;
   set_grid(grid: VineyardsGrid): void;
 
@@ -675,6 +1118,10 @@ export class Api {
   set grid(g: VineyardsGrid | VineyardsGridMesh);
   get grid(): VineyardsGrid | VineyardsGridMesh;
 
+  /** Setting the filtration clears the vineyards. */
+  set filtration(f: Filtration);
+  get filtration(): Filtration;
+
   run_vineyards(progress?: (label: string, i: number, n: number) => void): void;
 
   prune(dim: number, params: any, progress?: (label: string, i: number, n: number) => void): void;
@@ -683,7 +1130,14 @@ export class Api {
   edge_positions(): number[];
   vertex_positions(): number[];
   medial_axes_face_positions(dim: number): Float32Array;
-  swaplist_from_face_index(dim: number, face_index: number): [Index, Index, {dim: number, i: number, j: number}[]];
+  /** The medial axis as a welded quad mesh, with the attributes of each face.  With `sub_cell`,
+   * the vertices are placed where the swaps happened instead of at the grid cell centers. */
+  medial_axes_mesh(dim: number, sub_cell?: boolean): AxisMesh | undefined;
+  feature_size(dim: number, sub_cell?: boolean): FeatureSize | undefined;
+  /** Flattened RGB colors per vertex of the complex, from red for the smallest local feature size
+   * to blue for the largest, and gray where it is unknown. */
+  feature_size_colors(dim: number, sub_cell?: boolean): Float32Array;
+  swaplist_from_face_index(dim: number, face_index: number): [Index, Index, Swap[]];
 
   /** Take grid index, return list of lifetimes for each simplex in the complex, per dimension. */
   lifetimes_for_simplices(index: number[]): number[][];
@@ -700,16 +1154,29 @@ export class Api {
    * coordinate system as this instance. */
   deserialize_vineyards_load(c: Uint8Array): void;
 
+  /** Swaps from a slim or chunked state file, if one is loaded instead of vineyards.  With `dim`,
+   * only the swaps of that dimension are included. */
+  serialize_slim_swaps(dim?: number): Uint8Array | undefined;
+  deserialize_slim_swaps(c: Uint8Array): void;
+
   serialize_pruned_swaps(dim: number): Uint8Array;
   deserialize_pruned_swaps(dim: number, buffer: Uint8Array): void;
 
 
-  /** Load a file computed from the CLI. */
+  /** Load a file computed from the CLI.  Slim files only have the swaps, so the medial axes
+   * can be shown and pruned, but there are no barcodes. */
   deserialize_from_cli(buffer: Uint8Array): void;
+  /** Check if the start of a file is a chunked state file. */
+  is_chunked(buffer: Uint8Array): boolean;
+  /** Load a chunked file computed from the CLI.  The swaps of a dimension are read when the
+   * medial axis of that dimension is first needed. */
+  open_chunked(buffer: Uint8Array): void;
 
-  /** Check if Vineyards have been computed. */
+  /** Check if Vineyards have been computed.  This is false for slim state files. */
   has_vineyards(): boolean;
 
   barcode_for_index(index: Index): Barcode;
+  /** Barcodes at any point, not just at grid points. */
+  probe(point: Point): Probe;
 }
 "#;
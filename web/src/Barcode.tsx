@@ -1,5 +1,6 @@
 import styled, { CSSProperties } from "styled-components";
 import { BirthDeathPair } from "./types";
+import type { Barcode as Barcodes } from "mars_wasm";
 import { selectedBirthDeathPair, timelinePositionAtom } from "./state";
 import { useAtom, useAtomValue, useSetAtom } from "jotai";
 import {
@@ -34,6 +35,7 @@ const dim2label: Record<number, string> = {
   0: "v",
   1: "e",
   2: "t",
+  3: "T",
 };
 
 const Center = styled.div`
@@ -219,6 +221,7 @@ const dim2color: Record<number, string> = {
   0: colors.dim0,
   1: colors.dim1,
   2: colors.dim2,
+  3: colors.dim3,
 };
 
 const BarcodeDimDiv = styled.div`
@@ -499,12 +502,7 @@ const BarcodeInner = ({
   barcodes,
   showTimeline,
 }: {
-  barcodes: {
-    "-1": BirthDeathPair[];
-    0: BirthDeathPair[];
-    1: BirthDeathPair[];
-    2: BirthDeathPair[];
-  };
+  barcodes: Barcodes;
   showTimeline: boolean;
 }) => {
   const ref = useRef<HTMLDivElement>(null);
@@ -528,10 +526,11 @@ const BarcodeInner = ({
     };
   }, []);
 
-  const allPairs = (barcodes[-1] ?? [])
-    .concat(barcodes[0] ?? [])
-    .concat(barcodes[1] ?? [])
-    .concat(barcodes[2] ?? []);
+  // From the highest dimension down to the empty simplex.
+  const dims = Object.keys(barcodes)
+    .map(Number)
+    .sort((a, b) => b - a);
+  const allPairs = dims.flatMap((dim) => barcodes[dim] ?? []);
 
   const xmax =
     max(
@@ -550,13 +549,17 @@ const BarcodeInner = ({
       ref={ref}
     >
       <BarcodePlot>
-        <BarcodeDim width={width} xmax={xmax} pairs={barcodes[2]} dim={2} />
-        <hr />
-        <BarcodeDim width={width} xmax={xmax} pairs={barcodes[1]} dim={1} />
-        <hr />
-        <BarcodeDim width={width} xmax={xmax} pairs={barcodes[0]} dim={0} />
-        <hr />
-        <BarcodeDim width={width} xmax={xmax} pairs={barcodes[-1]} dim={-1} />
+        {dims.map((dim, i) => (
+          <Fragment key={dim}>
+            {i > 0 && <hr />}
+            <BarcodeDim
+              width={width}
+              xmax={xmax}
+              pairs={barcodes[dim]}
+              dim={dim}
+            />
+          </Fragment>
+        ))}
       </BarcodePlot>
       <BarcodeXAxis width={width} xmax={xmax} />
       {showTimeline && <TimelineBar xmax={xmax} />}
@@ -19,7 +19,7 @@ import "./Controls.css";
 import { HoverTooltip } from "./HoverTooltip";
 import { BuiltinMeshes } from "./controls/BuiltinMeshes";
 import { UploadMeshGridFilePicker } from "./controls/UploadMeshGridFilePicker";
-import { UploadObjFilePicker } from "./controls/UploadComplexFilePicker";
+import { UploadComplexFilePicker } from "./controls/UploadComplexFilePicker";
 import { UploadStateFilePicker } from "./controls/UploadStateFilePicker";
 import { GridControls } from "./controls/GridControls";
 import { MedialAxes } from "./controls/MedialAxes";
@@ -246,7 +246,7 @@ f ${v + 0} ${v + 1} ${v + 2} ${v + 3}
         <h3>Import / Export</h3>
 
         <h4>Import</h4>
-        <UploadObjFilePicker />
+        <UploadComplexFilePicker />
         <UploadMeshGridFilePicker />
         <UploadStateFilePicker />
 
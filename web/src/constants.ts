@@ -11,6 +11,7 @@ export const colors = {
   dim1: "orange", // FFA500
   dim_1: "gray",
   dim2: "#8cadf1",
+  dim3: "#8cd19b",
 };
 
 export const dim2color: Record<string, string> = {
@@ -18,6 +19,7 @@ export const dim2color: Record<string, string> = {
   "0": colors.dim0,
   "1": colors.dim1,
   "2": colors.dim2,
+  "3": colors.dim3,
 };
 
 export const dim2rgb: Record<string, [number, number, number]> = {
@@ -25,4 +27,5 @@ export const dim2rgb: Record<string, [number, number, number]> = {
   "0": [0xff / 255, 0xc0 / 255, 0xcb / 255],
   "1": [0xff / 255, 0xa5 / 255, 0x00 / 255],
   "2": [0x8c / 255, 0xad / 255, 0xf1 / 255],
+  "3": [0x8c / 255, 0xd1 / 255, 0x9b / 255],
 };
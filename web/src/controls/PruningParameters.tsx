@@ -34,11 +34,13 @@ const triggerSinglePrune = atom(
     const core = m.serialize_core();
     progress({ label: "Serialize input", i: 1, n: 2 });
     const vineyards = m.serialize_vineyards();
+    const slim = m.serialize_slim_swaps(dim);
     progress({ label: "Serialize input", i: 2, n: 2 });
 
     w.postMessage({
       core,
       vineyards,
+      slim,
       dim,
       params,
     });
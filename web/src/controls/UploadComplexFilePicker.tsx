@@ -1,28 +1,31 @@
+import { MarsError } from "mars_wasm";
 import { HoverTooltip } from "../HoverTooltip";
 import { toast } from "../Toast";
 import { mars } from "../global";
 
-export const UploadObjFilePicker = () => {
+export const UploadComplexFilePicker = () => {
   return (
     <label className="file">
-      <p>
-        Import <code>.obj</code>
-      </p>
+      <p>Import complex</p>
       <input
         type="file"
+        accept=".obj,.ply,.stl,.off"
         onChange={(e) => {
           const f = e.target.files?.[0];
           if (!f) return;
-          f.text()
-            .then((text) => mars().load_complex(text))
-            .catch((err: string) => {
-              toast("error", `Failed to parse .obj: ${err}`, 3);
+          // Read the raw bytes, since binary PLY and STL files are not text.
+          f.arrayBuffer()
+            .then((buf) => mars().load_complex_bytes(new Uint8Array(buf)))
+            .catch((err: MarsError) => {
+              toast("error", `Failed to read ${f.name}: ${err.message}`, 3);
             });
         }}
       />
       <HoverTooltip right>
         <p>
-          Upload a complex from an <code>.obj</code> file.
+          Upload a complex from an <code>.obj</code>, <code>.ply</code>,{" "}
+          <code>.stl</code> or <code>.off</code> file. The format is detected
+          from the contents of the file.
         </p>
       </HoverTooltip>
     </label>
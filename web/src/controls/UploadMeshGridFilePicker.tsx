@@ -1,3 +1,4 @@
+import { MarsError } from "mars_wasm";
 import { HoverTooltip } from "../HoverTooltip";
 import { toast } from "../Toast";
 import { mars } from "../global";
@@ -15,8 +16,8 @@ export const UploadMeshGridFilePicker = () => {
           if (!f) return;
           f.text()
             .then((text) => mars().load_mesh_grid(text))
-            .catch((err: string) => {
-              toast("error", `Failed to parse .obj: ${err}`, 3);
+            .catch((err: MarsError) => {
+              toast("error", `Failed to read .obj: ${err.message}`, 3);
             });
         }}
       />
@@ -10,8 +10,11 @@ export const UploadStateFilePicker = () => {
         onChange={async (e) => {
           const f = e.target.files?.[0];
           if (!f) return;
-          const bytes = await f.arrayBuffer();
-          mars().deserialize_from_cli(new Uint8Array(bytes));
+          const bytes = new Uint8Array(await f.arrayBuffer());
+          const m = mars();
+          // Chunked files are read lazily, one dimension at a time.
+          if (m.is_chunked(bytes.subarray(0, 8))) m.open_chunked(bytes);
+          else m.deserialize_from_cli(bytes);
         }}
       />
       <HoverTooltip>
@@ -8,7 +8,7 @@ function progress(label: string, i: number, n: number) {
 }
 
 onmessage = async (e) => {
-  const { core, vineyards, dim, params } = e.data;
+  const { core, vineyards, slim, dim, params } = e.data;
   await init();
   progress("Read input", 0, 1);
   const m = new Api();
@@ -16,7 +16,8 @@ onmessage = async (e) => {
   progress("Deserialize input", 0, 6);
   m.deserialize_core(core);
   progress("Deserialize input", 1, 6);
-  m.deserialize_vineyards(vineyards);
+  if (vineyards) m.deserialize_vineyards(vineyards);
+  if (slim) m.deserialize_slim_swaps(slim);
   progress("Deserialize input", 2, 6);
 
   progress("Pruning", 3, 6);